@@ -0,0 +1,40 @@
+// The body of the horizontal bloom blur, included by bloom_blur_h.comp.
+
+#include "color/format.glsl"
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, SCENE_FORMAT) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, SCENE_FORMAT) uniform writeonly image2D out_img;
+
+layout(push_constant) uniform PushConstants {
+  uint width;
+  uint height;
+} push_constants;
+
+// Gaussian weights for the center tap and each tap either side of it.
+const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+// Pixels between taps, spreads the blur wider for the same number of taps.
+const int TAP_SPACING = 2;
+
+vec3 load(ivec2 pos) {
+  pos = clamp(pos, ivec2(0), ivec2(push_constants.width - 1, push_constants.height - 1));
+  return imageLoad(in_img, pos).rgb;
+}
+
+void main() {
+  ivec2 pix_pos = ivec2(gl_GlobalInvocationID.xy);
+  if(pix_pos.x >= push_constants.width || pix_pos.y >= push_constants.height) {
+    return;
+  }
+
+  // Blur in the horizontal direction.
+  vec3 color = load(pix_pos) * WEIGHTS[0];
+  for (int i = 1; i < 5; i++) {
+    ivec2 offset = ivec2(1, 0) * i * TAP_SPACING;
+    color += load(pix_pos + offset) * WEIGHTS[i];
+    color += load(pix_pos - offset) * WEIGHTS[i];
+  }
+
+  imageStore(out_img, pix_pos, vec4(color, 1.0));
+}
@@ -0,0 +1,32 @@
+// The body of the bloom bright pass, included by bloom_threshold.comp.
+
+#include "color/format.glsl"
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, SCENE_FORMAT) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, SCENE_FORMAT) uniform writeonly image2D out_img;
+
+layout(push_constant) uniform PushConstants {
+  uint width;
+  uint height;
+} push_constants;
+
+// Luminance where pixels start to bloom, and how soft the cutoff is.
+const float THRESHOLD = 0.75;
+const float KNEE = 0.2;
+
+void main() {
+  ivec2 pix_pos = ivec2(gl_GlobalInvocationID.xy);
+  if(pix_pos.x >= push_constants.width || pix_pos.y >= push_constants.height) {
+    return;
+  }
+
+  vec4 pixel = imageLoad(in_img, pix_pos);
+
+  // Keep only the bright parts of the scene.
+  float luminance = dot(pixel.rgb, vec3(0.299, 0.587, 0.114));
+  float weight = smoothstep(THRESHOLD - KNEE, THRESHOLD, luminance);
+
+  imageStore(out_img, pix_pos, vec4(pixel.rgb * weight, 1.0));
+}
@@ -0,0 +1,7 @@
+// The format of the scene color images the post passes read before tonemapping. The passes are
+// compiled with HDR defined while the scene is rendered in half floats.
+#ifdef HDR
+#define SCENE_FORMAT rgba16f
+#else
+#define SCENE_FORMAT rgba8
+#endif
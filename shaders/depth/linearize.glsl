@@ -0,0 +1,8 @@
+// Converts a depth buffer value back to the view space distance. Reverse-Z depth is 1 minus
+// the regular depth, which is folded into the formula to keep its precision.
+float linearize_depth(float depth, float near, float far, bool reverse_z) {
+  if (reverse_z) {
+    return (2.0 * near * far) / (2.0 * near + depth * (far - near));
+  }
+  return (2.0 * near * far) / (far + near - depth * (far - near));
+}
@@ -0,0 +1,26 @@
+// Reads the scene depth. Define DEPTH_BINDING to the binding of the depth sampler before
+// including this, and compile with SINGLE_SAMPLED defined when MSAA is disabled since a single
+// sampled depth image can't be bound as a sampler2DMS.
+
+#ifdef SINGLE_SAMPLED
+layout(set = 0, binding = DEPTH_BINDING) uniform sampler2D depth_sampler;
+
+int depth_samples() {
+  return 1;
+}
+
+// There is only one sample, so the sample index is ignored.
+float fetch_depth(ivec2 pix_pos, int sample_index) {
+  return texelFetch(depth_sampler, pix_pos, 0).r;
+}
+#else
+layout(set = 0, binding = DEPTH_BINDING) uniform sampler2DMS depth_sampler;
+
+int depth_samples() {
+  return textureSamples(depth_sampler);
+}
+
+float fetch_depth(ivec2 pix_pos, int sample_index) {
+  return texelFetch(depth_sampler, pix_pos, sample_index).r;
+}
+#endif
@@ -0,0 +1,99 @@
+// The body of the depth of field pass, included by dof.comp. Blurs the scene by how far each pixel is from the focus distance.
+
+#include "color/format.glsl"
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, SCENE_FORMAT) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, SCENE_FORMAT) uniform writeonly image2D out_img;
+
+layout(push_constant) uniform PushConstants {
+  uint width;
+  uint height;
+  float exposure;
+  uint sky_top;
+  vec3 fog_color;
+  float fog_density;
+  float near;
+  float far;
+  float vignette_strength;
+  uint sky_bottom;
+  vec3 lift;
+  uint sky;
+  vec3 gamma;
+  vec3 gain;
+  uint debug_view;
+  float ssao_radius;
+  float ssao_intensity;
+  float projection_scale;
+  // View space distance that is in focus.
+  float focus_distance;
+  // How quickly the blur grows away from the focus distance.
+  float aperture;
+  float motion_blur;
+  // 1 when the depth is reverse-Z.
+  uint reverse_z;
+} push_constants;
+
+#include "depth/linearize.glsl"
+
+// The widest the blur gets in pixels, so a far out of focus pixel doesn't sample the whole image.
+const float MAX_COC_RADIUS = 12.0;
+const int TAP_COUNT = 32;
+const float GOLDEN_ANGLE = 2.39996323;
+
+// The radius in pixels of the circle of confusion at a view space distance.
+float coc_radius(float distance) {
+  float coc = push_constants.aperture * abs(distance - push_constants.focus_distance) / distance;
+  return clamp(coc * MAX_COC_RADIUS, 0.0, MAX_COC_RADIUS);
+}
+
+float load_distance(ivec2 pos) {
+  return linearize_depth(
+    fetch_depth(pos, 0),
+    push_constants.near,
+    push_constants.far,
+    push_constants.reverse_z != 0
+  );
+}
+
+void main() {
+  ivec2 pix_pos = ivec2(gl_GlobalInvocationID.xy);
+  if(pix_pos.x >= push_constants.width || pix_pos.y >= push_constants.height) {
+    return;
+  }
+
+  vec4 center = imageLoad(in_img, pix_pos);
+  float center_distance = load_distance(pix_pos);
+  float center_coc = coc_radius(center_distance);
+  if (center_coc < 0.5) {
+    imageStore(out_img, pix_pos, center);
+    return;
+  }
+
+  // Gather over a disk of taps spiralling out to the circle of confusion.
+  ivec2 max_pos = ivec2(push_constants.width - 1, push_constants.height - 1);
+  vec3 color = center.rgb;
+  float total_weight = 1.0;
+  for (int i = 1; i < TAP_COUNT; i++) {
+    float tap_radius = center_coc * sqrt(float(i) / float(TAP_COUNT));
+    float angle = float(i) * GOLDEN_ANGLE;
+    // Clamping to the edges keeps the bokeh inside the image instead of reading garbage.
+    ivec2 tap_pos = clamp(pix_pos + ivec2(round(vec2(cos(angle), sin(angle)) * tap_radius)), ivec2(0), max_pos);
+
+    // A tap only contributes if its own blur reaches this pixel. Taps behind this pixel are
+    // limited to its circle so a sharp foreground doesn't smear over a blurred background.
+    float tap_distance = load_distance(tap_pos);
+    float tap_coc = coc_radius(tap_distance);
+    if (tap_distance > center_distance) {
+      tap_coc = min(tap_coc, center_coc);
+    }
+    float weight = smoothstep(tap_radius - 1.0, tap_radius + 1.0, tap_coc);
+
+    color += imageLoad(in_img, tap_pos).rgb * weight;
+    total_weight += weight;
+  }
+
+  // Alpha comes from the scene.
+  imageStore(out_img, pix_pos, vec4(color / total_weight, center.a));
+}
@@ -0,0 +1,96 @@
+#version 450 core
+
+layout (location = 0) out vec4 o_color;
+
+layout (location = 0) in vec3 pos;
+layout (location = 1) in vec2 uv;
+layout (location = 2) in vec3 normal;
+layout (location = 3) in float height;
+layout (location = 4) in vec3 vertex_color;
+
+struct MeshFur {
+  vec3 base_color;
+  float density;
+  vec3 tip_color;
+  float grass_height;
+};
+
+layout(set = 0, binding = 6) uniform FurUniform {
+  uint resolution;
+  float specular_strength;
+  float shininess;
+  uint alpha_to_coverage;
+  float mask_threshold;
+  float wind_strength;
+  vec2 wind_direction;
+  float ambient;
+  vec3 interaction_point;
+  float interaction_radius;
+  vec3 gravity;
+  float stiffness;
+  float ao_strength;
+  uint use_albedo_texture;
+  float clumping;
+  uint use_comb_map;
+  MeshFur meshes[8];
+} fur;
+
+layout(set = 0, binding = 1) uniform LightingUniform {
+  vec3 position;
+  float intensity;
+  vec3 color;
+} lighting;
+
+layout(set = 0, binding = 3) uniform sampler2D density_mask;
+layout(set = 0, binding = 7) uniform sampler2D albedo_texture;
+
+// Matches the strand thickness of shell.frag without fur layers.
+const float THICKNESS = 3;
+
+float hash(uint n) {
+	n = (n << 13U) ^ n;
+	n = n * (n * n * 15731U + 0x789221U) + 0x13763129U;
+	return float(n & uint(0x7fffffffU)) / float(0x7fffffff);
+}
+
+void main() {
+  MeshFur mesh_fur = fur.meshes[0];
+  // The shells are spaced by the square root of their fraction, so a point this high up the
+  // fin lies on the shell at its square.
+  float h = height * height;
+
+  // The same strand cells and heights as shell.frag, so the fins continue the strands of the
+  // shells behind them.
+  vec2 new_uv = vec2(uv * vec2(11, 3) * mesh_fur.density);
+  vec2 local_uv = fract(new_uv) * 2 - 1;
+  uvec2 tid = uvec2(new_uv);
+  uint seed = (tid.x + 100) * (tid.y + 50) * 10;
+  float mask = texture(density_mask, uv).r;
+  if (mask < fur.mask_threshold) {
+    discard;
+  }
+  float rand = hash(seed) * mask;
+  if (THICKNESS * (rand - h) - length(local_uv) < 0.0) {
+    discard;
+  }
+
+  vec3 root_color = mesh_fur.base_color;
+  vec3 tip_color = mesh_fur.tip_color;
+  if (fur.use_albedo_texture != 0) {
+    vec3 albedo_color = texture(albedo_texture, uv).rgb;
+    root_color = albedo_color * 0.5;
+    tip_color = albedo_color;
+  }
+  vec3 color = mix(root_color, tip_color, h) * vertex_color;
+  color *= mix(1.0 - fur.ao_strength, 1.0, h);
+
+  // The light of the shells without the per strand normals and specular.
+  vec3 grass_to_light = normalize(lighting.position - pos);
+  float theta = dot(normal, grass_to_light) * 0.5 + 0.5;
+  float s = length(lighting.position - pos) / (6.0 * lighting.intensity);
+  float attenuation = lighting.intensity * (pow(1-s*s, 2)/(1+10.0*s*s));
+  float lambert = max(dot(normalize(normal), grass_to_light), 0.0);
+  color *= mix(fur.ambient, 1.0, lambert);
+
+  o_color = vec4(color * (h * h * theta * attenuation) * lighting.color, 1.0);
+}
@@ -0,0 +1,79 @@
+#version 450 core
+
+layout(location = 0) in vec3 vertex;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec3 normal;
+layout(location = 3) in vec3 tangent;
+layout(location = 4) in vec3 color;
+
+layout(location = 0) out vec3 p_position;
+layout(location = 1) out vec2 p_uv;
+layout(location = 2) out vec3 p_normal;
+// 0 at the root of the fin and 1 at its tip.
+layout(location = 3) out float p_height;
+layout(location = 4) out vec3 p_vertex_color;
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+  mat4 proj;
+  mat4 view;
+} camera;
+
+struct Model {
+  mat4 model;
+  // The inverse transpose of model, for normals.
+  mat4 normal;
+};
+
+// Fins are only built for the renderer's own mesh, the first model.
+layout(set = 0, binding = 2) uniform ModelUniform {
+  Model models[8];
+} models;
+
+struct MeshFur {
+  vec3 base_color;
+  float density;
+  vec3 tip_color;
+  // Height in cm.
+  float grass_height;
+};
+
+layout(set = 0, binding = 6) uniform FurUniform {
+  uint resolution;
+  float specular_strength;
+  float shininess;
+  uint alpha_to_coverage;
+  float mask_threshold;
+  float wind_strength;
+  vec2 wind_direction;
+  float ambient;
+  vec3 interaction_point;
+  float interaction_radius;
+  vec3 gravity;
+  float stiffness;
+  float ao_strength;
+  uint use_albedo_texture;
+  float clumping;
+  uint use_comb_map;
+  MeshFur meshes[8];
+} fur;
+
+// Each fin repeats the ends of its edge as root, root, tip, root, tip, tip.
+const float FIN_HEIGHTS[6] = float[](0.0, 0.0, 1.0, 0.0, 1.0, 1.0);
+
+void main() {
+  Model model = models.models[0];
+  float height = FIN_HEIGHTS[gl_VertexIndex % 6];
+
+  // Extruded the same way as the shells, but straight out without the wind and bending.
+  vec3 world_normal = normalize(mat3(model.normal) * normal);
+  float normal_scale = length(mat3(model.model) * normal);
+  vec3 position = (model.model * vec4(vertex, 1.0)).xyz;
+  position += world_normal * (height * fur.meshes[0].grass_height * normal_scale);
+
+  gl_Position = camera.proj * camera.view * vec4(position, 1.0);
+  p_position = position;
+  p_uv = uv;
+  p_normal = world_normal;
+  p_height = height;
+  p_vertex_color = color;
+}
@@ -0,0 +1,9 @@
+#version 450 core
+
+layout (location = 0) out vec4 o_color;
+
+layout (location = 0) in vec3 color;
+
+void main() {
+  o_color = vec4(color, 1.0);
+}
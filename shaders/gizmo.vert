@@ -0,0 +1,38 @@
+#version 450 core
+
+layout(location = 0) out vec3 p_color;
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+  mat4 proj;
+  mat4 view;
+} camera;
+
+layout(set = 0, binding = 1) uniform LightingUniform {
+  vec3 position;
+  float intensity;
+  vec3 color;
+} lighting;
+
+layout(push_constant) uniform PushConstants {
+  // Half the length of each arm of the cross drawn at the light.
+  float size;
+} push_constants;
+
+const vec3 CROSS_AXES[3] = vec3[](vec3(1, 0, 0), vec3(0, 1, 0), vec3(0, 0, 1));
+
+void main() {
+  vec3 position;
+  if (gl_VertexIndex == 0) {
+    // Start the line at the origin where the mesh sits.
+    position = vec3(0.0);
+  } else if (gl_VertexIndex == 1) {
+    position = lighting.position;
+  } else {
+    int i = gl_VertexIndex - 2;
+    float side = (i % 2 == 0) ? -1.0 : 1.0;
+    position = lighting.position + CROSS_AXES[i / 2] * push_constants.size * side;
+  }
+
+  gl_Position = camera.proj * camera.view * vec4(position, 1.0);
+  p_color = lighting.color;
+}
@@ -0,0 +1,37 @@
+#version 450 core
+
+layout (location = 0) out vec4 o_color;
+
+layout (location = 0) in vec3 world_position;
+
+layout(push_constant) uniform PushConstants {
+  // World space point on the ground under the camera.
+  vec3 center;
+  // Half the width of the grid quad.
+  float extent;
+  vec3 color;
+  // World space distance between lines.
+  float spacing;
+} push_constants;
+
+// Fraction of the extent the grid starts fading out at, so the edge of the quad never shows.
+const float FADE_START = 0.4;
+
+void main() {
+  // Distance to the nearest line in pixels, which keeps the lines a pixel wide at any distance.
+  vec2 coord = world_position.xz / push_constants.spacing;
+  vec2 derivative = fwidth(coord);
+  vec2 grid = abs(fract(coord - 0.5) - 0.5) / derivative;
+  float line = 1.0 - min(min(grid.x, grid.y), 1.0);
+  // Lines closer together than a couple of pixels turn into moire, so they fade out first.
+  line *= 1.0 - smoothstep(0.25, 0.5, max(derivative.x, derivative.y));
+
+  float distance = length(world_position.xz - push_constants.center.xz) / push_constants.extent;
+  float alpha = line * (1.0 - smoothstep(FADE_START, 1.0, distance));
+  if (alpha <= 0.0) {
+    discard;
+  }
+
+  // The alpha blends the line in and adds to the coverage the sky is composited behind.
+  o_color = vec4(push_constants.color, alpha);
+}
@@ -0,0 +1,32 @@
+#version 450 core
+
+layout(location = 0) out vec3 p_world_position;
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+  mat4 proj;
+  mat4 view;
+} camera;
+
+layout(push_constant) uniform PushConstants {
+  // World space point on the ground under the camera.
+  vec3 center;
+  // Half the width of the grid quad.
+  float extent;
+  vec3 color;
+  // World space distance between lines.
+  float spacing;
+} push_constants;
+
+// Two triangles covering the square around the camera.
+const vec2 QUAD_VERTICES[6] = vec2[](
+  vec2(-1, -1), vec2(1, -1), vec2(1, 1),
+  vec2(-1, -1), vec2(1, 1), vec2(-1, 1)
+);
+
+void main() {
+  vec2 local = QUAD_VERTICES[gl_VertexIndex];
+  vec3 position = push_constants.center + vec3(local.x, 0.0, local.y) * push_constants.extent;
+
+  gl_Position = camera.proj * camera.view * vec4(position, 1.0);
+  p_world_position = position;
+}
@@ -0,0 +1,24 @@
+#version 450 core
+
+layout (location = 0) out vec4 o_color;
+
+layout (location = 0) in vec2 local;
+
+layout(push_constant) uniform PushConstants {
+  // World space point on the ground under the mesh.
+  vec3 center;
+  // Radius of the shadow on the ground.
+  float radius;
+  // Fraction of the radius the shadow fades out over.
+  float softness;
+  // Darkness at the center of the shadow.
+  float opacity;
+} push_constants;
+
+void main() {
+  // Radial gradient, solid in the middle and fading to nothing at the edge of the quad.
+  float distance = length(local);
+  float shadow = 1.0 - smoothstep(1.0 - push_constants.softness, 1.0, distance);
+
+  o_color = vec4(0.0, 0.0, 0.0, shadow * push_constants.opacity);
+}
@@ -0,0 +1,33 @@
+#version 450 core
+
+layout(location = 0) out vec2 p_local;
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+  mat4 proj;
+  mat4 view;
+} camera;
+
+layout(push_constant) uniform PushConstants {
+  // World space point on the ground under the mesh.
+  vec3 center;
+  // Radius of the shadow on the ground.
+  float radius;
+  // Fraction of the radius the shadow fades out over.
+  float softness;
+  // Darkness at the center of the shadow.
+  float opacity;
+} push_constants;
+
+// Two triangles covering the square around the shadow.
+const vec2 QUAD_VERTICES[6] = vec2[](
+  vec2(-1, -1), vec2(1, -1), vec2(1, 1),
+  vec2(-1, -1), vec2(1, 1), vec2(-1, 1)
+);
+
+void main() {
+  vec2 local = QUAD_VERTICES[gl_VertexIndex];
+  vec3 position = push_constants.center + vec3(local.x, 0.0, local.y) * push_constants.radius;
+
+  gl_Position = camera.proj * camera.view * vec4(position, 1.0);
+  p_local = local;
+}
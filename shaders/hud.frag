@@ -0,0 +1,14 @@
+#version 450 core
+
+layout(location = 0) in vec2 p_uv;
+layout(location = 1) in vec4 p_color;
+
+layout(location = 0) out vec4 o_color;
+
+// The font atlas or a user texture, premultiplied and gamma encoded like the vertex colors.
+layout(set = 0, binding = 0) uniform sampler2D hud_texture;
+
+void main() {
+  // The output is gamma encoded too, so blending happens in gamma space as egui expects.
+  o_color = p_color * texture(hud_texture, p_uv);
+}
@@ -0,0 +1,21 @@
+#version 450 core
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+// Premultiplied and gamma encoded, unpacked from 8 bits per channel.
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 p_uv;
+layout(location = 1) out vec4 p_color;
+
+layout(push_constant) uniform PushConstants {
+  // The size of the output in egui points.
+  vec2 screen_size;
+} push_constants;
+
+void main() {
+  // egui's origin is the top left with y pointing down, the same as Vulkan's clip space.
+  gl_Position = vec4(position / push_constants.screen_size * 2.0 - 1.0, 0.0, 1.0);
+  p_uv = uv;
+  p_color = color;
+}
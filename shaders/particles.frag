@@ -0,0 +1,20 @@
+#version 450 core
+
+layout(location = 0) out vec4 o_color;
+
+layout(location = 0) in float alpha;
+
+layout(push_constant) uniform PushConstants {
+  vec3 color;
+  // The diameter of the sprites in pixels.
+  float size;
+} push_constants;
+
+void main() {
+  // Round sprites, soft towards the edge.
+  float distance = length(gl_PointCoord - 0.5) * 2.0;
+  if (distance > 1.0) {
+    discard;
+  }
+  o_color = vec4(push_constants.color, alpha * (1.0 - distance * distance));
+}
@@ -0,0 +1,34 @@
+#version 450 core
+
+layout(location = 0) in vec4 position_age;
+layout(location = 1) in vec4 velocity_lifetime;
+
+layout(location = 0) out float p_alpha;
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+  mat4 proj;
+  mat4 view;
+} camera;
+
+layout(push_constant) uniform PushConstants {
+  vec3 color;
+  // The diameter of the sprites in pixels.
+  float size;
+} push_constants;
+
+void main() {
+  float age = position_age.w;
+  float lifetime = velocity_lifetime.w;
+  if (age >= lifetime) {
+    // Dead particles are moved outside the clip volume so they are discarded.
+    gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+    gl_PointSize = 1.0;
+    p_alpha = 0.0;
+    return;
+  }
+
+  gl_Position = camera.proj * camera.view * vec4(position_age.xyz, 1.0);
+  gl_PointSize = push_constants.size;
+  // Fade out over the particle's life.
+  p_alpha = 1.0 - age / lifetime;
+}
@@ -0,0 +1,134 @@
+// The body of the final post pass, included by post.comp. Tonemaps the scene into the 8-bit output whatever the scene format is.
+
+#include "color/format.glsl"
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, SCENE_FORMAT) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, rgba8) uniform writeonly image2D out_img;
+// View space normal and shell height, only written while the G-buffer is enabled.
+layout(set = 0, binding = 4, rgba16f) uniform readonly image2D normal_img;
+
+layout(push_constant) uniform PushConstants {
+  uint width;
+  uint height;
+  float exposure;
+  // Packed RGBA8 colors at the top and bottom of the screen.
+  uint sky_top;
+  vec3 fog_color;
+  float fog_density;
+  float near;
+  float far;
+  float vignette_strength;
+  uint sky_bottom;
+  vec3 lift;
+  // 1 draws the sky gradient behind the scene.
+  uint sky;
+  vec3 gamma;
+  vec3 gain;
+  // 0 shows the scene, 1 the linearized depth, 2 the normals and 3 the shell height.
+  uint debug_view;
+  float ssao_radius;
+  float ssao_intensity;
+  float projection_scale;
+  float focus_distance;
+  float aperture;
+  float motion_blur;
+  // 1 when the depth is reverse-Z.
+  uint reverse_z;
+} push_constants;
+
+const float CONTRAST = 1.25;
+const float BRIGHTNESS = 0.095;
+
+const uint DEBUG_VIEW_DEPTH = 1;
+const uint DEBUG_VIEW_NORMALS = 2;
+const uint DEBUG_VIEW_SHELL_HEIGHT = 3;
+// View space distance the depth debug view fades to black over.
+const float DEBUG_DEPTH_RANGE = 10.0;
+
+#include "depth/linearize.glsl"
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 x) {
+  const float a = 2.51;
+  const float b = 0.03;
+  const float c = 2.43;
+  const float d = 0.59;
+  const float e = 0.14;
+  return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+vec3 quantize(vec3 color, float levels) {
+  return floor(color * levels) / levels;
+}
+
+void main() {
+  ivec2 pix_pos = ivec2(gl_GlobalInvocationID.xy);
+  if(pix_pos.x >= push_constants.width || pix_pos.y >= push_constants.height) {
+    return;
+  }
+
+  vec4 pixel = imageLoad(in_img, pix_pos);
+
+  // Exposure and tonemapping, only the color is touched so alpha passes through.
+  pixel.rgb = aces(pixel.rgb * push_constants.exposure);
+
+  // The sky gradient shows through wherever the scene doesn't cover the pixel, and the fog
+  // fades into it. The scene was cleared to transparent black, so its color is already
+  // weighted by the coverage in alpha, including strand tips covering part of a pixel.
+  vec3 fog_color = push_constants.fog_color;
+  if (push_constants.sky != 0) {
+    float sky_t = (float(pix_pos.y) + 0.5) / float(push_constants.height);
+    vec3 sky_color = mix(
+      unpackUnorm4x8(push_constants.sky_top).rgb,
+      unpackUnorm4x8(push_constants.sky_bottom).rgb,
+      sky_t
+    );
+    pixel.rgb += sky_color * (1.0 - clamp(pixel.a, 0.0, 1.0));
+    fog_color = sky_color;
+  }
+
+  // Exponential distance fog.
+  float depth = linearize_depth(
+    fetch_depth(pix_pos, 0),
+    push_constants.near,
+    push_constants.far,
+    push_constants.reverse_z != 0
+  );
+  float fog = 1.0 - exp(-push_constants.fog_density * depth);
+  pixel.rgb = mix(pixel.rgb, fog_color, fog);
+
+  // Brightness and contract
+  pixel.rgb = CONTRAST * (pixel.rgb - 0.5) + 0.5 + BRIGHTNESS;
+
+  // Gray scale
+  float gray = dot(pixel.rgb, vec3(0.299, 0.587, 0.114));
+  // pixel.rgb = vec3(gray, gray, gray); // Uncomment to enable gray scale
+  
+  // Modify colors so darker shades are brighter, quantize, and reverse.
+  pixel.rgb = pow(pixel.rgb, vec3(1/1.3));
+  pixel.rgb = quantize(pixel.rgb, 16);
+  pixel.rgb = pow(pixel.rgb, vec3(1.4));
+
+  // Lift, gamma, gain color grade, lift raises the shadows and gain scales the highlights.
+  pixel.rgb = push_constants.gain * (pixel.rgb + push_constants.lift * (1.0 - pixel.rgb));
+  pixel.rgb = pow(max(pixel.rgb, 0.0), 1.0 / push_constants.gamma);
+
+  // Vignette, the distance is normalized per axis so the corners are at 1 at any resolution.
+  vec2 resolution = vec2(push_constants.width, push_constants.height);
+  vec2 center_offset = ((vec2(pix_pos) + 0.5) / resolution - 0.5) * 2.0;
+  float vignette_distance = length(center_offset) / sqrt(2.0);
+  pixel.rgb *= 1.0 - push_constants.vignette_strength * vignette_distance * vignette_distance;
+
+  if (push_constants.debug_view == DEBUG_VIEW_DEPTH) {
+    pixel.rgb = vec3(1.0 - clamp(depth / DEBUG_DEPTH_RANGE, 0.0, 1.0));
+  } else if (push_constants.debug_view == DEBUG_VIEW_NORMALS) {
+    pixel.rgb = imageLoad(normal_img, pix_pos).xyz * 0.5 + 0.5;
+  } else if (push_constants.debug_view == DEBUG_VIEW_SHELL_HEIGHT) {
+    pixel.rgb = vec3(imageLoad(normal_img, pix_pos).w);
+  }
+
+  // The output is opaque whatever the scene covers.
+  imageStore(out_img, pix_pos, vec4(pixel.rgb, 1.0));
+}
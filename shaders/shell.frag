@@ -1,33 +1,134 @@
 #version 450 core
 
 layout (location = 0) out vec4 o_color;
+#ifdef GBUFFER
+// View space normal and shell height, only declared when the G-buffer attachment is bound.
+layout (location = 1) out vec4 o_normal;
+#endif
 
 layout (location = 0) in vec3 pos;
 layout (location = 1) in vec2 uv;
 layout (location = 2) in vec3 normal;
 layout (location = 3) flat in uint index;
 layout (location = 4) flat in uint v_index;
+layout (location = 5) flat in vec3 camera_position;
+layout (location = 6) flat in uint model_index;
+layout (location = 7) in vec3 vertex_color;
 
 layout(push_constant) uniform PushConstants {
   // Seconds since start.
   float time;
-  // Planes per cm.
-  uint resolution;
+  // Number of shells drawn per mesh.
+  uint shell_count;
+} push_constants;
+
+struct MeshFur {
+  // Strand color at the root.
+  vec3 base_color;
+  // Strands per uv unit.
+  float density;
+  // Strand color at the tip.
+  vec3 tip_color;
   // Height in cm.
   float grass_height;
-} push_constants;
+};
+
+layout(set = 0, binding = 6) uniform FurUniform {
+  // Planes per cm.
+  uint resolution;
+  // Intensity of the specular highlight.
+  float specular_strength;
+  // Specular exponent.
+  float shininess;
+  // 1 fades the strand edges out through the alpha instead of discarding them.
+  uint alpha_to_coverage;
+  // Density mask values below this are bald.
+  float mask_threshold;
+  // How far the tips sway in the wind.
+  float wind_strength;
+  // Normalized wind direction on the XZ plane.
+  vec2 wind_direction;
+  // The least of the scene light's Lambert term any fur receives.
+  float ambient;
+  // World space point the fur is pushed away from.
+  vec3 interaction_point;
+  // How far from the interaction point fur is pushed, 0 disables it.
+  float interaction_radius;
+  // World space direction the fur droops in, its length is the strength.
+  vec3 gravity;
+  // How much the fur resists drooping, 0 to 1.
+  float stiffness;
+  // How much darker the roots are than the tips.
+  float ao_strength;
+  // 1 colors the strands from the albedo texture instead of the procedural colors.
+  uint use_albedo_texture;
+  // How much strands gather into tufts, 0 to 1.
+  float clumping;
+  // 1 tilts the strands along the comb map instead of extruding them along the normal.
+  uint use_comb_map;
+  // Indexed like the models, the renderer's mesh first.
+  MeshFur meshes[8];
+} fur;
+
+layout(set = 0, binding = 0) uniform CameraUniform {
+  mat4 proj;
+  mat4 view;
+} camera;
+
+layout(set = 0, binding = 1) uniform LightingUniform {
+  vec3 position;
+  float intensity;
+  vec3 color;
+} lighting;
+
+layout(set = 0, binding = 3) uniform sampler2D density_mask;
+// A white pixel when no albedo texture is set.
+layout(set = 0, binding = 7) uniform sampler2D albedo_texture;
+
+struct FurLayer {
+  vec3 color;
+  // Strands per uv unit.
+  float density;
+  // Strand thickness at the root.
+  float thickness;
+  // Strand length as a fraction of the shell height, the top of this layer's range.
+  float shell_fraction;
+};
+
+layout(set = 0, binding = 4) uniform FurLayersUniform {
+  // 0 uses the mesh's colors and density as a single coat.
+  uint layer_count;
+  // Sorted by shell fraction.
+  FurLayer layers[4];
+} fur_layers;
+
+struct Light {
+  // World space position of point lights, direction of travel of directional lights.
+  vec3 position_or_direction;
+  float intensity;
+  vec3 color;
+  uint kind;
+};
+
+layout(set = 0, binding = 5) uniform LightsUniform {
+  uint light_count;
+  Light lights[4];
+} lights;
+
+const uint LIGHT_KIND_POINT = 1;
 
 const float TAU = 6.28318530718;
-const vec3 GRASS_COLOR = vec3(0.77, 0.97, 0.28);
 
-const vec3 LIGHT_POS = vec3(2.3, 1.5, -2);
-const float LIGHT_INTENSITY = 1.75;
-const vec3 LIGHT_COLOR = vec3(1.0, 1, 1.0);
 const vec3 UP_NORMAL = vec3(0.0, 1.0, 0.0);
 
-const float density = 126;
 const float thickness = 3;
 
+// How far the normal of the top shell bends out from the strand center.
+const float TIP_NORMAL_BEND = 0.5;
+
+// The width of a clump in strands.
+const float CLUMP_CELLS = 4.0;
+
 // Copied integer hash from Acerola which was copied from Hugo Elias.
 float hash(uint n) {
 	n = (n << 13U) ^ n;
@@ -36,19 +137,104 @@ float hash(uint n) {
 }
 
 void main() {
-  vec3 color = GRASS_COLOR;
+  float h = float(index) / float(push_constants.shell_count);
+  MeshFur mesh_fur = fur.meshes[model_index];
+
+  // Pick the coat whose shell range contains this shell, strands of each coat taper over their
+  // own length.
+  float density = mesh_fur.density;
+  float strand_thickness = thickness;
+  float strand_h = h;
+  vec3 root_color = mesh_fur.base_color;
+  vec3 tip_color = mesh_fur.tip_color;
+  if (fur_layers.layer_count > 0) {
+    uint layer_index = fur_layers.layer_count;
+    for (uint i = 0; i < fur_layers.layer_count; i++) {
+      if (h <= fur_layers.layers[i].shell_fraction) {
+        layer_index = i;
+        break;
+      }
+    }
+    // Above the longest coat there is no fur, only the base shell is left. This is the same for
+    // the whole shell so the derivatives taken below stay defined.
+    if (layer_index == fur_layers.layer_count) {
+      if (index > 0) {
+        discard;
+      }
+      layer_index = 0;
+    }
+    FurLayer layer = fur_layers.layers[layer_index];
+    density = layer.density;
+    strand_thickness = layer.thickness;
+    strand_h = h / layer.shell_fraction;
+    root_color = layer.color * 0.5;
+    tip_color = layer.color;
+  }
+
+  // An authored albedo replaces the colors of every coat, with the same darker roots as the
+  // layers. Like the mask it is sampled before the strand discards.
+  if (fur.use_albedo_texture != 0) {
+    vec3 albedo_color = texture(albedo_texture, uv).rgb;
+    root_color = albedo_color * 0.5;
+    tip_color = albedo_color;
+  }
+  // Painted vertex colors tint whichever colors were picked.
+  root_color *= vertex_color;
+  tip_color *= vertex_color;
 
   // We multiply be 11 and 3 to get a uniform distribution of grass due to the way the way the triangle uvs are laid out.
   vec2 new_uv = vec2(uv * vec2(11, 3) * density);
   vec2 local_uv = fract(new_uv) * 2 - 1;
+
+  // Lean each strand towards the center of its clump, up to half a cell at the tips. The clump
+  // comes from the cell alone so the pattern is stable from frame to frame.
+  vec2 clump_cell = floor(new_uv / CLUMP_CELLS);
+  vec2 to_clump = (clump_cell + 0.5) * CLUMP_CELLS - (floor(new_uv) + 0.5);
+  float clump_distance = length(to_clump);
+  if (clump_distance > 0.0) {
+    vec2 lean = to_clump / clump_distance * min(clump_distance, 1.0);
+    local_uv -= lean * (fur.clumping * strand_h);
+  }
+
+  // Tangent frame of the strand uvs, taken before any discard so the derivatives stay defined.
+  vec3 dp_dx = dFdx(pos);
+  vec3 dp_dy = dFdy(pos);
+  vec2 duv_dx = dFdx(new_uv);
+  vec2 duv_dy = dFdy(new_uv);
+  // How much the distance from the strand center changes per pixel, to fade the edge over.
+  float edge_width = max(fwidth(length(local_uv)), 0.0001);
   uvec2 tid = uvec2(new_uv);
   uint seed = (tid.x + 100) * (tid.y + 50) * 10;
   float rand = hash(seed);
-  float h = float(index) / float(push_constants.resolution);
-  bool outsideThickness = length(local_uv) > (thickness * (rand-h));
-  if (outsideThickness && index > 0) {
+  // Strands of a clump share part of its height, so clumps read as tufts.
+  uvec2 clump_id = uvec2(clump_cell);
+  float clump_rand = hash((clump_id.x + 100) * (clump_id.y + 50) * 10 + 7919);
+  rand = mix(rand, clump_rand, fur.clumping * 0.5);
+
+  // The density mask shortens strands, and removes them entirely below the threshold.
+  float mask = texture(density_mask, uv).r;
+  if (index > 0 && mask < fur.mask_threshold) {
     discard;
   }
+  rand *= mask;
+
+  // With alpha-to-coverage the edge fades out over a pixel and MSAA covers part of the
+  // samples, otherwise the strand is cut off hard.
+  float edge_distance = strand_thickness * (rand-strand_h) - length(local_uv);
+  float alpha = 1.0;
+  if (index > 0) {
+    if (fur.alpha_to_coverage != 0) {
+      alpha = clamp(edge_distance / edge_width + 0.5, 0.0, 1.0);
+      if (alpha <= 0.0) {
+        discard;
+      }
+    } else if (edge_distance < 0.0) {
+      discard;
+    }
+  }
+
+  // Darker roots fading to lighter tips.
+  vec3 color = mix(root_color, tip_color, strand_h);
 
   // Calculate some color variance for each grass blade.
   seed += 1632;
@@ -56,20 +242,70 @@ void main() {
   vec3 color_variance = (rand * 2 - 1) * vec3(0.15, 0.2, 0.15);
   color += color_variance;
 
-  vec3 grass_to_light = normalize(LIGHT_POS - pos);
+  // Fake self-shadowing, darkens toward the roots using the same height as the strand test.
+  color *= mix(1.0 - fur.ao_strength, 1.0, h);
+
+  vec3 grass_to_light = normalize(lighting.position - pos);
   
   // Half lambert shading, looks nicer.
   float theta = dot(normal, grass_to_light) * 0.5 + 0.5;
   
   // Constants to make the light falloff look nicer.
-  float s = length(LIGHT_POS - pos) / (6.0 * LIGHT_INTENSITY);
+  float s = length(lighting.position - pos) / (6.0 * lighting.intensity);
   float f = 10.0;
 
   // As the object gets further away from the light, it recieves less light.
-  float attenuation = LIGHT_INTENSITY * (pow(1-s*s, 2)/(1+f*s*s));
+  float attenuation = lighting.intensity * (pow(1-s*s, 2)/(1+f*s*s));
   
   // Ambient occlusion, the shorter the blade the darker, less light it recieves.
   float ao = pow(h, 2);
-  vec3 bd = (ao * theta * attenuation) * LIGHT_COLOR;
-  o_color = vec4(color * bd, 1.0);
+  vec3 bd = (ao * theta * attenuation) * lighting.color;
+
+  // Bend the normal out from the strand center on higher shells, so the tips shade like round
+  // strands instead of the flat surface under them.
+  vec3 n = normalize(normal);
+  vec3 dp_dy_perp = cross(dp_dy, n);
+  vec3 dp_dx_perp = cross(n, dp_dx);
+  vec3 tangent = dp_dy_perp * duv_dx.x + dp_dx_perp * duv_dy.x;
+  vec3 bitangent = dp_dy_perp * duv_dx.y + dp_dx_perp * duv_dy.y;
+  vec3 strand_offset = tangent * local_uv.x + bitangent * local_uv.y;
+  if (dot(strand_offset, strand_offset) > 0.0) {
+    strand_offset = normalize(strand_offset);
+  }
+  vec3 shell_normal = normalize(n + strand_offset * (h * TIP_NORMAL_BEND));
+
+  // The color before the scene light's Lambert term darkens it, lit again by the extra lights.
+  vec3 albedo = color;
+
+  // Lambertian scene light from the same position as the specular, kept above the ambient floor
+  // so the back isn't black.
+  float lambert = max(dot(shell_normal, grass_to_light), 0.0);
+  color *= mix(fur.ambient, 1.0, lambert);
+
+  // Blinn-Phong specular, the tips of the grass catch more of the highlight.
+  vec3 grass_to_camera = normalize(camera_position - pos);
+  vec3 half_dir = normalize(grass_to_light + grass_to_camera);
+  float spec = pow(max(dot(normalize(normal), half_dir), 0.0), fur.shininess);
+  vec3 specular = (fur.specular_strength * spec * h * attenuation) * lighting.color;
+
+  // Extra lights add their Lambert term, with the same root darkening as the scene light.
+  vec3 extra_light = vec3(0.0);
+  for (uint i = 0; i < lights.light_count; i++) {
+    Light light = lights.lights[i];
+    vec3 to_light = -light.position_or_direction;
+    float falloff = 1.0;
+    if (light.kind == LIGHT_KIND_POINT) {
+      to_light = light.position_or_direction - pos;
+      float distance = length(to_light);
+      to_light /= max(distance, 0.0001);
+      falloff = 1.0 / (1.0 + distance * distance);
+    }
+    float light_lambert = max(dot(shell_normal, to_light), 0.0);
+    extra_light += light.color * (light.intensity * falloff * light_lambert);
+  }
+
+  o_color = vec4(color * bd + specular + albedo * ao * extra_light, alpha);
+#ifdef GBUFFER
+  o_normal = vec4(normalize(mat3(camera.view) * shell_normal), h);
+#endif
 }
@@ -3,44 +3,181 @@
 layout(location = 0) in vec3 vertex;
 layout(location = 1) in vec2 uv;
 layout(location = 2) in vec3 normal;
+// Points along increasing u, for combing the fur in a direction across the surface.
+layout(location = 3) in vec3 tangent;
+// Tints the fur of the region, white for meshes without painted colors.
+layout(location = 4) in vec3 color;
 
 layout(location = 0) out vec3 p_position;
 layout(location = 1) out vec2 p_uv;
 layout(location = 2) out vec3 p_normal;
 layout (location = 3) out uint p_index;
 layout (location = 4) out uint p_v_index;
+layout (location = 5) flat out vec3 p_camera_position;
+// Indexes the models and the fur of each mesh.
+layout (location = 6) flat out uint p_model_index;
+layout (location = 7) out vec3 p_vertex_color;
 
 layout(set = 0, binding = 0) uniform CameraUniform {
   mat4 proj;
   mat4 view;
 } camera;
 
+struct Model {
+  mat4 model;
+  // The inverse transpose of model, for normals.
+  mat4 normal;
+};
+
+// The renderer's mesh first, then its instances.
+layout(set = 0, binding = 2) uniform ModelUniform {
+  Model models[8];
+} models;
+
 layout(push_constant) uniform PushConstants {
   // Seconds since start.
   float time;
-  // Planes per cm.
-  uint resolution;
+  // Number of shells drawn per mesh.
+  uint shell_count;
+} push_constants;
+
+struct MeshFur {
+  // Strand color at the root.
+  vec3 base_color;
+  // Strands per uv unit.
+  float density;
+  // Strand color at the tip.
+  vec3 tip_color;
   // Height in cm.
   float grass_height;
-} push_constants;
+};
+
+layout(set = 0, binding = 6) uniform FurUniform {
+  // Planes per cm.
+  uint resolution;
+  // Intensity of the specular highlight.
+  float specular_strength;
+  // Specular exponent.
+  float shininess;
+  // 1 fades the strand edges out through the alpha instead of discarding them.
+  uint alpha_to_coverage;
+  // Density mask values below this are bald.
+  float mask_threshold;
+  // How far the tips sway in the wind.
+  float wind_strength;
+  // Normalized wind direction on the XZ plane.
+  vec2 wind_direction;
+  // The least of the scene light's Lambert term any fur receives.
+  float ambient;
+  // World space point the fur is pushed away from.
+  vec3 interaction_point;
+  // How far from the interaction point fur is pushed, 0 disables it.
+  float interaction_radius;
+  // World space direction the fur droops in, its length is the strength.
+  vec3 gravity;
+  // How much the fur resists drooping, 0 to 1.
+  float stiffness;
+  // How much darker the roots are than the tips.
+  float ao_strength;
+  // 1 colors the strands from the albedo texture instead of the procedural colors.
+  uint use_albedo_texture;
+  // How much strands gather into tufts, 0 to 1.
+  float clumping;
+  // 1 tilts the strands along the comb map instead of extruding them along the normal.
+  uint use_comb_map;
+  // Indexed like the models, the renderer's mesh first.
+  MeshFur meshes[8];
+} fur;
+
+// Tangent space directions to comb the fur in, red along the tangent and green along the
+// bitangent with 0.5 as no tilt. A white pixel when no comb map is set.
+layout(set = 0, binding = 8) uniform sampler2D comb_map;
 
 const float SHELL_LENGTH = 0.5;
 const float CURVATURE = 0.88;
+// How far a fully combed strand tilts away from the normal, in radians.
+const float MAX_COMB_ANGLE = 1.2;
 
 void main() {
   vec3 position = vertex;
   // position = ICOSAHEDRON_VERTICES[gl_VertexIndex];
-  float h = float(gl_InstanceIndex) / push_constants.resolution;
+  // Each mesh starts its instances at its model index times the shell count.
+  uint instance_index = uint(gl_InstanceIndex);
+  uint shell_index = instance_index % push_constants.shell_count;
+  uint model_index = instance_index / push_constants.shell_count;
+  Model model = models.models[model_index];
+  MeshFur mesh_fur = fur.meshes[model_index];
+  float h = float(shell_index) / push_constants.shell_count;
+
+  // Extrude along the world space normal so the fur stays perpendicular to the surface under
+  // non-uniform scale, the length follows the scale of the mesh along the normal.
+  vec3 world_normal = normalize(mat3(model.normal) * normal);
+  float normal_scale = length(mat3(model.model) * normal);
+  vec3 offset = world_normal * (sqrt(h) * mesh_fur.grass_height * normal_scale);
+  position = (model.model * vec4(position, 1.0)).xyz;
+  vec3 root_position = position;
 
-  position += sqrt(h) * push_constants.grass_height * position;
+  // Tilt the extrusion in the surface tangent frame towards the combed direction, the length
+  // of the direction is how far it tilts. Rotating keeps the strand length the same.
+  if (fur.use_comb_map != 0) {
+    vec2 comb = textureLod(comb_map, uv, 0.0).rg * 2.0 - 1.0;
+    float comb_strength = min(length(comb), 1.0);
+    vec3 world_tangent = mat3(model.model) * tangent;
+    world_tangent -= world_normal * dot(world_tangent, world_normal);
+    if (comb_strength > 0.0001 && length(world_tangent) > 0.0001) {
+      world_tangent = normalize(world_tangent);
+      vec3 world_bitangent = cross(world_normal, world_tangent);
+      vec3 comb_direction = normalize(world_tangent * comb.x + world_bitangent * comb.y);
+      float angle = comb_strength * MAX_COMB_ANGLE;
+      offset = (world_normal * cos(angle) + comb_direction * sin(angle)) * length(offset);
+    }
+  }
+
+  // Bend the extrusion towards gravity, the tips bend the most and the base shell not at all.
+  // Bending the direction rather than moving the position keeps the strand length the same.
+  float offset_length = length(offset);
+  float gravity_strength = length(fur.gravity);
+  if (offset_length > 0.0 && gravity_strength > 0.0) {
+    vec3 direction = offset / offset_length;
+    float droop = clamp(gravity_strength * h * (1.0 - fur.stiffness), 0.0, 1.0);
+    vec3 bent = mix(direction, fur.gravity / gravity_strength, droop);
+    // Shells facing away from gravity would cancel out with it, so keep their direction.
+    if (length(bent) > 0.0001) {
+      direction = normalize(bent);
+    }
+    offset = direction * offset_length;
+  }
+  position += offset;
+
+  // Curve the grass downwards in world space so it droops the same way however the mesh is rotated.
   position.y -= (1 - CURVATURE) * pow(h, 1.5);
 
+  // Sway along the wind, phased by the root position so a whole strand moves together.
+  // Scaling by h keeps the base shell anchored, and time never wraps so the sway can't pop.
+  float sway = sin(push_constants.time + dot(root_position.xz, fur.wind_direction));
+  position.xz += fur.wind_direction * (sway * fur.wind_strength * h);
+
+  // Flatten the fur away from the interaction point, fading out towards the edge of the radius.
+  // Pushing by the shell offset keeps the base shell fixed and bends the tips the furthest.
+  float interaction_radius = fur.interaction_radius;
+  if (interaction_radius > 0.0) {
+    vec3 away = root_position - fur.interaction_point;
+    float distance = length(away);
+    if (distance > 0.0001 && distance < interaction_radius) {
+      float falloff = 1.0 - smoothstep(0.0, interaction_radius, distance);
+      position += (away / distance) * (falloff * offset_length);
+    }
+  }
+
   gl_Position = camera.proj * camera.view * vec4(position, 1.0);
 
   p_position = position;
   // p_uv = ICOSAHEDRON_UVS[gl_VertexIndex];
   p_uv = uv;
-  p_normal = normal;
-  p_index = gl_InstanceIndex;
+  p_normal = world_normal;
+  p_index = shell_index;
   p_v_index = gl_VertexIndex;
+  p_camera_position = inverse(camera.view)[3].xyz;
+  p_model_index = model_index;
+  p_vertex_color = color;
 }
@@ -0,0 +1,124 @@
+// The body of the SSAO pass, included by ssao.comp. Writes the ambient occlusion to every channel of the output.
+
+#include "color/format.glsl"
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+layout(set = 0, binding = 1, SCENE_FORMAT) uniform writeonly image2D out_img;
+layout(set = 0, binding = 4, rgba16f) uniform readonly image2D normal_img;
+
+layout(set = 0, binding = 5) uniform SsaoUniform {
+  // Offsets in the hemisphere around +Z.
+  vec4 kernel[16];
+  // Rotations of the kernel around the normal, tiled over 4x4 pixels.
+  vec4 noise[16];
+} ssao;
+
+layout(push_constant) uniform PushConstants {
+  uint width;
+  uint height;
+  float exposure;
+  uint sky_top;
+  vec3 fog_color;
+  float fog_density;
+  float near;
+  float far;
+  float vignette_strength;
+  uint sky_bottom;
+  vec3 lift;
+  uint sky;
+  vec3 gamma;
+  vec3 gain;
+  uint debug_view;
+  // View space distance occluders are searched within.
+  float ssao_radius;
+  // How much the occlusion darkens the scene.
+  float ssao_intensity;
+  // Pixels per view space unit at a distance of 1.
+  float projection_scale;
+  float focus_distance;
+  float aperture;
+  float motion_blur;
+  // 1 when the depth is reverse-Z.
+  uint reverse_z;
+} push_constants;
+
+#include "depth/linearize.glsl"
+
+const int KERNEL_SIZE = 16;
+// Keeps flat surfaces from occluding themselves through depth precision.
+const float BIAS = 0.01;
+
+float load_depth(ivec2 pos) {
+  pos = clamp(pos, ivec2(0), ivec2(push_constants.width - 1, push_constants.height - 1));
+  return linearize_depth(
+    fetch_depth(pos, 0),
+    push_constants.near,
+    push_constants.far,
+    push_constants.reverse_z != 0
+  );
+}
+
+vec2 screen_center() {
+  return vec2(push_constants.width, push_constants.height) * 0.5;
+}
+
+// The view space position of a pixel, the camera looks down +Z with +Y up.
+vec3 view_position(ivec2 pos, float depth) {
+  vec2 offset = (vec2(pos) + 0.5 - screen_center()) / push_constants.projection_scale;
+  return vec3(offset.x * depth, -offset.y * depth, depth);
+}
+
+void main() {
+  ivec2 pix_pos = ivec2(gl_GlobalInvocationID.xy);
+  if(pix_pos.x >= push_constants.width || pix_pos.y >= push_constants.height) {
+    return;
+  }
+
+  // The background has no normal and nothing to occlude it.
+  vec3 normal = imageLoad(normal_img, pix_pos).xyz;
+  if (dot(normal, normal) < 0.25) {
+    imageStore(out_img, pix_pos, vec4(1.0));
+    return;
+  }
+  normal = normalize(normal);
+
+  float depth = load_depth(pix_pos);
+  vec3 position = view_position(pix_pos, depth);
+
+  // Rotate the kernel around the normal by the noise for this pixel, the blur after this pass
+  // averages the 4x4 tile of rotations out.
+  vec3 random = ssao.noise[(pix_pos.y % 4) * 4 + pix_pos.x % 4].xyz;
+  vec3 tangent = random - normal * dot(random, normal);
+  if (dot(tangent, tangent) < 0.0001) {
+    tangent = abs(normal.x) < 0.9 ? vec3(1, 0, 0) : vec3(0, 1, 0);
+    tangent -= normal * dot(tangent, normal);
+  }
+  tangent = normalize(tangent);
+  mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+  float radius = push_constants.ssao_radius;
+  float occlusion = 0.0;
+  for (int i = 0; i < KERNEL_SIZE; i++) {
+    vec3 sample_position = position + tbn * ssao.kernel[i].xyz * radius;
+    if (sample_position.z <= push_constants.near) {
+      continue;
+    }
+
+    // Samples off screen have no depth to compare against, so they don't occlude.
+    vec2 sample_pixel = vec2(sample_position.x, -sample_position.y) / sample_position.z
+      * push_constants.projection_scale + screen_center();
+    if (any(lessThan(sample_pixel, vec2(0.0)))
+      || any(greaterThanEqual(sample_pixel, vec2(push_constants.width, push_constants.height)))) {
+      continue;
+    }
+
+    float scene_depth = load_depth(ivec2(sample_pixel));
+    // Fade out occluders far in front of this pixel, they are separate objects.
+    float range = smoothstep(0.0, 1.0, radius / abs(depth - scene_depth));
+    occlusion += (scene_depth <= sample_position.z - BIAS ? 1.0 : 0.0) * range;
+  }
+
+  float ao = clamp(1.0 - occlusion / float(KERNEL_SIZE) * push_constants.ssao_intensity, 0.0, 1.0);
+  imageStore(out_img, pix_pos, vec4(ao));
+}
@@ -0,0 +1,39 @@
+// The body of the SSAO blur, included by ssao_blur.comp.
+
+#include "color/format.glsl"
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, SCENE_FORMAT) uniform readonly image2D in_img;
+layout(set = 0, binding = 1, SCENE_FORMAT) uniform writeonly image2D out_img;
+layout(set = 0, binding = 3, SCENE_FORMAT) uniform readonly image2D scene_img;
+
+layout(push_constant) uniform PushConstants {
+  uint width;
+  uint height;
+} push_constants;
+
+float load(ivec2 pos) {
+  pos = clamp(pos, ivec2(0), ivec2(push_constants.width - 1, push_constants.height - 1));
+  return imageLoad(in_img, pos).r;
+}
+
+void main() {
+  ivec2 pix_pos = ivec2(gl_GlobalInvocationID.xy);
+  if(pix_pos.x >= push_constants.width || pix_pos.y >= push_constants.height) {
+    return;
+  }
+
+  // Box blur over the 4x4 tile the SSAO noise repeats over, which removes its pattern.
+  float ao = 0.0;
+  for (int y = -2; y < 2; y++) {
+    for (int x = -2; x < 2; x++) {
+      ao += load(pix_pos + ivec2(x, y));
+    }
+  }
+  ao /= 16.0;
+
+  // Darken the scene by the occlusion, alpha comes from the scene.
+  vec4 scene = imageLoad(scene_img, pix_pos);
+  imageStore(out_img, pix_pos, vec4(scene.rgb * ao, scene.a));
+}
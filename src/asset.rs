@@ -1,6 +1,467 @@
-use pyrite::{asset::loaders::spirv::SpirVLoader, prelude::AppBuilder};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use pyrite::{asset::Loader, prelude::AppBuilder};
 
 pub fn setup_asset_loaders(app_builder: &mut AppBuilder) {
     let mut assets = app_builder.get_resource_mut::<pyrite::asset::Assets>();
-    assets.add_loader::<SpirVLoader>();
+    assets.add_loader::<GlslSourceLoader>();
+}
+
+/// Compiles GLSL source to SPIR-V words when the shader is loaded, so the files under
+/// `shaders/` stay editable text and a hot reload recompiles them. Precompiled `.spv` files are
+/// loaded as is.
+///
+/// The shader stage comes from the file extension and the macros from `set_shader_defines`,
+/// compile errors are returned as the asset error rather than panicking. Every module is
+/// checked to be well formed SPIR-V before it is handed out, so a corrupt file is reported here
+/// instead of failing inside pipeline creation.
+pub struct GlslSourceLoader {}
+
+// The macros each shader file is compiled with, keyed by path. The loader is created by the
+// asset system so it can't be handed them directly.
+static SHADER_DEFINES: Mutex<BTreeMap<String, Vec<String>>> = Mutex::new(BTreeMap::new());
+
+/// Sets the macros defined when `file_path` is compiled, replacing any set before.
+///
+/// They apply from the next time the file is loaded, a file has one set of defines at a time.
+pub fn set_shader_defines(file_path: &str, defines: Vec<String>) {
+    let mut shader_defines = SHADER_DEFINES.lock().unwrap();
+    if defines.is_empty() {
+        shader_defines.remove(file_path);
+    } else {
+        shader_defines.insert(file_path.to_string(), defines);
+    }
+}
+
+fn shader_defines(file_path: &str) -> Vec<String> {
+    SHADER_DEFINES
+        .lock()
+        .unwrap()
+        .get(file_path)
+        .cloned()
+        .unwrap_or_default()
+}
+
+impl GlslSourceLoader {
+    fn shader_kind(file_path: &str) -> Result<shaderc::ShaderKind, String> {
+        match file_path.rsplit('.').next() {
+            Some("vert") => Ok(shaderc::ShaderKind::Vertex),
+            Some("frag") => Ok(shaderc::ShaderKind::Fragment),
+            Some("comp") => Ok(shaderc::ShaderKind::Compute),
+            _ => Err(format!(
+                "{} is not a .vert, .frag or .comp shader",
+                file_path
+            )),
+        }
+    }
+
+    fn load_spirv(file_path: &str) -> Result<Vec<u32>, String> {
+        let bytes = std::fs::read(file_path)
+            .map_err(|err| format!("Failed to read {}: {}", file_path, err))?;
+        spirv_from_bytes(&bytes)
+            .map_err(|err| format!("{} is not valid SPIR-V: {}", file_path, err))
+    }
+
+    fn compile(file_path: &str) -> Result<Vec<u32>, String> {
+        let shader_kind = Self::shader_kind(file_path)?;
+        let source = preprocess_shader(file_path)?.source;
+
+        let compiler = shaderc::Compiler::new()
+            .ok_or_else(|| "Failed to create the shaderc compiler".to_string())?;
+        let mut options = shaderc::CompileOptions::new()
+            .ok_or_else(|| "Failed to create the shaderc compile options".to_string())?;
+        for define in shader_defines(file_path) {
+            options.add_macro_definition(&define, None);
+        }
+        let artifact = compiler
+            .compile_into_spirv(&source, shader_kind, file_path, "main", Some(&options))
+            .map_err(|err| err.to_string())?;
+
+        if artifact.get_num_warnings() > 0 {
+            println!("{}", artifact.get_warning_messages());
+        }
+
+        let spirv = artifact.as_binary().to_vec();
+        validate_spirv(&spirv)
+            .map_err(|err| format!("{} compiled to invalid SPIR-V: {}", file_path, err))?;
+        Ok(spirv)
+    }
+}
+
+impl Loader for GlslSourceLoader {
+    fn new() -> Self
+    where
+        Self: Sized,
+    {
+        Self {}
+    }
+
+    fn load(&self, file_path: String) -> Result<Box<dyn Any + Send + Sync>, String> {
+        let spirv = if file_path.ends_with(".spv") {
+            Self::load_spirv(&file_path)
+        } else {
+            Self::compile(&file_path)
+        };
+        spirv.map(|spirv| Box::new(spirv) as Box<dyn Any + Send + Sync>)
+    }
+
+    fn identifiers() -> &'static [&'static str]
+    where
+        Self: Sized,
+    {
+        &["vert", "frag", "comp", "spv"]
+    }
+}
+
+const SPIRV_MAGIC: u32 = 0x07230203;
+const SPIRV_HEADER_WORDS: usize = 5;
+
+/// Reads little-endian SPIR-V words from a file's bytes and validates them.
+fn spirv_from_bytes(bytes: &[u8]) -> Result<Vec<u32>, String> {
+    if bytes.len() % 4 != 0 {
+        return Err(format!(
+            "the size of {} bytes is not a whole number of words",
+            bytes.len()
+        ));
+    }
+
+    let words = bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect::<Vec<_>>();
+    validate_spirv(&words)?;
+    Ok(words)
+}
+
+/// Checks the header of a SPIR-V module and that its instructions exactly fill the words
+/// after it.
+///
+/// This isn't full validation, it catches truncated, empty and non-SPIR-V files before
+/// they reach the driver.
+fn validate_spirv(words: &[u32]) -> Result<(), String> {
+    let Some(header) = words.get(..SPIRV_HEADER_WORDS) else {
+        return Err(format!(
+            "{} words is shorter than the {} word header",
+            words.len(),
+            SPIRV_HEADER_WORDS
+        ));
+    };
+
+    let magic = header[0];
+    if magic != SPIRV_MAGIC {
+        return Err(if magic.swap_bytes() == SPIRV_MAGIC {
+            "the module is big-endian, only little-endian modules are supported".to_string()
+        } else {
+            format!(
+                "the magic number is {:#010x} instead of {:#010x}",
+                magic, SPIRV_MAGIC
+            )
+        });
+    }
+
+    // The version is 0x00MMmm00, with a major version of 1.
+    let version = header[1];
+    if version & 0xff0000ff != 0 || (version >> 16) & 0xff != 1 {
+        return Err(format!("unsupported version {:#010x}", version));
+    }
+    // The bound is one more than the largest id, a module without ids can't have an entry point.
+    if header[3] == 0 {
+        return Err("the id bound is 0".to_string());
+    }
+    if header[4] != 0 {
+        return Err(format!(
+            "the reserved schema word is {} instead of 0",
+            header[4]
+        ));
+    }
+
+    let mut offset = SPIRV_HEADER_WORDS;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        if word_count == 0 {
+            return Err(format!(
+                "the instruction at word {} has a word count of 0",
+                offset
+            ));
+        }
+        if offset + word_count > words.len() {
+            return Err(format!(
+                "the instruction at word {} needs {} words but only {} are left, the file may be truncated",
+                offset,
+                word_count,
+                words.len() - offset
+            ));
+        }
+        offset += word_count;
+    }
+    if offset == SPIRV_HEADER_WORDS {
+        return Err("the module has no instructions".to_string());
+    }
+
+    Ok(())
+}
+
+/// GLSL source with every `#include` expanded in place.
+pub struct PreprocessedShader {
+    pub source: String,
+    /// Every file pulled in through an include, directly or through another include.
+    pub includes: Vec<PathBuf>,
+}
+
+/// Recursively expands `#include "path"` directives, with paths relative to the including file.
+///
+/// A file is only expanded the first time it is included so shared headers don't need include
+/// guards. `#line` directives are emitted around each include so compile errors still point at
+/// the right file and line.
+pub fn preprocess_shader(file_path: impl AsRef<Path>) -> Result<PreprocessedShader, String> {
+    let (shader, result) = expand_shader(file_path.as_ref());
+    result.map(|()| shader)
+}
+
+/// The files a shader includes, directly or through another include.
+///
+/// When the shader can't be preprocessed this still lists the includes read before the error,
+/// including a missing file, so the shader can be reloaded once the include is fixed.
+pub fn shader_includes(file_path: impl AsRef<Path>) -> Vec<PathBuf> {
+    expand_shader(file_path.as_ref()).0.includes
+}
+
+fn expand_shader(file_path: &Path) -> (PreprocessedShader, Result<(), String>) {
+    let mut shader = PreprocessedShader {
+        source: String::new(),
+        includes: Vec::new(),
+    };
+    let result = expand_includes(
+        file_path,
+        &mut Vec::new(),
+        &mut shader.includes,
+        &mut shader.source,
+    );
+    (shader, result)
+}
+
+fn expand_includes(
+    file_path: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    includes: &mut Vec<PathBuf>,
+    output: &mut String,
+) -> Result<(), String> {
+    let source = std::fs::read_to_string(file_path)
+        .map_err(|err| format!("Failed to read {}: {}", file_path.display(), err))?;
+    include_stack.push(canonical_path(file_path));
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let trimmed = line.trim_start();
+
+        if let Some(directive) = trimmed.strip_prefix("#include") {
+            let include_name = directive
+                .trim()
+                .strip_prefix('"')
+                .and_then(|rest| rest.strip_suffix('"'))
+                .ok_or_else(|| {
+                    format!(
+                        "{}:{}: expected #include \"path\"",
+                        file_path.display(),
+                        line_number
+                    )
+                })?;
+            let include_path = file_path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(include_name);
+
+            let canonical_include = canonical_path(&include_path);
+            if include_stack.contains(&canonical_include) {
+                return Err(format!(
+                    "{}:{}: including {} creates a cycle",
+                    file_path.display(),
+                    line_number,
+                    include_path.display()
+                ));
+            }
+
+            if !includes
+                .iter()
+                .any(|path| canonical_path(path) == canonical_include)
+            {
+                // Listed before it is checked so a missing include is still watched.
+                includes.push(include_path.clone());
+                if !include_path.is_file() {
+                    return Err(format!(
+                        "{}:{}: included file {} was not found",
+                        file_path.display(),
+                        line_number,
+                        include_path.display()
+                    ));
+                }
+                output.push_str(&format!("#line 1 \"{}\"\n", include_path.display()));
+                expand_includes(&include_path, include_stack, includes, output)?;
+            }
+            output.push_str(&format!(
+                "#line {} \"{}\"\n",
+                line_number + 1,
+                file_path.display()
+            ));
+            continue;
+        }
+
+        output.push_str(line);
+        output.push('\n');
+
+        // The quoted file names in the #line directives need this extension, and it has to
+        // come after the #version line of the root file.
+        if include_stack.len() == 1 && trimmed.starts_with("#version") {
+            output.push_str("#extension GL_GOOGLE_cpp_style_line_directive : require\n");
+            output.push_str(&format!(
+                "#line {} \"{}\"\n",
+                line_number + 1,
+                file_path.display()
+            ));
+        }
+    }
+
+    include_stack.pop();
+    Ok(())
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own under the temp directory, removed again when dropped.
+    struct ShaderDirectory(PathBuf);
+
+    impl ShaderDirectory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("furry-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, name: &str, source: &str) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, source).unwrap();
+            path
+        }
+    }
+
+    impl Drop for ShaderDirectory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn includes_are_expanded_once() {
+        let directory = ShaderDirectory::new("includes-expanded");
+        directory.write("common.glsl", "float common_value;\n");
+        directory.write("lighting.glsl", "#include \"common.glsl\"\nfloat light;\n");
+        let shader = directory.write(
+            "shader.frag",
+            "#version 450\n#include \"common.glsl\"\n#include \"lighting.glsl\"\n",
+        );
+
+        let preprocessed = preprocess_shader(&shader).unwrap();
+        assert_eq!(preprocessed.includes.len(), 2);
+        assert_eq!(
+            preprocessed.source.matches("float common_value;").count(),
+            1
+        );
+        assert!(preprocessed.source.contains("float light;"));
+    }
+
+    #[test]
+    fn missing_include_is_still_listed() {
+        let directory = ShaderDirectory::new("missing-include");
+        directory.write("common.glsl", "float common_value;\n");
+        let shader = directory.write(
+            "shader.frag",
+            "#version 450\n#include \"common.glsl\"\n#include \"missing.glsl\"\n",
+        );
+
+        assert!(preprocess_shader(&shader).is_err());
+        let includes = shader_includes(&shader);
+        assert_eq!(
+            includes,
+            vec![
+                directory.0.join("common.glsl"),
+                directory.0.join("missing.glsl")
+            ]
+        );
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let directory = ShaderDirectory::new("include-cycle");
+        directory.write("a.glsl", "#include \"b.glsl\"\n");
+        directory.write("b.glsl", "#include \"a.glsl\"\n");
+        let shader = directory.write("shader.frag", "#version 450\n#include \"a.glsl\"\n");
+
+        let err = preprocess_shader(&shader).err().unwrap();
+        assert!(err.contains("creates a cycle"), "{}", err);
+    }
+
+    // The header of a SPIR-V 1.0 module with an id bound of 2, followed by OpCapability Shader.
+    fn minimal_spirv() -> Vec<u32> {
+        vec![SPIRV_MAGIC, 0x0001_0000, 0, 2, 0, (2 << 16) | 17, 1]
+    }
+
+    fn spirv_bytes(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn valid_spirv_is_loaded() {
+        let words = minimal_spirv();
+        assert_eq!(spirv_from_bytes(&spirv_bytes(&words)), Ok(words));
+    }
+
+    #[test]
+    fn corrupt_spirv_is_rejected_with_the_reason() {
+        let bytes = spirv_bytes(&minimal_spirv());
+        let err = spirv_from_bytes(&bytes[..bytes.len() - 2]).unwrap_err();
+        assert!(err.contains("not a whole number of words"), "{}", err);
+
+        let mut words = minimal_spirv();
+        words[0] = 0xdeadbeef;
+        let err = spirv_from_bytes(&spirv_bytes(&words)).unwrap_err();
+        assert!(err.contains("magic number is 0xdeadbeef"), "{}", err);
+
+        words[0] = SPIRV_MAGIC.swap_bytes();
+        let err = spirv_from_bytes(&spirv_bytes(&words)).unwrap_err();
+        assert!(err.contains("big-endian"), "{}", err);
+
+        let words = minimal_spirv();
+        let err = spirv_from_bytes(&spirv_bytes(&words[..6])).unwrap_err();
+        assert!(err.contains("may be truncated"), "{}", err);
+
+        let err = spirv_from_bytes(&spirv_bytes(&words[..3])).unwrap_err();
+        assert!(err.contains("shorter than the 5 word header"), "{}", err);
+    }
+
+    #[test]
+    fn corrupt_spirv_file_error_names_the_file() {
+        let directory = ShaderDirectory::new("corrupt-spirv");
+        let path = directory.0.join("shader.spv");
+        std::fs::write(&path, b"not a shader").unwrap();
+
+        let path = path.to_str().unwrap();
+        let err = GlslSourceLoader::load_spirv(path).unwrap_err();
+        assert!(
+            err.starts_with(&format!("{} is not valid SPIR-V", path)),
+            "{}",
+            err
+        );
+    }
 }
@@ -1,17 +1,41 @@
+use std::path::Path;
+
 use asset::setup_asset_loaders;
 use pyrite::desktop::window::WindowState;
 use pyrite::prelude::*;
 use render::camera::setup_camera_preset;
+use render::frame_pacing::{FramePacing, FramePacingMode};
+use render::golden::setup_golden_capture;
+use render::settings::{setup_render_settings, RenderSettings};
 use render::setup_render_preset;
+use render::shell::{auto_tune::ShellAutoTune, ShellRenderer};
 
 mod asset;
 mod render;
 
 const APP_NAME: &str = "The Furry Game";
 
+// Whether the Khronos validation layer is loaded unless the settings file says otherwise,
+// `FURRY_VALIDATION=0` or `=1` overrides both.
+const VALIDATION_LAYERS: bool = cfg!(debug_assertions);
+const VALIDATION_LAYER_NAME: &str = "VK_LAYER_KHRONOS_validation";
+
+// The present modes `FURRY_PRESENT_MODE` can ask Mesa for, FIFO is vsync.
+const PRESENT_MODES: [&str; 3] = ["fifo", "mailbox", "immediate"];
+
+// Frames recorded ahead of the GPU, `FURRY_FRAMES_IN_FLIGHT` overrides it.
+const FRAMES_IN_FLIGHT: usize = 2;
+
+// The fur and camera settings are loaded from here on startup and saved here with F9, the
+// desktop settings are only read on startup.
+const SETTINGS_FILE_PATH: &str = "render_settings.toml";
+
 fn main() {
     let mut app_builder = AppBuilder::new();
 
+    configure_validation_layers();
+    configure_mesa_present_mode();
+
     // Sets up the pyrite_desktop preset.
     setup_desktop_preset(
         &mut app_builder,
@@ -28,9 +52,107 @@ fn main() {
     // Setup assets.
     setup_asset_loaders(&mut app_builder);
 
-    // Setup rendering.
+    // Setup rendering, the camera sizes its buffers from the render manager's frames in flight.
+    setup_render_preset(&mut app_builder, frames_in_flight());
     setup_camera_preset(&mut app_builder);
-    setup_render_preset(&mut app_builder);
+
+    // `FURRY_AUTO_TUNE=0` keeps the default shell count instead of measuring the GPU at startup.
+    if std::env::var("FURRY_AUTO_TUNE").as_deref() == Ok("0") {
+        app_builder
+            .get_resource_mut::<ShellAutoTune>()
+            .set_enabled(false);
+    }
+
+    // `FURRY_LOW_LATENCY=1` starts in low-latency frame pacing, F3 still switches it.
+    if std::env::var("FURRY_LOW_LATENCY").as_deref() == Ok("1") {
+        app_builder
+            .get_resource_mut::<FramePacing>()
+            .set_mode(FramePacingMode::LowLatency);
+    }
+
+    // `FURRY_GOLDEN` checks a fixed frame of the default scene against a stored golden, so the
+    // saved settings and mesh are skipped to keep the frame the same on every machine.
+    if let Ok(name) = std::env::var("FURRY_GOLDEN") {
+        setup_golden_capture(&mut app_builder, name);
+    } else {
+        setup_render_settings(&mut app_builder, SETTINGS_FILE_PATH);
+
+        // `FURRY_MESH` grows the fur on an OBJ or glTF file, loaded in the background while the
+        // sphere is shown.
+        if let Ok(path) = std::env::var("FURRY_MESH") {
+            app_builder
+                .get_resource_mut::<ShellRenderer>()
+                .load_mesh(path);
+        }
+    }
 
     app_builder.run();
 }
+
+fn frames_in_flight() -> usize {
+    match std::env::var("FURRY_FRAMES_IN_FLIGHT") {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            println!(
+                "Invalid FURRY_FRAMES_IN_FLIGHT of {}, using {}",
+                value, FRAMES_IN_FLIGHT
+            );
+            FRAMES_IN_FLIGHT
+        }),
+        Err(_) => FRAMES_IN_FLIGHT,
+    }
+}
+
+/// Enables or disables the validation layer through the Vulkan loader, the instance is created
+/// by the desktop preset so this has to happen before it is set up.
+fn configure_validation_layers() {
+    // A settings file that fails to load is reported once the rest of the settings are applied.
+    let configured = RenderSettings::load(Path::new(SETTINGS_FILE_PATH))
+        .ok()
+        .flatten()
+        .and_then(|settings| settings.desktop)
+        .and_then(|desktop| desktop.validation_layers);
+    let enabled = match std::env::var("FURRY_VALIDATION").as_deref() {
+        Ok("0") => false,
+        Ok("1") => true,
+        _ => configured.unwrap_or(VALIDATION_LAYERS),
+    };
+
+    let (variable, separator) = if enabled {
+        ("VK_INSTANCE_LAYERS", if cfg!(windows) { ";" } else { ":" })
+    } else {
+        ("VK_LOADER_LAYERS_DISABLE", ",")
+    };
+    let layers = match std::env::var(variable) {
+        Ok(layers) if !layers.is_empty() => {
+            format!("{}{}{}", layers, separator, VALIDATION_LAYER_NAME)
+        }
+        _ => VALIDATION_LAYER_NAME.to_string(),
+    };
+    std::env::set_var(variable, layers);
+}
+
+/// Passes `FURRY_PRESENT_MODE` on to Mesa's `MESA_VK_WSI_PRESENT_MODE` override, so vsync can
+/// be turned off on Mesa drivers to measure the raw frame rate.
+///
+/// This is not a present mode setting. The desktop preset creates the swapchain and pyrite has
+/// no way to choose its present mode or recreate it with another one, so there is no runtime
+/// toggle, other drivers ignore the variable, and a mode the device doesn't support is left to
+/// Mesa. Only a value that isn't one of `PRESENT_MODES` is replaced with FIFO here.
+fn configure_mesa_present_mode() {
+    let Ok(value) = std::env::var("FURRY_PRESENT_MODE") else {
+        return;
+    };
+
+    let present_mode = value.to_lowercase();
+    let present_mode = if PRESENT_MODES.contains(&present_mode.as_str()) {
+        present_mode
+    } else {
+        println!(
+            "Invalid FURRY_PRESENT_MODE of {}, expected one of {}, using fifo",
+            value,
+            PRESENT_MODES.join(", ")
+        );
+        "fifo".to_string()
+    };
+    std::env::set_var("MESA_VK_WSI_PRESENT_MODE", present_mode);
+}
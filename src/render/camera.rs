@@ -1,27 +1,113 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use ash::vk;
-use na::{Matrix4, Perspective3, Rotation3, Vector3};
+use na::{Matrix4, Orthographic3, Perspective3, Rotation3, Vector3};
 use pyrite::{
     desktop::window::{CursorGrabMode, Window},
     prelude::{AppBuilder, Input, Key, Res, ResMut, Resource, Swapchain, Time},
+    render::render_manager::RenderManager,
     vulkan::{Buffer, BufferInfo, StageType, UntypedBuffer, Vulkan, VulkanAllocator, VulkanStager},
 };
 
+use super::render::RenderPipeline;
+
 extern crate nalgebra as na;
 
 const WALKING_SPEED: f32 = 1.42;
 const RUNNING_SPEED: f32 = 3.0;
 
+// Keeps the pitch just short of straight up or down so the view never flips over.
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.001;
+
+// Range the field of view can be zoomed in, in degrees.
+const MIN_FOV: f32 = 30.0;
+const MAX_FOV: f32 = 120.0;
+// Degrees of field of view zoomed per scroll step.
+const FOV_ZOOM_SPEED: f32 = 5.0;
+
+// Closest the orbit camera can zoom in, stops it passing through the target.
+const MIN_ORBIT_DISTANCE: f32 = 0.2;
+// Fraction of the orbit distance zoomed per scroll step.
+const ORBIT_ZOOM_SPEED: f32 = 0.1;
+
+// Where the saved viewpoints are kept between runs.
+const SLOTS_FILE_PATH: &str = "camera_slots.txt";
+// The number keys used for the save slots, with the slot number each one selects.
+const SLOT_KEYS: [(Key, u8); 9] = [
+    (Key::Key1, 1),
+    (Key::Key2, 2),
+    (Key::Key3, 3),
+    (Key::Key4, 4),
+    (Key::Key5, 5),
+    (Key::Key6, 6),
+    (Key::Key7, 7),
+    (Key::Key8, 8),
+    (Key::Key9, 9),
+];
+
+// How much room is left around a framed sphere, as a multiple of its radius.
+const FRAME_PADDING: f32 = 1.1;
+
+// Smallest view height of the orthographic projection.
+const MIN_ORTHO_HEIGHT: f32 = 0.01;
+// Fraction of the orthographic view height zoomed per scroll step.
+const ORTHO_ZOOM_SPEED: f32 = 0.1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CameraMode {
+    /// Moves with WASD and looks around with the mouse.
+    FreeFly,
+    /// Circles `target` at `distance` with the mouse and zooms with the scroll wheel.
+    Orbit { target: Vector3<f32>, distance: f32 },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProjectionKind {
+    /// Uses the field of view.
+    Perspective,
+    /// No foreshortening, `height` is the height of the view in world units.
+    Orthographic { height: f32 },
+}
+
+/// A saved viewpoint.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CameraState {
+    pub position: Vector3<f32>,
+    pub rx: f32,
+    pub ry: f32,
+}
+
 #[derive(Resource)]
 pub struct Camera {
     position: Vector3<f32>,
     rx: f32,
     ry: f32,
+    // Where the mouse has turned the camera to, rx and ry ease towards these when smoothing.
+    target_rx: f32,
+    target_ry: f32,
+    // Seconds it takes movement and look to mostly catch up with the input, 0 disables it.
+    smoothing: f32,
+    velocity: Vector3<f32>,
     speed: f32,
     cursor_locked: bool,
+    // Set while another system like the HUD is using the mouse or keyboard, so scrolling and
+    // movement are left to it.
+    input_captured: bool,
+    // Cleared to freeze the camera, it then ignores all input and keeps the cursor released.
+    input_enabled: bool,
+    mode: CameraMode,
+    projection_kind: ProjectionKind,
+    // The vertical field of view in degrees.
+    fov: f32,
+    near: f32,
+    far: f32,
+    slots: HashMap<u8, CameraState>,
 
-    buffer: Arc<UntypedBuffer>,
+    // One buffer per frame in flight, written in turn so a frame still on the GPU keeps
+    // reading its own matrices while the next frame's are uploaded.
+    buffers: Vec<Arc<UntypedBuffer>>,
+    // The buffer written by the latest update, which is the one the next frame binds.
+    buffer_index: usize,
     data: CameraBufferData,
 }
 
@@ -35,41 +121,79 @@ impl Camera {
         vulkan: &Vulkan,
         vulkan_allocator: &mut VulkanAllocator,
         window: &mut Window,
+        frames_in_flight: usize,
     ) -> Self {
-        let buffer = UntypedBuffer::new(
-            vulkan,
-            vulkan_allocator,
-            &BufferInfo::builder()
-                .size(std::mem::size_of::<CameraBufferData>() as u64)
-                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
-                .build(),
-        );
+        let buffers = (0..frames_in_flight.max(1))
+            .map(|_| {
+                Arc::new(UntypedBuffer::new(
+                    vulkan,
+                    vulkan_allocator,
+                    &BufferInfo::builder()
+                        .size(std::mem::size_of::<CameraBufferData>() as u64)
+                        .usage(
+                            vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
+                        .build(),
+                ))
+            })
+            .collect();
         window.set_cursor_grab_mode(CursorGrabMode::None);
         window.set_cursor_visible(true);
         Self {
             position: Vector3::new(0.0, 0.3, -3.0),
             rx: 0.0,
             ry: 0.0,
+            target_rx: 0.0,
+            target_ry: 0.0,
+            smoothing: 0.0,
+            velocity: Vector3::zeros(),
             speed: WALKING_SPEED,
             cursor_locked: false,
+            input_captured: false,
+            input_enabled: true,
+            mode: CameraMode::FreeFly,
+            projection_kind: ProjectionKind::Perspective,
+            fov: 90.0,
+            near: 0.01,
+            far: 1000.0,
+            slots: load_slots(),
             data: CameraBufferData {
                 projection: Matrix4::identity(),
                 view: Matrix4::identity(),
             },
-            buffer: Arc::new(buffer),
+            buffers,
+            buffer_index: 0,
         }
     }
 
-    fn calculate_projection(&mut self, width: u32, height: u32, fov: f32, near: f32, far: f32) {
-        self.data.projection = Perspective3::new((width as f32) / (height as f32), fov, near, far)
-            .as_matrix()
-            .to_owned();
-        self.data.projection.m22 *= -1.0;
-        self.data.projection.m33 *= -1.0;
-        self.data.projection.m43 *= -1.0;
+    fn calculate_projection(
+        &mut self,
+        width: u32,
+        height: u32,
+        fov: f32,
+        near: f32,
+        far: f32,
+        reverse_z: bool,
+    ) {
+        let aspect = (width as f32) / (height as f32);
+        self.data.projection =
+            projection_matrix(self.projection_kind, aspect, fov, near, far, reverse_z);
+    }
+
+    /// The direction the camera is looking in world space.
+    fn forward(&self) -> Vector3<f32> {
+        (Rotation3::from_euler_angles(0.0, self.rx, 0.0)
+            * Rotation3::from_euler_angles(self.ry, 0.0, 0.0))
+            * Vector3::z()
     }
 
     fn calculate_view(&mut self) {
+        // The orbit camera sits behind the target along its view direction so it always looks at it.
+        if let CameraMode::Orbit { target, distance } = self.mode {
+            self.position = target - self.forward() * distance;
+        }
+
         let inverted_position = -self.position;
         let inverted_rotation = Rotation3::from_euler_angles(-self.ry, 0.0, 0.0)
             * Rotation3::from_euler_angles(0.0, -self.rx, 0.0);
@@ -77,6 +201,147 @@ impl Camera {
             inverted_rotation.to_homogeneous() * Matrix4::new_translation(&inverted_position);
     }
 
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.mode = match mode {
+            CameraMode::Orbit { target, distance } => CameraMode::Orbit {
+                target,
+                distance: distance.max(MIN_ORBIT_DISTANCE),
+            },
+            CameraMode::FreeFly => CameraMode::FreeFly,
+        };
+    }
+
+    /// Stores the current viewpoint in a slot, the slots are written to disk so they are
+    /// there next run.
+    pub fn save_slot(&mut self, slot: u8) {
+        self.slots.insert(slot, self.state());
+        if let Err(err) = save_slots(&self.slots) {
+            println!("Failed to save the camera slots: {}", err);
+        }
+    }
+
+    /// Snaps to the viewpoint stored in a slot, returns false if the slot is empty.
+    pub fn load_slot(&mut self, slot: u8) -> bool {
+        let Some(state) = self.slots.get(&slot).copied() else {
+            return false;
+        };
+        self.set_state(state);
+        true
+    }
+
+    /// The current viewpoint.
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            position: self.position,
+            rx: self.rx,
+            ry: self.ry,
+        }
+    }
+
+    /// Snaps to a viewpoint.
+    ///
+    /// An orbiting camera switches to free-fly, since orbiting would move it off the given
+    /// position.
+    pub fn set_state(&mut self, state: CameraState) {
+        self.mode = CameraMode::FreeFly;
+        self.position = state.position;
+        self.rx = state.rx;
+        self.ry = state.ry.clamp(-MAX_PITCH, MAX_PITCH);
+        self.target_rx = self.rx;
+        self.target_ry = self.ry;
+        self.velocity = Vector3::zeros();
+    }
+
+    /// Turns to face `center` from the current position and backs up until the sphere fits in
+    /// the view, whichever of the vertical and horizontal field of view is narrower.
+    ///
+    /// An orbiting camera orbits the center from then on. A zero radius sphere has nothing to
+    /// fit, so the camera only turns towards it.
+    pub fn frame_mesh(&mut self, center: Vector3<f32>, radius: f32) {
+        let to_center = center - self.position;
+        if to_center.magnitude() > f32::EPSILON {
+            let direction = to_center.normalize();
+            self.rx = direction.x.atan2(direction.z);
+            self.ry = (-direction.y).asin().clamp(-MAX_PITCH, MAX_PITCH);
+            self.target_rx = self.rx;
+            self.target_ry = self.ry;
+        }
+        if radius <= 0.0 {
+            return;
+        }
+
+        // The projection scales x by the aspect more than y, their ratio is the aspect.
+        let aspect = (self.data.projection.m22 / self.data.projection.m11).abs();
+        let half_fov = (self.fov.to_radians() * 0.5).tan();
+        let half_fov = half_fov.min(half_fov * aspect).atan();
+        let distance = (radius * FRAME_PADDING / half_fov.sin()).max(MIN_ORBIT_DISTANCE);
+
+        match self.mode {
+            CameraMode::Orbit { .. } => self.set_mode(CameraMode::Orbit {
+                target: center,
+                distance,
+            }),
+            CameraMode::FreeFly => self.position = center - self.forward() * distance,
+        }
+        if let ProjectionKind::Orthographic { .. } = self.projection_kind {
+            self.set_projection_kind(ProjectionKind::Orthographic {
+                height: 2.0 * radius * FRAME_PADDING,
+            });
+        }
+        self.velocity = Vector3::zeros();
+    }
+
+    pub fn is_input_enabled(&self) -> bool {
+        self.input_enabled
+    }
+
+    /// With input disabled the camera holds still and releases the cursor while everything
+    /// else keeps running, unlike pausing time.
+    pub fn set_input_enabled(&mut self, input_enabled: bool) {
+        self.input_enabled = input_enabled;
+        if !input_enabled {
+            self.velocity = Vector3::zeros();
+            (self.target_rx, self.target_ry) = (self.rx, self.ry);
+        }
+    }
+
+    pub fn is_cursor_locked(&self) -> bool {
+        self.cursor_locked
+    }
+
+    /// Stops the camera reacting to scrolling and the movement keys until released, for UI
+    /// that is using the mouse or keyboard.
+    pub fn set_input_captured(&mut self, input_captured: bool) {
+        self.input_captured = input_captured;
+    }
+
+    pub fn smoothing(&self) -> f32 {
+        self.smoothing
+    }
+
+    /// Eases movement and look towards the input over roughly `smoothing` seconds, 0 snaps
+    /// straight to it.
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.max(0.0);
+    }
+
+    pub fn projection_kind(&self) -> ProjectionKind {
+        self.projection_kind
+    }
+
+    pub fn set_projection_kind(&mut self, projection_kind: ProjectionKind) {
+        self.projection_kind = match projection_kind {
+            ProjectionKind::Orthographic { height } => ProjectionKind::Orthographic {
+                height: height.max(MIN_ORTHO_HEIGHT),
+            },
+            ProjectionKind::Perspective => ProjectionKind::Perspective,
+        };
+    }
+
     pub fn update(
         input: Res<Input>,
         time: Res<Time>,
@@ -85,94 +350,417 @@ impl Camera {
         mut vulkan_allocator: ResMut<VulkanAllocator>,
         mut camera: ResMut<Camera>,
         mut stager: ResMut<VulkanStager>,
+        render_pipeline: Res<RenderPipeline>,
     ) {
+        // Hold the framing while the world keeps animating, the matrices are still rebuilt
+        // below so the projection follows resizes.
+        if input.is_key_pressed(Key::Backslash) {
+            let input_enabled = !camera.input_enabled;
+            camera.set_input_enabled(input_enabled);
+            println!("Camera input: {}", input_enabled);
+        }
+        if camera.input_enabled {
+            camera.handle_input(&*input, &*time, &mut *window);
+        } else if camera.cursor_locked {
+            // Give the cursor back so the HUD can be used while frozen.
+            camera.cursor_locked = false;
+            window.set_cursor_grab_mode(CursorGrabMode::None);
+            window.set_cursor_visible(true);
+        }
+
+        // Update camera matrix data and upload to GPU
+        let (fov, near, far) = (camera.fov.to_radians(), camera.near, camera.far);
+        // The render extent differs from the window's shape while letterboxed.
+        let render_extent = render_pipeline.render_extent();
+        camera.calculate_projection(
+            render_extent.width,
+            render_extent.height,
+            fov,
+            near,
+            far,
+            render_pipeline.reverse_z(),
+        );
+        camera.calculate_view();
+
+        let mut data = camera.data.projection.as_slice().to_owned();
+        data.append(&mut camera.data.view.as_slice().to_owned());
+        let data_slice = data.as_slice();
+
+        // The render manager has waited on the frame that last read the next buffer, as it
+        // was recorded frames in flight frames ago.
+        camera.buffer_index = (camera.buffer_index + 1) % camera.buffers.len();
+        unsafe {
+            stager.schedule_stage_buffer(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                data_slice.as_ptr() as *const u8,
+                (data.len() * std::mem::size_of::<f32>()) as u64,
+                &camera.buffers[camera.buffer_index],
+                StageType::Immediate,
+            );
+        }
+    }
+
+    /// Moves and turns the camera from the mouse and keyboard, and handles the camera keys.
+    fn handle_input(&mut self, input: &Input, time: &Time, window: &mut Window) {
+        // The fraction of the way to the input that smoothing moves this frame.
+        let ease = if self.smoothing > 0.0 {
+            1.0 - (-time.delta().as_secs_f32() / self.smoothing).exp()
+        } else {
+            1.0
+        };
+
         // Calculate rotation if the cursor is locked.
-        if camera.cursor_locked {
+        if self.cursor_locked {
             let (mdx, mdy) = input.mouse_delta();
-            camera.rx += mdx as f32 * 0.02;
-            camera.ry += mdy as f32 * 0.02;
+            self.target_rx += mdx as f32 * 0.02;
+            self.target_ry = turn_pitch(self.target_ry, mdy as f32);
+        }
+        if self.smoothing > 0.0 {
+            let (rx, ry) = (self.rx, self.ry);
+            self.rx = rx + (self.target_rx - rx) * ease;
+            self.ry = ry + (self.target_ry - ry) * ease;
+        } else {
+            self.rx = self.target_rx;
+            self.ry = self.target_ry;
+        }
+
+        // Toggle between free-fly and orbiting the origin from the current distance.
+        if input.is_key_pressed(Key::Tab) {
+            let mode = match self.mode {
+                CameraMode::FreeFly => CameraMode::Orbit {
+                    target: Vector3::zeros(),
+                    distance: self.position.magnitude(),
+                },
+                CameraMode::Orbit { .. } => CameraMode::FreeFly,
+            };
+            self.set_mode(mode);
+            println!("Camera mode: {:?}", self.mode);
+        }
+
+        // Toggle the orthographic projection, starting with the view height the perspective
+        // projection has at the orbit target or the origin.
+        if input.is_key_pressed(Key::R) {
+            let projection_kind = match self.projection_kind {
+                ProjectionKind::Perspective => {
+                    let distance = match self.mode {
+                        CameraMode::Orbit { distance, .. } => distance,
+                        CameraMode::FreeFly => self.position.magnitude(),
+                    };
+                    ProjectionKind::Orthographic {
+                        height: 2.0 * distance * (self.fov.to_radians() * 0.5).tan(),
+                    }
+                }
+                ProjectionKind::Orthographic { .. } => ProjectionKind::Perspective,
+            };
+            self.set_projection_kind(projection_kind);
+            println!("Camera projection: {:?}", self.projection_kind);
+        }
+
+        // Zoom by moving the orbit camera, or by narrowing the field of view when flying.
+        // Moving doesn't zoom an orthographic view, so it scales the view height instead.
+        let (_, scroll) = input.scroll_delta();
+        let scroll = scroll as f32;
+        if scroll != 0.0 && !self.input_captured {
+            match (self.projection_kind, self.mode) {
+                (ProjectionKind::Orthographic { height }, _) => {
+                    self.set_projection_kind(ProjectionKind::Orthographic {
+                        height: height * (1.0 - scroll * ORTHO_ZOOM_SPEED),
+                    });
+                }
+                (ProjectionKind::Perspective, CameraMode::Orbit { target, distance }) => {
+                    self.set_mode(CameraMode::Orbit {
+                        target,
+                        distance: distance * (1.0 - scroll * ORBIT_ZOOM_SPEED),
+                    });
+                }
+                (ProjectionKind::Perspective, CameraMode::FreeFly) => {
+                    let fov = self.fov - scroll * FOV_ZOOM_SPEED;
+                    self.set_fov(fov);
+                    println!("Field of view: {:.0}", self.fov);
+                }
+            }
+        }
+
+        // Save the viewpoint with Alt and a number, or jump back to it with the number alone.
+        // This runs before the matrices are built so the jump is uploaded this frame.
+        for (key, slot) in SLOT_KEYS {
+            if input.is_key_pressed(key) {
+                if input.is_key_down(Key::LAlt) {
+                    self.save_slot(slot);
+                    println!("Saved camera slot {}", slot);
+                } else if self.load_slot(slot) {
+                    println!("Loaded camera slot {}", slot);
+                }
+            }
         }
 
         // Calculate translation
         let mut translation = Vector3::new(0.0, 0.0, 0.0);
-        let mut speed = camera.speed;
-        if input.is_key_down(Key::W) {
+        let mut speed = self.speed;
+        let input_captured = self.input_captured;
+        let key_down = |key| !input_captured && input.is_key_down(key);
+        if key_down(Key::W) {
             translation.z = 1.0;
         }
-        if input.is_key_down(Key::S) {
+        if key_down(Key::S) {
             translation.z = -1.0;
         }
-        if input.is_key_down(Key::A) {
+        if key_down(Key::A) {
             translation.x = -1.0;
         }
-        if input.is_key_down(Key::D) {
+        if key_down(Key::D) {
             translation.x = 1.0;
         }
-        if input.is_key_down(Key::LShift) {
+        if key_down(Key::LShift) {
             translation.y = -1.0;
         }
-        if input.is_key_down(Key::Space) {
+        if key_down(Key::Space) {
             translation.y = 1.0;
         }
-        if input.is_key_down(Key::LControl) {
+        if key_down(Key::LControl) {
             speed = RUNNING_SPEED;
         }
-        let translation = translation.normalize() * (speed * time.delta().as_secs_f32());
+        if self.smoothing > 0.0 {
+            // Ease the velocity towards the input and move with that instead.
+            let mut target_velocity = Vector3::zeros();
+            if translation.magnitude() > 0.0 && self.mode == CameraMode::FreeFly {
+                target_velocity = Rotation3::from_euler_angles(0.0, self.rx, 0.0)
+                    * (translation.normalize() * speed);
+            }
+            let velocity = self.velocity + (target_velocity - self.velocity) * ease;
+            self.velocity = velocity;
+            if self.mode == CameraMode::FreeFly {
+                self.position += velocity * time.delta().as_secs_f32();
+            }
+        } else {
+            let translation = translation.normalize() * (speed * time.delta().as_secs_f32());
 
-        if translation.magnitude() > 0.0 {
-            let translation = Rotation3::from_euler_angles(0.0, camera.rx, 0.0).to_homogeneous()
-                * translation.to_homogeneous();
-            camera.position += translation.xyz();
+            if translation.magnitude() > 0.0 && self.mode == CameraMode::FreeFly {
+                let translation = Rotation3::from_euler_angles(0.0, self.rx, 0.0).to_homogeneous()
+                    * translation.to_homogeneous();
+                self.position += translation.xyz();
+            }
+            self.velocity = Vector3::zeros();
         }
 
         // Toggle cursor lock
         if input.is_key_pressed(Key::E) {
-            camera.cursor_locked = !camera.cursor_locked;
-            window.set_cursor_grab_mode(if camera.cursor_locked {
+            self.cursor_locked = !self.cursor_locked;
+            window.set_cursor_grab_mode(if self.cursor_locked {
                 CursorGrabMode::Confined
             } else {
                 CursorGrabMode::None
             });
-            window.set_cursor_visible(!camera.cursor_locked);
+            window.set_cursor_visible(!self.cursor_locked);
         }
+    }
 
-        // Update camera matrix data and upload to GPU
-        camera.calculate_projection(
-            window.width(),
-            window.height(),
-            90.0f32.to_radians(),
-            0.01,
-            1000.0,
-        );
-        camera.calculate_view();
+    /// The position of the camera in world space.
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
 
-        let mut data = camera.data.projection.as_slice().to_owned();
-        data.append(&mut camera.data.view.as_slice().to_owned());
-        let data_slice = data.as_slice();
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
 
-        unsafe {
-            stager.schedule_stage_buffer(
-                &*vulkan,
-                &mut *vulkan_allocator,
-                data_slice.as_ptr() as *const u8,
-                (data.len() * std::mem::size_of::<f32>()) as u64,
-                &camera.buffer,
-                StageType::Immediate,
-            );
-        }
+    /// The projection times the view matrix of the latest update.
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.data.projection * self.data.view
+    }
+
+    /// Sets the vertical field of view in degrees, clamped between 30 and 120.
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov.clamp(MIN_FOV, MAX_FOV);
     }
 
+    /// The distance to the near clipping plane.
+    pub fn near(&self) -> f32 {
+        self.near
+    }
+
+    /// The distance to the far clipping plane.
+    pub fn far(&self) -> f32 {
+        self.far
+    }
+
+    /// Sets the clipping planes, far is kept beyond near so the projection stays valid.
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) {
+        self.near = near.max(0.001);
+        self.far = far.max(self.near + 0.001);
+    }
+
+    /// The buffer holding the matrices of the latest update, for the frame being recorded.
     pub fn camera_buffer(&self) -> &Arc<UntypedBuffer> {
-        &self.buffer
+        &self.buffers[self.buffer_index]
     }
 }
 
+// Vulkan clip space with Y down and the camera looking down +Z.
+fn projection_matrix(
+    kind: ProjectionKind,
+    aspect: f32,
+    fov: f32,
+    near: f32,
+    far: f32,
+    reverse_z: bool,
+) -> Matrix4<f32> {
+    let mut projection = match kind {
+        ProjectionKind::Perspective => Perspective3::new(aspect, fov, near, far)
+            .as_matrix()
+            .to_owned(),
+        ProjectionKind::Orthographic { height } => {
+            let half_height = height * 0.5;
+            let half_width = half_height * aspect;
+            let mut projection = Orthographic3::new(
+                -half_width,
+                half_width,
+                -half_height,
+                half_height,
+                near,
+                far,
+            )
+            .as_matrix()
+            .to_owned();
+            // Orthographic3 maps depth to [-1, 1] like OpenGL and Vulkan clips below 0, so the
+            // depth row is replaced to end up as (z - near) / (far - near) after the flips.
+            projection.m33 = -1.0 / (far - near);
+            projection.m34 = -near / (far - near);
+            projection
+        }
+    };
+    // Flip Y for Vulkan and look down +Z.
+    projection.m22 *= -1.0;
+    projection.m33 *= -1.0;
+    projection.m43 *= -1.0;
+
+    // Reverse-Z takes the depth as w minus z, giving 1 - depth after the divide. The
+    // subtraction is done on the matrix so no precision is lost per vertex.
+    if reverse_z {
+        let depth_row = projection.row(3) - projection.row(2);
+        projection.set_row(2, &depth_row);
+    }
+    projection
+}
+
+// Turns the pitch by a vertical mouse delta in pixels, stopping short of straight up or down.
+fn turn_pitch(pitch: f32, mouse_delta: f32) -> f32 {
+    (pitch + mouse_delta * 0.02).clamp(-MAX_PITCH, MAX_PITCH)
+}
+
+// Each line is a slot number followed by the position, rx and ry.
+fn load_slots() -> HashMap<u8, CameraState> {
+    let Ok(contents) = std::fs::read_to_string(SLOTS_FILE_PATH) else {
+        return HashMap::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut values = line.split_whitespace();
+            let slot = values.next()?.parse::<u8>().ok()?;
+            let values = values
+                .map(|value| value.parse::<f32>().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let [x, y, z, rx, ry] = values[..] else {
+                return None;
+            };
+            Some((
+                slot,
+                CameraState {
+                    position: Vector3::new(x, y, z),
+                    rx,
+                    ry,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn save_slots(slots: &HashMap<u8, CameraState>) -> std::io::Result<()> {
+    let mut slot_numbers = slots.keys().copied().collect::<Vec<_>>();
+    slot_numbers.sort();
+
+    let contents = slot_numbers
+        .into_iter()
+        .map(|slot| {
+            let state = &slots[&slot];
+            format!(
+                "{} {} {} {} {} {}\n",
+                slot, state.position.x, state.position.y, state.position.z, state.rx, state.ry
+            )
+        })
+        .collect::<String>();
+    std::fs::write(SLOTS_FILE_PATH, contents)
+}
+
 pub fn setup_camera_preset(app_builder: &mut AppBuilder) {
     let camera = Camera::new(
         &*app_builder.get_resource::<Vulkan>(),
         &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
         &mut *app_builder.get_resource_mut::<Window>(),
+        app_builder
+            .get_resource::<RenderManager>()
+            .frames_in_flight(),
     );
     app_builder.add_resource(camera);
     app_builder.add_system(Camera::update);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_mouse_delta_keeps_pitch_in_range() {
+        for delta in [1.0e6, -1.0e6, f32::MAX, f32::MIN] {
+            let pitch = turn_pitch(0.0, delta);
+            assert!(
+                (-MAX_PITCH..=MAX_PITCH).contains(&pitch),
+                "{} -> {}",
+                delta,
+                pitch
+            );
+        }
+        assert_eq!(turn_pitch(0.0, 1.0e6), MAX_PITCH);
+        assert_eq!(turn_pitch(0.0, -1.0e6), -MAX_PITCH);
+    }
+
+    #[test]
+    fn pitch_turns_by_the_mouse_delta_within_range() {
+        assert!((turn_pitch(0.0, 10.0) - 0.2).abs() < 1e-6);
+        assert!((turn_pitch(MAX_PITCH, -10.0) - (MAX_PITCH - 0.2)).abs() < 1e-6);
+    }
+
+    // The depth of a view space point after the perspective divide.
+    fn depth(projection: &Matrix4<f32>, z: f32) -> f32 {
+        let clip = projection * na::Vector4::new(0.0, 0.0, z, 1.0);
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn orthographic_depth_is_zero_to_one() {
+        let kind = ProjectionKind::Orthographic { height: 4.0 };
+        let projection = projection_matrix(kind, 1.5, 90.0, 0.5, 100.0, false);
+        assert!(depth(&projection, 0.5).abs() < 1e-6);
+        assert!((depth(&projection, 50.25) - 0.5).abs() < 1e-6);
+        assert!((depth(&projection, 100.0) - 1.0).abs() < 1e-6);
+
+        let projection = projection_matrix(kind, 1.5, 90.0, 0.5, 100.0, true);
+        assert!((depth(&projection, 0.5) - 1.0).abs() < 1e-6);
+        assert!(depth(&projection, 100.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn projections_flip_y_for_vulkan() {
+        for kind in [
+            ProjectionKind::Perspective,
+            ProjectionKind::Orthographic { height: 4.0 },
+        ] {
+            let projection = projection_matrix(kind, 1.0, 1.0, 0.5, 100.0, false);
+            let clip = projection * na::Vector4::new(0.0, 1.0, 2.0, 1.0);
+            assert!(clip.y / clip.w < 0.0, "{:?}", kind);
+        }
+    }
+}
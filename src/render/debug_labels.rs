@@ -0,0 +1,88 @@
+use std::ffi::{CStr, CString};
+
+use ash::vk;
+use pyrite::{
+    prelude::{AppBuilder, Resource, Vulkan},
+    vulkan::CommandBuffer,
+};
+
+pub fn setup_debug_labels(app_builder: &mut AppBuilder) {
+    let debug_labels = DebugLabels::new(&*app_builder.get_resource::<Vulkan>());
+    app_builder.add_resource(debug_labels);
+}
+
+/// Names regions of the frame's command buffer so captures in tools like RenderDoc are
+/// readable.
+///
+/// The labels come from `VK_EXT_debug_utils`, when the extension isn't enabled on the
+/// instance every call is a no-op.
+#[derive(Resource)]
+pub struct DebugLabels {
+    debug_utils: Option<vk::ExtDebugUtilsFn>,
+}
+
+impl DebugLabels {
+    fn new(vulkan: &Vulkan) -> Self {
+        let instance = vulkan.instance();
+        let device = vulkan.device().handle();
+        let get_device_proc_addr =
+            |name: &CStr| unsafe { instance.get_device_proc_addr(device, name.as_ptr()) };
+
+        // The function pointers are only there if the instance enabled the extension.
+        let begin_name = CStr::from_bytes_with_nul(b"vkCmdBeginDebugUtilsLabelEXT\0").unwrap();
+        let debug_utils = get_device_proc_addr(begin_name).is_some().then(|| {
+            vk::ExtDebugUtilsFn::load(|name| unsafe {
+                std::mem::transmute(get_device_proc_addr(name))
+            })
+        });
+
+        Self { debug_utils }
+    }
+
+    /// Opens a labeled region, every `begin_region` needs a matching `end_region` in the same
+    /// command buffer.
+    pub fn begin_region(&self, command_buffer: &CommandBuffer, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            unsafe {
+                (debug_utils.cmd_begin_debug_utils_label_ext)(
+                    command_buffer.command_buffer(),
+                    &*label,
+                );
+            }
+        }
+    }
+
+    pub fn end_region(&self, command_buffer: &CommandBuffer) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe {
+                (debug_utils.cmd_end_debug_utils_label_ext)(command_buffer.command_buffer());
+            }
+        }
+    }
+
+    /// Opens a labeled region on a queue, for work submitted outside the frame's command
+    /// buffer like immediate staging. Needs a matching `end_queue_region` on the same queue.
+    pub fn begin_queue_region(&self, queue: vk::Queue, name: &str, color: [f32; 4]) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::builder()
+                .label_name(&name)
+                .color(color);
+            unsafe {
+                (debug_utils.queue_begin_debug_utils_label_ext)(queue, &*label);
+            }
+        }
+    }
+
+    pub fn end_queue_region(&self, queue: vk::Queue) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe {
+                (debug_utils.queue_end_debug_utils_label_ext)(queue);
+            }
+        }
+    }
+}
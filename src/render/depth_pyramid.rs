@@ -0,0 +1,482 @@
+use std::{any::Any, sync::Arc};
+
+use ash::vk;
+use pyrite::{
+    prelude::{AppBuilder, Assets, Res, ResMut, Resource},
+    vulkan::{
+        CommandBuffer, ComputePipeline, ComputePipelineInfo, DescriptorSet, DescriptorSetLayout,
+        Image, ImageInfo, Sampler, SamplerInfo, Shader, Vulkan, VulkanAllocator,
+    },
+};
+
+use super::{
+    device_lost::check_device_lost,
+    render::RenderPipeline,
+    watched_shaders::{DependencySignal, WatchedShaders},
+};
+
+pub fn setup_depth_pyramid(app_builder: &mut AppBuilder) {
+    let depth_pyramid = DepthPyramid::new(
+        &*app_builder.get_resource::<Vulkan>(),
+        &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
+        &*app_builder.get_resource::<RenderPipeline>(),
+        &mut *app_builder.get_resource_mut::<Assets>(),
+        &mut *app_builder.get_resource_mut::<WatchedShaders>(),
+    );
+    app_builder.add_resource(depth_pyramid);
+    app_builder.add_system(DepthPyramid::update_system);
+}
+
+const INIT_FILE_PATH: &str = "shaders/hiz_init.comp";
+const DOWNSAMPLE_FILE_PATH: &str = "shaders/hiz_downsample.comp";
+const INIT_NAME: &str = "hiz_init_comp";
+const DOWNSAMPLE_NAME: &str = "hiz_downsample_comp";
+
+const DEFAULT_MIP_LEVELS: u32 = 8;
+
+#[repr(C)]
+struct PyramidPushConstants {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    // 1 reduces with max instead of min, the nearest depth under reverse-Z.
+    reverse_z: u32,
+}
+
+/// A hierarchical depth (Hi-Z) pyramid built from the backbuffer depth, each texel keeps the
+/// nearest depth it covers. That is the minimum, or the maximum with reverse-Z.
+///
+/// Level 0 is the full resolution depth resolved from the multisampled depth buffer, each level
+/// after is half the size of the previous one. Each level is its own image so it can be bound
+/// as a storage image on its own.
+#[derive(Resource)]
+pub struct DepthPyramid {
+    enabled: bool,
+    mip_levels: u32,
+    levels_dirty: bool,
+    // The backbuffer generation the levels were created for.
+    backbuffer_generation: u32,
+    // The sample count the init shader was compiled for.
+    msaa_samples: vk::SampleCountFlags,
+
+    shader_dependency_signal: DependencySignal,
+    init_pipeline: Option<ComputePipeline>,
+    downsample_pipeline: Option<ComputePipeline>,
+
+    init_descriptor_set_layout: DescriptorSetLayout,
+    downsample_descriptor_set_layout: DescriptorSetLayout,
+    depth_sampler: Sampler,
+
+    levels: Vec<Image>,
+    init_descriptor_set: DescriptorSet,
+    // Set i reads level i and writes level i + 1. Kept when the levels are recreated and only
+    // grown, so resizing doesn't allocate from the pool again. Sets past the last level are unused.
+    downsample_descriptor_sets: Vec<DescriptorSet>,
+}
+
+impl DepthPyramid {
+    fn new(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        render_pipeline: &RenderPipeline,
+        assets: &mut Assets,
+        watched_shaders: &mut WatchedShaders,
+    ) -> Self {
+        let shader_dependency_signal = watched_shaders.create_dependency_signal();
+        watched_shaders.load_shader_with_defines(
+            assets,
+            INIT_FILE_PATH,
+            INIT_NAME,
+            init_defines(render_pipeline.msaa_samples()),
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            DOWNSAMPLE_FILE_PATH,
+            DOWNSAMPLE_NAME,
+            &shader_dependency_signal,
+        );
+
+        let init_descriptor_set_layout = DescriptorSetLayout::new(
+            vulkan,
+            &[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            ],
+        );
+
+        let downsample_descriptor_set_layout = DescriptorSetLayout::new(
+            vulkan,
+            &[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            ],
+        );
+
+        let depth_sampler = Sampler::new(vulkan, &SamplerInfo::builder().build());
+
+        let init_descriptor_set = render_pipeline
+            .descriptor_pool()
+            .allocate_descriptor_sets(&init_descriptor_set_layout, 1)
+            .pop()
+            .unwrap();
+
+        let mut depth_pyramid = Self {
+            enabled: false,
+            mip_levels: DEFAULT_MIP_LEVELS,
+            levels_dirty: false,
+            backbuffer_generation: render_pipeline.backbuffer_generation(),
+            msaa_samples: render_pipeline.msaa_samples(),
+            shader_dependency_signal,
+            init_pipeline: None,
+            downsample_pipeline: None,
+            init_descriptor_set_layout,
+            downsample_descriptor_set_layout,
+            depth_sampler,
+            levels: Vec::new(),
+            init_descriptor_set,
+            downsample_descriptor_sets: Vec::new(),
+        };
+        depth_pyramid.recreate_levels(vulkan, vulkan_allocator, render_pipeline);
+        depth_pyramid
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    /// Sets the requested number of levels, the pyramid stops early once a level reaches 1x1.
+    pub fn set_mip_levels(&mut self, mip_levels: u32) {
+        let mip_levels = mip_levels.max(1);
+        if self.mip_levels != mip_levels {
+            self.mip_levels = mip_levels;
+            self.levels_dirty = true;
+        }
+    }
+
+    /// The pyramid levels from full resolution down, each is sampleable in `GENERAL` layout.
+    pub fn levels(&self) -> &[Image] {
+        &self.levels
+    }
+
+    pub fn backbuffer_generation(&self) -> u32 {
+        self.backbuffer_generation
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.init_pipeline.is_some() && self.downsample_pipeline.is_some()
+    }
+
+    fn recreate_levels(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        render_pipeline: &RenderPipeline,
+    ) {
+        let mut extent = render_pipeline.backbuffer_depth_image().image_extent();
+        let mut levels = Vec::new();
+        for _ in 0..self.mip_levels {
+            levels.push(Image::new(
+                vulkan,
+                vulkan_allocator,
+                &ImageInfo::builder()
+                    .extent(extent)
+                    .format(vk::Format::R32_SFLOAT)
+                    .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+                    .view_subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1)
+                            .level_count(1)
+                            .build(),
+                    )
+                    .build(),
+            ));
+
+            if extent.width == 1 && extent.height == 1 {
+                break;
+            }
+            extent.width = (extent.width / 2).max(1);
+            extent.height = (extent.height / 2).max(1);
+        }
+
+        // Safety: Only waits, the descriptor sets rewritten below may still be read by frames in
+        // flight.
+        if let Err(err) = unsafe { vulkan.device().device_wait_idle() } {
+            check_device_lost(err, "waiting to recreate the depth pyramid");
+            println!("Failed to wait to recreate the depth pyramid: {}", err);
+        }
+
+        self.init_descriptor_set
+            .write()
+            .set_combined_image_sampler(
+                0,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                render_pipeline.backbuffer_depth_image().create_dep(),
+                &self.depth_sampler,
+            )
+            .set_storage_image(1, levels[0].create_dep())
+            .submit_writes();
+
+        let downsample_count = levels.len() - 1;
+        if self.downsample_descriptor_sets.len() < downsample_count {
+            let missing = downsample_count - self.downsample_descriptor_sets.len();
+            self.downsample_descriptor_sets.extend(
+                render_pipeline.descriptor_pool().allocate_descriptor_sets(
+                    &self.downsample_descriptor_set_layout,
+                    missing as u32,
+                ),
+            );
+        }
+        for (i, descriptor_set) in self.downsample_descriptor_sets[..downsample_count]
+            .iter()
+            .enumerate()
+        {
+            descriptor_set
+                .write()
+                .set_storage_image(0, levels[i].create_dep())
+                .set_storage_image(1, levels[i + 1].create_dep())
+                .submit_writes();
+        }
+
+        self.levels = levels;
+        self.levels_dirty = false;
+        self.backbuffer_generation = render_pipeline.backbuffer_generation();
+    }
+
+    /// Builds the pyramid, expects the depth image to be in `DEPTH_STENCIL_READ_ONLY_OPTIMAL`.
+    pub fn render(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        reverse_z: bool,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        if !self.enabled {
+            return vec![];
+        }
+
+        let (Some(init_pipeline), Some(downsample_pipeline)) =
+            (&self.init_pipeline, &self.downsample_pipeline)
+        else {
+            return vec![];
+        };
+
+        // Every level is fully rewritten, so the previous contents can be discarded.
+        let barriers = self
+            .levels
+            .iter()
+            .map(|level| {
+                level.image_memory_barrier(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::GENERAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::SHADER_WRITE,
+                )
+            })
+            .collect::<Vec<_>>();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::ALL_GRAPHICS,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barriers,
+        );
+
+        let base_extent = self.levels[0].image_extent();
+        command_buffer.bind_compute_pipeline(init_pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            init_pipeline.pipeline_layout(),
+            &[&self.init_descriptor_set],
+        );
+        command_buffer.write_push_constants_typed(
+            init_pipeline.pipeline_layout(),
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &PyramidPushConstants {
+                src_width: base_extent.width,
+                src_height: base_extent.height,
+                dst_width: base_extent.width,
+                dst_height: base_extent.height,
+                reverse_z: reverse_z as u32,
+            },
+        );
+        command_buffer.dispatch_compute(
+            (base_extent.width + 15) / 16,
+            (base_extent.height + 15) / 16,
+            1,
+        );
+
+        let downsample_descriptor_sets = &self.downsample_descriptor_sets[..self.levels.len() - 1];
+        command_buffer.bind_compute_pipeline(downsample_pipeline);
+        for (i, descriptor_set) in downsample_descriptor_sets.iter().enumerate() {
+            let src = &self.levels[i];
+            let dst = &self.levels[i + 1];
+
+            // Wait for the previous level to be written before reducing it.
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[src.image_memory_barrier(
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::GENERAL,
+                    vk::AccessFlags::SHADER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                downsample_pipeline.pipeline_layout(),
+                &[descriptor_set],
+            );
+            command_buffer.write_push_constants_typed(
+                downsample_pipeline.pipeline_layout(),
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &PyramidPushConstants {
+                    src_width: src.image_extent().width,
+                    src_height: src.image_extent().height,
+                    dst_width: dst.image_extent().width,
+                    dst_height: dst.image_extent().height,
+                    reverse_z: reverse_z as u32,
+                },
+            );
+            command_buffer.dispatch_compute(
+                (dst.image_extent().width + 15) / 16,
+                (dst.image_extent().height + 15) / 16,
+                1,
+            );
+        }
+
+        // Make the last level visible to whoever samples the pyramid next.
+        let last_level = self.levels.last().unwrap();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[last_level.image_memory_barrier(
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+            )],
+        );
+
+        let mut deps = self
+            .levels
+            .iter()
+            .map(|level| level.create_dep() as Arc<dyn Any + Send + Sync>)
+            .collect::<Vec<_>>();
+        deps.push(self.init_descriptor_set.create_dep());
+        deps.extend(
+            downsample_descriptor_sets
+                .iter()
+                .map(|descriptor_set| descriptor_set.create_dep()),
+        );
+        deps
+    }
+
+    fn refresh_pipelines(&mut self, vulkan: &Vulkan, watched_shaders: &WatchedShaders) {
+        let create_pipeline = |name: &str, descriptor_set_layout: &DescriptorSetLayout| {
+            ComputePipeline::new(
+                vulkan,
+                ComputePipelineInfo::builder()
+                    .shader(Shader::new(
+                        vulkan,
+                        &watched_shaders.get_shader(name).unwrap(),
+                    ))
+                    .descriptor_set_layouts(vec![descriptor_set_layout])
+                    .push_constant_ranges(vec![vk::PushConstantRange::builder()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .size(std::mem::size_of::<PyramidPushConstants>() as u32)
+                        .build()])
+                    .build(),
+            )
+        };
+
+        self.init_pipeline = Some(create_pipeline(INIT_NAME, &self.init_descriptor_set_layout));
+        self.downsample_pipeline = Some(create_pipeline(
+            DOWNSAMPLE_NAME,
+            &self.downsample_descriptor_set_layout,
+        ));
+    }
+
+    fn update_system(
+        mut depth_pyramid: ResMut<DepthPyramid>,
+        vulkan: Res<Vulkan>,
+        mut vulkan_allocator: ResMut<VulkanAllocator>,
+        render_pipeline: Res<RenderPipeline>,
+        mut watched_shaders: ResMut<WatchedShaders>,
+        mut assets: ResMut<Assets>,
+    ) {
+        let depth_pyramid = &mut *depth_pyramid;
+
+        // The init shader binds the depth differently without MSAA, nothing is built until it
+        // recompiles.
+        if depth_pyramid.msaa_samples != render_pipeline.msaa_samples() {
+            depth_pyramid.msaa_samples = render_pipeline.msaa_samples();
+            watched_shaders.set_defines(
+                &mut *assets,
+                INIT_NAME,
+                init_defines(render_pipeline.msaa_samples()),
+            );
+            depth_pyramid.init_pipeline = None;
+        }
+
+        if watched_shaders.is_dependency_signaled(&depth_pyramid.shader_dependency_signal)
+            && !watched_shaders.is_reloading(&depth_pyramid.shader_dependency_signal)
+        {
+            depth_pyramid.refresh_pipelines(&*vulkan, &*watched_shaders);
+        }
+
+        if depth_pyramid.levels_dirty
+            || depth_pyramid.backbuffer_generation != render_pipeline.backbuffer_generation()
+        {
+            depth_pyramid.recreate_levels(&*vulkan, &mut *vulkan_allocator, &*render_pipeline);
+        }
+    }
+}
+
+// A single sampled depth image can't be bound as the sampler2DMS the init shader reads.
+fn init_defines(msaa_samples: vk::SampleCountFlags) -> Vec<String> {
+    if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+        vec!["SINGLE_SAMPLED".to_string()]
+    } else {
+        Vec::new()
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    ffi::CStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use ash::vk;
+use pyrite::prelude::{AppBuilder, Vulkan};
+
+// Describes the device for the log when it is lost, written once at setup.
+static DEVICE_DESCRIPTION: OnceLock<String> = OnceLock::new();
+// Set once any call reports the device lost, it never comes back.
+static DEVICE_LOST: AtomicBool = AtomicBool::new(false);
+
+pub fn setup_device_lost(app_builder: &mut AppBuilder) {
+    let vulkan = app_builder.get_resource::<Vulkan>();
+    let properties = unsafe {
+        vulkan
+            .instance()
+            .get_physical_device_properties(vulkan.physical_device())
+    };
+    // Safety: The driver writes a null terminated name.
+    let device_name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+    let _ = DEVICE_DESCRIPTION.set(format!(
+        "{} (vendor {:#06x}, device {:#06x}, driver version {:#x}, Vulkan {}.{}.{})",
+        device_name.to_string_lossy(),
+        properties.vendor_id,
+        properties.device_id,
+        properties.driver_version,
+        vk::api_version_major(properties.api_version),
+        vk::api_version_minor(properties.api_version),
+        vk::api_version_patch(properties.api_version),
+    ));
+}
+
+/// Logs the device and what was happening the first time `result` is `ERROR_DEVICE_LOST`, and
+/// marks the device lost. Every other result is left to the caller.
+///
+/// Nothing created on a lost device can be used again, so the renderer stops recording frames
+/// once it is marked. The app keeps its window until it is closed, so it still shuts down
+/// normally and every resource is dropped.
+pub fn check_device_lost(result: vk::Result, context: &str) {
+    if result != vk::Result::ERROR_DEVICE_LOST || DEVICE_LOST.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    println!("The GPU device was lost while {}.", context);
+    if let Some(description) = DEVICE_DESCRIPTION.get() {
+        println!("Device: {}", description);
+    }
+    println!(
+        "This is usually a driver reset after a hang, enable the validation layer with \
+         FURRY_VALIDATION=1 to look for the cause. Rendering has stopped, close the window to \
+         exit."
+    );
+}
+
+/// Whether the device was lost, nothing should be submitted to it after this.
+pub fn is_device_lost() -> bool {
+    DEVICE_LOST.load(Ordering::Relaxed)
+}
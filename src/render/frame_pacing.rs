@@ -0,0 +1,67 @@
+use pyrite::{
+    desktop::PRE_UPDATE_STAGE,
+    prelude::{AppBuilder, Input, Key, Res, ResMut, Resource, Vulkan},
+};
+
+use super::device_lost::{check_device_lost, is_device_lost};
+
+pub fn setup_frame_pacing(app_builder: &mut AppBuilder) {
+    app_builder.add_resource(FramePacing::new());
+    app_builder.add_system_to_stage(FramePacing::update_system, PRE_UPDATE_STAGE);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FramePacingMode {
+    /// Lets the CPU run ahead of the GPU by the frames in flight, for the best frame rate.
+    Throughput,
+    /// Waits for the GPU to finish the previous frame before input is read, so the frame
+    /// being recorded reflects the newest input at the cost of some GPU idle time.
+    LowLatency,
+}
+
+#[derive(Resource)]
+pub struct FramePacing {
+    mode: FramePacingMode,
+}
+
+impl FramePacing {
+    pub fn new() -> Self {
+        Self {
+            mode: FramePacingMode::Throughput,
+        }
+    }
+
+    pub fn mode(&self) -> FramePacingMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: FramePacingMode) {
+        self.mode = mode;
+    }
+
+    // Runs before the update stage so the wait happens before the camera samples input.
+    fn update_system(
+        mut frame_pacing: ResMut<FramePacing>,
+        vulkan: Res<Vulkan>,
+        input: Res<Input>,
+    ) {
+        if input.is_key_pressed(Key::F3) {
+            frame_pacing.mode = match frame_pacing.mode {
+                FramePacingMode::Throughput => FramePacingMode::LowLatency,
+                FramePacingMode::LowLatency => FramePacingMode::Throughput,
+            };
+            println!("Frame pacing: {:?}", frame_pacing.mode);
+        }
+
+        // The render manager doesn't hand out its frame fences, so this waits for the whole
+        // device. Nothing but the frames in flight is submitted, so it ends as the previous
+        // frame finishes.
+        if frame_pacing.mode == FramePacingMode::LowLatency && !is_device_lost() {
+            // Safety: Only waits, no resources are touched.
+            if let Err(err) = unsafe { vulkan.device().device_wait_idle() } {
+                check_device_lost(err, "waiting for the previous frame");
+                println!("Failed to wait for the previous frame: {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+
+use ash::vk;
+use pyrite::{
+    prelude::{AppBuilder, Input, Key, Res, ResMut, Resource, Vulkan},
+    render::render_manager::RenderManager,
+    vulkan::CommandBuffer,
+};
+
+use super::device_lost::check_device_lost;
+
+pub fn setup_frame_stats(app_builder: &mut AppBuilder) {
+    let frame_stats = FrameStats::new(
+        &*app_builder.get_resource::<Vulkan>(),
+        &*app_builder.get_resource::<RenderManager>(),
+    );
+    app_builder.add_resource(frame_stats);
+    app_builder.add_system(FrameStats::update_system);
+}
+
+// The number of frames the rolling averages are taken over.
+const AVERAGE_FRAMES: usize = 60;
+
+/// The points in the frame a GPU timestamp is written at.
+#[derive(Clone, Copy)]
+pub enum FrameTimestamp {
+    FrameStart = 0,
+    ShellEnd = 1,
+    PostEnd = 2,
+    FrameEnd = 3,
+}
+
+const TIMESTAMP_COUNT: u32 = 4;
+
+/// GPU times of the frame measured with timestamp queries, averaged over the last 60 frames.
+///
+/// Each frame in flight has its own set of queries. The results of a set are read back the
+/// next time its frame index comes around, by then the render manager has waited on that
+/// frame so the results are ready without stalling.
+#[derive(Resource)]
+pub struct FrameStats {
+    device: ash::Device,
+    // None if the graphics queue doesn't support timestamps.
+    query_pool: Option<vk::QueryPool>,
+    // Nanoseconds per timestamp tick.
+    timestamp_period: f64,
+    // Whether the queries of each frame in flight were written the last time it was recorded.
+    frames_written: Vec<bool>,
+
+    shell_times: VecDeque<f64>,
+    post_times: VecDeque<f64>,
+    total_times: VecDeque<f64>,
+    // Counts every frame whose timestamps were read back.
+    frames_measured: u64,
+    print: bool,
+    frames_since_print: usize,
+}
+
+impl FrameStats {
+    fn new(vulkan: &Vulkan, render_manager: &RenderManager) -> Self {
+        let limits = unsafe {
+            vulkan
+                .instance()
+                .get_physical_device_properties(vulkan.physical_device())
+        }
+        .limits;
+        let frames_in_flight = render_manager.frames_in_flight();
+
+        let query_pool = if limits.timestamp_compute_and_graphics == vk::TRUE {
+            let info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(TIMESTAMP_COUNT * frames_in_flight as u32);
+            // Safety: The pool is destroyed on drop, after the device is idle.
+            match unsafe { vulkan.device().create_query_pool(&info, None) } {
+                Ok(query_pool) => Some(query_pool),
+                Err(err) => {
+                    println!("Failed to create the frame stats query pool: {}", err);
+                    None
+                }
+            }
+        } else {
+            println!("Timestamp queries are not supported, frame stats are disabled.");
+            None
+        };
+
+        Self {
+            device: vulkan.device().clone(),
+            query_pool,
+            timestamp_period: limits.timestamp_period as f64,
+            frames_written: vec![false; frames_in_flight as usize],
+            shell_times: VecDeque::with_capacity(AVERAGE_FRAMES),
+            post_times: VecDeque::with_capacity(AVERAGE_FRAMES),
+            total_times: VecDeque::with_capacity(AVERAGE_FRAMES),
+            frames_measured: 0,
+            print: false,
+            frames_since_print: 0,
+        }
+    }
+
+    /// The average GPU time of the shell pass in milliseconds.
+    pub fn shell_ms(&self) -> f64 {
+        average(&self.shell_times)
+    }
+
+    /// The average GPU time of the post processing passes in milliseconds.
+    pub fn post_ms(&self) -> f64 {
+        average(&self.post_times)
+    }
+
+    /// The average GPU time of the whole frame in milliseconds.
+    pub fn total_ms(&self) -> f64 {
+        average(&self.total_times)
+    }
+
+    /// Whether the device supports the timestamp queries, without them every time reads 0.
+    pub fn is_supported(&self) -> bool {
+        self.query_pool.is_some()
+    }
+
+    /// The number of frames measured so far, it goes up as each frame's timestamps are read back.
+    pub fn frames_measured(&self) -> u64 {
+        self.frames_measured
+    }
+
+    /// The GPU time of the whole frame last measured in milliseconds, not averaged.
+    pub fn last_total_ms(&self) -> f64 {
+        self.total_times.back().copied().unwrap_or(0.0)
+    }
+
+    /// Reads back the results from the last time this frame index was recorded, then resets
+    /// its queries and writes the frame start timestamp.
+    pub fn begin_frame(&mut self, command_buffer: &CommandBuffer, frame_index: usize) {
+        let Some(query_pool) = self.query_pool else {
+            return;
+        };
+        let first_query = frame_index as u32 * TIMESTAMP_COUNT;
+
+        if self.frames_written[frame_index] {
+            let mut timestamps = [0u64; TIMESTAMP_COUNT as usize];
+            // Safety: The render manager waited on this frame before it is recorded again.
+            let result = unsafe {
+                self.device.get_query_pool_results(
+                    query_pool,
+                    first_query,
+                    TIMESTAMP_COUNT,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            match result {
+                Ok(()) => self.record_timestamps(&timestamps),
+                Err(err) => check_device_lost(err, "reading back the frame timestamps"),
+            }
+        }
+
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                command_buffer.command_buffer(),
+                query_pool,
+                first_query,
+                TIMESTAMP_COUNT,
+            );
+        }
+        self.frames_written[frame_index] = true;
+        self.write_timestamp(command_buffer, frame_index, FrameTimestamp::FrameStart);
+    }
+
+    /// Writes a timestamp once all previously recorded commands have finished.
+    pub fn write_timestamp(
+        &self,
+        command_buffer: &CommandBuffer,
+        frame_index: usize,
+        timestamp: FrameTimestamp,
+    ) {
+        if let Some(query_pool) = self.query_pool {
+            unsafe {
+                self.device.cmd_write_timestamp(
+                    command_buffer.command_buffer(),
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query_pool,
+                    frame_index as u32 * TIMESTAMP_COUNT + timestamp as u32,
+                );
+            }
+        }
+    }
+
+    fn record_timestamps(&mut self, timestamps: &[u64]) {
+        let milliseconds = |from: FrameTimestamp, to: FrameTimestamp| {
+            let ticks = timestamps[to as usize].saturating_sub(timestamps[from as usize]);
+            ticks as f64 * self.timestamp_period / 1_000_000.0
+        };
+        let shell_ms = milliseconds(FrameTimestamp::FrameStart, FrameTimestamp::ShellEnd);
+        let post_ms = milliseconds(FrameTimestamp::ShellEnd, FrameTimestamp::PostEnd);
+        let total_ms = milliseconds(FrameTimestamp::FrameStart, FrameTimestamp::FrameEnd);
+
+        for (times, time) in [
+            (&mut self.shell_times, shell_ms),
+            (&mut self.post_times, post_ms),
+            (&mut self.total_times, total_ms),
+        ] {
+            if times.len() == AVERAGE_FRAMES {
+                times.pop_front();
+            }
+            times.push_back(time);
+        }
+        self.frames_measured += 1;
+        self.frames_since_print += 1;
+    }
+
+    fn update_system(mut frame_stats: ResMut<FrameStats>, input: Res<Input>) {
+        if input.is_key_pressed(Key::P) {
+            frame_stats.print = !frame_stats.print;
+            frame_stats.frames_since_print = 0;
+        }
+
+        // Print once per averaging window.
+        if frame_stats.print && frame_stats.frames_since_print >= AVERAGE_FRAMES {
+            frame_stats.frames_since_print = 0;
+            println!(
+                "GPU shell: {:.2}ms, post: {:.2}ms, total: {:.2}ms",
+                frame_stats.shell_ms(),
+                frame_stats.post_ms(),
+                frame_stats.total_ms()
+            );
+        }
+    }
+}
+
+impl Drop for FrameStats {
+    fn drop(&mut self) {
+        if let Some(query_pool) = self.query_pool {
+            unsafe {
+                let _ = self.device.device_wait_idle();
+                self.device.destroy_query_pool(query_pool, None);
+            }
+        }
+    }
+}
+
+fn average(times: &VecDeque<f64>) -> f64 {
+    if times.is_empty() {
+        return 0.0;
+    }
+    times.iter().sum::<f64>() / times.len() as f64
+}
@@ -0,0 +1,85 @@
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+/// The six planes of a camera's view volume in world space, for culling on the CPU.
+pub struct Frustum {
+    // Each plane is (normal, distance) with the normal pointing into the frustum, they aren't
+    // normalized up front so the distances are scaled by the normal lengths.
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from the rows of a projection times view matrix.
+    ///
+    /// The near plane is taken from OpenGL's -w to w depth range, which contains Vulkan's 0 to
+    /// w range, so the test stays conservative with either projection.
+    pub fn from_view_projection(view_projection: &Matrix4<f32>) -> Self {
+        let row = |index: usize| view_projection.row(index).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        Self {
+            planes: [w + x, w - x, w + y, w - y, w + z, w - z],
+        }
+    }
+
+    /// Whether any part of the sphere may be inside the frustum. Spheres near a corner can pass
+    /// while being just outside, which only costs drawing them.
+    pub fn intersects_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            let length = normal.magnitude();
+            // A degenerate plane can't reject anything.
+            length <= f32::EPSILON || normal.dot(&center) + plane.w >= -radius * length
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Perspective3, Point3};
+
+    use super::*;
+
+    // The identity clips to the cube from -1 to 1 on every axis.
+    fn unit_cube() -> Frustum {
+        Frustum::from_view_projection(&Matrix4::identity())
+    }
+
+    #[test]
+    fn sphere_inside_is_kept() {
+        let frustum = unit_cube();
+        assert!(frustum.intersects_sphere(Vector3::zeros(), 0.5));
+        assert!(frustum.intersects_sphere(Vector3::new(0.4, -0.4, 0.4), 0.1));
+    }
+
+    #[test]
+    fn sphere_outside_a_plane_is_culled() {
+        let frustum = unit_cube();
+        assert!(!frustum.intersects_sphere(Vector3::new(3.0, 0.0, 0.0), 0.5));
+        assert!(!frustum.intersects_sphere(Vector3::new(0.0, -3.0, 0.0), 0.5));
+        assert!(!frustum.intersects_sphere(Vector3::new(0.0, 0.0, 1.6), 0.5));
+    }
+
+    #[test]
+    fn sphere_straddling_a_plane_is_kept() {
+        let frustum = unit_cube();
+        assert!(frustum.intersects_sphere(Vector3::new(1.2, 0.0, 0.0), 0.5));
+        assert!(frustum.intersects_sphere(Vector3::new(0.0, 0.0, -1.4), 0.5));
+    }
+
+    #[test]
+    fn perspective_culls_behind_the_near_plane() {
+        let projection = Perspective3::new(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let view = Matrix4::look_at_rh(
+            &Point3::new(0.0, 0.0, 5.0),
+            &Point3::origin(),
+            &Vector3::y(),
+        );
+        let frustum = Frustum::from_view_projection(&(projection.to_homogeneous() * view));
+
+        assert!(frustum.intersects_sphere(Vector3::zeros(), 1.0));
+        // The near plane is at z = 4.9, this one crosses it.
+        assert!(frustum.intersects_sphere(Vector3::new(0.0, 0.0, 4.95), 0.2));
+        assert!(!frustum.intersects_sphere(Vector3::new(0.0, 0.0, 5.5), 0.2));
+        assert!(!frustum.intersects_sphere(Vector3::new(0.0, 0.0, -200.0), 1.0));
+    }
+}
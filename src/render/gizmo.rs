@@ -0,0 +1,315 @@
+use std::{any::Any, sync::Arc};
+
+use ash::vk;
+use pyrite::{
+    prelude::{AppBuilder, Assets, Input, Key, Res, ResMut, Resource},
+    render::render_manager::RenderManager,
+    vulkan::{
+        AttachmentInfo, GraphicsPipeline, GraphicsPipelineInfo, RenderPass, Shader, Subpass,
+        Vulkan,
+    },
+};
+
+use super::{
+    render::RenderPipeline,
+    shell::ShellRenderer,
+    watched_shaders::{DependencySignal, WatchedShaders},
+};
+
+pub fn setup_light_gizmo(app_builder: &mut AppBuilder) {
+    let light_gizmo = LightGizmo::new(
+        &mut *app_builder.get_resource_mut::<Assets>(),
+        &mut *app_builder.get_resource_mut::<WatchedShaders>(),
+        app_builder
+            .get_resource::<RenderPipeline>()
+            .backbuffer_generation(),
+    );
+    app_builder.add_resource(light_gizmo);
+    app_builder.add_system(LightGizmo::update_system);
+}
+
+const VERTEX_FILE_PATH: &str = "shaders/gizmo.vert";
+const FRAGMENT_FILE_PATH: &str = "shaders/gizmo.frag";
+const VERTEX_NAME: &str = "gizmo_vert";
+const FRAGMENT_NAME: &str = "gizmo_frag";
+
+// A line from the origin to the light, and a three axis cross marking the light itself.
+const GIZMO_VERTEX_COUNT: u32 = 8;
+
+/// Draws a debug line pointing towards the scene light on top of the shell output.
+#[derive(Resource)]
+pub struct LightGizmo {
+    shader_dependency_signal: DependencySignal,
+    pipeline: Option<GraphicsPipeline>,
+    enabled: bool,
+    size: f32,
+    // The backbuffer generation of the shell resolve image the render pass was built for.
+    backbuffer_generation: u32,
+}
+
+#[repr(C)]
+struct GizmoPushConstants {
+    // Half the length of each arm of the cross drawn at the light.
+    size: f32,
+}
+
+impl LightGizmo {
+    fn new(
+        assets: &mut Assets,
+        watched_shaders: &mut WatchedShaders,
+        backbuffer_generation: u32,
+    ) -> Self {
+        let shader_dependency_signal = watched_shaders.create_dependency_signal();
+        watched_shaders.load_shader(
+            assets,
+            VERTEX_FILE_PATH,
+            VERTEX_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            FRAGMENT_FILE_PATH,
+            FRAGMENT_NAME,
+            &shader_dependency_signal,
+        );
+
+        Self {
+            shader_dependency_signal,
+            pipeline: None,
+            enabled: false,
+            size: 0.1,
+            backbuffer_generation,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn backbuffer_generation(&self) -> u32 {
+        self.backbuffer_generation
+    }
+
+    /// Renders the gizmo over the resolved shell image, does nothing if disabled or not ready.
+    pub fn render(
+        &self,
+        render_manager: &mut RenderManager,
+        render_pipeline: &RenderPipeline,
+        shell_renderer: &ShellRenderer,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        if !self.enabled {
+            return vec![];
+        }
+
+        if let Some(pipeline) = &self.pipeline {
+            let resolve_image = shell_renderer.resolve_image();
+
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: resolve_image.image_extent().width,
+                    height: resolve_image.image_extent().height,
+                },
+            };
+
+            // Wait for the shell pass to finish writing the resolve image.
+            render_manager.frame().command_buffer().pipeline_barrier(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[resolve_image.image_memory_barrier(
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::GENERAL,
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                )],
+            );
+
+            render_manager
+                .frame()
+                .command_buffer()
+                .dynamic_state_viewport(
+                    vk::Viewport::builder()
+                        .width(resolve_image.image_extent().width as f32)
+                        .height(resolve_image.image_extent().height as f32)
+                        .min_depth(0.0)
+                        .max_depth(1.0)
+                        .build(),
+                );
+            render_manager
+                .frame()
+                .command_buffer()
+                .dynamic_state_scissor(render_area);
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_graphics_pipeline(pipeline);
+
+            let descriptor_sets = [render_pipeline.frame(render_manager).descriptor_set()];
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline_layout(),
+                    &descriptor_sets,
+                );
+
+            render_manager.frame().command_buffer().begin_render_pass(
+                pipeline.render_pass(),
+                render_area,
+                &[],
+            );
+
+            render_manager
+                .frame()
+                .command_buffer()
+                .write_push_constants_typed(
+                    pipeline.pipeline_layout(),
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    &GizmoPushConstants { size: self.size },
+                );
+
+            render_manager
+                .frame()
+                .command_buffer()
+                .draw(GIZMO_VERTEX_COUNT, 1, 0, 0);
+
+            render_manager.frame().command_buffer().end_render_pass();
+
+            return vec![resolve_image.create_dep()];
+        }
+
+        vec![]
+    }
+
+    fn refresh_pipeline(
+        &mut self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        render_pipeline: &RenderPipeline,
+        shell_renderer: &ShellRenderer,
+    ) {
+        let mut subpass = Subpass::new();
+        subpass.color_attachment(
+            &shell_renderer.resolve_image().as_attachment(
+                AttachmentInfo::default()
+                    .load_op(vk::AttachmentLoadOp::LOAD)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .initial_layout(vk::ImageLayout::GENERAL)
+                    .final_layout(vk::ImageLayout::GENERAL),
+            ),
+        );
+
+        let render_pass = RenderPass::new(vulkan, &[subpass]);
+
+        let vertex_shader = Shader::new(vulkan, &watched_shaders.get_shader(VERTEX_NAME).unwrap());
+        let fragment_shader =
+            Shader::new(vulkan, &watched_shaders.get_shader(FRAGMENT_NAME).unwrap());
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        let graphics_pipeline = GraphicsPipeline::new(
+            vulkan,
+            GraphicsPipelineInfo::builder()
+                .vertex_shader(vertex_shader)
+                .fragment_shader(fragment_shader)
+                .vertex_input_state(vk::PipelineVertexInputStateCreateInfo::builder().build())
+                .input_assembly_state(
+                    vk::PipelineInputAssemblyStateCreateInfo::builder()
+                        .topology(vk::PrimitiveTopology::LINE_LIST)
+                        .primitive_restart_enable(false)
+                        .build(),
+                )
+                .rasterization_state(
+                    vk::PipelineRasterizationStateCreateInfo::builder()
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .line_width(1.0)
+                        .build(),
+                )
+                .viewport_state(
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewports(&[])
+                        .viewport_count(1)
+                        .scissors(&[])
+                        .scissor_count(1)
+                        .build(),
+                )
+                .color_blend_state(
+                    vk::PipelineColorBlendStateCreateInfo::builder()
+                        .logic_op(vk::LogicOp::CLEAR)
+                        .attachments(&[vk::PipelineColorBlendAttachmentState::builder()
+                            .blend_enable(false)
+                            .color_write_mask(vk::ColorComponentFlags::RGBA)
+                            .build()])
+                        .build(),
+                )
+                .depth_stencil_state(
+                    vk::PipelineDepthStencilStateCreateInfo::builder()
+                        .depth_test_enable(false)
+                        .depth_write_enable(false)
+                        .build(),
+                )
+                .multisample_state(
+                    vk::PipelineMultisampleStateCreateInfo::builder()
+                        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                        .build(),
+                )
+                .dynamic_state(
+                    vk::PipelineDynamicStateCreateInfo::builder()
+                        .dynamic_states(&dynamic_states)
+                        .build(),
+                )
+                .descriptor_set_layout(render_pipeline.descriptor_set_layout())
+                .push_constant_ranges(vec![vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX,
+                    offset: 0,
+                    size: std::mem::size_of::<GizmoPushConstants>() as u32,
+                }])
+                .render_pass(render_pass)
+                .build(),
+        );
+
+        self.pipeline = Some(graphics_pipeline);
+    }
+
+    fn update_system(
+        mut light_gizmo: ResMut<LightGizmo>,
+        vulkan: Res<Vulkan>,
+        watched_shaders: Res<WatchedShaders>,
+        render_pipeline: Res<RenderPipeline>,
+        shell_renderer: Res<ShellRenderer>,
+        input: Res<Input>,
+    ) {
+        let light_gizmo = &mut *light_gizmo;
+
+        // The render pass targets the shell resolve image, so rebuild it when that is recreated.
+        let resized = light_gizmo.backbuffer_generation != shell_renderer.backbuffer_generation();
+        light_gizmo.backbuffer_generation = shell_renderer.backbuffer_generation();
+
+        if watched_shaders.is_dependency_signaled(&light_gizmo.shader_dependency_signal)
+            || (resized && light_gizmo.pipeline.is_some())
+        {
+            light_gizmo.refresh_pipeline(
+                &*vulkan,
+                &*watched_shaders,
+                &*render_pipeline,
+                &*shell_renderer,
+            );
+        }
+
+        if input.is_key_pressed(Key::G) {
+            light_gizmo.enabled = !light_gizmo.enabled;
+            println!("Light gizmo: {}", light_gizmo.enabled);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use pyrite::prelude::{AppBuilder, Res, ResMut, Resource, Vulkan};
+
+use super::{
+    post::PostProcessing,
+    readback::ReadbackPixels,
+    shell::{auto_tune::ShellAutoTune, ShellRenderer},
+    time_control::TimeControl,
+};
+
+const GOLDEN_DIRECTORY: &str = "goldens";
+
+// Frames rendered once everything is ready before the output is checked, so every frame in
+// flight has caught up with the finished pipelines.
+const SETTLE_FRAMES: u32 = 8;
+
+// The output is shrunk to this many cells along each side before it is compared, so a few
+// pixels shifting between drivers don't fail the check while a changed shape or color does.
+const THUMBNAIL_SIZE: usize = 16;
+
+// How far a cell channel may drift from the golden, out of 255.
+const TOLERANCE: u8 = 6;
+
+/// Renders a fixed frame and checks the output against `goldens/<name>.txt`.
+///
+/// The animation clock is paused at zero and the shell count auto-tune is skipped, so every run
+/// renders the same frame whatever the GPU. Setting `FURRY_UPDATE_GOLDENS=1` writes the golden
+/// from this run instead of checking it.
+pub fn setup_golden_capture(app_builder: &mut AppBuilder, name: String) {
+    {
+        let mut time_control = app_builder.get_resource_mut::<TimeControl>();
+        time_control.set_animation_time(0.0);
+        time_control.set_paused(true);
+    }
+    app_builder
+        .get_resource_mut::<ShellAutoTune>()
+        .set_enabled(false);
+
+    let update = std::env::var("FURRY_UPDATE_GOLDENS").as_deref() == Ok("1");
+    app_builder.add_resource(GoldenCapture {
+        path: Path::new(GOLDEN_DIRECTORY).join(format!("{}.txt", name)),
+        name,
+        update,
+        settle_frames: SETTLE_FRAMES,
+        finished: false,
+    });
+    app_builder.add_system(GoldenCapture::update_system);
+}
+
+/// Checks the output once, the result is printed as `Golden <name>: <result>` for the golden
+/// tests to read.
+#[derive(Resource)]
+pub struct GoldenCapture {
+    name: String,
+    path: PathBuf,
+    update: bool,
+    settle_frames: u32,
+    finished: bool,
+}
+
+impl GoldenCapture {
+    fn check(&self, thumbnail: &Thumbnail) -> anyhow::Result<String> {
+        if self.update {
+            if let Some(directory) = self.path.parent() {
+                std::fs::create_dir_all(directory)?;
+            }
+            std::fs::write(&self.path, thumbnail.to_text())?;
+            return Ok(format!("updated {}", self.path.display()));
+        }
+
+        let source = std::fs::read_to_string(&self.path).map_err(|err| {
+            anyhow::anyhow!(
+                "mismatch, couldn't read {} ({}), run with FURRY_UPDATE_GOLDENS=1 to create it",
+                self.path.display(),
+                err
+            )
+        })?;
+        let golden = Thumbnail::parse(&source).map_err(|err| {
+            anyhow::anyhow!("mismatch, {} is corrupt: {}", self.path.display(), err)
+        })?;
+
+        let difference = thumbnail.max_difference(&golden);
+        if difference > TOLERANCE {
+            anyhow::bail!(
+                "mismatch, a cell differs by {} where {} is allowed, run with \
+                 FURRY_UPDATE_GOLDENS=1 if the change is intended",
+                difference,
+                TOLERANCE
+            );
+        }
+        Ok(format!("match, largest difference {}", difference))
+    }
+
+    fn update_system(
+        mut golden: ResMut<GoldenCapture>,
+        vulkan: Res<Vulkan>,
+        shell_renderer: Res<ShellRenderer>,
+        post_processing: Res<PostProcessing>,
+    ) {
+        let golden = &mut *golden;
+        if golden.finished || !(shell_renderer.is_ready() && post_processing.is_ready()) {
+            return;
+        }
+        if golden.settle_frames > 0 {
+            golden.settle_frames -= 1;
+            return;
+        }
+        golden.finished = true;
+
+        let result = post_processing
+            .read_output_pixels(&*vulkan)
+            .and_then(|pixels| golden.check(&Thumbnail::from_pixels(&pixels)));
+        match result {
+            Ok(result) => println!("Golden {}: {}", golden.name, result),
+            Err(err) => println!("Golden {}: {}", golden.name, err),
+        }
+    }
+}
+
+/// The average RGB of every cell in a `THUMBNAIL_SIZE` grid over the image, row by row.
+#[derive(Debug, PartialEq)]
+struct Thumbnail {
+    cells: Vec<[u8; 3]>,
+}
+
+impl Thumbnail {
+    fn from_pixels(pixels: &ReadbackPixels) -> Self {
+        let width = pixels.extent.width as usize;
+        let height = pixels.extent.height as usize;
+
+        let mut sums = vec![([0u64; 3], 0u64); THUMBNAIL_SIZE * THUMBNAIL_SIZE];
+        for (index, pixel) in pixels.pixels.iter().enumerate() {
+            let cell_x = (index % width) * THUMBNAIL_SIZE / width;
+            let cell_y = (index / width) * THUMBNAIL_SIZE / height;
+            let (sum, count) = &mut sums[cell_y * THUMBNAIL_SIZE + cell_x];
+            for (sum, channel) in sum.iter_mut().zip(pixel) {
+                *sum += *channel as u64;
+            }
+            *count += 1;
+        }
+
+        // Images smaller than the grid leave some cells empty, those stay black.
+        let cells = sums
+            .into_iter()
+            .map(|(sum, count)| sum.map(|channel| (channel / count.max(1)) as u8))
+            .collect();
+        Self { cells }
+    }
+
+    /// One line of hex colors per row, so a changed golden shows where the image changed.
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.cells.chunks(THUMBNAIL_SIZE) {
+            for [r, g, b] in row {
+                let _ = write!(text, "{:02x}{:02x}{:02x}", r, g, b);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let rows = source.lines().collect::<Vec<_>>();
+        if rows.len() != THUMBNAIL_SIZE {
+            return Err(format!(
+                "expected {} rows, found {}",
+                THUMBNAIL_SIZE,
+                rows.len()
+            ));
+        }
+
+        let mut cells = Vec::with_capacity(THUMBNAIL_SIZE * THUMBNAIL_SIZE);
+        for (row_index, row) in rows.iter().enumerate() {
+            let row = row.trim();
+            if row.len() != THUMBNAIL_SIZE * 6 || !row.is_ascii() {
+                return Err(format!(
+                    "row {} should be {} hex colors",
+                    row_index + 1,
+                    THUMBNAIL_SIZE
+                ));
+            }
+            for color in row.as_bytes().chunks(6) {
+                let mut cell = [0; 3];
+                for (channel, hex) in cell.iter_mut().zip(color.chunks(2)) {
+                    // The row is ascii, so every two bytes are a valid str.
+                    let hex = std::str::from_utf8(hex).unwrap();
+                    *channel = u8::from_str_radix(hex, 16)
+                        .map_err(|_| format!("row {} has an invalid color", row_index + 1))?;
+                }
+                cells.push(cell);
+            }
+        }
+        Ok(Self { cells })
+    }
+
+    fn max_difference(&self, other: &Thumbnail) -> u8 {
+        self.cells
+            .iter()
+            .zip(&other.cells)
+            .flat_map(|(a, b)| (0..3).map(move |channel| a[channel].abs_diff(b[channel])))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+
+    use super::*;
+
+    fn solid_pixels(width: u32, height: u32, color: [u8; 4]) -> ReadbackPixels {
+        ReadbackPixels {
+            extent: vk::Extent2D { width, height },
+            pixels: vec![color; (width * height) as usize],
+        }
+    }
+
+    #[test]
+    fn thumbnail_averages_each_cell() {
+        let mut pixels = solid_pixels(64, 32, [10, 20, 30, 255]);
+        // Paint the left half white, every cell is 4 pixels wide so none straddle the edge.
+        for (index, pixel) in pixels.pixels.iter_mut().enumerate() {
+            if index % 64 < 32 {
+                *pixel = [255, 255, 255, 255];
+            }
+        }
+
+        let thumbnail = Thumbnail::from_pixels(&pixels);
+        assert_eq!(thumbnail.cells.len(), THUMBNAIL_SIZE * THUMBNAIL_SIZE);
+        for (index, cell) in thumbnail.cells.iter().enumerate() {
+            let expected = if index % THUMBNAIL_SIZE < THUMBNAIL_SIZE / 2 {
+                [255, 255, 255]
+            } else {
+                [10, 20, 30]
+            };
+            assert_eq!(*cell, expected);
+        }
+    }
+
+    #[test]
+    fn thumbnail_text_round_trips() {
+        let mut pixels = solid_pixels(32, 32, [0, 0, 0, 255]);
+        for (index, pixel) in pixels.pixels.iter_mut().enumerate() {
+            *pixel = [(index % 256) as u8, (index / 4 % 256) as u8, 200, 255];
+        }
+
+        let thumbnail = Thumbnail::from_pixels(&pixels);
+        assert_eq!(Thumbnail::parse(&thumbnail.to_text()), Ok(thumbnail));
+    }
+
+    #[test]
+    fn corrupt_golden_is_rejected() {
+        assert!(Thumbnail::parse("").is_err());
+        let text = Thumbnail::from_pixels(&solid_pixels(16, 16, [1, 2, 3, 255])).to_text();
+        assert!(Thumbnail::parse(&text.replacen("01", "zz", 1)).is_err());
+        assert!(Thumbnail::parse(&text[1..]).is_err());
+    }
+
+    #[test]
+    fn difference_is_the_largest_channel_change() {
+        let golden = Thumbnail::from_pixels(&solid_pixels(16, 16, [100, 100, 100, 255]));
+        let mut pixels = solid_pixels(16, 16, [100, 100, 100, 255]);
+        pixels.pixels[0] = [100, 140, 90, 255];
+
+        let thumbnail = Thumbnail::from_pixels(&pixels);
+        assert_eq!(thumbnail.max_difference(&golden), 40);
+        assert_eq!(golden.max_difference(&golden), 0);
+    }
+}
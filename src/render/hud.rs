@@ -0,0 +1,986 @@
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+use ash::vk;
+use pyrite::{
+    prelude::{AppBuilder, Assets, Input, Key, MouseButton, Res, ResMut, Resource, Time},
+    render::render_manager::RenderManager,
+    vulkan::{
+        AttachmentInfo, BufferInfo, DescriptorSet, DescriptorSetLayout, GraphicsPipeline,
+        GraphicsPipelineInfo, RenderPass, Sampler, Shader, StageType, Subpass, UntypedBuffer,
+        Vulkan, VulkanAllocator, VulkanStager,
+    },
+};
+
+use super::{
+    camera::Camera,
+    depth_pyramid::DepthPyramid,
+    device_lost::check_device_lost,
+    frame_pacing::{FramePacing, FramePacingMode},
+    frame_stats::FrameStats,
+    post::PostProcessing,
+    render::RenderPipeline,
+    sampler::SamplerSettings,
+    shell::ShellRenderer,
+    texture::Texture,
+    time_control::TimeControl,
+    watched_shaders::{DependencySignal, WatchedShaders},
+};
+
+pub fn setup_hud(app_builder: &mut AppBuilder) {
+    let hud = Hud::new(
+        &*app_builder.get_resource::<Vulkan>(),
+        &mut *app_builder.get_resource_mut::<Assets>(),
+        &mut *app_builder.get_resource_mut::<WatchedShaders>(),
+    );
+    app_builder.add_resource(hud);
+    app_builder.add_system(Hud::update_system);
+    app_builder.add_system(Hud::update_pipeline_system);
+}
+
+const VERTEX_FILE_PATH: &str = "shaders/hud.vert";
+const FRAGMENT_FILE_PATH: &str = "shaders/hud.frag";
+const VERTEX_NAME: &str = "hud_vert";
+const FRAGMENT_NAME: &str = "hud_frag";
+
+// Points scrolled per scroll wheel step.
+const SCROLL_POINTS: f32 = 50.0;
+
+#[repr(C)]
+struct HudPushConstants {
+    // The size of the output in egui points.
+    screen_size: [f32; 2],
+}
+
+/// An egui texture, kept on the CPU as well so partial updates can be patched in.
+struct HudTexture {
+    texture: Texture,
+    descriptor_set: DescriptorSet,
+    size: [usize; 2],
+    pixels: Vec<u8>,
+}
+
+/// A run of indices drawn with one texture and scissor.
+struct HudDraw {
+    texture_id: egui::TextureId,
+    // In points, clamped to the screen.
+    clip_rect: egui::Rect,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+/// An egui window with sliders for the fur and post processing settings and the frame stats,
+/// drawn over the post processing output. Toggled with F1.
+///
+/// While the mouse is over the window or a widget has focus the camera ignores scrolling and
+/// the movement keys. The HUD gets no mouse input while the cursor is locked to the camera.
+#[derive(Resource)]
+pub struct Hud {
+    shader_dependency_signal: DependencySignal,
+    pipeline: Option<GraphicsPipeline>,
+    descriptor_set_layout: DescriptorSetLayout,
+    sampler: Sampler,
+    context: egui::Context,
+    enabled: bool,
+    textures: HashMap<egui::TextureId, HudTexture>,
+    // The sets of freed textures, reused by the next new texture since pool sets aren't freed.
+    free_descriptor_sets: Vec<DescriptorSet>,
+    // This frame's triangles, None while there is nothing to draw.
+    buffers: Option<(Arc<UntypedBuffer>, Arc<UntypedBuffer>)>,
+    draws: Vec<HudDraw>,
+    screen_size: [f32; 2],
+    pointer_down: bool,
+    // The post processing output generation the render pass was built for.
+    output_generation: u32,
+}
+
+impl Hud {
+    fn new(vulkan: &Vulkan, assets: &mut Assets, watched_shaders: &mut WatchedShaders) -> Self {
+        let shader_dependency_signal = watched_shaders.create_dependency_signal();
+        watched_shaders.load_shader(
+            assets,
+            VERTEX_FILE_PATH,
+            VERTEX_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            FRAGMENT_FILE_PATH,
+            FRAGMENT_NAME,
+            &shader_dependency_signal,
+        );
+
+        let descriptor_set_layout = DescriptorSetLayout::new(
+            vulkan,
+            &[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()],
+        );
+
+        // The font atlas is made to be sampled linearly without mipmaps.
+        let sampler = SamplerSettings {
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..SamplerSettings::linear(1.0)
+        }
+        .create(vulkan);
+
+        Self {
+            shader_dependency_signal,
+            pipeline: None,
+            descriptor_set_layout,
+            sampler,
+            context: egui::Context::default(),
+            enabled: false,
+            textures: HashMap::new(),
+            free_descriptor_sets: Vec::new(),
+            buffers: None,
+            draws: Vec::new(),
+            screen_size: [1.0, 1.0],
+            pointer_down: false,
+            output_generation: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Draws the HUD over the post processing output, does nothing if disabled or not ready.
+    ///
+    /// Returns no dependencies when nothing was drawn, the output is then still as the post
+    /// processing left it.
+    pub fn render(
+        &self,
+        vulkan: &Vulkan,
+        render_manager: &mut RenderManager,
+        post_processing: &PostProcessing,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        let (Some(pipeline), Some((vertex_buffer, index_buffer))) = (&self.pipeline, &self.buffers)
+        else {
+            return vec![];
+        };
+        if !self.enabled || self.output_generation != post_processing.output_generation() {
+            return vec![];
+        }
+
+        let output_image = post_processing.output_image();
+        let mut deps: Vec<Arc<dyn Any + Send + Sync>> = vec![
+            output_image.create_dep(),
+            vertex_buffer.clone(),
+            index_buffer.clone(),
+        ];
+
+        // Copy new and changed textures in before the render pass samples them.
+        for hud_texture in self.textures.values() {
+            deps.extend(
+                hud_texture
+                    .texture
+                    .record_upload(vulkan, render_manager.frame().command_buffer()),
+            );
+            deps.push(hud_texture.texture.image().create_dep());
+            deps.push(hud_texture.descriptor_set.create_dep());
+        }
+
+        // Wait for the last post pass to finish writing the output.
+        render_manager.frame().command_buffer().pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[output_image.image_memory_barrier(
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )],
+        );
+
+        let extent = output_image.image_extent();
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D {
+                width: extent.width,
+                height: extent.height,
+            },
+        };
+
+        render_manager
+            .frame()
+            .command_buffer()
+            .dynamic_state_viewport(
+                vk::Viewport::builder()
+                    .width(extent.width as f32)
+                    .height(extent.height as f32)
+                    .min_depth(0.0)
+                    .max_depth(1.0)
+                    .build(),
+            );
+        render_manager
+            .frame_mut()
+            .command_buffer_mut()
+            .bind_graphics_pipeline(pipeline);
+
+        render_manager.frame().command_buffer().begin_render_pass(
+            pipeline.render_pass(),
+            render_area,
+            &[],
+        );
+
+        render_manager
+            .frame_mut()
+            .command_buffer_mut()
+            .bind_vertex_buffer(0, vertex_buffer);
+        render_manager
+            .frame_mut()
+            .command_buffer_mut()
+            .bind_index_buffer(index_buffer, vk::IndexType::UINT32);
+        render_manager
+            .frame()
+            .command_buffer()
+            .write_push_constants_typed(
+                pipeline.pipeline_layout(),
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                &HudPushConstants {
+                    screen_size: self.screen_size,
+                },
+            );
+
+        // The points are the viewport's window pixels, which the render scale may stretch the output
+        // from.
+        let scale_x = extent.width as f32 / self.screen_size[0];
+        let scale_y = extent.height as f32 / self.screen_size[1];
+        for draw in &self.draws {
+            // Freed textures are skipped, egui only frees them once they are no longer drawn.
+            let Some(hud_texture) = self.textures.get(&draw.texture_id) else {
+                continue;
+            };
+            let (min, max) = (draw.clip_rect.min, draw.clip_rect.max);
+            let (min_x, min_y) = ((min.x * scale_x) as u32, (min.y * scale_y) as u32);
+            let (max_x, max_y) = (
+                ((max.x * scale_x).ceil() as u32).min(extent.width),
+                ((max.y * scale_y).ceil() as u32).min(extent.height),
+            );
+            if max_x <= min_x || max_y <= min_y {
+                continue;
+            }
+            render_manager
+                .frame()
+                .command_buffer()
+                .dynamic_state_scissor(vk::Rect2D {
+                    offset: vk::Offset2D {
+                        x: min_x as i32,
+                        y: min_y as i32,
+                    },
+                    extent: vk::Extent2D {
+                        width: max_x - min_x,
+                        height: max_y - min_y,
+                    },
+                });
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline_layout(),
+                    &[&hud_texture.descriptor_set],
+                );
+            render_manager.frame().command_buffer().draw_indexed(
+                draw.index_count,
+                1,
+                draw.first_index,
+                draw.vertex_offset,
+                0,
+            );
+        }
+
+        render_manager.frame().command_buffer().end_render_pass();
+
+        deps
+    }
+
+    fn refresh_pipeline(
+        &mut self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        post_processing: &PostProcessing,
+    ) {
+        let mut subpass = Subpass::new();
+        subpass.color_attachment(
+            &post_processing.output_image().as_attachment(
+                AttachmentInfo::default()
+                    .load_op(vk::AttachmentLoadOp::LOAD)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .initial_layout(vk::ImageLayout::GENERAL)
+                    .final_layout(vk::ImageLayout::GENERAL),
+            ),
+        );
+
+        let render_pass = RenderPass::new(vulkan, &[subpass]);
+
+        let vertex_shader = Shader::new(vulkan, &watched_shaders.get_shader(VERTEX_NAME).unwrap());
+        let fragment_shader =
+            Shader::new(vulkan, &watched_shaders.get_shader(FRAGMENT_NAME).unwrap());
+
+        // Matches egui's vertex, a position and uv in points followed by an RGBA8 color.
+        let vertex_input_binding_descriptions = [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<egui::epaint::Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+        let vertex_input_attribute_descriptions = [
+            vk::VertexInputAttributeDescription::builder()
+                .location(0)
+                .binding(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .location(1)
+                .binding(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(8)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .location(2)
+                .binding(0)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .offset(16)
+                .build(),
+        ];
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        let graphics_pipeline = GraphicsPipeline::new(
+            vulkan,
+            GraphicsPipelineInfo::builder()
+                .vertex_shader(vertex_shader)
+                .fragment_shader(fragment_shader)
+                .vertex_input_state(
+                    vk::PipelineVertexInputStateCreateInfo::builder()
+                        .vertex_binding_descriptions(&vertex_input_binding_descriptions)
+                        .vertex_attribute_descriptions(&vertex_input_attribute_descriptions)
+                        .build(),
+                )
+                .input_assembly_state(
+                    vk::PipelineInputAssemblyStateCreateInfo::builder()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                        .primitive_restart_enable(false)
+                        .build(),
+                )
+                .rasterization_state(
+                    vk::PipelineRasterizationStateCreateInfo::builder()
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .line_width(1.0)
+                        .build(),
+                )
+                .viewport_state(
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewports(&[])
+                        .viewport_count(1)
+                        .scissors(&[])
+                        .scissor_count(1)
+                        .build(),
+                )
+                .color_blend_state(
+                    vk::PipelineColorBlendStateCreateInfo::builder()
+                        .logic_op(vk::LogicOp::CLEAR)
+                        // egui's colors are premultiplied.
+                        .attachments(&[vk::PipelineColorBlendAttachmentState::builder()
+                            .blend_enable(true)
+                            .src_color_blend_factor(vk::BlendFactor::ONE)
+                            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                            .color_blend_op(vk::BlendOp::ADD)
+                            .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_DST_ALPHA)
+                            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                            .alpha_blend_op(vk::BlendOp::ADD)
+                            .color_write_mask(vk::ColorComponentFlags::RGBA)
+                            .build()])
+                        .build(),
+                )
+                .depth_stencil_state(
+                    vk::PipelineDepthStencilStateCreateInfo::builder()
+                        .depth_test_enable(false)
+                        .depth_write_enable(false)
+                        .build(),
+                )
+                .multisample_state(
+                    vk::PipelineMultisampleStateCreateInfo::builder()
+                        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                        .build(),
+                )
+                .dynamic_state(
+                    vk::PipelineDynamicStateCreateInfo::builder()
+                        .dynamic_states(&dynamic_states)
+                        .build(),
+                )
+                .descriptor_set_layout(&self.descriptor_set_layout)
+                .push_constant_ranges(vec![vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX,
+                    offset: 0,
+                    size: std::mem::size_of::<HudPushConstants>() as u32,
+                }])
+                .render_pass(render_pass)
+                .build(),
+        );
+
+        self.pipeline = Some(graphics_pipeline);
+        self.output_generation = post_processing.output_generation();
+    }
+
+    // Builds the egui input from the mouse, the HUD only sees the mouse while it is free.
+    fn take_raw_input(
+        &mut self,
+        input: &Input,
+        viewport: vk::Rect2D,
+        time: &Time,
+        camera: &Camera,
+    ) -> egui::RawInput {
+        // Laid out over the rendered image, which is inset from the window while letterboxed.
+        self.screen_size = [viewport.extent.width as f32, viewport.extent.height as f32];
+        let mut events = Vec::new();
+
+        if camera.is_cursor_locked() {
+            events.push(egui::Event::PointerGone);
+            self.pointer_down = false;
+        } else {
+            let (x, y) = input.mouse_position();
+            let pos = egui::pos2(
+                x as f32 - viewport.offset.x as f32,
+                y as f32 - viewport.offset.y as f32,
+            );
+            events.push(egui::Event::PointerMoved(pos));
+
+            let pointer_down = input.is_mouse_button_down(MouseButton::Left);
+            if pointer_down != self.pointer_down {
+                events.push(egui::Event::PointerButton {
+                    pos,
+                    button: egui::PointerButton::Primary,
+                    pressed: pointer_down,
+                    modifiers: egui::Modifiers::default(),
+                });
+                self.pointer_down = pointer_down;
+            }
+
+            let (scroll_x, scroll_y) = input.scroll_delta();
+            if scroll_x != 0.0 || scroll_y != 0.0 {
+                events.push(egui::Event::Scroll(
+                    egui::vec2(scroll_x as f32, scroll_y as f32) * SCROLL_POINTS,
+                ));
+            }
+        }
+
+        egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(self.screen_size[0], self.screen_size[1]),
+            )),
+            predicted_dt: time.delta().as_secs_f32(),
+            events,
+            ..Default::default()
+        }
+    }
+
+    // Creates, patches and frees the textures egui asked for this frame.
+    fn update_textures(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+        render_pipeline: &RenderPipeline,
+        textures_delta: &egui::TexturesDelta,
+    ) {
+        let mut waited = false;
+        for (texture_id, delta) in &textures_delta.set {
+            let size = delta.image.size();
+            let pixels: Vec<u8> = match &delta.image {
+                egui::ImageData::Color(image) => image
+                    .pixels
+                    .iter()
+                    .flat_map(|color| color.to_array())
+                    .collect(),
+                egui::ImageData::Font(image) => image
+                    .srgba_pixels(None)
+                    .flat_map(|color| color.to_array())
+                    .collect(),
+            };
+
+            let (size, pixels, descriptor_set) = match (delta.pos, self.textures.remove(texture_id))
+            {
+                (None, previous) => (
+                    size,
+                    pixels,
+                    previous.map(|hud_texture| hud_texture.descriptor_set),
+                ),
+                (Some([x, y]), Some(mut hud_texture)) => {
+                    let row_length = size[0] * 4;
+                    for row in 0..size[1] {
+                        let start = ((y + row) * hud_texture.size[0] + x) * 4;
+                        hud_texture.pixels[start..start + row_length]
+                            .copy_from_slice(&pixels[row * row_length..(row + 1) * row_length]);
+                    }
+                    (
+                        hud_texture.size,
+                        hud_texture.pixels,
+                        Some(hud_texture.descriptor_set),
+                    )
+                }
+                // egui always sets a whole texture before patching it.
+                (Some(_), None) => continue,
+            };
+
+            let texture = Texture::from_rgba8(
+                vulkan,
+                vulkan_allocator,
+                vulkan_stager,
+                size[0] as u32,
+                size[1] as u32,
+                &pixels,
+            );
+
+            // A replaced or freed texture's set is rewritten rather than allocating another.
+            let descriptor_set = match descriptor_set.or_else(|| self.free_descriptor_sets.pop()) {
+                Some(descriptor_set) => {
+                    if !waited {
+                        // Safety: Only waits, the reused set may still be bound by frames in
+                        // flight.
+                        if let Err(err) = unsafe { vulkan.device().device_wait_idle() } {
+                            check_device_lost(err, "waiting to update the HUD textures");
+                            println!("Failed to wait to update the HUD textures: {}", err);
+                        }
+                        waited = true;
+                    }
+                    descriptor_set
+                }
+                None => render_pipeline
+                    .descriptor_pool()
+                    .allocate_descriptor_sets(&self.descriptor_set_layout, 1)
+                    .pop()
+                    .unwrap(),
+            };
+            descriptor_set
+                .write()
+                .set_combined_image_sampler(
+                    0,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    texture.image().create_dep(),
+                    &self.sampler,
+                )
+                .submit_writes();
+
+            self.textures.insert(
+                *texture_id,
+                HudTexture {
+                    texture,
+                    descriptor_set,
+                    size,
+                    pixels,
+                },
+            );
+        }
+
+        for texture_id in &textures_delta.free {
+            if let Some(hud_texture) = self.textures.remove(texture_id) {
+                self.free_descriptor_sets.push(hud_texture.descriptor_set);
+            }
+        }
+    }
+
+    // Packs the meshes into one vertex and index buffer, with a draw per mesh.
+    fn update_buffers(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+        primitives: Vec<egui::ClippedPrimitive>,
+    ) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        self.draws.clear();
+
+        let screen_rect = egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(self.screen_size[0], self.screen_size[1]),
+        );
+        for egui::ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in primitives
+        {
+            let egui::epaint::Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+            if mesh.indices.is_empty() {
+                continue;
+            }
+
+            let clip_rect = clip_rect.intersect(screen_rect);
+            if clip_rect.width() <= 0.0 || clip_rect.height() <= 0.0 {
+                continue;
+            }
+
+            self.draws.push(HudDraw {
+                texture_id: mesh.texture_id,
+                clip_rect,
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        if self.draws.is_empty() {
+            self.buffers = None;
+            return;
+        }
+
+        // New buffers every frame, the frames in flight keep theirs alive until they finish.
+        let vertex_buffer = Arc::new(UntypedBuffer::new(
+            vulkan,
+            vulkan_allocator,
+            &BufferInfo::builder()
+                .size(std::mem::size_of_val(vertices.as_slice()) as u64)
+                .usage(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                .build(),
+        ));
+        let index_buffer = Arc::new(UntypedBuffer::new(
+            vulkan,
+            vulkan_allocator,
+            &BufferInfo::builder()
+                .size(std::mem::size_of_val(indices.as_slice()) as u64)
+                .usage(vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                .build(),
+        ));
+
+        // Safety: The pointers are valid for the sizes given.
+        unsafe {
+            vulkan_stager.schedule_stage_buffer(
+                vulkan,
+                vulkan_allocator,
+                vertices.as_ptr() as *const u8,
+                std::mem::size_of_val(vertices.as_slice()) as u64,
+                &vertex_buffer,
+                StageType::Immediate,
+            );
+            vulkan_stager.schedule_stage_buffer(
+                vulkan,
+                vulkan_allocator,
+                indices.as_ptr() as *const u8,
+                std::mem::size_of_val(indices.as_slice()) as u64,
+                &index_buffer,
+                StageType::Immediate,
+            );
+        }
+
+        self.buffers = Some((vertex_buffer, index_buffer));
+    }
+
+    fn update_system(
+        mut hud: ResMut<Hud>,
+        vulkan: Res<Vulkan>,
+        mut vulkan_allocator: ResMut<VulkanAllocator>,
+        mut vulkan_stager: ResMut<VulkanStager>,
+        mut render_pipeline: ResMut<RenderPipeline>,
+        input: Res<Input>,
+        time: Res<Time>,
+        frame_stats: Res<FrameStats>,
+        mut shell_renderer: ResMut<ShellRenderer>,
+        mut post_processing: ResMut<PostProcessing>,
+        mut camera: ResMut<Camera>,
+        mut time_control: ResMut<TimeControl>,
+        mut depth_pyramid: ResMut<DepthPyramid>,
+        mut frame_pacing: ResMut<FramePacing>,
+    ) {
+        let hud = &mut *hud;
+
+        if input.is_key_pressed(Key::F1) {
+            hud.enabled = !hud.enabled;
+            println!("HUD: {}", hud.enabled);
+        }
+
+        if !hud.enabled {
+            hud.buffers = None;
+            hud.draws.clear();
+            camera.set_input_captured(false);
+            return;
+        }
+
+        let raw_input = hud.take_raw_input(&*input, render_pipeline.viewport(), &*time, &*camera);
+        let context = hud.context.clone();
+        let full_output = context.run(raw_input, |context| {
+            build_ui(
+                context,
+                &*vulkan,
+                &mut *render_pipeline,
+                &mut *shell_renderer,
+                &mut *post_processing,
+                &mut *time_control,
+                &mut *depth_pyramid,
+                &mut *frame_pacing,
+                &*frame_stats,
+                &*time,
+            );
+        });
+
+        camera.set_input_captured(
+            context.wants_pointer_input()
+                || context.is_pointer_over_area()
+                || context.wants_keyboard_input(),
+        );
+
+        hud.update_textures(
+            &*vulkan,
+            &mut *vulkan_allocator,
+            &mut *vulkan_stager,
+            &*render_pipeline,
+            &full_output.textures_delta,
+        );
+        let primitives = context.tessellate(full_output.shapes, full_output.pixels_per_point);
+        hud.update_buffers(
+            &*vulkan,
+            &mut *vulkan_allocator,
+            &mut *vulkan_stager,
+            primitives,
+        );
+    }
+
+    fn update_pipeline_system(
+        mut hud: ResMut<Hud>,
+        vulkan: Res<Vulkan>,
+        watched_shaders: Res<WatchedShaders>,
+        post_processing: Res<PostProcessing>,
+    ) {
+        // The render pass targets the post processing output, so rebuild it when that changes.
+        let output_changed = hud.output_generation != post_processing.output_generation();
+
+        if watched_shaders.is_dependency_signaled(&hud.shader_dependency_signal)
+            || (output_changed && hud.pipeline.is_some())
+        {
+            hud.refresh_pipeline(&*vulkan, &*watched_shaders, &*post_processing);
+        }
+    }
+}
+
+fn build_ui(
+    context: &egui::Context,
+    vulkan: &Vulkan,
+    render_pipeline: &mut RenderPipeline,
+    shell_renderer: &mut ShellRenderer,
+    post_processing: &mut PostProcessing,
+    time_control: &mut TimeControl,
+    depth_pyramid: &mut DepthPyramid,
+    frame_pacing: &mut FramePacing,
+    frame_stats: &FrameStats,
+    time: &Time,
+) {
+    egui::Window::new("Furry").show(context, |ui| {
+        ui.label(format!(
+            "Frame {:.2} ms, GPU shell {:.2} ms, post {:.2} ms, total {:.2} ms",
+            time.delta().as_secs_f64() * 1000.0,
+            frame_stats.shell_ms(),
+            frame_stats.post_ms(),
+            frame_stats.total_ms(),
+        ));
+
+        let mut low_latency = frame_pacing.mode() == FramePacingMode::LowLatency;
+        if ui.checkbox(&mut low_latency, "Low latency").changed() {
+            frame_pacing.set_mode(if low_latency {
+                FramePacingMode::LowLatency
+            } else {
+                FramePacingMode::Throughput
+            });
+        }
+
+        // Counts the device doesn't support fall back to the highest one it does.
+        let msaa_samples = render_pipeline.msaa_samples();
+        egui::ComboBox::from_label("MSAA")
+            .selected_text(format!("{}x", msaa_samples.as_raw()))
+            .show_ui(ui, |ui| {
+                for samples in [
+                    vk::SampleCountFlags::TYPE_1,
+                    vk::SampleCountFlags::TYPE_2,
+                    vk::SampleCountFlags::TYPE_4,
+                    vk::SampleCountFlags::TYPE_8,
+                ] {
+                    let label = format!("{}x", samples.as_raw());
+                    if ui
+                        .selectable_label(msaa_samples == samples, label)
+                        .clicked()
+                    {
+                        render_pipeline.set_msaa_samples(vulkan, samples);
+                    }
+                }
+            });
+
+        // More precise far away, the shells and the camera pick it up on the next update.
+        let mut reverse_z = render_pipeline.reverse_z();
+        if ui.checkbox(&mut reverse_z, "Reverse Z").changed() {
+            render_pipeline.set_reverse_z(reverse_z);
+        }
+
+        // Keeps highlights brighter than white for bloom, unsupported devices stay in 8-bit.
+        let mut hdr = render_pipeline.hdr();
+        if ui.checkbox(&mut hdr, "HDR").changed() {
+            render_pipeline.set_hdr(vulkan, hdr);
+        }
+
+        // Dragging the time seeks the animation clock, the same as scrubbing with the keys.
+        ui.horizontal(|ui| {
+            let mut animation_time = time_control.animation_time();
+            if ui
+                .add(
+                    egui::DragValue::new(&mut animation_time)
+                        .speed(0.01)
+                        .clamp_range(0.0..=f32::MAX)
+                        .suffix(" s"),
+                )
+                .changed()
+            {
+                time_control.set_animation_time(animation_time);
+            }
+            ui.label("Animation time");
+
+            let mut paused = time_control.is_paused();
+            if ui.checkbox(&mut paused, "Paused").changed() {
+                time_control.set_paused(paused);
+            }
+        });
+
+        ui.collapsing("Shells", |ui| {
+            let mut resolution = shell_renderer.resolution();
+            if ui
+                .add(egui::Slider::new(&mut resolution, 1..=512).text("Resolution"))
+                .changed()
+            {
+                shell_renderer.set_resolution(resolution);
+            }
+
+            let mut shell_thickness = shell_renderer.shell_thickness();
+            if ui
+                .add(egui::Slider::new(&mut shell_thickness, 0.05..=5.0).text("Thickness (cm)"))
+                .changed()
+            {
+                shell_renderer.set_shell_thickness(shell_thickness);
+            }
+            ui.label(format!("Shells: {}", shell_renderer.shell_count()));
+
+            // Compare the GPU shell time at the top of the window with it on and off.
+            let mut depth_prepass = shell_renderer.depth_prepass();
+            if ui.checkbox(&mut depth_prepass, "Depth pre-pass").changed() {
+                shell_renderer.set_depth_prepass(depth_prepass);
+            }
+
+            let (mut base_color, mut tip_color) = shell_renderer.colors();
+            let colors_changed = ui
+                .horizontal(|ui| {
+                    let base_changed = ui.color_edit_button_rgb(&mut base_color).changed();
+                    ui.label("Base");
+                    let tip_changed = ui.color_edit_button_rgb(&mut tip_color).changed();
+                    ui.label("Tip");
+                    base_changed || tip_changed
+                })
+                .inner;
+            if colors_changed {
+                shell_renderer.set_colors(base_color, tip_color);
+            }
+
+            // The direction is edited as an angle on the XZ plane.
+            let (wind_direction, mut wind_strength) = shell_renderer.wind();
+            let mut wind_angle = wind_direction[1].atan2(wind_direction[0]).to_degrees();
+            let angle_changed = ui
+                .add(egui::Slider::new(&mut wind_angle, -180.0..=180.0).text("Wind angle"))
+                .changed();
+            let strength_changed = ui
+                .add(egui::Slider::new(&mut wind_strength, 0.0..=1.0).text("Wind strength"))
+                .changed();
+            if angle_changed || strength_changed {
+                let wind_angle = wind_angle.to_radians();
+                shell_renderer.set_wind([wind_angle.cos(), wind_angle.sin()], wind_strength);
+            }
+        });
+
+        // The grid has its own height, keep it at the ground shadow's to draw the shadow on it.
+        ui.collapsing("Grid", |ui| {
+            let grid = render_pipeline.grid();
+            let mut enabled = grid.is_some();
+            let mut settings = grid.unwrap_or_default();
+            let mut changed = ui.checkbox(&mut enabled, "Enabled").changed();
+            if enabled {
+                changed |= ui
+                    .add(egui::Slider::new(&mut settings.height, -5.0..=5.0).text("Height"))
+                    .changed();
+                changed |= ui
+                    .horizontal(|ui| {
+                        let color_changed = ui.color_edit_button_rgb(&mut settings.color).changed();
+                        let spacing_changed = ui
+                            .add(
+                                egui::Slider::new(&mut settings.spacing, 0.05..=5.0)
+                                    .text("Spacing"),
+                            )
+                            .changed();
+                        color_changed || spacing_changed
+                    })
+                    .inner;
+            }
+            if changed {
+                render_pipeline.set_grid(
+                    enabled,
+                    settings.height,
+                    settings.spacing,
+                    settings.color,
+                );
+            }
+        });
+
+        ui.collapsing("Post processing", |ui| {
+            let mut exposure = post_processing.exposure();
+            if ui
+                .add(egui::Slider::new(&mut exposure, 0.0..=5.0).text("Exposure"))
+                .changed()
+            {
+                post_processing.set_exposure(exposure);
+            }
+
+            let (mut fog_color, mut fog_density) = post_processing.fog();
+            let fog_changed = ui
+                .horizontal(|ui| {
+                    let color_changed = ui.color_edit_button_rgb(&mut fog_color).changed();
+                    let density_changed = ui
+                        .add(egui::Slider::new(&mut fog_density, 0.0..=1.0).text("Fog"))
+                        .changed();
+                    color_changed || density_changed
+                })
+                .inner;
+            if fog_changed {
+                post_processing.set_fog(fog_color, fog_density);
+            }
+
+            // Nothing reads the pyramid yet, this is for checking it in a capture.
+            let mut depth_pyramid_enabled = depth_pyramid.is_enabled();
+            if ui
+                .checkbox(&mut depth_pyramid_enabled, "Depth pyramid")
+                .changed()
+            {
+                depth_pyramid.set_enabled(depth_pyramid_enabled);
+            }
+            let mut mip_levels = depth_pyramid.mip_levels();
+            if ui
+                .add(egui::Slider::new(&mut mip_levels, 1..=16).text("Depth pyramid levels"))
+                .changed()
+            {
+                depth_pyramid.set_mip_levels(mip_levels);
+            }
+        });
+    });
+}
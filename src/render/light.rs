@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use ash::vk;
+use na::Vector3;
+use pyrite::{
+    prelude::{AppBuilder, Res, ResMut, Resource},
+    render::render_manager::RenderManager,
+    vulkan::{BufferInfo, StageType, UntypedBuffer, Vulkan, VulkanAllocator, VulkanStager},
+};
+
+extern crate nalgebra as na;
+
+pub fn setup_lighting(app_builder: &mut AppBuilder) {
+    let lighting = Lighting::new(
+        &*app_builder.get_resource::<Vulkan>(),
+        &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
+        app_builder
+            .get_resource::<RenderManager>()
+            .frames_in_flight(),
+    );
+    app_builder.add_resource(lighting);
+    app_builder.add_system(Lighting::update_system);
+}
+
+/// The scene light, shared through a uniform buffer so every pass reads the same values.
+#[derive(Resource)]
+pub struct Lighting {
+    position: Vector3<f32>,
+    color: Vector3<f32>,
+    intensity: f32,
+
+    // One buffer per frame in flight, written in turn like the camera's so a frame still on
+    // the GPU keeps reading its own light.
+    buffers: Vec<Arc<UntypedBuffer>>,
+    // The buffer written by the latest update, which is the one the next frame binds.
+    buffer_index: usize,
+}
+
+// Matches the std140 layout of `LightingUniform` in the shaders.
+#[repr(C)]
+struct LightingBufferData {
+    position: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    _padding: f32,
+}
+
+impl Lighting {
+    pub fn new(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        frames_in_flight: usize,
+    ) -> Self {
+        let buffers = (0..frames_in_flight.max(1))
+            .map(|_| {
+                Arc::new(UntypedBuffer::new(
+                    vulkan,
+                    vulkan_allocator,
+                    &BufferInfo::builder()
+                        .size(std::mem::size_of::<LightingBufferData>() as u64)
+                        .usage(
+                            vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
+                        .build(),
+                ))
+            })
+            .collect();
+
+        Self {
+            position: Vector3::new(2.3, 1.5, -2.0),
+            color: Vector3::new(1.0, 1.0, 1.0),
+            intensity: 1.75,
+            buffers,
+            buffer_index: 0,
+        }
+    }
+
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vector3<f32>) {
+        self.position = position;
+    }
+
+    pub fn set_color(&mut self, color: Vector3<f32>) {
+        self.color = color;
+    }
+
+    pub fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity.max(0.0);
+    }
+
+    /// The buffer holding the light of the latest update, for the frame being recorded.
+    pub fn lighting_buffer(&self) -> &Arc<UntypedBuffer> {
+        &self.buffers[self.buffer_index]
+    }
+
+    fn update_system(
+        mut lighting: ResMut<Lighting>,
+        vulkan: Res<Vulkan>,
+        mut vulkan_allocator: ResMut<VulkanAllocator>,
+        mut stager: ResMut<VulkanStager>,
+    ) {
+        let data = LightingBufferData {
+            position: lighting.position.into(),
+            intensity: lighting.intensity,
+            color: lighting.color.into(),
+            _padding: 0.0,
+        };
+
+        // The render manager has waited on the frame that last read the next buffer, as it was
+        // recorded frames in flight frames ago.
+        lighting.buffer_index = (lighting.buffer_index + 1) % lighting.buffers.len();
+        unsafe {
+            stager.schedule_stage_buffer(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                &data as *const LightingBufferData as *const u8,
+                std::mem::size_of::<LightingBufferData>() as u64,
+                &lighting.buffers[lighting.buffer_index],
+                StageType::Immediate,
+            );
+        }
+    }
+}
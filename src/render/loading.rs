@@ -0,0 +1,219 @@
+use std::{any::Any, sync::Arc};
+
+use ash::vk;
+use pyrite::{
+    prelude::{AppBuilder, Resource},
+    vulkan::{
+        BufferInfo, CommandBuffer, Image, StageType, UntypedBuffer, Vulkan, VulkanAllocator,
+        VulkanStager,
+    },
+};
+
+pub fn setup_loading_screen(app_builder: &mut AppBuilder) {
+    let loading_screen = LoadingScreen::new(
+        &*app_builder.get_resource::<Vulkan>(),
+        &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
+        &mut *app_builder.get_resource_mut::<VulkanStager>(),
+    );
+    app_builder.add_resource(loading_screen);
+}
+
+const BAR_WIDTH: u32 = 512;
+const BAR_HEIGHT: u32 = 16;
+
+const BACKGROUND_COLOR: [f32; 4] = [0.05, 0.05, 0.06, 1.0];
+const TRACK_COLOR: [u8; 4] = [40, 40, 46, 255];
+const FILL_COLOR: [u8; 4] = [196, 247, 71, 255];
+
+/// How far along startup is, each stage is counted as done or not done.
+pub struct LoadingProgress {
+    pub shaders_loaded: usize,
+    pub shader_count: usize,
+    pub pipelines_ready: usize,
+    pub pipeline_count: usize,
+}
+
+impl LoadingProgress {
+    pub fn fraction(&self) -> f32 {
+        let total = self.shader_count + self.pipeline_count;
+        if total == 0 {
+            return 1.0;
+        }
+        (self.shaders_loaded + self.pipelines_ready) as f32 / total as f32
+    }
+}
+
+/// Draws a progress bar into the output image while shaders and pipelines are still loading.
+///
+/// This is done entirely with transfer commands since the shaders it is waiting on aren't available.
+#[derive(Resource)]
+pub struct LoadingScreen {
+    // Holds a full bar of the track color followed by a full bar of the fill color.
+    bar_buffer: Arc<UntypedBuffer>,
+    last_percent: Option<u32>,
+}
+
+impl LoadingScreen {
+    pub fn new(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+    ) -> Self {
+        let pixel_count = (BAR_WIDTH * BAR_HEIGHT) as usize;
+        let mut pixels = Vec::with_capacity(pixel_count * 2);
+        pixels.extend(std::iter::repeat(TRACK_COLOR).take(pixel_count));
+        pixels.extend(std::iter::repeat(FILL_COLOR).take(pixel_count));
+
+        let data_size = (pixels.len() * std::mem::size_of::<[u8; 4]>()) as u64;
+        let bar_buffer = Arc::new(UntypedBuffer::new(
+            vulkan,
+            vulkan_allocator,
+            &BufferInfo::builder()
+                .size(data_size)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+                .build(),
+        ));
+
+        // Safety: pixels is a valid pointer to data_size bytes.
+        unsafe {
+            vulkan_stager.schedule_stage_buffer(
+                vulkan,
+                vulkan_allocator,
+                pixels.as_ptr() as *const u8,
+                data_size,
+                &bar_buffer,
+                StageType::Immediate,
+            )
+        };
+
+        Self {
+            bar_buffer,
+            last_percent: None,
+        }
+    }
+
+    /// Records the loading screen into `image`, leaving it in `TRANSFER_DST_OPTIMAL`.
+    pub fn render(
+        &mut self,
+        vulkan: &Vulkan,
+        command_buffer: &mut CommandBuffer,
+        image: &Image,
+        progress: &LoadingProgress,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        let fraction = progress.fraction().clamp(0.0, 1.0);
+
+        let percent = (fraction * 100.0) as u32;
+        if self.last_percent != Some(percent) {
+            println!(
+                "Loading {}% ({}/{} shaders, {}/{} pipelines)",
+                percent,
+                progress.shaders_loaded,
+                progress.shader_count,
+                progress.pipelines_ready,
+                progress.pipeline_count
+            );
+            self.last_percent = Some(percent);
+        }
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[image.image_memory_barrier(
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+            )],
+        );
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .level_count(1)
+            .build();
+
+        let extent = image.image_extent();
+        let bar_x = (extent.width.saturating_sub(BAR_WIDTH) / 2) as i32;
+        let bar_y = (extent.height.saturating_sub(BAR_HEIGHT) / 2) as i32;
+        let fill_width = (BAR_WIDTH as f32 * fraction) as u32;
+
+        let bar_region = |buffer_offset: u64, x_offset: u32, width: u32| {
+            vk::BufferImageCopy::builder()
+                .buffer_offset(buffer_offset)
+                .buffer_row_length(BAR_WIDTH)
+                .buffer_image_height(BAR_HEIGHT)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_offset(vk::Offset3D {
+                    x: bar_x + x_offset as i32,
+                    y: bar_y,
+                    z: 0,
+                })
+                .image_extent(vk::Extent3D {
+                    width,
+                    height: BAR_HEIGHT,
+                    depth: 1,
+                })
+                .build()
+        };
+
+        // The filled part and the remaining track don't overlap, so both copies can go in one command.
+        let pixel_size = std::mem::size_of::<[u8; 4]>() as u64;
+        let fill_offset = (BAR_WIDTH * BAR_HEIGHT) as u64 * pixel_size;
+        let mut regions = Vec::new();
+        if fill_width > 0 {
+            regions.push(bar_region(fill_offset, 0, fill_width));
+        }
+        if fill_width < BAR_WIDTH {
+            regions.push(bar_region(
+                fill_width as u64 * pixel_size,
+                fill_width,
+                BAR_WIDTH - fill_width,
+            ));
+        }
+
+        unsafe {
+            vulkan.device().cmd_clear_color_image(
+                command_buffer.command_buffer(),
+                image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue {
+                    float32: BACKGROUND_COLOR,
+                },
+                &[subresource_range],
+            );
+
+            // The clear and the copies write the same pixels, so they must be ordered.
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[image.image_memory_barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+            );
+
+            vulkan.device().cmd_copy_buffer_to_image(
+                command_buffer.command_buffer(),
+                self.bar_buffer.buffer(),
+                image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+        }
+
+        vec![self.bar_buffer.clone(), image.create_dep()]
+    }
+}
@@ -1,26 +1,73 @@
+use ash::vk;
 use pyrite::{
     prelude::*,
     render::render_manager::{setup_render_manager, RenderManagerConfig},
 };
 
 use self::{
-    post::setup_post_processing, render::setup_render_pipeline,
+    debug_labels::setup_debug_labels, depth_pyramid::setup_depth_pyramid,
+    device_lost::setup_device_lost, frame_pacing::setup_frame_pacing,
+    frame_stats::setup_frame_stats, gizmo::setup_light_gizmo, hud::setup_hud,
+    light::setup_lighting, loading::setup_loading_screen, particles::setup_particle_system,
+    post::setup_post_processing, render::setup_render_pipeline, time_control::setup_time_control,
     watched_shaders::setup_watched_shaders,
 };
 
 pub mod camera;
+pub mod debug_labels;
+pub mod depth_pyramid;
+pub mod device_lost;
+pub mod frame_pacing;
+pub mod frame_stats;
+pub mod frustum;
+pub mod gizmo;
+pub mod golden;
+pub mod hud;
+pub mod light;
+pub mod loading;
+pub mod particles;
 pub mod post;
+pub mod readback;
 pub mod render;
+pub mod sampler;
+pub mod settings;
 pub mod shell;
+pub mod texture;
+pub mod time_control;
 pub mod watched_shaders;
 
-pub fn setup_render_preset(app_builder: &mut AppBuilder) {
+// The sample count at startup, the HUD switches it at runtime. TYPE_1 disables MSAA. Unsupported
+// counts fall back to the highest the device supports.
+const MSAA_SAMPLES: vk::SampleCountFlags = vk::SampleCountFlags::TYPE_4;
+
+// Whether the scene starts out rendered in half floats until it is tonemapped, keeping
+// highlights brighter than white for bloom. The HUD switches it at runtime. Falls back to 8-bit
+// if the device can't render and store the format.
+const HDR: bool = false;
+
+/// Sets up rendering with `frames_in_flight` frames recorded ahead of the GPU, at least 1.
+///
+/// More frames in flight can smooth out pacing on high refresh displays at the cost of latency.
+pub fn setup_render_preset(app_builder: &mut AppBuilder, frames_in_flight: usize) {
     setup_render_manager(
         app_builder,
-        &RenderManagerConfig::builder().frames_in_flight(2).build(),
+        &RenderManagerConfig::builder()
+            .frames_in_flight(frames_in_flight.max(1))
+            .build(),
     );
 
+    setup_device_lost(app_builder);
     setup_watched_shaders(app_builder);
-    setup_render_pipeline(app_builder);
+    setup_lighting(app_builder);
+    setup_time_control(app_builder);
+    setup_frame_pacing(app_builder);
+    setup_frame_stats(app_builder);
+    setup_debug_labels(app_builder);
+    setup_loading_screen(app_builder);
+    setup_render_pipeline(app_builder, MSAA_SAMPLES, HDR);
+    setup_light_gizmo(app_builder);
+    setup_particle_system(app_builder);
     setup_post_processing(app_builder);
+    setup_hud(app_builder);
+    setup_depth_pyramid(app_builder);
 }
@@ -0,0 +1,625 @@
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use ash::vk;
+use nalgebra::Vector3;
+use pyrite::{
+    prelude::{AppBuilder, Assets, Res, ResMut, Resource, Time},
+    render::render_manager::RenderManager,
+    vulkan::{
+        AttachmentInfo, BufferInfo, ComputePipeline, ComputePipelineInfo, DescriptorSet,
+        DescriptorSetLayout, GraphicsPipeline, GraphicsPipelineInfo, RenderPass, Shader, Subpass,
+        UntypedBuffer, Vulkan, VulkanAllocator,
+    },
+};
+
+use super::{
+    render::RenderPipeline,
+    shell::ShellRenderer,
+    watched_shaders::{DependencySignal, WatchedShaders},
+};
+
+pub fn setup_particle_system(app_builder: &mut AppBuilder) {
+    let particle_system = ParticleSystem::new(
+        &*app_builder.get_resource::<Vulkan>(),
+        &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
+        &mut *app_builder.get_resource_mut::<Assets>(),
+        &mut *app_builder.get_resource_mut::<WatchedShaders>(),
+        &*app_builder.get_resource::<RenderPipeline>(),
+    );
+    app_builder.add_resource(particle_system);
+    app_builder.add_system(ParticleSystem::update_system);
+}
+
+const UPDATE_FILE_PATH: &str = "shaders/particles.comp";
+const VERTEX_FILE_PATH: &str = "shaders/particles.vert";
+const FRAGMENT_FILE_PATH: &str = "shaders/particles.frag";
+const UPDATE_NAME: &str = "particles_comp";
+const VERTEX_NAME: &str = "particles_vert";
+const FRAGMENT_NAME: &str = "particles_frag";
+
+/// The most particles alive at once, spawning more replaces the oldest.
+pub const MAX_PARTICLES: u32 = 1024;
+// Must match the local size in particles.comp.
+const UPDATE_GROUP_SIZE: u32 = 64;
+
+// How long a particle falls for before it has faded out, in seconds. Each particle lives
+// between 0.75 and 1.25 times this.
+const PARTICLE_LIFETIME: f32 = 1.5;
+const MAX_PARTICLE_LIFETIME: f32 = PARTICLE_LIFETIME * 1.25;
+// The diameter of the point sprites in pixels.
+const PARTICLE_SIZE: f32 = 6.0;
+
+// Must match the particle struct in particles.comp and the vertex attributes.
+#[repr(C)]
+struct Particle {
+    position: [f32; 3],
+    age: f32,
+    velocity: [f32; 3],
+    // Dead once the age reaches this, a zeroed particle is dead.
+    lifetime: f32,
+}
+
+#[repr(C)]
+struct UpdatePushConstants {
+    spawn_position: [f32; 3],
+    delta_time: f32,
+    gravity: [f32; 3],
+    // The first particle slot replaced this frame, the count after it wraps around the buffer.
+    spawn_start: u32,
+    spawn_count: u32,
+    seed: u32,
+    lifetime: f32,
+}
+
+#[repr(C)]
+struct DrawPushConstants {
+    color: [f32; 3],
+    size: f32,
+}
+
+// The particles spawned in a frame, merged from every spawn_particles call made during it.
+#[derive(Clone, Copy)]
+struct ParticleSpawn {
+    position: Vector3<f32>,
+    start: u32,
+    count: u32,
+}
+
+/// Shed fur falling away from the mesh, simulated by a compute shader and drawn as point
+/// sprites over the shell output.
+///
+/// The particles live in a fixed size ring buffer on the GPU, so spawning never allocates and
+/// the oldest particles are reused first.
+#[derive(Resource)]
+pub struct ParticleSystem {
+    shader_dependency_signal: DependencySignal,
+    update_pipeline: Option<ComputePipeline>,
+    draw_pipeline: Option<GraphicsPipeline>,
+    // The backbuffer generation of the shell resolve image the render pass was built for.
+    backbuffer_generation: u32,
+
+    descriptor_set_layout: DescriptorSetLayout,
+    descriptor_set: DescriptorSet,
+    particle_buffer: Arc<UntypedBuffer>,
+    // The buffer starts out undefined, it is zeroed before the first update.
+    clear_pending: AtomicBool,
+
+    gravity: Vector3<f32>,
+    // The next ring buffer slot to spawn into.
+    next_particle: u32,
+    pending_spawn: Option<ParticleSpawn>,
+    // What this frame's update spawns, taken from pending_spawn by the update system.
+    frame_spawn: Option<ParticleSpawn>,
+    delta_time: f32,
+    // Varies the spawned particles from frame to frame.
+    frame_seed: u32,
+    // How long until every spawned particle is dead, nothing is updated or drawn after that.
+    active_time: f32,
+}
+
+impl ParticleSystem {
+    fn new(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        assets: &mut Assets,
+        watched_shaders: &mut WatchedShaders,
+        render_pipeline: &RenderPipeline,
+    ) -> Self {
+        let shader_dependency_signal = watched_shaders.create_dependency_signal();
+        watched_shaders.load_shader(
+            assets,
+            UPDATE_FILE_PATH,
+            UPDATE_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            VERTEX_FILE_PATH,
+            VERTEX_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            FRAGMENT_FILE_PATH,
+            FRAGMENT_NAME,
+            &shader_dependency_signal,
+        );
+
+        let descriptor_set_layout = DescriptorSetLayout::new(
+            vulkan,
+            &[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()],
+        );
+
+        // Written by the update and read as the vertex buffer of the sprites.
+        let particle_buffer = Arc::new(UntypedBuffer::new(
+            vulkan,
+            vulkan_allocator,
+            &BufferInfo::builder()
+                .size(MAX_PARTICLES as u64 * std::mem::size_of::<Particle>() as u64)
+                .usage(
+                    vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::VERTEX_BUFFER
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                )
+                .build(),
+        ));
+
+        let descriptor_set = render_pipeline
+            .descriptor_pool()
+            .allocate_descriptor_sets(&descriptor_set_layout, 1)
+            .pop()
+            .unwrap();
+        descriptor_set
+            .write()
+            .set_storage_buffer(0, &particle_buffer)
+            .submit_writes();
+
+        Self {
+            shader_dependency_signal,
+            update_pipeline: None,
+            draw_pipeline: None,
+            backbuffer_generation: render_pipeline.backbuffer_generation(),
+            descriptor_set_layout,
+            descriptor_set,
+            particle_buffer,
+            clear_pending: AtomicBool::new(true),
+            gravity: Vector3::new(0.0, -3.0, 0.0),
+            next_particle: 0,
+            pending_spawn: None,
+            frame_spawn: None,
+            delta_time: 0.0,
+            frame_seed: 0,
+            active_time: 0.0,
+        }
+    }
+
+    /// Spawns `count` particles at the world space `position` next frame, reusing the oldest
+    /// particles once `MAX_PARTICLES` are alive.
+    ///
+    /// Spawns made in the same frame are merged at the latest position.
+    pub fn spawn_particles(&mut self, position: Vector3<f32>, count: u32) {
+        if count == 0 {
+            return;
+        }
+
+        let start = self
+            .pending_spawn
+            .map_or(self.next_particle, |spawn| spawn.start);
+        let count = self
+            .pending_spawn
+            .map_or(0, |spawn| spawn.count)
+            .saturating_add(count)
+            .min(MAX_PARTICLES);
+        self.pending_spawn = Some(ParticleSpawn {
+            position,
+            start,
+            count,
+        });
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
+        self.gravity = gravity;
+    }
+
+    pub fn gravity(&self) -> Vector3<f32> {
+        self.gravity
+    }
+
+    pub fn backbuffer_generation(&self) -> u32 {
+        self.backbuffer_generation
+    }
+
+    /// Steps the particles and draws them over the resolved shell image, does nothing while
+    /// none are alive or the pipelines aren't ready.
+    pub fn render(
+        &self,
+        vulkan: &Vulkan,
+        render_manager: &mut RenderManager,
+        render_pipeline: &RenderPipeline,
+        shell_renderer: &ShellRenderer,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        if self.active_time <= 0.0 {
+            return vec![];
+        }
+        let (Some(update_pipeline), Some(draw_pipeline)) =
+            (&self.update_pipeline, &self.draw_pipeline)
+        else {
+            return vec![];
+        };
+
+        let particle_buffer_barrier = |src_access, dst_access| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.particle_buffer.buffer())
+                .size(vk::WHOLE_SIZE)
+                .build()
+        };
+
+        if self.clear_pending.swap(false, Ordering::AcqRel) {
+            // Safety: The buffer is kept alive by the returned deps.
+            unsafe {
+                vulkan.device().cmd_fill_buffer(
+                    render_manager.frame().command_buffer().command_buffer(),
+                    self.particle_buffer.buffer(),
+                    0,
+                    vk::WHOLE_SIZE,
+                    0,
+                );
+            }
+            render_manager.frame().command_buffer().pipeline_barrier(
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[particle_buffer_barrier(
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                )],
+                &[],
+            );
+        } else {
+            // The previous frame's sprites read the particles this update overwrites.
+            render_manager.frame().command_buffer().pipeline_barrier(
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[particle_buffer_barrier(
+                    vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                )],
+                &[],
+            );
+        }
+
+        let spawn = self.frame_spawn.unwrap_or(ParticleSpawn {
+            position: Vector3::zeros(),
+            start: 0,
+            count: 0,
+        });
+        let command_buffer = render_manager.frame_mut().command_buffer_mut();
+        command_buffer.bind_compute_pipeline(update_pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            update_pipeline.pipeline_layout(),
+            &[&self.descriptor_set],
+        );
+        command_buffer.write_push_constants_typed(
+            update_pipeline.pipeline_layout(),
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &UpdatePushConstants {
+                spawn_position: spawn.position.into(),
+                delta_time: self.delta_time,
+                gravity: self.gravity.into(),
+                spawn_start: spawn.start,
+                spawn_count: spawn.count,
+                seed: self.frame_seed,
+                lifetime: PARTICLE_LIFETIME,
+            },
+        );
+        command_buffer.dispatch_compute(
+            (MAX_PARTICLES + UPDATE_GROUP_SIZE - 1) / UPDATE_GROUP_SIZE,
+            1,
+            1,
+        );
+
+        let resolve_image = shell_renderer.resolve_image();
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D {
+                width: resolve_image.image_extent().width,
+                height: resolve_image.image_extent().height,
+            },
+        };
+
+        // Wait for the update and for the shell pass to finish writing the resolve image.
+        render_manager.frame().command_buffer().pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[particle_buffer_barrier(
+                vk::AccessFlags::SHADER_WRITE,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            )],
+            &[resolve_image.image_memory_barrier(
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )],
+        );
+
+        render_manager
+            .frame()
+            .command_buffer()
+            .dynamic_state_viewport(
+                vk::Viewport::builder()
+                    .width(resolve_image.image_extent().width as f32)
+                    .height(resolve_image.image_extent().height as f32)
+                    .min_depth(0.0)
+                    .max_depth(1.0)
+                    .build(),
+            );
+        render_manager
+            .frame()
+            .command_buffer()
+            .dynamic_state_scissor(render_area);
+        render_manager
+            .frame_mut()
+            .command_buffer_mut()
+            .bind_graphics_pipeline(draw_pipeline);
+
+        let descriptor_sets = [render_pipeline.frame(render_manager).descriptor_set()];
+        render_manager
+            .frame_mut()
+            .command_buffer_mut()
+            .bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                draw_pipeline.pipeline_layout(),
+                &descriptor_sets,
+            );
+
+        render_manager.frame().command_buffer().begin_render_pass(
+            draw_pipeline.render_pass(),
+            render_area,
+            &[],
+        );
+
+        // Shed from the tips, so they take the tip color.
+        let (_, tip_color) = shell_renderer.colors();
+        render_manager
+            .frame()
+            .command_buffer()
+            .write_push_constants_typed(
+                draw_pipeline.pipeline_layout(),
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &DrawPushConstants {
+                    color: tip_color,
+                    size: PARTICLE_SIZE,
+                },
+            );
+        render_manager
+            .frame_mut()
+            .command_buffer_mut()
+            .bind_vertex_buffer(0, &self.particle_buffer);
+        render_manager
+            .frame()
+            .command_buffer()
+            .draw(MAX_PARTICLES, 1, 0, 0);
+
+        render_manager.frame().command_buffer().end_render_pass();
+
+        vec![
+            self.particle_buffer.clone(),
+            self.descriptor_set.create_dep(),
+            resolve_image.create_dep(),
+        ]
+    }
+
+    fn refresh_pipeline(
+        &mut self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        render_pipeline: &RenderPipeline,
+        shell_renderer: &ShellRenderer,
+    ) {
+        watched_shaders.check_push_constant_size(
+            UPDATE_NAME,
+            std::mem::size_of::<UpdatePushConstants>(),
+            true,
+        );
+        watched_shaders.check_push_constant_size(
+            VERTEX_NAME,
+            std::mem::size_of::<DrawPushConstants>(),
+            true,
+        );
+
+        self.update_pipeline = Some(ComputePipeline::new(
+            vulkan,
+            ComputePipelineInfo::builder()
+                .shader(Shader::new(
+                    vulkan,
+                    &watched_shaders.get_shader(UPDATE_NAME).unwrap(),
+                ))
+                .descriptor_set_layouts(vec![&self.descriptor_set_layout])
+                .push_constant_ranges(vec![vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .size(std::mem::size_of::<UpdatePushConstants>() as u32)
+                    .build()])
+                .build(),
+        ));
+
+        let mut subpass = Subpass::new();
+        subpass.color_attachment(
+            &shell_renderer.resolve_image().as_attachment(
+                AttachmentInfo::default()
+                    .load_op(vk::AttachmentLoadOp::LOAD)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .initial_layout(vk::ImageLayout::GENERAL)
+                    .final_layout(vk::ImageLayout::GENERAL),
+            ),
+        );
+
+        let render_pass = RenderPass::new(vulkan, &[subpass]);
+
+        let vertex_shader = Shader::new(vulkan, &watched_shaders.get_shader(VERTEX_NAME).unwrap());
+        let fragment_shader =
+            Shader::new(vulkan, &watched_shaders.get_shader(FRAGMENT_NAME).unwrap());
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        // Each particle is one point, its position and age then its velocity and lifetime.
+        let vertex_input_binding_descriptions = [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<Particle>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+        let vertex_input_attribute_descriptions = [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(16)
+                .build(),
+        ];
+
+        let graphics_pipeline = GraphicsPipeline::new(
+            vulkan,
+            GraphicsPipelineInfo::builder()
+                .vertex_shader(vertex_shader)
+                .fragment_shader(fragment_shader)
+                .vertex_input_state(
+                    vk::PipelineVertexInputStateCreateInfo::builder()
+                        .vertex_binding_descriptions(&vertex_input_binding_descriptions)
+                        .vertex_attribute_descriptions(&vertex_input_attribute_descriptions)
+                        .build(),
+                )
+                .input_assembly_state(
+                    vk::PipelineInputAssemblyStateCreateInfo::builder()
+                        .topology(vk::PrimitiveTopology::POINT_LIST)
+                        .primitive_restart_enable(false)
+                        .build(),
+                )
+                .rasterization_state(
+                    vk::PipelineRasterizationStateCreateInfo::builder()
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .line_width(1.0)
+                        .build(),
+                )
+                .viewport_state(
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewports(&[])
+                        .viewport_count(1)
+                        .scissors(&[])
+                        .scissor_count(1)
+                        .build(),
+                )
+                .color_blend_state(
+                    vk::PipelineColorBlendStateCreateInfo::builder()
+                        .logic_op(vk::LogicOp::CLEAR)
+                        .attachments(&[vk::PipelineColorBlendAttachmentState::builder()
+                            .blend_enable(true)
+                            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                            .color_blend_op(vk::BlendOp::ADD)
+                            // Add to the coverage so the sky is composited under them.
+                            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                            .alpha_blend_op(vk::BlendOp::ADD)
+                            .color_write_mask(vk::ColorComponentFlags::RGBA)
+                            .build()])
+                        .build(),
+                )
+                // There's no single sample depth of the scene to test against, the particles
+                // are small and short lived enough to draw on top.
+                .depth_stencil_state(
+                    vk::PipelineDepthStencilStateCreateInfo::builder()
+                        .depth_test_enable(false)
+                        .depth_write_enable(false)
+                        .build(),
+                )
+                .multisample_state(
+                    vk::PipelineMultisampleStateCreateInfo::builder()
+                        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                        .build(),
+                )
+                .dynamic_state(
+                    vk::PipelineDynamicStateCreateInfo::builder()
+                        .dynamic_states(&dynamic_states)
+                        .build(),
+                )
+                .descriptor_set_layout(render_pipeline.descriptor_set_layout())
+                .push_constant_ranges(vec![vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    offset: 0,
+                    size: std::mem::size_of::<DrawPushConstants>() as u32,
+                }])
+                .render_pass(render_pass)
+                .build(),
+        );
+
+        self.draw_pipeline = Some(graphics_pipeline);
+    }
+
+    fn update_system(
+        mut particle_system: ResMut<ParticleSystem>,
+        vulkan: Res<Vulkan>,
+        watched_shaders: Res<WatchedShaders>,
+        render_pipeline: Res<RenderPipeline>,
+        shell_renderer: Res<ShellRenderer>,
+        time: Res<Time>,
+    ) {
+        let particle_system = &mut *particle_system;
+
+        // The render pass targets the shell resolve image, so rebuild it when that is recreated.
+        let resized =
+            particle_system.backbuffer_generation != shell_renderer.backbuffer_generation();
+        particle_system.backbuffer_generation = shell_renderer.backbuffer_generation();
+
+        if watched_shaders.is_dependency_signaled(&particle_system.shader_dependency_signal)
+            || (resized && particle_system.draw_pipeline.is_some())
+        {
+            particle_system.refresh_pipeline(
+                &*vulkan,
+                &*watched_shaders,
+                &*render_pipeline,
+                &*shell_renderer,
+            );
+        }
+
+        particle_system.delta_time = time.delta().as_secs_f32();
+        particle_system.active_time -= particle_system.delta_time;
+        particle_system.frame_seed = particle_system.frame_seed.wrapping_add(1);
+        particle_system.frame_spawn = particle_system.pending_spawn.take();
+        if let Some(spawn) = particle_system.frame_spawn {
+            particle_system.next_particle = (spawn.start + spawn.count) % MAX_PARTICLES;
+            particle_system.active_time = MAX_PARTICLE_LIFETIME;
+        }
+    }
+}
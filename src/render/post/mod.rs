@@ -1,22 +1,30 @@
 use std::{any::Any, ops::Deref, sync::Arc};
 
+use self::pass::{create_pass_image, PostPass, PostPassImages};
+
 use ash::vk;
+use nalgebra::Vector3;
 use pyrite::{
-    prelude::{AppBuilder, Assets, Res, ResMut, Resource},
+    prelude::{AppBuilder, Assets, Input, Key, Res, ResMut, Resource},
     render::render_manager::{self, RenderManager},
     vulkan::{
-        CommandBuffer, ComputePipeline, ComputePipelineInfo, DescriptorSet, DescriptorSetLayout,
-        Image, ImageDep, ImageInfo, InternalImage, Sampler, SamplerInfo, Shader, Vulkan,
-        VulkanAllocator,
+        BufferInfo, CommandBuffer, DescriptorSetLayout, Image, ImageDep, InternalImage, Sampler,
+        StageType, UntypedBuffer, Vulkan, VulkanAllocator, VulkanStager,
     },
 };
 
 use super::{
-    render::RenderPipeline,
+    camera::Camera,
+    device_lost::check_device_lost,
+    readback::{read_image_pixels, ReadbackPixels},
+    render::{RenderPipeline, HDR_COLOR_FORMAT, LDR_COLOR_FORMAT},
+    sampler::SamplerSettings,
     shell::ShellRenderer,
     watched_shaders::{self, DependencySignal, WatchedShaders},
 };
 
+mod pass;
+
 pub fn setup_post_processing(app_builder: &mut AppBuilder) {
     let post_processing = {
         let in_image = {
@@ -27,15 +35,21 @@ pub fn setup_post_processing(app_builder: &mut AppBuilder) {
             .get_resource::<RenderPipeline>()
             .backbuffer_depth_image()
             .create_dep();
+        let in_normal_image = app_builder
+            .get_resource::<ShellRenderer>()
+            .shell_normal_image()
+            .create_dep();
         PostProcessing::new(
             &*app_builder.get_resource::<Vulkan>(),
             &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
+            &mut *app_builder.get_resource_mut::<VulkanStager>(),
             &*app_builder.get_resource::<RenderManager>(),
             &*app_builder.get_resource::<RenderPipeline>(),
             &mut *app_builder.get_resource_mut::<Assets>(),
             &mut *app_builder.get_resource_mut::<WatchedShaders>(),
             in_image,
             in_depth_image,
+            in_normal_image,
         )
     };
     app_builder.add_resource(post_processing);
@@ -43,59 +57,274 @@ pub fn setup_post_processing(app_builder: &mut AppBuilder) {
     app_builder.add_system(PostProcessing::update_system);
 }
 
+/// The setting a post pass is switched on and off by.
+#[derive(Clone, Copy, PartialEq)]
+enum PostEffect {
+    Always,
+    Ssao,
+    Dof,
+    Fxaa,
+    MotionBlur,
+}
+
+struct PostPassConfig {
+    shader_path: &'static str,
+    shader_name: &'static str,
+    effect: PostEffect,
+    // Whether the passes after this composite onto its output instead of the shell output.
+    outputs_scene: bool,
+    // Whether this pass tonemaps the scene into the 8-bit output, the passes before it read
+    // and write the scene color format. They are compiled with HDR defined while the scene is
+    // HDR, which declares their scene images as rgba16f.
+    tonemaps: bool,
+    // Whether the shader samples the scene depth, it is compiled with SINGLE_SAMPLED defined
+    // when MSAA is disabled since a single sampled depth image can't be bound as a sampler2DMS.
+    reads_depth: bool,
+}
+
+impl PostPassConfig {
+    const fn new(shader_path: &'static str, shader_name: &'static str) -> Self {
+        Self {
+            shader_path,
+            shader_name,
+            effect: PostEffect::Always,
+            outputs_scene: false,
+            tonemaps: false,
+            reads_depth: false,
+        }
+    }
+
+    const fn effect(mut self, effect: PostEffect) -> Self {
+        self.effect = effect;
+        self
+    }
+
+    const fn outputs_scene(mut self) -> Self {
+        self.outputs_scene = true;
+        self
+    }
+
+    const fn tonemaps(mut self) -> Self {
+        self.tonemaps = true;
+        self
+    }
+
+    const fn reads_depth(mut self) -> Self {
+        self.reads_depth = true;
+        self
+    }
+
+    /// `reads_hdr` is whether the scene this pass reads is HDR, see `reads_hdr`.
+    fn defines(&self, msaa_samples: vk::SampleCountFlags, reads_hdr: bool) -> Vec<String> {
+        let mut defines = Vec::new();
+        if self.reads_depth && msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            defines.push("SINGLE_SAMPLED".to_string());
+        }
+        if reads_hdr {
+            defines.push("HDR".to_string());
+        }
+        defines
+    }
+}
+
+/// Whether the pass at `index` reads the scene color in HDR_COLOR_FORMAT, which is every pass
+/// up to and including the one that tonemaps while the scene is HDR.
+fn reads_hdr(index: usize, hdr: bool) -> bool {
+    hdr && POST_PASSES[..index].iter().all(|config| !config.tonemaps)
+}
+
+/// The format of the output of the pass at `index`, the scene format until it is tonemapped.
+fn output_format(index: usize, hdr: bool) -> vk::Format {
+    if reads_hdr(index, hdr) && !POST_PASSES[index].tonemaps {
+        HDR_COLOR_FORMAT
+    } else {
+        LDR_COLOR_FORMAT
+    }
+}
+
+// The passes run in this order, each reading the output of the enabled pass before it.
+// SSAO writes the occlusion then blurs it onto the scene, so bloom composites onto the
+// occluded scene. Bloom is a bright pass followed by a separable blur that composites back
+// onto the scene. Depth of field blurs the composited scene before it is tonemapped. Motion
+// blur goes last so it accumulates the finished frames.
+const POST_PASSES: [PostPassConfig; 9] = [
+    PostPassConfig::new("shaders/ssao.comp", "ssao_comp")
+        .effect(PostEffect::Ssao)
+        .reads_depth(),
+    PostPassConfig::new("shaders/ssao_blur.comp", "ssao_blur_comp")
+        .effect(PostEffect::Ssao)
+        .outputs_scene(),
+    PostPassConfig::new("shaders/bloom_threshold.comp", "bloom_threshold_comp"),
+    PostPassConfig::new("shaders/bloom_blur_h.comp", "bloom_blur_h_comp"),
+    PostPassConfig::new("shaders/bloom_blur_v.comp", "bloom_blur_v_comp"),
+    PostPassConfig::new("shaders/dof.comp", "dof_comp")
+        .effect(PostEffect::Dof)
+        .reads_depth(),
+    PostPassConfig::new("shaders/post.comp", "post_comp")
+        .tonemaps()
+        .reads_depth(),
+    PostPassConfig::new("shaders/fxaa.comp", "fxaa_comp").effect(PostEffect::Fxaa),
+    PostPassConfig::new("shaders/motion_blur.comp", "motion_blur_comp")
+        .effect(PostEffect::MotionBlur),
+];
+
+// The motion blur amount F4 toggles on.
+const DEFAULT_MOTION_BLUR: f32 = 0.6;
+// Keeping all of the previous frame would freeze the image.
+const MAX_MOTION_BLUR: f32 = 0.95;
+
+// Must match the sample counts in ssao.glsl.
+const SSAO_KERNEL_SIZE: usize = 16;
+const SSAO_NOISE_SIZE: usize = 16;
+
+// Must match the SSAO uniform in ssao.glsl, each sample is a vec4 in std140.
+#[repr(C)]
+struct SsaoUniform {
+    // Offsets in the hemisphere around +Z, denser towards the center.
+    kernel: [[f32; 4]; SSAO_KERNEL_SIZE],
+    // Random rotations of the kernel around the normal, tiled over 4x4 pixels.
+    noise: [[f32; 4]; SSAO_NOISE_SIZE],
+}
+
+/// A debug visualization the final pass shows in place of the scene.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugView {
+    /// The post processed scene.
+    None = 0,
+    /// Linearized depth as grayscale, white is near.
+    Depth = 1,
+    /// View space normals of the shells mapped from [-1, 1] to color.
+    Normals = 2,
+    /// How far up the fur each pixel's shell is, black at the base and white at the tips.
+    ShellHeight = 3,
+}
+
+impl DebugView {
+    fn next(self) -> Self {
+        match self {
+            DebugView::None => DebugView::Depth,
+            DebugView::Depth => DebugView::Normals,
+            DebugView::Normals => DebugView::ShellHeight,
+            DebugView::ShellHeight => DebugView::None,
+        }
+    }
+
+    /// Whether the view is read from the shell pass's G-buffer.
+    fn reads_gbuffer(self) -> bool {
+        matches!(self, DebugView::Normals | DebugView::ShellHeight)
+    }
+}
+
+#[repr(C)]
 struct PushConstants {
     width: u32,
     height: u32,
+    // Scales the scene color before tonemapping.
+    exposure: f32,
+    // The sky gradient colors packed as RGBA8, they fill the padding before the vec3s which
+    // are 16 byte aligned in GLSL.
+    sky_top: u32,
+    fog_color: [f32; 3],
+    // How quickly the fog thickens with distance, 0 disables it.
+    fog_density: f32,
+    // The camera clipping planes, used to linearize the depth.
+    near: f32,
+    far: f32,
+    // How much the corners are darkened, 0 disables the vignette.
+    vignette_strength: f32,
+    sky_bottom: u32,
+    // The lift, gamma and gain of the color grade, each vec3 is padded to 16 bytes.
+    lift: [f32; 3],
+    // 1 draws the sky gradient where the scene doesn't cover the pixel, packed after lift.
+    sky: u32,
+    gamma: [f32; 3],
+    _gamma_padding: f32,
+    gain: [f32; 3],
+    // The DebugView shown by the final pass, packed after gain to fill its padding.
+    debug_view: u32,
+    // The view space distance SSAO looks for occluders within.
+    ssao_radius: f32,
+    // How much the occlusion darkens the scene.
+    ssao_intensity: f32,
+    // Pixels per view space unit at a distance of 1, half the height times the projection's
+    // Y scale. SSAO rebuilds view space positions from the depth with it.
+    projection_scale: f32,
+    // The view space distance depth of field keeps sharp.
+    focus_distance: f32,
+    // How quickly the depth of field blur grows away from the focus distance.
+    aperture: f32,
+    // The fraction of the previous frame blended into this one.
+    motion_blur: f32,
+    // 1 when the depth is reverse-Z, the depth reads flip to match.
+    reverse_z: u32,
 }
 
 /// The post processor is responsible for setting up the different pipeline effects.
 #[derive(Resource)]
 pub struct PostProcessing {
-    pipeline: Option<ComputePipeline>,
     shader_dependency_signal: DependencySignal,
     in_image: ImageDep,
     in_depth_image: ImageDep,
-    out_image: Image,
+    in_normal_image: ImageDep,
+    // The backbuffer generation of the shell resolve image the passes read from.
+    backbuffer_generation: u32,
+    // The sample count the depth reading passes were compiled for.
+    msaa_samples: vk::SampleCountFlags,
+    // Whether the passes up to tonemapping were compiled for and output the HDR scene format.
+    hdr: bool,
+    // One pass per entry in POST_PASSES, the last enabled pass writes the final output image.
+    passes: Vec<PostPass>,
+    // Set when a pass is switched on or off, so the chain is rebound around it.
+    chain_dirty: bool,
+    // Counts the times the pass outputs were recreated, for render passes targeting the output.
+    output_generation: u32,
     descriptor_set_layout: DescriptorSetLayout,
     depth_sampler: Sampler,
-    descriptor_set: DescriptorSet,
+    depth_sampler_settings: SamplerSettings,
+    // Set when the depth sampler settings change, it is recreated with the pass outputs.
+    depth_sampler_dirty: bool,
+    // Whether to clear out_image before the compute pass, for effects that don't write every pixel.
+    clear_output: bool,
+    clear_color: [f32; 4],
+    exposure: f32,
+    fog_color: [f32; 3],
+    fog_density: f32,
+    fxaa: bool,
+    ssao: bool,
+    ssao_radius: f32,
+    ssao_intensity: f32,
+    ssao_buffer: Arc<UntypedBuffer>,
+    dof: bool,
+    focus_distance: f32,
+    aperture: f32,
+    motion_blur: f32,
+    // The previous frame's motion blur output, kept between frames unlike the pass outputs.
+    history_image: Image,
+    // Set when the history holds nothing useful, after it is recreated or blur is switched on.
+    history_reset: bool,
+    vignette_strength: f32,
+    lift: [f32; 3],
+    gamma: [f32; 3],
+    gain: [f32; 3],
+    debug_view: DebugView,
+    // The top and bottom colors of the sky, None leaves the clear color.
+    sky_gradient: Option<([f32; 3], [f32; 3])>,
 }
 
 impl PostProcessing {
     pub fn new(
         vulkan: &Vulkan,
         vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
         render_manager: &RenderManager,
         render_pipeline: &RenderPipeline,
         assets: &mut Assets,
         watched_shaders: &mut WatchedShaders,
         in_image: ImageDep,
         in_depth_image: ImageDep,
+        in_normal_image: ImageDep,
     ) -> Self {
-        let out_image = Image::new(
-            vulkan,
-            vulkan_allocator,
-            &ImageInfo::builder()
-                .extent(render_pipeline.backbuffer_image().image_extent())
-                .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
-                .format(vk::Format::R8G8B8A8_UNORM)
-                .view_subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .level_count(1)
-                        .build(),
-                )
-                .build(),
-        );
-
         let shader_dependency_signal = watched_shaders.create_dependency_signal();
-        watched_shaders.load_shader(
-            assets,
-            "shaders/post.comp",
-            "post_comp",
-            &shader_dependency_signal,
-        );
 
         let descriptor_set_layout = DescriptorSetLayout::new(
             vulkan,
@@ -118,94 +347,559 @@ impl PostProcessing {
                     .descriptor_count(1)
                     .stage_flags(vk::ShaderStageFlags::COMPUTE)
                     .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(3)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(4)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(5)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(6)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
             ],
         );
 
-        let depth_sampler = Sampler::new(vulkan, &SamplerInfo::builder().build());
-
-        let descriptor_set = render_pipeline
-            .descriptor_pool()
-            .allocate_descriptor_sets(&descriptor_set_layout, 1)
-            .pop()
-            .unwrap();
-
-        descriptor_set
-            .write()
-            .set_storage_image(0, in_image.clone())
-            .set_storage_image(1, out_image.create_dep())
-            .set_combined_image_sampler(
-                2,
-                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
-                in_depth_image.clone(),
-                &depth_sampler,
-            )
-            .submit_writes();
+        let depth_sampler = SamplerSettings::default().create(vulkan);
+
+        let ssao_buffer = Arc::new(UntypedBuffer::new(
+            vulkan,
+            vulkan_allocator,
+            &BufferInfo::builder()
+                .size(std::mem::size_of::<SsaoUniform>() as u64)
+                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                .build(),
+        ));
+        let ssao_uniform = create_ssao_uniform();
+        unsafe {
+            vulkan_stager.schedule_stage_buffer(
+                vulkan,
+                vulkan_allocator,
+                &ssao_uniform as *const SsaoUniform as *const u8,
+                std::mem::size_of::<SsaoUniform>() as u64,
+                &ssao_buffer,
+                StageType::Immediate,
+            );
+        }
+
+        // Motion blur runs after tonemapping, so the history is always 8-bit.
+        let history_image =
+            create_pass_image(vulkan, vulkan_allocator, render_pipeline, LDR_COLOR_FORMAT);
+
+        // SSAO, depth of field, FXAA and motion blur start disabled, so the chain skips them.
+        let mut passes: Vec<PostPass> = Vec::with_capacity(POST_PASSES.len());
+        let (mut input, mut scene) = (in_image.clone(), in_image.clone());
+        let hdr = render_pipeline.hdr();
+        for (index, config) in POST_PASSES.iter().enumerate() {
+            let pass = PostPass::new(
+                vulkan,
+                vulkan_allocator,
+                render_pipeline,
+                assets,
+                watched_shaders,
+                &shader_dependency_signal,
+                &descriptor_set_layout,
+                config.shader_path,
+                config.shader_name,
+                config.defines(render_pipeline.msaa_samples(), reads_hdr(index, hdr)),
+                output_format(index, hdr),
+                PostPassImages {
+                    input: input.clone(),
+                    scene: scene.clone(),
+                    depth: in_depth_image.clone(),
+                    depth_sampler: &depth_sampler,
+                    normal: in_normal_image.clone(),
+                    ssao_buffer: &ssao_buffer,
+                    history: history_image.create_dep(),
+                },
+            );
+            if config.effect == PostEffect::Always {
+                input = pass.output().create_dep();
+                if config.outputs_scene {
+                    scene = pass.output().create_dep();
+                }
+            }
+            passes.push(pass);
+        }
 
         Self {
-            pipeline: None,
             shader_dependency_signal,
             in_image,
             in_depth_image,
-            out_image,
+            in_normal_image,
+            backbuffer_generation: render_pipeline.backbuffer_generation(),
+            msaa_samples: render_pipeline.msaa_samples(),
+            hdr,
+            passes,
+            chain_dirty: false,
+            output_generation: 0,
             descriptor_set_layout,
             depth_sampler,
-            descriptor_set,
+            depth_sampler_settings: SamplerSettings::default(),
+            depth_sampler_dirty: false,
+            clear_output: false,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            exposure: 1.0,
+            fog_color: [0.3, 0.4, 0.68],
+            fog_density: 0.05,
+            fxaa: false,
+            ssao: false,
+            ssao_radius: 0.1,
+            ssao_intensity: 1.0,
+            ssao_buffer,
+            dof: false,
+            focus_distance: 3.0,
+            aperture: 0.5,
+            motion_blur: 0.0,
+            history_image,
+            history_reset: true,
+            vignette_strength: 0.0,
+            lift: [0.0; 3],
+            gamma: [1.0; 3],
+            gain: [1.0; 3],
+            debug_view: DebugView::None,
+            sky_gradient: None,
         }
     }
 
+    /// Enables clearing the output image before the compute pass runs.
+    pub fn set_clear_output(&mut self, clear_output: bool) {
+        self.clear_output = clear_output;
+    }
+
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+    }
+
+    /// Sets the exposure applied before ACES tonemapping in the final pass.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure.max(0.0);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Sets the exponential distance fog the scene fades into.
+    pub fn set_fog(&mut self, fog_color: [f32; 3], fog_density: f32) {
+        self.fog_color = fog_color;
+        self.fog_density = fog_density.max(0.0);
+    }
+
+    /// The fog color and density.
+    pub fn fog(&self) -> ([f32; 3], f32) {
+        (self.fog_color, self.fog_density)
+    }
+
+    /// Draws a vertical gradient from `top` to `bottom` behind the scene, showing through
+    /// wherever the scene's coverage leaves the pixel uncovered.
+    ///
+    /// The fog fades into the sky color behind each pixel instead of the fog color, so the mesh
+    /// blends into the sky with distance. The colors are applied after tonemapping like the fog
+    /// and are stored with 8 bits per channel.
+    pub fn set_sky_gradient(&mut self, top: [f32; 3], bottom: [f32; 3]) {
+        self.sky_gradient = Some((top, bottom));
+    }
+
+    /// Removes the sky gradient, the background is the clear color faded into the fog again.
+    pub fn clear_sky_gradient(&mut self) {
+        self.sky_gradient = None;
+    }
+
+    pub fn sky_gradient(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.sky_gradient
+    }
+
+    /// Darkens the corners of the frame, 0 disables the vignette and 1 makes the corners black.
+    pub fn set_vignette(&mut self, vignette_strength: f32) {
+        self.vignette_strength = vignette_strength.clamp(0.0, 1.0);
+    }
+
+    /// Sets the lift, gamma and gain color grade applied last, per channel.
+    ///
+    /// A lift of 0, gamma of 1 and gain of 1 leaves the image unchanged.
+    pub fn set_color_grade(&mut self, lift: [f32; 3], gamma: [f32; 3], gain: [f32; 3]) {
+        self.lift = lift;
+        // A gamma of 0 would divide by zero in the shader.
+        self.gamma = gamma.map(|gamma| gamma.max(0.01));
+        self.gain = gain;
+    }
+
+    /// Sets how the passes sample the shell depth, the sampler is recreated with the pass
+    /// outputs on the next update.
+    pub fn set_depth_sampler(&mut self, settings: SamplerSettings) {
+        if self.depth_sampler_settings != settings {
+            self.depth_sampler_settings = settings;
+            self.depth_sampler_dirty = true;
+            self.chain_dirty = true;
+        }
+    }
+
+    pub fn depth_sampler(&self) -> SamplerSettings {
+        self.depth_sampler_settings
+    }
+
+    /// Runs FXAA on the final image, a cheaper alternative to MSAA for smoothing edges.
+    pub fn set_fxaa(&mut self, fxaa: bool) {
+        if self.fxaa != fxaa {
+            self.fxaa = fxaa;
+            self.chain_dirty = true;
+        }
+    }
+
+    pub fn fxaa(&self) -> bool {
+        self.fxaa
+    }
+
+    /// Darkens creases in the scene with screen space ambient occlusion, using the normals of
+    /// the shell renderer's G-buffer, which is enabled along with it.
+    ///
+    /// `radius` is the view space distance occluders are searched within and `intensity`
+    /// scales the darkening. The passes are skipped entirely when disabled. The view space
+    /// positions are rebuilt assuming a perspective projection.
+    pub fn set_ssao(&mut self, enabled: bool, radius: f32, intensity: f32) {
+        if self.ssao != enabled {
+            self.ssao = enabled;
+            self.chain_dirty = true;
+        }
+        self.ssao_radius = radius.max(0.001);
+        self.ssao_intensity = intensity.max(0.0);
+    }
+
+    pub fn ssao(&self) -> bool {
+        self.ssao
+    }
+
+    /// Blurs the scene by how far it is from `focus_distance`, a view space distance.
+    ///
+    /// `aperture` scales how quickly the blur grows away from the focus, the blur radius is
+    /// capped at a few pixels either way. Has no effect until enabled with `set_dof_enabled`.
+    pub fn set_dof(&mut self, focus_distance: f32, aperture: f32) {
+        self.focus_distance = focus_distance.max(0.01);
+        self.aperture = aperture.max(0.0);
+    }
+
+    pub fn set_dof_enabled(&mut self, enabled: bool) {
+        if self.dof != enabled {
+            self.dof = enabled;
+            self.chain_dirty = true;
+        }
+    }
+
+    pub fn dof_enabled(&self) -> bool {
+        self.dof
+    }
+
+    /// Blends `amount` of the previous output into each frame, smearing anything moving across
+    /// the screen.
+    ///
+    /// This accumulates whole frames rather than blurring along per pixel velocities, so still
+    /// objects stay sharp only while the camera is still too. An amount of 0 skips the pass
+    /// entirely, the amount is capped below 1 so the image keeps updating.
+    pub fn set_motion_blur(&mut self, amount: f32) {
+        let amount = amount.clamp(0.0, MAX_MOTION_BLUR);
+        if (self.motion_blur > 0.0) != (amount > 0.0) {
+            self.chain_dirty = true;
+            // The history stopped updating while blur was off.
+            self.history_reset = true;
+        }
+        self.motion_blur = amount;
+    }
+
+    pub fn motion_blur(&self) -> f32 {
+        self.motion_blur
+    }
+
+    fn is_effect_enabled(&self, effect: PostEffect) -> bool {
+        match effect {
+            PostEffect::Always => true,
+            PostEffect::Ssao => self.ssao,
+            PostEffect::Dof => self.dof,
+            PostEffect::Fxaa => self.fxaa,
+            PostEffect::MotionBlur => self.motion_blur > 0.0,
+        }
+    }
+
+    /// Replaces the final image with a debug visualization, `DebugView::None` shows the scene.
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    // The passes recorded this frame, the passes of disabled effects are skipped.
+    fn active_passes(&self) -> Vec<&PostPass> {
+        self.passes
+            .iter()
+            .zip(&POST_PASSES)
+            .filter(|(_, config)| self.is_effect_enabled(config.effect))
+            .map(|(pass, _)| pass)
+            .collect()
+    }
+
     pub fn render(
-        &self,
+        &mut self,
+        vulkan: &Vulkan,
         command_buffer: &mut CommandBuffer,
         render_pipeline: &RenderPipeline,
+        camera: &Camera,
     ) -> Vec<Arc<dyn Any + Send + Sync>> {
-        if let Some(pipeline) = &self.pipeline {
+        if !self.is_ready() {
+            return vec![];
+        }
+
+        let (sky_top, sky_bottom) = self.sky_gradient.unwrap_or(([0.0; 3], [0.0; 3]));
+        let push_constants = PushConstants {
+            width: render_pipeline.backbuffer_image().image_extent().width,
+            height: render_pipeline.backbuffer_image().image_extent().height,
+            exposure: self.exposure,
+            sky_top: pack_unorm_color(sky_top),
+            fog_color: self.fog_color,
+            fog_density: self.fog_density,
+            near: camera.near(),
+            far: camera.far(),
+            vignette_strength: self.vignette_strength,
+            sky_bottom: pack_unorm_color(sky_bottom),
+            lift: self.lift,
+            sky: self.sky_gradient.is_some() as u32,
+            gamma: self.gamma,
+            _gamma_padding: 0.0,
+            gain: self.gain,
+            debug_view: self.debug_view as u32,
+            ssao_radius: self.ssao_radius,
+            ssao_intensity: self.ssao_intensity,
+            projection_scale: render_pipeline.backbuffer_image().image_extent().height as f32
+                / (2.0 * (camera.fov().to_radians() * 0.5).tan()),
+            focus_distance: self.focus_distance,
+            aperture: self.aperture,
+            // Blending in a stale or undefined history would flash the old frame.
+            motion_blur: if self.history_reset {
+                0.0
+            } else {
+                self.motion_blur
+            },
+            reverse_z: render_pipeline.reverse_z() as u32,
+        };
+
+        // The history is only touched by the motion blur pass, so it is made ready once up front.
+        if self.is_effect_enabled(PostEffect::MotionBlur) {
+            let (old_layout, src_access) = if self.history_reset {
+                (vk::ImageLayout::UNDEFINED, vk::AccessFlags::empty())
+            } else {
+                (vk::ImageLayout::GENERAL, vk::AccessFlags::SHADER_WRITE)
+            };
             command_buffer.pipeline_barrier(
-                vk::PipelineStageFlags::ALL_GRAPHICS,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
                 vk::PipelineStageFlags::COMPUTE_SHADER,
                 vk::DependencyFlags::empty(),
                 &[],
                 &[],
-                &[self.out_image.image_memory_barrier(
-                    vk::ImageLayout::UNDEFINED,
+                &[self.history_image.image_memory_barrier(
+                    old_layout,
                     vk::ImageLayout::GENERAL,
-                    vk::AccessFlags::empty(),
-                    vk::AccessFlags::SHADER_WRITE,
+                    src_access,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
                 )],
             );
+            self.history_reset = false;
+        }
 
-            command_buffer.bind_compute_pipeline(pipeline);
+        let passes = self.active_passes();
+        let last_index = passes.len() - 1;
+        for (index, pass) in passes.iter().enumerate() {
+            if index == last_index && self.clear_output {
+                self.clear_out_image(vulkan, command_buffer);
+            } else {
+                command_buffer.pipeline_barrier(
+                    vk::PipelineStageFlags::ALL_GRAPHICS | vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[pass.output().image_memory_barrier(
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::GENERAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_WRITE,
+                    )],
+                );
+            }
 
-            command_buffer.bind_descriptor_sets(
-                vk::PipelineBindPoint::COMPUTE,
-                pipeline.pipeline_layout(),
-                &[&self.descriptor_set],
-            );
+            pass.record(command_buffer, &push_constants);
+
+            // The next pass reads what this one wrote.
+            if index != last_index {
+                command_buffer.pipeline_barrier(
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[pass.output().image_memory_barrier(
+                        vk::ImageLayout::GENERAL,
+                        vk::ImageLayout::GENERAL,
+                        vk::AccessFlags::SHADER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    )],
+                );
+            }
+        }
+
+        let mut deps = self
+            .passes
+            .iter()
+            .flat_map(PostPass::deps)
+            .collect::<Vec<_>>();
+        deps.push(self.history_image.create_dep());
+        deps
+    }
+
+    /// Clears the output image to the clear color, leaving it in the general layout for the last pass.
+    fn clear_out_image(&self, vulkan: &Vulkan, command_buffer: &mut CommandBuffer) {
+        let out_image = self.output_image();
 
-            command_buffer.write_push_constants_typed(
-                pipeline.pipeline_layout(),
-                vk::ShaderStageFlags::COMPUTE,
-                0,
-                &PushConstants {
-                    width: render_pipeline.backbuffer_image().image_extent().width,
-                    height: render_pipeline.backbuffer_image().image_extent().height,
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::ALL_GRAPHICS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[out_image.image_memory_barrier(
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+            )],
+        );
+
+        unsafe {
+            vulkan.device().cmd_clear_color_image(
+                command_buffer.command_buffer(),
+                out_image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue {
+                    float32: self.clear_color,
                 },
+                &[vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build()],
             );
+        }
 
-            command_buffer.dispatch_compute(
-                render_pipeline.backbuffer_image().image_extent().width / 16,
-                render_pipeline.backbuffer_image().image_extent().height / 16,
-                1,
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[out_image.image_memory_barrier(
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_WRITE,
+            )],
+        );
+    }
+
+    pub fn backbuffer_generation(&self) -> u32 {
+        self.backbuffer_generation
+    }
+
+    /// Recreates every pass output at the backbuffer size and rebinds them to the new inputs,
+    /// chaining each pass to the enabled pass before it.
+    fn recreate_outputs(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        render_pipeline: &RenderPipeline,
+        shell_renderer: &ShellRenderer,
+    ) {
+        // Safety: Only waits, the descriptor sets rewritten below may still be read by frames in
+        // flight.
+        if let Err(err) = unsafe { vulkan.device().device_wait_idle() } {
+            check_device_lost(err, "waiting to recreate the post processing outputs");
+            println!(
+                "Failed to wait to recreate the post processing outputs: {}",
+                err
             );
         }
-        vec![]
+
+        if self.depth_sampler_dirty {
+            self.depth_sampler = self.depth_sampler_settings.create(vulkan);
+            self.depth_sampler_dirty = false;
+        }
+
+        self.in_image = shell_renderer.resolve_image().create_dep();
+        self.in_depth_image = render_pipeline.backbuffer_depth_image().create_dep();
+        self.in_normal_image = shell_renderer.shell_normal_image().create_dep();
+        // A resize leaves nothing to blend with, so the history starts over.
+        self.history_image =
+            create_pass_image(vulkan, vulkan_allocator, render_pipeline, LDR_COLOR_FORMAT);
+        self.history_reset = true;
+
+        let (mut input, mut scene) = (self.in_image.clone(), self.in_image.clone());
+        for (index, config) in POST_PASSES.iter().enumerate() {
+            let images = PostPassImages {
+                input: input.clone(),
+                scene: scene.clone(),
+                depth: self.in_depth_image.clone(),
+                depth_sampler: &self.depth_sampler,
+                normal: self.in_normal_image.clone(),
+                ssao_buffer: &self.ssao_buffer,
+                history: self.history_image.create_dep(),
+            };
+            self.passes[index].recreate_output(vulkan, vulkan_allocator, render_pipeline, images);
+            if self.is_effect_enabled(config.effect) {
+                input = self.passes[index].output().create_dep();
+                if config.outputs_scene {
+                    scene = self.passes[index].output().create_dep();
+                }
+            }
+        }
+
+        self.backbuffer_generation = shell_renderer.backbuffer_generation();
+        self.output_generation += 1;
+        self.chain_dirty = false;
     }
 
     pub fn is_ready(&self) -> bool {
-        self.pipeline.is_some()
+        self.passes.iter().all(PostPass::is_ready)
+    }
+
+    /// Changes whenever `output_image` may be a different image.
+    pub fn output_generation(&self) -> u32 {
+        self.output_generation
     }
 
     pub fn output_image(&self) -> &Image {
-        &self.out_image
+        let last_pass: &PostPass = *self.active_passes().last().unwrap();
+        last_pass.output()
+    }
+
+    /// Copies the last rendered output back to the CPU as RGBA pixels.
+    ///
+    /// This stalls the GPU until every frame in flight is done, it is meant for tooling like
+    /// captures and visual tests rather than the hot path.
+    pub fn read_output_pixels(&self, vulkan: &Vulkan) -> anyhow::Result<ReadbackPixels> {
+        read_image_pixels(vulkan, self.output_image())
     }
 
     fn refresh_pipeline(
@@ -215,21 +909,9 @@ impl PostProcessing {
         render_pipeline: &RenderPipeline,
         watched_shaders: &WatchedShaders,
     ) {
-        let pipeline = ComputePipeline::new(
-            vulkan,
-            ComputePipelineInfo::builder()
-                .shader(Shader::new(
-                    vulkan,
-                    &watched_shaders.get_shader("post_comp").unwrap(),
-                ))
-                .descriptor_set_layouts(vec![&self.descriptor_set_layout])
-                .push_constant_ranges(vec![vk::PushConstantRange::builder()
-                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
-                    .size(std::mem::size_of::<PushConstants>() as u32)
-                    .build()])
-                .build(),
-        );
-        self.pipeline = Some(pipeline);
+        for pass in &mut self.passes {
+            pass.refresh_pipeline(vulkan, watched_shaders, &self.descriptor_set_layout);
+        }
     }
 
     pub fn update_system(
@@ -237,9 +919,116 @@ impl PostProcessing {
         mut vulkan_allocator: ResMut<VulkanAllocator>,
         render_pipeline: Res<RenderPipeline>,
         mut post_processing: ResMut<PostProcessing>,
-        watched_shaders: Res<WatchedShaders>,
+        mut watched_shaders: ResMut<WatchedShaders>,
+        mut assets: ResMut<Assets>,
+        mut shell_renderer: ResMut<ShellRenderer>,
+        input: Res<Input>,
     ) {
-        if watched_shaders.is_dependency_signaled(&post_processing.shader_dependency_signal) {
+        if input.is_key_pressed(Key::V) {
+            let fxaa = !post_processing.fxaa;
+            post_processing.set_fxaa(fxaa);
+            println!("FXAA: {}", fxaa);
+        }
+
+        if input.is_key_pressed(Key::Q) {
+            let (ssao, radius, intensity) = (
+                !post_processing.ssao,
+                post_processing.ssao_radius,
+                post_processing.ssao_intensity,
+            );
+            post_processing.set_ssao(ssao, radius, intensity);
+            println!("SSAO: {}", ssao);
+        }
+
+        if input.is_key_pressed(Key::F8) {
+            let dof = !post_processing.dof;
+            post_processing.set_dof_enabled(dof);
+            println!("Depth of field: {}", dof);
+        }
+
+        if input.is_key_pressed(Key::F4) {
+            let motion_blur = if post_processing.motion_blur > 0.0 {
+                0.0
+            } else {
+                DEFAULT_MOTION_BLUR
+            };
+            post_processing.set_motion_blur(motion_blur);
+            println!("Motion blur: {}", motion_blur);
+        }
+
+        // SSAO and the G-buffer debug views read the normals, which the shell pass only writes
+        // with the G-buffer on.
+        if (post_processing.ssao || post_processing.debug_view.reads_gbuffer())
+            && !shell_renderer.gbuffer()
+        {
+            shell_renderer.set_gbuffer(true);
+        }
+
+        if input.is_key_pressed(Key::Y) {
+            let debug_view = post_processing.debug_view.next();
+            post_processing.set_debug_view(debug_view);
+            println!("Debug view: {:?}", debug_view);
+        }
+
+        // The depth is bound differently without MSAA, so recompile the passes reading it.
+        if post_processing.msaa_samples != render_pipeline.msaa_samples() {
+            post_processing.msaa_samples = render_pipeline.msaa_samples();
+            let hdr = post_processing.hdr;
+            for (index, (pass, config)) in post_processing
+                .passes
+                .iter_mut()
+                .zip(&POST_PASSES)
+                .enumerate()
+            {
+                if config.reads_depth {
+                    pass.set_defines(
+                        &mut *assets,
+                        &mut *watched_shaders,
+                        config.defines(render_pipeline.msaa_samples(), reads_hdr(index, hdr)),
+                    );
+                }
+            }
+        }
+
+        // The passes up to tonemapping declare the scene format, so recompile them and switch
+        // their outputs when it changes. The outputs are recreated below with the backbuffer.
+        if post_processing.hdr != render_pipeline.hdr() {
+            post_processing.hdr = render_pipeline.hdr();
+            let hdr = post_processing.hdr;
+            for (index, (pass, config)) in post_processing
+                .passes
+                .iter_mut()
+                .zip(&POST_PASSES)
+                .enumerate()
+            {
+                if reads_hdr(index, true) {
+                    pass.set_output_format(output_format(index, hdr));
+                    pass.set_defines(
+                        &mut *assets,
+                        &mut *watched_shaders,
+                        config.defines(render_pipeline.msaa_samples(), reads_hdr(index, hdr)),
+                    );
+                }
+            }
+            post_processing.chain_dirty = true;
+        }
+
+        // The passes read the shell resolve image, so follow it when it is recreated.
+        if post_processing.backbuffer_generation != shell_renderer.backbuffer_generation()
+            || post_processing.chain_dirty
+        {
+            post_processing.recreate_outputs(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                &*render_pipeline,
+                &*shell_renderer,
+            );
+        }
+
+        // Wait for every recompiling pass, so the chain isn't rebuilt with a mix of shaders.
+        if watched_shaders.is_dependency_signaled(&post_processing.shader_dependency_signal)
+            && !watched_shaders.is_reloading(&post_processing.shader_dependency_signal)
+        {
             post_processing.refresh_pipeline(
                 &*vulkan,
                 &mut *vulkan_allocator,
@@ -249,3 +1038,42 @@ impl PostProcessing {
         }
     }
 }
+
+// Packs a color the way unpackUnorm4x8 reads it, red in the lowest byte and an opaque alpha.
+fn pack_unorm_color(color: [f32; 3]) -> u32 {
+    let [r, g, b] = color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u32);
+    r | (g << 8) | (b << 16) | (255 << 24)
+}
+
+// Builds the SSAO kernel and noise from a fixed seed, so the pattern is the same every run.
+fn create_ssao_uniform() -> SsaoUniform {
+    // Xorshift, returns a float in [0, 1).
+    let mut state = 0x9e3779b9u32;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+
+    let mut kernel = [[0.0; 4]; SSAO_KERNEL_SIZE];
+    for (index, sample) in kernel.iter_mut().enumerate() {
+        let direction = Vector3::new(
+            random() * 2.0 - 1.0,
+            random() * 2.0 - 1.0,
+            random().max(0.05),
+        )
+        .normalize();
+        // Pull most samples close to the center, where occluders matter most.
+        let t = index as f32 / SSAO_KERNEL_SIZE as f32;
+        let direction = direction * random() * (0.1 + 0.9 * t * t);
+        *sample = [direction.x, direction.y, direction.z, 0.0];
+    }
+
+    let mut noise = [[0.0; 4]; SSAO_NOISE_SIZE];
+    for rotation in noise.iter_mut() {
+        *rotation = [random() * 2.0 - 1.0, random() * 2.0 - 1.0, 0.0, 0.0];
+    }
+
+    SsaoUniform { kernel, noise }
+}
@@ -0,0 +1,242 @@
+use std::{any::Any, sync::Arc};
+
+use ash::vk;
+use pyrite::{
+    prelude::Assets,
+    vulkan::{
+        CommandBuffer, ComputePipeline, ComputePipelineInfo, DescriptorSet, DescriptorSetLayout,
+        Image, ImageDep, ImageInfo, Sampler, Shader, UntypedBuffer, Vulkan, VulkanAllocator,
+    },
+};
+
+use crate::render::{
+    render::RenderPipeline,
+    watched_shaders::{DependencySignal, WatchedShaders},
+};
+
+use super::PushConstants;
+
+/// Where a post pass reads its images from and writes its output to.
+///
+/// Every pass uses the same descriptor set layout:
+/// - binding 0: the output of the previous pass, or the scene for the first pass,
+/// - binding 1: the output of this pass,
+/// - binding 2: the scene depth,
+/// - binding 3: the scene to composite onto, the shell output or the output of the last pass
+///   replacing it,
+/// - binding 4: the view space normals and shell height from the shell G-buffer,
+/// - binding 5: the SSAO kernel and noise,
+/// - binding 6: the motion blur history, which keeps its contents between frames.
+pub struct PostPassImages<'a> {
+    pub input: ImageDep,
+    pub scene: ImageDep,
+    pub depth: ImageDep,
+    pub depth_sampler: &'a Sampler,
+    pub normal: ImageDep,
+    pub ssao_buffer: &'a Arc<UntypedBuffer>,
+    pub history: ImageDep,
+}
+
+/// A single compute shader in the post processing chain, with its own output image.
+pub struct PostPass {
+    shader_name: &'static str,
+    pipeline: Option<ComputePipeline>,
+    descriptor_set: DescriptorSet,
+    output: Image,
+    output_format: vk::Format,
+}
+
+impl PostPass {
+    pub fn new(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        render_pipeline: &RenderPipeline,
+        assets: &mut Assets,
+        watched_shaders: &mut WatchedShaders,
+        shader_dependency_signal: &DependencySignal,
+        descriptor_set_layout: &DescriptorSetLayout,
+        shader_path: &str,
+        shader_name: &'static str,
+        defines: Vec<String>,
+        output_format: vk::Format,
+        images: PostPassImages,
+    ) -> Self {
+        watched_shaders.load_shader_with_defines(
+            assets,
+            shader_path,
+            shader_name,
+            defines,
+            shader_dependency_signal,
+        );
+
+        let output = create_pass_image(vulkan, vulkan_allocator, render_pipeline, output_format);
+        let descriptor_set = render_pipeline
+            .descriptor_pool()
+            .allocate_descriptor_sets(descriptor_set_layout, 1)
+            .pop()
+            .unwrap();
+        write_descriptor_set(&descriptor_set, &output, images);
+
+        Self {
+            shader_name,
+            pipeline: None,
+            descriptor_set,
+            output,
+            output_format,
+        }
+    }
+
+    /// Recreates the output at the backbuffer size and rebinds the images, the pipeline and
+    /// descriptor set are kept.
+    ///
+    /// The descriptor set is rewritten in place, so no frame in flight may still be using it.
+    pub fn recreate_output(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        render_pipeline: &RenderPipeline,
+        images: PostPassImages,
+    ) {
+        self.output = create_pass_image(
+            vulkan,
+            vulkan_allocator,
+            render_pipeline,
+            self.output_format,
+        );
+        write_descriptor_set(&self.descriptor_set, &self.output, images);
+    }
+
+    /// Sets the format of the output, which takes effect the next time it is recreated.
+    pub fn set_output_format(&mut self, output_format: vk::Format) {
+        self.output_format = output_format;
+    }
+
+    /// Recompiles the shader with different macros, the pass does nothing until the new
+    /// pipeline is built.
+    pub fn set_defines(
+        &mut self,
+        assets: &mut Assets,
+        watched_shaders: &mut WatchedShaders,
+        defines: Vec<String>,
+    ) {
+        watched_shaders.set_defines(assets, self.shader_name, defines);
+        self.pipeline = None;
+    }
+
+    pub fn output(&self) -> &Image {
+        &self.output
+    }
+
+    pub fn deps(&self) -> Vec<Arc<dyn Any + Send + Sync>> {
+        vec![self.output.create_dep(), self.descriptor_set.create_dep()]
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.pipeline.is_some()
+    }
+
+    pub fn refresh_pipeline(
+        &mut self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        descriptor_set_layout: &DescriptorSetLayout,
+    ) {
+        // Passes only declare the members of the shared push constants they read.
+        watched_shaders.check_push_constant_size(
+            self.shader_name,
+            std::mem::size_of::<PushConstants>(),
+            false,
+        );
+
+        let pipeline = ComputePipeline::new(
+            vulkan,
+            ComputePipelineInfo::builder()
+                .shader(Shader::new(
+                    vulkan,
+                    &watched_shaders.get_shader(self.shader_name).unwrap(),
+                ))
+                .descriptor_set_layouts(vec![descriptor_set_layout])
+                .push_constant_ranges(vec![vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .size(std::mem::size_of::<PushConstants>() as u32)
+                    .build()])
+                .build(),
+        );
+        self.pipeline = Some(pipeline);
+    }
+
+    /// Dispatches the pass over the whole output, the output must already be in `GENERAL`.
+    pub fn record(&self, command_buffer: &mut CommandBuffer, push_constants: &PushConstants) {
+        if let Some(pipeline) = &self.pipeline {
+            command_buffer.bind_compute_pipeline(pipeline);
+
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline_layout(),
+                &[&self.descriptor_set],
+            );
+
+            command_buffer.write_push_constants_typed(
+                pipeline.pipeline_layout(),
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                push_constants,
+            );
+
+            command_buffer.dispatch_compute(
+                (push_constants.width + 15) / 16,
+                (push_constants.height + 15) / 16,
+                1,
+            );
+        }
+    }
+}
+
+/// An image at the backbuffer size that passes can read and write, `format` must match the
+/// storage image format the shaders declare for it.
+pub fn create_pass_image(
+    vulkan: &Vulkan,
+    vulkan_allocator: &mut VulkanAllocator,
+    render_pipeline: &RenderPipeline,
+    format: vk::Format,
+) -> Image {
+    Image::new(
+        vulkan,
+        vulkan_allocator,
+        &ImageInfo::builder()
+            .extent(render_pipeline.backbuffer_image().image_extent())
+            .usage(
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .format(format)
+            .view_subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build(),
+            )
+            .build(),
+    )
+}
+
+fn write_descriptor_set(descriptor_set: &DescriptorSet, output: &Image, images: PostPassImages) {
+    descriptor_set
+        .write()
+        .set_storage_image(0, images.input)
+        .set_storage_image(1, output.create_dep())
+        .set_combined_image_sampler(
+            2,
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            images.depth,
+            images.depth_sampler,
+        )
+        .set_storage_image(3, images.scene)
+        .set_storage_image(4, images.normal)
+        .set_uniform_buffer(5, images.ssao_buffer)
+        .set_storage_image(6, images.history)
+        .submit_writes();
+}
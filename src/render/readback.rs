@@ -0,0 +1,179 @@
+use ash::vk;
+use pyrite::vulkan::{Image, Vulkan};
+
+/// The pixels of an RGBA8 image copied back to the CPU, tightly packed row by row from the
+/// top left.
+pub struct ReadbackPixels {
+    pub extent: vk::Extent2D,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+/// Copies an `R8G8B8A8_UNORM` image in the `GENERAL` layout into host memory.
+///
+/// This waits for the device to go idle before and after the copy, so it stalls the GPU. It is
+/// meant for tooling like captures and visual tests, not for calling every frame.
+pub fn read_image_pixels(vulkan: &Vulkan, image: &Image) -> anyhow::Result<ReadbackPixels> {
+    let extent = image.image_extent();
+    let size = extent.width as u64 * extent.height as u64 * 4;
+    let device = vulkan.device();
+    let queue = vulkan.default_queue();
+
+    // Safety: Every object created here is destroyed before returning, after the device is
+    // idle, and the image is only read once the frames using it have finished.
+    unsafe {
+        device.device_wait_idle()?;
+
+        let buffer = device.create_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            None,
+        )?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let Some(memory_type_index) = find_host_memory_type(vulkan, requirements.memory_type_bits)
+        else {
+            device.destroy_buffer(buffer, None);
+            anyhow::bail!("No host visible memory type to read the image back into");
+        };
+        let memory = match device.allocate_memory(
+            &vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index),
+            None,
+        ) {
+            Ok(memory) => memory,
+            Err(err) => {
+                device.destroy_buffer(buffer, None);
+                return Err(err.into());
+            }
+        };
+
+        let result = device
+            .bind_buffer_memory(buffer, memory, 0)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| {
+                let command_pool = device.create_command_pool(
+                    &vk::CommandPoolCreateInfo::builder()
+                        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                        .queue_family_index(queue.queue_family_index()),
+                    None,
+                )?;
+                let pixels = copy_image_to_buffer(
+                    device,
+                    queue.queue(),
+                    command_pool,
+                    image,
+                    buffer,
+                    memory,
+                    extent,
+                );
+                device.destroy_command_pool(command_pool, None);
+                pixels
+            });
+
+        device.destroy_buffer(buffer, None);
+        device.free_memory(memory, None);
+
+        result.map(|pixels| ReadbackPixels {
+            extent: vk::Extent2D {
+                width: extent.width,
+                height: extent.height,
+            },
+            pixels,
+        })
+    }
+}
+
+/// Records and waits on the copy, then reads the pixels out of the mapped memory.
+unsafe fn copy_image_to_buffer(
+    device: &ash::Device,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    image: &Image,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    extent: vk::Extent3D,
+) -> anyhow::Result<Vec<[u8; 4]>> {
+    let command_buffer = device.allocate_command_buffers(
+        &vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1),
+    )?[0];
+
+    device.begin_command_buffer(
+        command_buffer,
+        &vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+    )?;
+    // Buffer row length and image height of 0 pack the rows tightly, so the row stride is
+    // the width times 4 bytes whatever the image's own tiling is.
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        image.image(),
+        vk::ImageLayout::GENERAL,
+        buffer,
+        &[vk::BufferImageCopy::builder()
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_extent(extent)
+            .build()],
+    );
+    // Make the transfer write visible to the host read below.
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build()],
+        &[],
+        &[],
+    );
+    device.end_command_buffer(command_buffer)?;
+
+    let command_buffers = [command_buffer];
+    device.queue_submit(
+        queue,
+        &[vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build()],
+        vk::Fence::null(),
+    )?;
+    device.device_wait_idle()?;
+
+    let size = extent.width as u64 * extent.height as u64 * 4;
+    let data = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())?;
+    let bytes = std::slice::from_raw_parts(data as *const u8, size as usize);
+    let pixels = bytes
+        .chunks_exact(4)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+        .collect();
+    device.unmap_memory(memory);
+    Ok(pixels)
+}
+
+/// Host coherent memory is used so the mapped pixels don't need invalidating.
+fn find_host_memory_type(vulkan: &Vulkan, memory_type_bits: u32) -> Option<u32> {
+    let properties = unsafe {
+        vulkan
+            .instance()
+            .get_physical_device_memory_properties(vulkan.physical_device())
+    };
+    let flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+    (0..properties.memory_type_count).find(|&index| {
+        memory_type_bits & (1 << index) != 0
+            && properties.memory_types[index as usize]
+                .property_flags
+                .contains(flags)
+    })
+}
@@ -5,21 +5,67 @@ use pyrite::{
     desktop::RENDER_STAGE,
     prelude::*,
     render::render_manager::{FrameConfig, RenderManager},
-    vulkan::{DescriptorSet, DescriptorSetLayout, DescriptorSetPool},
+    vulkan::{CommandBuffer, DescriptorSet, DescriptorSetLayout, DescriptorSetPool},
 };
 
 use super::{
     camera::Camera,
+    debug_labels::DebugLabels,
+    depth_pyramid::DepthPyramid,
+    device_lost::is_device_lost,
+    frame_stats::{FrameStats, FrameTimestamp},
+    gizmo::LightGizmo,
+    hud::Hud,
+    light::Lighting,
+    loading::{LoadingProgress, LoadingScreen},
+    particles::ParticleSystem,
     post::PostProcessing,
     shell::{setup_shell_renderer, ShellRenderer},
+    time_control::TimeControl,
+    watched_shaders::WatchedShaders,
 };
 
-pub fn setup_render_pipeline(app_builder: &mut AppBuilder) {
-    // Setup render pipeline resource.
+// The range the render scale is clamped to.
+const MIN_RENDER_SCALE: f32 = 0.25;
+const MAX_RENDER_SCALE: f32 = 2.0;
+// How much the render scale keys change it per press.
+const RENDER_SCALE_STEP: f32 = 0.1;
+// The aspect ratio the letterbox key switches to.
+const LETTERBOX_ASPECT: f32 = 16.0 / 9.0;
+
+/// The format of the presented image, and of the scene color unless rendering in HDR.
+pub const LDR_COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+/// The format of the scene color up to tonemapping when rendering in HDR.
+pub const HDR_COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Closer lines than this would be far below a pixel apart everywhere but right under the camera.
+const MIN_GRID_SPACING: f32 = 0.01;
+
+// The colors of the debug label regions in frame captures.
+const SHELL_LABEL_COLOR: [f32; 4] = [0.45, 0.75, 0.3, 1.0];
+const POST_LABEL_COLOR: [f32; 4] = [0.35, 0.55, 0.9, 1.0];
+const DEPTH_PYRAMID_LABEL_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+const HUD_LABEL_COLOR: [f32; 4] = [0.9, 0.7, 0.3, 1.0];
+
+pub fn setup_render_pipeline(
+    app_builder: &mut AppBuilder,
+    msaa_samples: vk::SampleCountFlags,
+    hdr: bool,
+) {
+    // Setup render pipeline resource, matching the window. A minimized window still needs valid
+    // images to create everything else.
+    let (width, height) = {
+        let window = app_builder.get_resource::<Window>();
+        (window.width().max(1), window.height().max(1))
+    };
     let render_pipeline = RenderPipeline::new(
         &*app_builder.get_resource::<Vulkan>(),
         &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
         &*app_builder.get_resource::<RenderManager>(),
+        width,
+        height,
+        msaa_samples,
+        hdr,
     );
     app_builder.add_resource(render_pipeline);
     app_builder.add_system(RenderPipeline::update_system);
@@ -36,10 +82,85 @@ pub struct RenderPipeline {
     frames: Vec<Frame>,
     backbuffer_image: Image,
     backbuffer_depth_image: Image,
+    // The sample count of the backbuffer, with TYPE_1 the shell pass renders straight into its
+    // output and the backbuffer color image is unused.
+    msaa_samples: vk::SampleCountFlags,
+    // The sample count set at runtime, the backbuffer switches to it on the next update.
+    requested_msaa_samples: vk::SampleCountFlags,
+    // Whether the scene color is HDR_COLOR_FORMAT up until the post chain tonemaps it, so
+    // highlights brighter than white survive into bloom and tonemapping.
+    hdr: bool,
+    // Set at runtime like the sample count, the backbuffer switches format on the next update.
+    requested_hdr: bool,
+    // Bumped whenever the backbuffer images are recreated, resources built from them compare
+    // against it to know when to rebuild.
+    backbuffer_generation: u32,
+    // The size the final image is presented at. Every other size derives from it, on desktop it
+    // follows the window each update.
+    output_extent: vk::Extent2D,
+    // Nothing is rendered while the output has no area.
+    minimized: bool,
+    // The backbuffer is the output size times this, the final image is scaled to the output
+    // when presented.
+    render_scale: f32,
+    // The width over height the scene is rendered at, letterboxed inside the output. None fills
+    // the output.
+    target_aspect: Option<f32>,
+    // Where the backbuffer is shown in the output, in output pixels.
+    viewport: vk::Rect2D,
+    // The output sized image the scene is blitted into with black bars, exists while a target
+    // aspect is set.
+    present_image: Option<Image>,
+    // Depth is cleared to 0 and nearer fragments have greater depth, spreading the float
+    // precision over the distance instead of bunching it up at the near plane.
+    reverse_z: bool,
+    // The ground grid drawn under the fur, None while disabled.
+    grid: Option<GridSettings>,
+}
+
+/// How the ground grid looks, see `RenderPipeline::set_grid`.
+#[derive(Clone, Copy)]
+pub struct GridSettings {
+    /// The world space height of the plane the grid lies on.
+    pub height: f32,
+    /// The world space distance between lines.
+    pub spacing: f32,
+    pub color: [f32; 3],
+}
+
+impl Default for GridSettings {
+    /// A gray line every unit at the shell renderer's default ground height.
+    fn default() -> Self {
+        Self {
+            height: -1.0,
+            spacing: 1.0,
+            color: [0.5, 0.5, 0.5],
+        }
+    }
 }
 
 pub struct Frame {
     descriptor_set: DescriptorSet,
+    // What the descriptor set was last written with, None until the first write.
+    bindings: Option<FrameBindings>,
+}
+
+// The identities of everything bound to a frame's descriptor set. The set is only rewritten
+// when one of them changes, since rewriting it every frame would mostly write the same handles.
+#[derive(PartialEq)]
+struct FrameBindingKey {
+    // The addresses of the uniform buffers in binding order.
+    buffers: [usize; 6],
+    // The density mask, albedo texture and comb map images.
+    images: [vk::Image; 3],
+    sampler_generation: u32,
+}
+
+struct FrameBindings {
+    key: FrameBindingKey,
+    // Keeps the bound buffers and images alive while the set refers to them, so a handle in
+    // the key can't be reused by a replacement that would compare equal.
+    resources: Vec<Arc<dyn Any + Send + Sync>>,
 }
 
 impl Frame {
@@ -49,73 +170,113 @@ impl Frame {
 }
 
 impl RenderPipeline {
+    /// Creates the backbuffer at the given size, it is only resized to follow the output extent
+    /// after.
     fn new(
         vulkan: &Vulkan,
         vulkan_allocator: &mut VulkanAllocator,
         render_manager: &RenderManager,
+        width: u32,
+        height: u32,
+        msaa_samples: vk::SampleCountFlags,
+        hdr: bool,
     ) -> Self {
+        let msaa_samples = supported_msaa_samples(vulkan, msaa_samples);
+        let hdr_supported = supports_hdr(vulkan, msaa_samples);
+        if hdr && !hdr_supported {
+            println!(
+                "HDR rendering with {:?} is not supported, falling back to {:?}",
+                HDR_COLOR_FORMAT, LDR_COLOR_FORMAT
+            );
+        }
+        let hdr = hdr && hdr_supported;
+
         let descriptor_set_layout = DescriptorSetLayout::new(
             vulkan,
-            &[vk::DescriptorSetLayoutBinding {
-                binding: 0,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: 1,
-                stage_flags: vk::ShaderStageFlags::VERTEX,
-                p_immutable_samplers: std::ptr::null(),
-            }],
+            &[
+                vk::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 2,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::VERTEX,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 3,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 4,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 5,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 6,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 7,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 8,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::VERTEX,
+                    p_immutable_samplers: std::ptr::null(),
+                },
+            ],
         );
 
         let descriptor_set_pool = DescriptorSetPool::new(vulkan);
         let frames = descriptor_set_pool
             .allocate_descriptor_sets(&descriptor_set_layout, render_manager.frames_in_flight())
             .into_iter()
-            .map(|descriptor_set| Frame { descriptor_set })
+            .map(|descriptor_set| Frame {
+                descriptor_set,
+                bindings: None,
+            })
             .collect::<Vec<_>>();
 
-        let extent = vk::Extent3D {
-            width: 2560,
-            height: 1440,
-            depth: 1,
-        };
-
-        let backbuffer_image = Image::new(
-            vulkan,
-            vulkan_allocator,
-            &ImageInfo::builder()
-                .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
-                .extent(extent.clone())
-                .format(vk::Format::R8G8B8A8_UNORM)
-                .samples(vk::SampleCountFlags::TYPE_4)
-                .view_subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .level_count(1)
-                        .build(),
-                )
-                .build(),
-        );
-
-        let backbuffer_depth_image = Image::new(
+        let (backbuffer_image, backbuffer_depth_image) = create_backbuffer_images(
             vulkan,
             vulkan_allocator,
-            &ImageInfo::builder()
-                .usage(
-                    vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
-                        | vk::ImageUsageFlags::SAMPLED
-                        | vk::ImageUsageFlags::TRANSFER_SRC,
-                )
-                .extent(extent)
-                .format(vk::Format::D32_SFLOAT)
-                .samples(vk::SampleCountFlags::TYPE_4)
-                .view_subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                        .layer_count(1)
-                        .level_count(1)
-                        .build(),
-                )
-                .build(),
+            width,
+            height,
+            msaa_samples,
+            color_format(hdr),
         );
 
         Self {
@@ -124,6 +285,22 @@ impl RenderPipeline {
             frames,
             backbuffer_image,
             backbuffer_depth_image,
+            msaa_samples,
+            requested_msaa_samples: msaa_samples,
+            hdr,
+            requested_hdr: hdr,
+            backbuffer_generation: 0,
+            output_extent: vk::Extent2D { width, height },
+            minimized: false,
+            render_scale: 1.0,
+            target_aspect: None,
+            viewport: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D { width, height },
+            },
+            present_image: None,
+            reverse_z: false,
+            grid: None,
         }
     }
 
@@ -151,81 +328,831 @@ impl RenderPipeline {
         &self.backbuffer_depth_image
     }
 
-    fn update_system(mut render_pipeline: ResMut<RenderPipeline>, window: Res<Window>) {
+    pub fn msaa_samples(&self) -> vk::SampleCountFlags {
+        self.msaa_samples
+    }
+
+    /// Switches the backbuffer to `msaa_samples`, falling back to the highest count below it
+    /// the device supports.
+    ///
+    /// The backbuffer is recreated on the next update and everything rendering into it or
+    /// reading its depth rebuilds. The scene format is kept, so with HDR a count the device
+    /// can't render HDR at is ignored.
+    pub fn set_msaa_samples(&mut self, vulkan: &Vulkan, msaa_samples: vk::SampleCountFlags) {
+        let msaa_samples = supported_msaa_samples(vulkan, msaa_samples);
+        if self.requested_hdr && !supports_hdr(vulkan, msaa_samples) {
+            println!(
+                "HDR rendering with {:?} is not supported at MSAA {:?}, keeping {:?}",
+                HDR_COLOR_FORMAT, msaa_samples, self.requested_msaa_samples
+            );
+            return;
+        }
+        self.requested_msaa_samples = msaa_samples;
+    }
+
+    /// Whether the scene is rendered into HDR_COLOR_FORMAT images, false if it was requested
+    /// but the device can't render, resolve or store to that format.
+    pub fn hdr(&self) -> bool {
+        self.hdr
+    }
+
+    /// Switches the scene color between HDR_COLOR_FORMAT and LDR_COLOR_FORMAT, ignored if the
+    /// device can't render HDR at the sample count.
+    ///
+    /// The backbuffer is recreated on the next update, the shell images and the post passes
+    /// follow it like they follow a new sample count.
+    pub fn set_hdr(&mut self, vulkan: &Vulkan, hdr: bool) {
+        if hdr && !supports_hdr(vulkan, self.requested_msaa_samples) {
+            println!(
+                "HDR rendering with {:?} is not supported at MSAA {:?}, keeping {:?}",
+                HDR_COLOR_FORMAT, self.requested_msaa_samples, LDR_COLOR_FORMAT
+            );
+            return;
+        }
+        self.requested_hdr = hdr;
+    }
+
+    /// The format of the backbuffer, the shell resolve image and the post passes before
+    /// tonemapping.
+    pub fn color_format(&self) -> vk::Format {
+        color_format(self.hdr)
+    }
+
+    pub fn backbuffer_generation(&self) -> u32 {
+        self.backbuffer_generation
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    pub fn output_extent(&self) -> vk::Extent2D {
+        self.output_extent
+    }
+
+    /// Sets the size the final image is presented at, the backbuffer, letterboxing and render
+    /// scale all follow it on the next update. An extent with no area skips rendering.
+    ///
+    /// The desktop update sets it to the window size every frame.
+    pub fn set_output_extent(&mut self, output_extent: vk::Extent2D) {
+        self.output_extent = output_extent;
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Renders at the output size times `render_scale`, clamped between 0.25 and 2.
+    ///
+    /// The backbuffer is reallocated on the next update, trading quality for GPU time.
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale = render_scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+    }
+
+    pub fn target_aspect(&self) -> Option<f32> {
+        self.target_aspect
+    }
+
+    /// Renders at a fixed width over height fit inside the output, with black bars filling the
+    /// rest, so captures look the same whatever shape the window is. `None` fills the output.
+    ///
+    /// The backbuffer is reallocated on the next update.
+    pub fn set_target_aspect(&mut self, target_aspect: Option<f32>) {
+        self.target_aspect = target_aspect.filter(|aspect| aspect.is_finite() && *aspect > 0.0);
+    }
+
+    /// The size the scene is rendered at, its aspect is the one projections should use.
+    pub fn render_extent(&self) -> vk::Extent2D {
+        let extent = self.backbuffer_image.image_extent();
+        vk::Extent2D {
+            width: extent.width,
+            height: extent.height,
+        }
+    }
+
+    /// Where the rendered image is shown in the output, in output pixels. Covers the whole
+    /// output unless letterboxed.
+    pub fn viewport(&self) -> vk::Rect2D {
+        self.viewport
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    /// Maps the near plane to depth 1 and the far plane to 0, the depth clear value and compare
+    /// ops flip to match. Off by default.
+    ///
+    /// The camera projection and the shell pipelines pick it up on the next update.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    pub fn grid(&self) -> Option<GridSettings> {
+        self.grid
+    }
+
+    /// Draws a grid of lines `spacing` apart on the plane at `height` under the camera, fading
+    /// out with distance so it looks endless. It is drawn before the fur and depth tested with
+    /// it.
+    ///
+    /// The ground shadow only lines up with the grid when they are given the same height.
+    /// `spacing` is clamped to at least 0.01.
+    pub fn set_grid(&mut self, enabled: bool, height: f32, spacing: f32, color: [f32; 3]) {
+        self.grid = enabled.then(|| GridSettings {
+            height,
+            spacing: spacing.max(MIN_GRID_SPACING),
+            color,
+        });
+    }
+
+    /// The value the depth buffer is cleared to, the far plane.
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    /// The compare op passing fragments nearer than or as near as the stored depth.
+    pub fn depth_compare_op(&self) -> vk::CompareOp {
+        if self.reverse_z {
+            vk::CompareOp::GREATER_OR_EQUAL
+        } else {
+            vk::CompareOp::LESS_OR_EQUAL
+        }
+    }
+
+    fn update_system(
+        mut render_pipeline: ResMut<RenderPipeline>,
+        window: Res<Window>,
+        vulkan: Res<Vulkan>,
+        mut vulkan_allocator: ResMut<VulkanAllocator>,
+        input: Res<Input>,
+    ) {
         let render_pipeline = &mut *render_pipeline;
-        let window = &*window;
+        render_pipeline.set_output_extent(vk::Extent2D {
+            width: window.width(),
+            height: window.height(),
+        });
+
+        let mut render_scale = render_pipeline.render_scale;
+        if input.is_key_pressed(Key::Minus) {
+            render_scale -= RENDER_SCALE_STEP;
+        }
+        if input.is_key_pressed(Key::Equals) {
+            render_scale += RENDER_SCALE_STEP;
+        }
+        if render_scale != render_pipeline.render_scale {
+            render_pipeline.set_render_scale(render_scale);
+            println!("Render scale: {:.2}", render_pipeline.render_scale);
+        }
+
+        if input.is_key_pressed(Key::F12) {
+            let target_aspect = match render_pipeline.target_aspect {
+                Some(_) => None,
+                None => Some(LETTERBOX_ASPECT),
+            };
+            render_pipeline.set_target_aspect(target_aspect);
+            println!("Target aspect: {:?}", render_pipeline.target_aspect);
+        }
+
+        let output_extent = render_pipeline.output_extent;
+        render_pipeline.minimized = output_extent.width == 0 || output_extent.height == 0;
+        if render_pipeline.minimized {
+            return;
+        }
+
+        // Center the target aspect in the output, the present image holds the bars around it.
+        let viewport = match render_pipeline.target_aspect {
+            Some(aspect) => fit_aspect(output_extent.width, output_extent.height, aspect),
+            None => vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: output_extent,
+            },
+        };
+        render_pipeline.viewport = viewport;
+        let present_image_matches = render_pipeline.present_image.as_ref().is_some_and(|image| {
+            let extent = image.image_extent();
+            extent.width == output_extent.width && extent.height == output_extent.height
+        });
+        if render_pipeline.target_aspect.is_none() {
+            render_pipeline.present_image = None;
+        } else if !present_image_matches {
+            render_pipeline.present_image = Some(create_present_image(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                output_extent.width,
+                output_extent.height,
+            ));
+        }
+
+        let scaled =
+            |size: u32| ((size as f32 * render_pipeline.render_scale).round() as u32).max(1);
+        let (width, height) = (
+            scaled(viewport.extent.width),
+            scaled(viewport.extent.height),
+        );
+
+        // Reallocate the backbuffer to match the scaled output, the new sample count or the new
+        // format, the old images are kept alive by any frames still using them.
+        let extent = render_pipeline.backbuffer_image.image_extent();
+        let msaa_changed = render_pipeline.msaa_samples != render_pipeline.requested_msaa_samples;
+        let hdr_changed = render_pipeline.hdr != render_pipeline.requested_hdr;
+        if extent.width != width || extent.height != height || msaa_changed || hdr_changed {
+            if msaa_changed {
+                render_pipeline.msaa_samples = render_pipeline.requested_msaa_samples;
+                println!("MSAA: {:?}", render_pipeline.msaa_samples);
+            }
+            if hdr_changed {
+                render_pipeline.hdr = render_pipeline.requested_hdr;
+                println!("HDR: {}", render_pipeline.hdr);
+            }
+            let (backbuffer_image, backbuffer_depth_image) = create_backbuffer_images(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                width,
+                height,
+                render_pipeline.msaa_samples,
+                render_pipeline.color_format(),
+            );
+            render_pipeline.backbuffer_image = backbuffer_image;
+            render_pipeline.backbuffer_depth_image = backbuffer_depth_image;
+            render_pipeline.backbuffer_generation += 1;
+            println!("Resized backbuffer to {}x{}", width, height);
+        }
     }
 
     fn render_system(
         mut render_pipeline: ResMut<RenderPipeline>,
         camera: Res<Camera>,
+        lighting: Res<Lighting>,
         mut render_manager: ResMut<RenderManager>,
         vulkan: Res<Vulkan>,
         shell_renderer: Res<ShellRenderer>,
-        post_processing: Res<PostProcessing>,
-        time: Res<Time>,
+        light_gizmo: Res<LightGizmo>,
+        particle_system: Res<ParticleSystem>,
+        mut post_processing: ResMut<PostProcessing>,
+        time_control: Res<TimeControl>,
+        watched_shaders: Res<WatchedShaders>,
+        mut loading_screen: ResMut<LoadingScreen>,
+        depth_pyramid: Res<DepthPyramid>,
+        mut frame_stats: ResMut<FrameStats>,
+        debug_labels: Res<DebugLabels>,
+        hud: Res<Hud>,
     ) {
         let render_pipeline = &mut *render_pipeline;
         let render_manager = &mut *render_manager;
 
+        // Skip the frame while minimized, or while resources are still catching up with a resize.
+        // Nothing is recorded at all once the device is lost.
+        let backbuffer_generation = render_pipeline.backbuffer_generation();
+        if is_device_lost()
+            || render_pipeline.is_minimized()
+            || shell_renderer.backbuffer_generation() != backbuffer_generation
+            || light_gizmo.backbuffer_generation() != backbuffer_generation
+            || particle_system.backbuffer_generation() != backbuffer_generation
+            || post_processing.backbuffer_generation() != backbuffer_generation
+            || depth_pyramid.backbuffer_generation() != backbuffer_generation
+        {
+            return;
+        }
+
         let ready_to_render = shell_renderer.is_ready() && post_processing.is_ready();
 
         // See if we are ready to render.
         if ready_to_render {
+            let frame_index = render_manager.frame_index();
+            frame_stats.begin_frame(render_manager.frame().command_buffer(), frame_index);
+
             let pipeline_frame = render_pipeline.frame_mut(render_manager);
 
-            // Update descriptor sets
-            let descriptor_set = &mut pipeline_frame.descriptor_set;
-            descriptor_set
-                .write()
-                .set_uniform_buffer(0, &camera.camera_buffer())
-                .submit_writes();
+            // Update the descriptor set if anything bound to it was replaced since it was last
+            // written, such as a recreated texture or the camera's buffer for this frame.
+            let buffers = [
+                camera.camera_buffer(),
+                lighting.lighting_buffer(),
+                shell_renderer.model_buffer(),
+                shell_renderer.layers_buffer(),
+                shell_renderer.lights_buffer(),
+                shell_renderer.fur_buffer(),
+            ];
+            let images = [
+                shell_renderer.density_mask().image(),
+                shell_renderer.albedo_texture().image(),
+                shell_renderer.comb_map().image(),
+            ];
+            let key = FrameBindingKey {
+                buffers: buffers.map(|buffer| Arc::as_ptr(buffer) as usize),
+                images: images.map(|image| image.image()),
+                sampler_generation: shell_renderer.mask_sampler_generation(),
+            };
+            if pipeline_frame
+                .bindings
+                .as_ref()
+                .map_or(true, |bindings| bindings.key != key)
+            {
+                let mut resources: Vec<Arc<dyn Any + Send + Sync>> = buffers
+                    .iter()
+                    .map(|buffer| (*buffer).clone() as Arc<dyn Any + Send + Sync>)
+                    .collect();
+                resources.extend(images.iter().map(|image| image.create_dep()));
+                pipeline_frame
+                    .descriptor_set
+                    .write()
+                    .set_uniform_buffer(0, &camera.camera_buffer())
+                    .set_uniform_buffer(1, &lighting.lighting_buffer())
+                    .set_uniform_buffer(2, shell_renderer.model_buffer())
+                    .set_combined_image_sampler(
+                        3,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        shell_renderer.density_mask().image().create_dep(),
+                        shell_renderer.mask_sampler(),
+                    )
+                    .set_uniform_buffer(4, shell_renderer.layers_buffer())
+                    .set_uniform_buffer(5, shell_renderer.lights_buffer())
+                    .set_uniform_buffer(6, shell_renderer.fur_buffer())
+                    .set_combined_image_sampler(
+                        7,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        shell_renderer.albedo_texture().image().create_dep(),
+                        shell_renderer.mask_sampler(),
+                    )
+                    .set_combined_image_sampler(
+                        8,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        shell_renderer.comb_map().image().create_dep(),
+                        shell_renderer.mask_sampler(),
+                    )
+                    .submit_writes();
+                pipeline_frame.bindings = Some(FrameBindings { key, resources });
+            }
 
             // Render the furry shell textured ball.
+            debug_labels.begin_region(
+                render_manager.frame().command_buffer(),
+                "Shell pass",
+                SHELL_LABEL_COLOR,
+            );
             let shell_deps = shell_renderer.render(
+                &*vulkan,
                 render_manager,
                 render_pipeline,
-                time.elapsed().as_secs_f32(),
+                &camera,
+                time_control.animation_time(),
+            );
+
+            // Draw the light gizmo over the shell output if enabled.
+            let gizmo_deps = light_gizmo.render(render_manager, render_pipeline, &shell_renderer);
+            // Draw the shed fur over it too.
+            let particle_deps =
+                particle_system.render(&*vulkan, render_manager, render_pipeline, &shell_renderer);
+            debug_labels.end_region(render_manager.frame().command_buffer());
+            frame_stats.write_timestamp(
+                render_manager.frame().command_buffer(),
+                frame_index,
+                FrameTimestamp::ShellEnd,
             );
 
             // Apply post processing.
+            debug_labels.begin_region(
+                render_manager.frame().command_buffer(),
+                "Post processing",
+                POST_LABEL_COLOR,
+            );
             let post_processing_deps = post_processing.render(
+                &*vulkan,
                 render_manager.frame_mut().command_buffer_mut(),
                 render_pipeline,
+                &camera,
+            );
+            debug_labels.end_region(render_manager.frame().command_buffer());
+            frame_stats.write_timestamp(
+                render_manager.frame().command_buffer(),
+                frame_index,
+                FrameTimestamp::PostEnd,
             );
 
+            // Build the depth pyramid from this frame's depth if enabled.
+            debug_labels.begin_region(
+                render_manager.frame().command_buffer(),
+                "Depth pyramid",
+                DEPTH_PYRAMID_LABEL_COLOR,
+            );
+            let depth_pyramid_deps = depth_pyramid.render(
+                render_manager.frame_mut().command_buffer_mut(),
+                render_pipeline.reverse_z,
+            );
+            debug_labels.end_region(render_manager.frame().command_buffer());
+
+            // Draw the HUD over the final image if enabled.
+            debug_labels.begin_region(
+                render_manager.frame().command_buffer(),
+                "HUD",
+                HUD_LABEL_COLOR,
+            );
+            let hud_deps = hud.render(&*vulkan, render_manager, &post_processing);
+            debug_labels.end_region(render_manager.frame().command_buffer());
+            // The last write to the output is the HUD's if it drew anything.
+            let output_access = if hud_deps.is_empty() {
+                vk::AccessFlags::SHADER_WRITE
+            } else {
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            };
+            frame_stats.write_timestamp(
+                render_manager.frame().command_buffer(),
+                frame_index,
+                FrameTimestamp::FrameEnd,
+            );
+
+            let (present_image, present_layout, present_access, letterbox_deps) = render_pipeline
+                .record_letterbox(
+                    &*vulkan,
+                    render_manager.frame().command_buffer(),
+                    post_processing.output_image(),
+                    vk::ImageLayout::GENERAL,
+                    output_access,
+                );
+
             let mut frame_deps = vec![
                 render_pipeline
                     .frame(render_manager)
                     .descriptor_set
                     .create_dep(),
                 render_pipeline.backbuffer_depth_image().create_dep() as Arc<dyn Any + Send + Sync>,
+                lighting.lighting_buffer().clone(),
             ];
             frame_deps.extend(shell_deps);
+            frame_deps.extend(gizmo_deps);
+            frame_deps.extend(particle_deps);
             frame_deps.extend(post_processing_deps);
+            frame_deps.extend(depth_pyramid_deps);
+            frame_deps.extend(hud_deps);
+            frame_deps.extend(letterbox_deps);
 
             // Set the final layout of the backbuffer to the last layout.
             render_manager.set_frame_config(
                 &FrameConfig::builder()
-                    .backbuffer(
-                        post_processing.output_image(),
-                        vk::ImageLayout::GENERAL,
-                        vk::AccessFlags::SHADER_WRITE,
-                    )
+                    .backbuffer(present_image, present_layout, present_access)
                     .used_objects(frame_deps)
                     .build(),
             );
         } else {
-            // If not, do nothing.
+            // If not, show the loading screen. The mesh is staged immediately when the shell
+            // renderer is created so only the shaders and pipelines are left to wait on.
+            let pipelines_ready = [shell_renderer.is_ready(), post_processing.is_ready()];
+            let progress = LoadingProgress {
+                shaders_loaded: watched_shaders.loaded_shader_count(),
+                shader_count: watched_shaders.shader_count(),
+                pipelines_ready: pipelines_ready.iter().filter(|ready| **ready).count(),
+                pipeline_count: pipelines_ready.len(),
+            };
+
+            let mut loading_deps = loading_screen.render(
+                &*vulkan,
+                render_manager.frame_mut().command_buffer_mut(),
+                post_processing.output_image(),
+                &progress,
+            );
+            let (present_image, present_layout, present_access, letterbox_deps) = render_pipeline
+                .record_letterbox(
+                    &*vulkan,
+                    render_manager.frame().command_buffer(),
+                    post_processing.output_image(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                );
+            loading_deps.extend(letterbox_deps);
+
             render_manager.set_frame_config(
                 &FrameConfig::builder()
-                    .backbuffer(
-                        render_pipeline.backbuffer_image(),
-                        vk::ImageLayout::UNDEFINED,
-                        vk::AccessFlags::empty(),
-                    )
+                    .backbuffer(present_image, present_layout, present_access)
+                    .used_objects(loading_deps)
                     .build(),
             );
         }
     }
+
+    /// Blits `output` into the middle of the present image over black if letterboxing, then
+    /// returns `output` to `output_layout`.
+    ///
+    /// Gives the image to present with its final layout and last access, which is just
+    /// `output` when the output extent is filled.
+    fn record_letterbox<'a>(
+        &'a self,
+        vulkan: &Vulkan,
+        command_buffer: &CommandBuffer,
+        output: &'a Image,
+        output_layout: vk::ImageLayout,
+        output_access: vk::AccessFlags,
+    ) -> (
+        &'a Image,
+        vk::ImageLayout,
+        vk::AccessFlags,
+        Vec<Arc<dyn Any + Send + Sync>>,
+    ) {
+        let Some(present_image) = &self.present_image else {
+            return (output, output_layout, output_access, vec![]);
+        };
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .level_count(1)
+            .build();
+        let subresource_layers = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .build();
+        let output_extent = output.image_extent();
+        let viewport = self.viewport;
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::ALL_GRAPHICS
+                | vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[
+                output.image_memory_barrier(
+                    output_layout,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    output_access,
+                    vk::AccessFlags::TRANSFER_READ,
+                ),
+                present_image.image_memory_barrier(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            ],
+        );
+
+        // Safety: Both images are in the layouts given and are kept alive by the returned deps.
+        unsafe {
+            vulkan.device().cmd_clear_color_image(
+                command_buffer.command_buffer(),
+                present_image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+                &[subresource_range],
+            );
+        }
+
+        // The blit writes over the middle of the clear.
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[present_image.image_memory_barrier(
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_WRITE,
+            )],
+        );
+
+        unsafe {
+            vulkan.device().cmd_blit_image(
+                command_buffer.command_buffer(),
+                output.image(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                present_image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageBlit::builder()
+                    .src_subresource(subresource_layers)
+                    .src_offsets([
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D {
+                            x: output_extent.width as i32,
+                            y: output_extent.height as i32,
+                            z: 1,
+                        },
+                    ])
+                    .dst_subresource(subresource_layers)
+                    .dst_offsets([
+                        vk::Offset3D {
+                            x: viewport.offset.x,
+                            y: viewport.offset.y,
+                            z: 0,
+                        },
+                        vk::Offset3D {
+                            x: viewport.offset.x + viewport.extent.width as i32,
+                            y: viewport.offset.y + viewport.extent.height as i32,
+                            z: 1,
+                        },
+                    ])
+                    .build()],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        // Tooling reading the output back expects it in the layout it was left in.
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[output.image_memory_barrier(
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                output_layout,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::empty(),
+            )],
+        );
+
+        (
+            present_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vec![output.create_dep(), present_image.create_dep()],
+        )
+    }
+}
+
+/// The largest rect with the given width over height that fits centered in the output.
+fn fit_aspect(output_width: u32, output_height: u32, aspect: f32) -> vk::Rect2D {
+    let (width, height) = if output_width as f32 / output_height as f32 > aspect {
+        let width = (output_height as f32 * aspect).round() as u32;
+        (width.clamp(1, output_width), output_height)
+    } else {
+        let height = (output_width as f32 / aspect).round() as u32;
+        (output_width, height.clamp(1, output_height))
+    };
+
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: ((output_width - width) / 2) as i32,
+            y: ((output_height - height) / 2) as i32,
+        },
+        extent: vk::Extent2D { width, height },
+    }
+}
+
+fn create_present_image(
+    vulkan: &Vulkan,
+    vulkan_allocator: &mut VulkanAllocator,
+    width: u32,
+    height: u32,
+) -> Image {
+    Image::new(
+        vulkan,
+        vulkan_allocator,
+        &ImageInfo::builder()
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .format(LDR_COLOR_FORMAT)
+            .view_subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build(),
+            )
+            .build(),
+    )
+}
+
+fn create_backbuffer_images(
+    vulkan: &Vulkan,
+    vulkan_allocator: &mut VulkanAllocator,
+    width: u32,
+    height: u32,
+    msaa_samples: vk::SampleCountFlags,
+    color_format: vk::Format,
+) -> (Image, Image) {
+    let extent = vk::Extent3D {
+        width,
+        height,
+        depth: 1,
+    };
+
+    let backbuffer_image = Image::new(
+        vulkan,
+        vulkan_allocator,
+        &ImageInfo::builder()
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+            .extent(extent.clone())
+            .format(color_format)
+            .samples(msaa_samples)
+            .view_subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build(),
+            )
+            .build(),
+    );
+
+    let backbuffer_depth_image = Image::new(
+        vulkan,
+        vulkan_allocator,
+        &ImageInfo::builder()
+            .usage(
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+            )
+            .extent(extent)
+            .format(vk::Format::D32_SFLOAT)
+            .samples(msaa_samples)
+            .view_subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build(),
+            )
+            .build(),
+    );
+
+    (backbuffer_image, backbuffer_depth_image)
+}
+
+fn color_format(hdr: bool) -> vk::Format {
+    if hdr {
+        HDR_COLOR_FORMAT
+    } else {
+        LDR_COLOR_FORMAT
+    }
+}
+
+/// Whether HDR_COLOR_FORMAT can be rendered to with blending at `msaa_samples`, resolved, and
+/// read and written as a storage image by the post passes.
+fn supports_hdr(vulkan: &Vulkan, msaa_samples: vk::SampleCountFlags) -> bool {
+    let instance = vulkan.instance();
+    let physical_device = vulkan.physical_device();
+    let features = unsafe {
+        instance.get_physical_device_format_properties(physical_device, HDR_COLOR_FORMAT)
+    }
+    .optimal_tiling_features;
+    let required_features = vk::FormatFeatureFlags::COLOR_ATTACHMENT
+        | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND
+        | vk::FormatFeatureFlags::STORAGE_IMAGE;
+
+    let sample_counts = unsafe {
+        instance.get_physical_device_image_format_properties(
+            physical_device,
+            HDR_COLOR_FORMAT,
+            vk::ImageType::TYPE_2D,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageCreateFlags::empty(),
+        )
+    }
+    .map_or(vk::SampleCountFlags::empty(), |properties| {
+        properties.sample_counts
+    });
+
+    features.contains(required_features) && sample_counts.contains(msaa_samples)
+}
+
+/// Falls back to the highest sample count the device supports for both color and depth
+/// attachments if the requested one isn't supported.
+fn supported_msaa_samples(
+    vulkan: &Vulkan,
+    requested: vk::SampleCountFlags,
+) -> vk::SampleCountFlags {
+    let limits = unsafe {
+        vulkan
+            .instance()
+            .get_physical_device_properties(vulkan.physical_device())
+    }
+    .limits;
+    let supported = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+    let msaa_samples = [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .filter(|samples| samples.as_raw() <= requested.as_raw())
+    .find(|samples| supported.contains(*samples))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1);
+
+    if msaa_samples != requested {
+        println!(
+            "MSAA {:?} is not supported, falling back to {:?}",
+            requested, msaa_samples
+        );
+    }
+    msaa_samples
 }
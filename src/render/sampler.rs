@@ -0,0 +1,70 @@
+use ash::vk;
+use pyrite::vulkan::{Sampler, SamplerInfo, Vulkan};
+
+/// How a texture is filtered and addressed when sampled.
+///
+/// The default matches `SamplerInfo`'s own defaults, which every sampler used before these
+/// settings existed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SamplerSettings {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode: vk::SamplerAddressMode,
+    /// The anisotropic filtering level, 1 disables it. The device is created without
+    /// anisotropic filtering, so higher levels are currently ignored when creating the sampler.
+    pub anisotropy: f32,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            anisotropy: 1.0,
+        }
+    }
+}
+
+impl SamplerSettings {
+    /// Linear filtering with the given anisotropy, for textures seen at grazing angles.
+    pub fn linear(anisotropy: f32) -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            anisotropy,
+            ..Default::default()
+        }
+    }
+
+    /// Creates the sampler. Anisotropic filtering is always left off, pyrite creates the device
+    /// without the `samplerAnisotropy` feature so enabling it would be invalid usage.
+    pub fn create(&self, vulkan: &Vulkan) -> Sampler {
+        if *self == Self::default() {
+            return Sampler::new(vulkan, &SamplerInfo::builder().build());
+        }
+
+        if self.anisotropy > 1.0 {
+            println!(
+                "Anisotropy of {} is unavailable, the sampler is created without it.",
+                self.anisotropy
+            );
+        }
+
+        Sampler::new(
+            vulkan,
+            &SamplerInfo::builder()
+                .mag_filter(self.mag_filter)
+                .min_filter(self.min_filter)
+                .mipmap_mode(self.mipmap_mode)
+                .address_mode_u(self.address_mode)
+                .address_mode_v(self.address_mode)
+                .address_mode_w(self.address_mode)
+                .anisotropy_enable(false)
+                .build(),
+        )
+    }
+}
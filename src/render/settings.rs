@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+
+use nalgebra::Vector3;
+use pyrite::prelude::{AppBuilder, Input, Key, Res, Resource};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    camera::{Camera, CameraState},
+    shell::ShellRenderer,
+};
+
+/// Applies the settings file at `path` if there is one, and saves the current settings to it
+/// when F9 is pressed.
+///
+/// Must be set up after the render preset so the shell renderer and camera exist.
+pub fn setup_render_settings(app_builder: &mut AppBuilder, path: impl Into<PathBuf>) {
+    let settings_file = RenderSettingsFile { path: path.into() };
+
+    match RenderSettings::load(&settings_file.path) {
+        Ok(Some(settings)) => {
+            settings.apply(
+                &mut *app_builder.get_resource_mut::<ShellRenderer>(),
+                &mut *app_builder.get_resource_mut::<Camera>(),
+            );
+            println!("Loaded settings from {}", settings_file.path.display());
+        }
+        Ok(None) => {}
+        // Keep the defaults, the file is overwritten on the next save.
+        Err(err) => println!("{}", err),
+    }
+
+    app_builder.add_resource(settings_file);
+    app_builder.add_system(RenderSettingsFile::update_system);
+}
+
+/// Where the settings are loaded from and saved to.
+#[derive(Resource)]
+pub struct RenderSettingsFile {
+    path: PathBuf,
+}
+
+impl RenderSettingsFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn update_system(
+        settings_file: Res<RenderSettingsFile>,
+        shell_renderer: Res<ShellRenderer>,
+        camera: Res<Camera>,
+        input: Res<Input>,
+    ) {
+        if input.is_key_pressed(Key::F9) {
+            let mut settings = RenderSettings::capture(&*shell_renderer, &*camera);
+            // There is nothing to capture the desktop settings from at runtime.
+            settings.desktop = RenderSettings::load(&settings_file.path)
+                .ok()
+                .flatten()
+                .and_then(|saved| saved.desktop);
+            match settings.save(&settings_file.path) {
+                Ok(()) => println!("Saved settings to {}", settings_file.path.display()),
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+}
+
+/// The tunable fur and camera settings, saved as TOML.
+///
+/// Missing fields keep their current value when applied, so older files still load.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub shell: Option<ShellSettings>,
+    pub camera: Option<CameraSettings>,
+    pub desktop: Option<DesktopSettings>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ShellSettings {
+    pub resolution: Option<u32>,
+    pub shell_thickness: Option<f32>,
+    pub base_color: Option<[f32; 3]>,
+    pub tip_color: Option<[f32; 3]>,
+    pub wind_direction: Option<[f32; 2]>,
+    pub wind_strength: Option<f32>,
+    pub density: Option<f32>,
+    pub clumping: Option<f32>,
+    pub ao_strength: Option<f32>,
+    pub ambient: Option<f32>,
+    pub specular_strength: Option<f32>,
+    pub shininess: Option<f32>,
+    pub gravity: Option<[f32; 3]>,
+    pub stiffness: Option<f32>,
+    pub mask_threshold: Option<f32>,
+}
+
+/// Read before the window and Vulkan instance are created, so changes need a restart. Only
+/// edited by hand, saving keeps whatever the file has.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct DesktopSettings {
+    /// Loads the Khronos validation layer, `FURRY_VALIDATION` overrides it.
+    pub validation_layers: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CameraSettings {
+    pub position: Option<[f32; 3]>,
+    pub rx: Option<f32>,
+    pub ry: Option<f32>,
+    pub fov: Option<f32>,
+}
+
+impl RenderSettings {
+    pub fn capture(shell_renderer: &ShellRenderer, camera: &Camera) -> Self {
+        let (base_color, tip_color) = shell_renderer.colors();
+        let (wind_direction, wind_strength) = shell_renderer.wind();
+        let camera_state = camera.state();
+
+        Self {
+            shell: Some(ShellSettings {
+                resolution: Some(shell_renderer.resolution()),
+                shell_thickness: Some(shell_renderer.shell_thickness()),
+                base_color: Some(base_color),
+                tip_color: Some(tip_color),
+                wind_direction: Some(wind_direction),
+                wind_strength: Some(wind_strength),
+                density: Some(shell_renderer.density()),
+                clumping: Some(shell_renderer.clumping()),
+                ao_strength: Some(shell_renderer.ao_strength()),
+                ambient: Some(shell_renderer.ambient()),
+                specular_strength: Some(shell_renderer.specular_strength()),
+                shininess: Some(shell_renderer.shininess()),
+                gravity: Some(shell_renderer.gravity().into()),
+                stiffness: Some(shell_renderer.stiffness()),
+                mask_threshold: Some(shell_renderer.mask_threshold()),
+            }),
+            camera: Some(CameraSettings {
+                position: Some(camera_state.position.into()),
+                rx: Some(camera_state.rx),
+                ry: Some(camera_state.ry),
+                fov: Some(camera.fov()),
+            }),
+            desktop: None,
+        }
+    }
+
+    /// Sets every field that is present through the usual setters, so out of range values are
+    /// clamped the same as when set from code.
+    pub fn apply(&self, shell_renderer: &mut ShellRenderer, camera: &mut Camera) {
+        if let Some(shell) = &self.shell {
+            if let Some(resolution) = shell.resolution {
+                shell_renderer.set_resolution(resolution);
+            }
+            if let Some(shell_thickness) = shell.shell_thickness {
+                shell_renderer.set_shell_thickness(shell_thickness);
+            }
+            let (base_color, tip_color) = shell_renderer.colors();
+            shell_renderer.set_colors(
+                shell.base_color.unwrap_or(base_color),
+                shell.tip_color.unwrap_or(tip_color),
+            );
+            let (wind_direction, wind_strength) = shell_renderer.wind();
+            shell_renderer.set_wind(
+                shell.wind_direction.unwrap_or(wind_direction),
+                shell.wind_strength.unwrap_or(wind_strength),
+            );
+            if let Some(density) = shell.density {
+                shell_renderer.set_density(density);
+            }
+            if let Some(clumping) = shell.clumping {
+                shell_renderer.set_clumping(clumping);
+            }
+            if let Some(ao_strength) = shell.ao_strength {
+                shell_renderer.set_ao_strength(ao_strength);
+            }
+            if let Some(ambient) = shell.ambient {
+                shell_renderer.set_ambient(ambient);
+            }
+            if let Some(specular_strength) = shell.specular_strength {
+                shell_renderer.set_specular_strength(specular_strength);
+            }
+            if let Some(shininess) = shell.shininess {
+                shell_renderer.set_shininess(shininess);
+            }
+            if let Some(gravity) = shell.gravity {
+                shell_renderer.set_gravity(Vector3::from(gravity));
+            }
+            if let Some(stiffness) = shell.stiffness {
+                shell_renderer.set_stiffness(stiffness);
+            }
+            if let Some(mask_threshold) = shell.mask_threshold {
+                shell_renderer.set_mask_threshold(mask_threshold);
+            }
+        }
+
+        if let Some(camera_settings) = &self.camera {
+            let state = camera.state();
+            camera.set_state(CameraState {
+                position: camera_settings
+                    .position
+                    .map_or(state.position, Vector3::from),
+                rx: camera_settings.rx.unwrap_or(state.rx),
+                ry: camera_settings.ry.unwrap_or(state.ry),
+            });
+            if let Some(fov) = camera_settings.fov {
+                camera.set_fov(fov);
+            }
+        }
+    }
+
+    /// Reads the settings at `path`, `None` if there is no file there.
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to read the settings {}: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        };
+
+        toml::from_str(&contents).map(Some).map_err(|err| {
+            anyhow::anyhow!("Failed to parse the settings {}: {}", path.display(), err)
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = toml::to_string_pretty(self)
+            .map_err(|err| anyhow::anyhow!("Failed to serialize the settings: {}", err))?;
+        std::fs::write(path, contents).map_err(|err| {
+            anyhow::anyhow!("Failed to save the settings {}: {}", path.display(), err)
+        })
+    }
+}
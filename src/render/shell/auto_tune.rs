@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use pyrite::prelude::{Res, ResMut, Resource, Time};
+
+use super::ShellRenderer;
+use crate::render::frame_stats::FrameStats;
+
+const MIN_SHELL_COUNT: u32 = 16;
+
+// Frames skipped after changing the shell count so pipeline and driver hitches aren't measured.
+// This also covers the frames in flight, whose timestamps are still from the old shell count.
+const WARMUP_FRAMES: u32 = 10;
+const MEASURE_FRAMES: u32 = 30;
+
+/// Picks the starting shell count by stepping it up over the first frames until the GPU frame
+/// time goes over budget, then keeps the last count that fit.
+///
+/// The GPU time comes from the frame stats timestamp queries, so vsync and CPU time don't count
+/// against the budget. Devices without timestamp queries fall back to the CPU frame time, where
+/// the budget should sit above the refresh interval.
+#[derive(Resource)]
+pub struct ShellAutoTune {
+    enabled: bool,
+    target_frame_time: Duration,
+    state: AutoTuneState,
+}
+
+enum AutoTuneState {
+    Waiting,
+    Measuring {
+        shell_count: u32,
+        frames: u32,
+        // The frame stats measurement the last sample was taken from.
+        last_measured: u64,
+        samples: u32,
+        total: Duration,
+        best: Option<u32>,
+    },
+    Done(u32),
+}
+
+impl ShellAutoTune {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            // Leaves headroom under the 16.6ms of 60Hz for the CPU and the compositor.
+            target_frame_time: Duration::from_millis(12),
+            state: AutoTuneState::Waiting,
+        }
+    }
+
+    /// Skips the auto-tune and keeps the shell renderer's default shell count when disabled.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_target_frame_time(&mut self, target_frame_time: Duration) {
+        self.target_frame_time = target_frame_time;
+    }
+
+    /// The chosen shell count, `None` until the auto-tune has finished.
+    pub fn result(&self) -> Option<u32> {
+        match self.state {
+            AutoTuneState::Done(shell_count) => Some(shell_count),
+            _ => None,
+        }
+    }
+
+    pub(super) fn update_system(
+        mut auto_tune: ResMut<ShellAutoTune>,
+        mut shell_renderer: ResMut<ShellRenderer>,
+        frame_stats: Res<FrameStats>,
+        time: Res<Time>,
+    ) {
+        let auto_tune = &mut *auto_tune;
+        if !auto_tune.enabled || !shell_renderer.is_ready() {
+            return;
+        }
+
+        match &mut auto_tune.state {
+            AutoTuneState::Waiting => {
+                shell_renderer.set_shell_count(MIN_SHELL_COUNT);
+                auto_tune.state = AutoTuneState::Measuring {
+                    shell_count: MIN_SHELL_COUNT,
+                    frames: 0,
+                    last_measured: frame_stats.frames_measured(),
+                    samples: 0,
+                    total: Duration::ZERO,
+                    best: None,
+                };
+            }
+            AutoTuneState::Measuring {
+                shell_count,
+                frames,
+                last_measured,
+                samples,
+                total,
+                best,
+            } => {
+                *frames += 1;
+                if *frames <= WARMUP_FRAMES {
+                    *last_measured = frame_stats.frames_measured();
+                    return;
+                }
+
+                // Only frames whose timestamps were read back since the last sample count.
+                if frame_stats.is_supported() {
+                    if frame_stats.frames_measured() == *last_measured {
+                        return;
+                    }
+                    *last_measured = frame_stats.frames_measured();
+                    *total += Duration::from_secs_f64(frame_stats.last_total_ms() / 1000.0);
+                } else {
+                    *total += time.delta();
+                }
+                *samples += 1;
+                if *samples < MEASURE_FRAMES {
+                    return;
+                }
+
+                let average = *total / MEASURE_FRAMES;
+                println!(
+                    "Auto-tune: {} shells took {:.2}ms",
+                    shell_count,
+                    average.as_secs_f64() * 1000.0
+                );
+
+                let next_shell_count = *shell_count * 3 / 2;
+                if average <= auto_tune.target_frame_time
+                    && next_shell_count <= shell_renderer.max_shells()
+                {
+                    *best = Some(*shell_count);
+                    *shell_count = next_shell_count;
+                    *frames = 0;
+                    *samples = 0;
+                    *total = Duration::ZERO;
+                    shell_renderer.set_shell_count(next_shell_count);
+                    return;
+                }
+
+                let chosen = if average <= auto_tune.target_frame_time {
+                    *shell_count
+                } else {
+                    best.unwrap_or(MIN_SHELL_COUNT)
+                };
+                println!("Auto-tune: picked {} shells", chosen);
+                shell_renderer.set_shell_count(chosen);
+                auto_tune.state = AutoTuneState::Done(chosen);
+            }
+            AutoTuneState::Done(_) => {}
+        }
+    }
+}
@@ -1,6 +1,13 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    fmt,
+    panic::AssertUnwindSafe,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
 use ash::vk;
+use nalgebra::{Matrix3, Matrix4, Point3, Vector3};
 use pyrite::vulkan::{BufferInfo, UntypedBuffer, Vulkan, VulkanAllocator, VulkanStager};
 
 // Align to 16 bytes for GLSL compatibility.
@@ -8,6 +15,7 @@ use pyrite::vulkan::{BufferInfo, UntypedBuffer, Vulkan, VulkanAllocator, VulkanS
 // From the OpenGL spec:
 // "If the member is a three-component vector with components consuming N basic machine units, the base alignment is 4N."
 // See https://registry.khronos.org/OpenGL/specs/gl/glspec45.core.pdf#page=159 for more info.
+#[derive(Clone, Copy)]
 #[repr(align(16))]
 struct GlslVec3f {
     x: f32,
@@ -15,24 +23,56 @@ struct GlslVec3f {
     z: f32,
 }
 
+#[derive(Clone, Copy)]
 #[repr(align(8))]
 struct GlslVec2f {
     x: f32,
     y: f32,
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Vertex {
     position: GlslVec3f,
     uv: GlslVec2f,
     normal: GlslVec3f,
+    // Points along increasing u, perpendicular to the normal.
+    tangent: GlslVec3f,
+    // Multiplies the fur color, white unless the mesh has painted colors.
+    color: GlslVec3f,
+}
+
+// Every vec3 takes a full 16 bytes and the vec2 is padded up to the next vec3, the attribute
+// offsets below assume this layout.
+const _: () = assert!(std::mem::size_of::<Vertex>() == 80);
+
+// Generated meshes have exactly duplicated vertices, this only absorbs floating point error.
+const SMOOTH_WELD_EPSILON: f32 = 1e-5;
+/// Faces meeting at up to this many degrees are smoothed together, 180 smooths every face.
+pub const DEFAULT_SMOOTHING_ANGLE: f32 = 180.0;
+
+/// Every fin is two triangles, must match FIN_HEIGHTS in fin.vert.
+pub const FIN_VERTICES_PER_EDGE: usize = 6;
+
+// An edge between two triangles, vertices split by a uv seam or hard normal still share their
+// edges. The second triangle is None on the boundary of an open mesh.
+struct MeshEdge {
+    vertices: [u32; 2],
+    triangles: (u32, Option<u32>),
 }
 
 pub struct Mesh {
     vertices: Vec<Vertex>,
     indices: Vec<u32>,
+    // The min and max corners of the box around every vertex, then the center and radius of a
+    // sphere around them, both in mesh space.
+    bounding_box: (Vector3<f32>, Vector3<f32>),
+    bounding_sphere: (Vector3<f32>, f32),
+    edges: Vec<MeshEdge>,
     vertex_buffer: Arc<UntypedBuffer>,
     index_buffer: Arc<UntypedBuffer>,
+    // Every edge once as a line list, for drawing the wireframe.
+    edge_index_buffer: Arc<UntypedBuffer>,
 }
 
 impl Mesh {
@@ -92,14 +132,108 @@ impl Mesh {
             )
         };
 
+        let bounding_box = compute_bounding_box(&vertices);
+        let bounding_sphere = compute_bounding_sphere(&vertices, bounding_box);
+        let edges = compute_edges(&vertices, &indices);
+
+        let edge_indices = edges
+            .iter()
+            .flat_map(|edge| edge.vertices)
+            .collect::<Vec<_>>();
+        let edge_index_buffer = Arc::new(UntypedBuffer::new(
+            vulkan,
+            vulkan_allocator,
+            &BufferInfo::builder()
+                .size((edge_indices.len() * std::mem::size_of::<u32>()) as u64)
+                .usage(vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                .build(),
+        ));
+
+        let data_ptr = edge_indices.as_slice().as_ptr() as *const u8;
+        let data_size = edge_indices.len() * std::mem::size_of::<u32>();
+
+        unsafe {
+            vulkan_stager.schedule_stage_buffer(
+                vulkan,
+                vulkan_allocator,
+                data_ptr,
+                data_size as u64,
+                &edge_index_buffer,
+                pyrite::vulkan::StageType::Immediate,
+            )
+        };
+
         Self {
             vertices,
             indices,
+            bounding_box,
+            bounding_sphere,
+            edges,
             vertex_buffer,
             index_buffer,
+            edge_index_buffer,
         }
     }
 
+    /// Creates a new mesh with vertices that are within `epsilon` of each other merged.
+    ///
+    /// Vertices are only merged if their uv and normal also match within `epsilon`, so seams
+    /// and hard edges are kept.
+    pub fn weld(
+        &self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+        epsilon: f32,
+    ) -> Mesh {
+        let (vertices, indices) = weld_vertices(&self.vertices, &self.indices, epsilon, true);
+        println!(
+            "Welded mesh from {} to {} vertices",
+            self.vertices.len(),
+            vertices.len()
+        );
+
+        Mesh::new(vulkan, vulkan_allocator, vulkan_stager, vertices, indices)
+    }
+
+    /// Merges duplicated vertices whose position, uv and normal match, remapping the indices
+    /// and staging the compacted buffers to the GPU again.
+    ///
+    /// The tangents of merged vertices are recomputed from all the faces sharing them.
+    pub fn optimize(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+    ) {
+        let (mut vertices, indices) = optimize_vertices(&self.vertices, &self.indices);
+        compute_uv_tangents(&mut vertices, &indices);
+        *self = Mesh::new(vulkan, vulkan_allocator, vulkan_stager, vertices, indices);
+    }
+
+    /// Replaces the normals with smooth normals averaged from the surrounding faces.
+    ///
+    /// Only faces meeting at `smoothing_angle` degrees or less are averaged together, vertices
+    /// on sharper edges are split so the crease stays hard. 180 smooths across every edge.
+    ///
+    /// Duplicated vertices are welded first, which changes the vertex count and index buffer,
+    /// and the new buffers are staged to the GPU again.
+    pub fn recompute_smooth_normals(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+        smoothing_angle: f32,
+    ) {
+        let (vertices, indices) = smooth_vertex_normals(
+            &self.vertices,
+            &self.indices,
+            SMOOTH_WELD_EPSILON,
+            smoothing_angle,
+        );
+        *self = Mesh::new(vulkan, vulkan_allocator, vulkan_stager, vertices, indices);
+    }
+
     pub fn vk_vertex_input_binding_description() -> vk::VertexInputBindingDescription {
         vk::VertexInputBindingDescription::builder()
             .binding(0)
@@ -108,7 +242,7 @@ impl Mesh {
             .build()
     }
 
-    pub fn vk_vertex_input_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+    pub fn vk_vertex_input_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 5] {
         [
             vk::VertexInputAttributeDescription::builder()
                 .binding(0)
@@ -126,9 +260,19 @@ impl Mesh {
                 .binding(0)
                 .location(2)
                 .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(
-                    (std::mem::align_of::<GlslVec3f>() * 2) as u32,
-                )
+                .offset((std::mem::align_of::<GlslVec3f>() * 2) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(3)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset((std::mem::align_of::<GlslVec3f>() * 3) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(4)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset((std::mem::align_of::<GlslVec3f>() * 4) as u32)
                 .build(),
         ]
     }
@@ -140,6 +284,15 @@ impl Mesh {
             .build()
     }
 
+    /// Draws `edge_index_buffer` as lines, which unlike `vk::PolygonMode::LINE` doesn't need the
+    /// `fillModeNonSolid` device feature.
+    pub fn vk_edge_input_assembly_info() -> vk::PipelineInputAssemblyStateCreateInfo {
+        vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::LINE_LIST)
+            .primitive_restart_enable(false)
+            .build()
+    }
+
     pub fn vertex_buffer(&self) -> &Arc<UntypedBuffer> {
         &self.vertex_buffer
     }
@@ -151,12 +304,342 @@ impl Mesh {
     pub fn vertex_count(&self) -> usize {
         self.indices.len()
     }
+
+    pub fn edge_index_buffer(&self) -> &Arc<UntypedBuffer> {
+        &self.edge_index_buffer
+    }
+
+    pub fn edge_index_count(&self) -> usize {
+        self.edges.len() * 2
+    }
+
+    /// The min and max corners of the axis aligned box containing the whole mesh, in mesh space.
+    pub fn bounding_box(&self) -> (Vector3<f32>, Vector3<f32>) {
+        self.bounding_box
+    }
+
+    /// The center and radius of a sphere containing the whole mesh, in mesh space.
+    pub fn bounding_sphere(&self) -> (Vector3<f32>, f32) {
+        self.bounding_sphere
+    }
+
+    /// The edges between a triangle facing `eye` and one facing away from it, plus the edges of
+    /// any holes, as pairs of vertex indices. `eye` is in mesh space.
+    pub fn silhouette_edges(&self, eye: Vector3<f32>) -> Vec<[u32; 2]> {
+        silhouette_edges(&self.vertices, &self.indices, &self.edges, eye)
+    }
+
+    /// A quad for each edge, `FIN_VERTICES_PER_EDGE` vertices apiece, with both ends of the edge
+    /// repeated in the order fin.vert extrudes them in.
+    pub fn fin_vertices(&self, edges: &[[u32; 2]]) -> Vec<Vertex> {
+        let mut vertices = Vec::with_capacity(edges.len() * FIN_VERTICES_PER_EDGE);
+        for &[a, b] in edges {
+            let (a, b) = (self.vertices[a as usize], self.vertices[b as usize]);
+            vertices.extend([a, b, b, a, b, a]);
+        }
+        vertices
+    }
+
+    /// The point on the surface of the mesh closest to `point`, both in mesh space.
+    pub fn closest_point(&self, point: Vector3<f32>) -> Option<Vector3<f32>> {
+        let position = |index: u32| {
+            let position = &self.vertices[index as usize].position;
+            Vector3::new(position.x, position.y, position.z)
+        };
+
+        self.indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                closest_point_on_triangle(
+                    point,
+                    position(triangle[0]),
+                    position(triangle[1]),
+                    position(triangle[2]),
+                )
+            })
+            .min_by(|a, b| {
+                (a - point)
+                    .magnitude_squared()
+                    .total_cmp(&(b - point).magnitude_squared())
+            })
+    }
+}
+
+#[derive(Debug)]
+pub enum MeshLoadError {
+    Io(String, std::io::Error),
+    Parse {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    Empty(String),
+    /// The glTF file couldn't be imported or doesn't contain a usable mesh.
+    Gltf(String, String),
+    /// The loader panicked, a bug in the parser rather than the file.
+    Panicked(String, String),
+}
+
+impl fmt::Display for MeshLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshLoadError::Io(path, err) => write!(f, "Failed to read mesh {}: {}", path, err),
+            MeshLoadError::Parse {
+                path,
+                line,
+                message,
+            } => write!(
+                f,
+                "Failed to parse mesh {} at line {}: {}",
+                path, line, message
+            ),
+            MeshLoadError::Empty(path) => write!(f, "Mesh {} has no faces", path),
+            MeshLoadError::Gltf(path, message) => {
+                write!(f, "Failed to load glTF mesh {}: {}", path, message)
+            }
+            MeshLoadError::Panicked(path, message) => {
+                write!(f, "Loading mesh {} panicked: {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshLoadError {}
+
+/// The vertices and indices of a mesh before its buffers are created, parsing needs no GPU
+/// access so it can happen on any thread.
+pub struct MeshData {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// Loads a Wavefront OBJ file, polygons are triangulated as fans.
+    ///
+    /// Faces without normals get a flat normal from their triangle, and without uvs get (0, 0).
+    /// Vertex colors are read from positions written as `v x y z r g b`, others are white.
+    pub fn from_obj(path: &str) -> Result<Self, MeshLoadError> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| MeshLoadError::Io(path.to_string(), err))?;
+        let (vertices, indices) =
+            parse_obj(&source).map_err(|(line, message)| MeshLoadError::Parse {
+                path: path.to_string(),
+                line,
+                message,
+            })?;
+        if indices.is_empty() {
+            return Err(MeshLoadError::Empty(path.to_string()));
+        }
+
+        let colors = vertices.iter().map(|(_, color)| *color).collect::<Vec<_>>();
+        let mut vertices = into_vertices(vertices.into_iter().map(|(vertex, _)| vertex).collect());
+        set_vertex_colors(&mut vertices, colors);
+        compute_uv_tangents(&mut vertices, &indices);
+
+        Ok(Self { vertices, indices })
+    }
+
+    /// Loads the first primitive of mesh `mesh_index` in a glTF file, either a `.glb` or a
+    /// `.gltf` with embedded or external buffers.
+    ///
+    /// Only triangle lists are supported. The transform of the first node in the default scene
+    /// using the mesh is applied, including its parents. Primitives without normals get smooth
+    /// normals, without uvs get (0, 0) and without indices are drawn in vertex order. Vertex
+    /// colors are read from `COLOR_0` if the primitive has them.
+    pub fn from_gltf(path: &str, mesh_index: usize) -> Result<Self, MeshLoadError> {
+        let gltf_error = |message: String| MeshLoadError::Gltf(path.to_string(), message);
+        let (document, buffers, _) =
+            gltf::import(path).map_err(|err| gltf_error(err.to_string()))?;
+
+        let mesh = document.meshes().nth(mesh_index).ok_or_else(|| {
+            gltf_error(format!(
+                "mesh {} doesn't exist, the file has {} meshes",
+                mesh_index,
+                document.meshes().len()
+            ))
+        })?;
+        let primitive = mesh
+            .primitives()
+            .next()
+            .ok_or_else(|| gltf_error(format!("mesh {} has no primitives", mesh_index)))?;
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err(gltf_error(format!(
+                "mesh {} is drawn as {:?}, only triangle lists are supported",
+                mesh_index,
+                primitive.mode()
+            )));
+        }
+
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .ok_or_else(|| gltf_error(format!("mesh {} has no positions", mesh_index)))?
+            .collect();
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+        let uvs: Option<Vec<[f32; 2]>> = reader
+            .read_tex_coords(0)
+            .map(|uvs| uvs.into_f32().collect());
+        let colors: Option<Vec<[f32; 3]>> = reader
+            .read_colors(0)
+            .map(|colors| colors.into_rgb_f32().collect());
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if indices.len() < 3 {
+            return Err(MeshLoadError::Empty(path.to_string()));
+        }
+        // Every attribute is indexed like the positions, so a shorter one would run out.
+        for (name, len) in [
+            ("normals", normals.as_ref().map(Vec::len)),
+            ("uvs", uvs.as_ref().map(Vec::len)),
+            ("colors", colors.as_ref().map(Vec::len)),
+        ] {
+            if let Some(len) = len.filter(|len| *len != positions.len()) {
+                return Err(gltf_error(format!(
+                    "mesh {} has {} {} for {} positions",
+                    mesh_index,
+                    len,
+                    name,
+                    positions.len()
+                )));
+            }
+        }
+        if let Some(index) = indices
+            .iter()
+            .find(|index| **index as usize >= positions.len())
+        {
+            return Err(gltf_error(format!(
+                "index {} is out of range of the {} vertices",
+                index,
+                positions.len()
+            )));
+        }
+
+        // Normals go through the inverse transpose so they stay perpendicular to the surface
+        // under non-uniform scale.
+        let transform =
+            gltf_mesh_transform(&document, mesh_index).unwrap_or_else(Matrix4::identity);
+        let normal_transform = transform
+            .fixed_view::<3, 3>(0, 0)
+            .into_owned()
+            .try_inverse()
+            .map_or_else(Matrix3::identity, |inverse| inverse.transpose());
+
+        let vertices = positions
+            .iter()
+            .enumerate()
+            .map(|(index, position)| {
+                let position = transform.transform_point(&Point3::from(*position));
+                let normal = normals.as_ref().map_or(Vector3::y(), |normals| {
+                    (normal_transform * Vector3::from(normals[index]))
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or(Vector3::y())
+                });
+                let uv = uvs.as_ref().map_or([0.0, 0.0], |uvs| uvs[index]);
+                (
+                    (position.x, position.y, position.z),
+                    (uv[0], uv[1]),
+                    (normal.x, normal.y, normal.z),
+                )
+            })
+            .collect();
+
+        let mut vertices = into_vertices(vertices);
+        if let Some(colors) = colors {
+            set_vertex_colors(&mut vertices, colors);
+        }
+        let (mut vertices, indices) = if normals.is_none() {
+            smooth_vertex_normals(
+                &vertices,
+                &indices,
+                SMOOTH_WELD_EPSILON,
+                DEFAULT_SMOOTHING_ANGLE,
+            )
+        } else {
+            (vertices, indices)
+        };
+        compute_uv_tangents(&mut vertices, &indices);
+
+        Ok(Self { vertices, indices })
+    }
+}
+
+/// A mesh file parsing on a worker thread, so large models don't stall the frame loading them.
+///
+/// Poll `is_ready`, then `take` the parsed mesh or the error and upload it with
+/// `MeshFactory::create_from_data` on the main thread.
+pub struct MeshHandle {
+    path: String,
+    result: Arc<Mutex<Option<Result<MeshData, MeshLoadError>>>>,
+}
+
+impl MeshHandle {
+    /// Starts parsing the file at `path`, `.gltf` and `.glb` files load their first mesh and
+    /// anything else is read as OBJ.
+    pub fn load(path: impl Into<String>) -> Self {
+        let path = path.into();
+        let result = Arc::new(Mutex::new(None));
+
+        let thread_path = path.clone();
+        let thread_result = result.clone();
+        std::thread::spawn(move || {
+            let is_gltf = Path::new(&thread_path)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+                });
+            let data = catch_load_panic(&thread_path, || {
+                if is_gltf {
+                    MeshData::from_gltf(&thread_path, 0)
+                } else {
+                    MeshData::from_obj(&thread_path)
+                }
+            });
+            *thread_result.lock().unwrap() = Some(data);
+        });
+
+        Self { path, result }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Whether parsing has finished, successfully or not.
+    pub fn is_ready(&self) -> bool {
+        self.result.lock().unwrap().is_some()
+    }
+
+    /// The parsed mesh or why it failed once ready, `None` before then or once taken.
+    pub fn take(&self) -> Option<Result<MeshData, MeshLoadError>> {
+        self.result.lock().unwrap().take()
+    }
+}
+
+/// Runs `load`, turning a panic into an error so the handle still becomes ready.
+fn catch_load_panic(
+    path: &str,
+    load: impl FnOnce() -> Result<MeshData, MeshLoadError>,
+) -> Result<MeshData, MeshLoadError> {
+    std::panic::catch_unwind(AssertUnwindSafe(load)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_owned());
+        Err(MeshLoadError::Panicked(path.to_owned(), message))
+    })
 }
 
 pub struct MeshFactory<'a, 'b, 'c> {
     vulkan: &'a Vulkan,
     vulkan_allocator: &'b mut VulkanAllocator,
     vulkan_stager: &'c mut VulkanStager,
+    smooth_normals: bool,
+    smoothing_angle: f32,
+    optimize: bool,
 }
 
 impl<'a, 'b, 'c> MeshFactory<'a, 'b, 'c> {
@@ -169,19 +652,97 @@ impl<'a, 'b, 'c> MeshFactory<'a, 'b, 'c> {
             vulkan,
             vulkan_allocator,
             vulkan_stager,
+            smooth_normals: false,
+            smoothing_angle: DEFAULT_SMOOTHING_ANGLE,
+            optimize: false,
         }
     }
 
-    /// Creates a plane on the XZ plane.
-    pub fn create_plane(&mut self) -> Mesh {
-        let vertices = into_vertices(vec![
-            ((0.0, 0.0, 0.0), (0.0, 0.0), (0.0, 1.0, 0.0)),
-            ((1.0, 0.0, 0.0), (1.0, 0.0), (0.0, 1.0, 0.0)),
-            ((1.0, 0.0, 1.0), (1.0, 1.0), (0.0, 1.0, 0.0)),
-            ((0.0, 0.0, 1.0), (0.0, 1.0), (0.0, 1.0, 0.0)),
-        ]);
+    /// Merges the duplicated vertices of the generated icosahedron spheres, see `Mesh::optimize`.
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Recomputes smooth normals on the generated spheres and cylinders.
+    ///
+    /// This welds duplicated vertices, so the meshes end up with fewer vertices.
+    pub fn smooth_normals(mut self, smooth_normals: bool) -> Self {
+        self.smooth_normals = smooth_normals;
+        self
+    }
+
+    /// The largest angle in degrees between faces that are smoothed together, see
+    /// `Mesh::recompute_smooth_normals`. Defaults to `DEFAULT_SMOOTHING_ANGLE`.
+    pub fn smoothing_angle(mut self, smoothing_angle: f32) -> Self {
+        self.smoothing_angle = smoothing_angle;
+        self
+    }
+
+    fn create_mesh(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>) -> Mesh {
+        let (vertices, indices) = if self.smooth_normals {
+            smooth_vertex_normals(
+                &vertices,
+                &indices,
+                SMOOTH_WELD_EPSILON,
+                self.smoothing_angle,
+            )
+        } else {
+            (vertices, indices)
+        };
+
+        Mesh::new(
+            self.vulkan,
+            self.vulkan_allocator,
+            self.vulkan_stager,
+            vertices,
+            indices,
+        )
+    }
+
+    /// Creates a `size` by `size` grid on the XZ plane starting at the origin, with
+    /// `subdivisions` cells along each side. Zero subdivisions is the same single quad as one.
+    pub fn create_plane(&mut self, size: f32, subdivisions: u32) -> Mesh {
+        let cells = subdivisions.max(1);
+        let mut vertices = Vec::with_capacity(((cells + 1) * (cells + 1)) as usize);
+        let mut indices = Vec::with_capacity((cells * cells * 6) as usize);
+
+        // u runs along X, so that is the tangent.
+        for z in 0..=cells {
+            for x in 0..=cells {
+                let u = x as f32 / cells as f32;
+                let v = z as f32 / cells as f32;
+                vertices.push((
+                    (u * size, 0.0, v * size),
+                    (u, v),
+                    (0.0, 1.0, 0.0),
+                    (1.0, 0.0, 0.0),
+                ));
+            }
+        }
+
+        for z in 0..cells {
+            for x in 0..cells {
+                let a = z * (cells + 1) + x;
+                let b = a + 1;
+                let c = a + cells + 2;
+                let d = a + cells + 1;
+                indices.extend([a, b, c, c, d, a]);
+            }
+        }
+
+        Mesh::new(
+            self.vulkan,
+            self.vulkan_allocator,
+            self.vulkan_stager,
+            into_vertices_with_tangents(vertices),
+            indices,
+        )
+    }
 
-        let indices = vec![0, 1, 2, 2, 3, 0];
+    /// Creates a cube centered on the origin, each face has its own vertices so the normals stay flat.
+    pub fn create_cube(&mut self, size: f32) -> Mesh {
+        let (vertices, indices) = cube_geometry(size);
 
         Mesh::new(
             self.vulkan,
@@ -192,6 +753,118 @@ impl<'a, 'b, 'c> MeshFactory<'a, 'b, 'c> {
         )
     }
 
+    /// Creates a cylinder along the Y axis centered on the origin, optionally closed with caps.
+    pub fn create_cylinder(
+        &mut self,
+        radius: f32,
+        height: f32,
+        segments: u32,
+        capped: bool,
+    ) -> Mesh {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let half_height = height / 2.0;
+        let segments = segments.max(3);
+
+        // Add side vertices, the seam is duplicated so the uvs can wrap around from 0 to 1.
+        for i in 0..=segments {
+            let theta = 2.0 * std::f32::consts::PI * (i as f32) / (segments as f32);
+            let x = theta.cos();
+            let z = theta.sin();
+            let u = (i as f32) / (segments as f32);
+            // u follows theta, so the tangent is the direction around the circle.
+            let tangent = (-z, 0.0, x);
+            vertices.push((
+                (x * radius, half_height, z * radius),
+                (u, 0.0),
+                (x, 0.0, z),
+                tangent,
+            ));
+            vertices.push((
+                (x * radius, -half_height, z * radius),
+                (u, 1.0),
+                (x, 0.0, z),
+                tangent,
+            ));
+        }
+
+        // Add side quads.
+        for i in 0..segments {
+            let top = i * 2;
+            let bottom = i * 2 + 1;
+            let next_top = (i + 1) * 2;
+            let next_bottom = (i + 1) * 2 + 1;
+            indices.push(top);
+            indices.push(next_top);
+            indices.push(next_bottom);
+            indices.push(next_bottom);
+            indices.push(bottom);
+            indices.push(top);
+        }
+
+        // Add cap triangle fans, the uvs are the cap projected onto a disc.
+        if capped {
+            for (y, normal_y) in [(half_height, 1.0), (-half_height, -1.0)] {
+                let center = vertices.len() as u32;
+                // The disc uvs run u along X.
+                vertices.push((
+                    (0.0, y, 0.0),
+                    (0.5, 0.5),
+                    (0.0, normal_y, 0.0),
+                    (1.0, 0.0, 0.0),
+                ));
+                for i in 0..segments {
+                    let theta = 2.0 * std::f32::consts::PI * (i as f32) / (segments as f32);
+                    let x = theta.cos();
+                    let z = theta.sin();
+                    vertices.push((
+                        (x * radius, y, z * radius),
+                        (0.5 + x * 0.5, 0.5 + z * 0.5),
+                        (0.0, normal_y, 0.0),
+                        (1.0, 0.0, 0.0),
+                    ));
+                }
+
+                for i in 0..segments {
+                    let a = center + 1 + i;
+                    let b = center + 1 + (i + 1) % segments;
+                    indices.push(center);
+                    // Flip the winding of the bottom cap so both caps face outward.
+                    if normal_y > 0.0 {
+                        indices.push(b);
+                        indices.push(a);
+                    } else {
+                        indices.push(a);
+                        indices.push(b);
+                    }
+                }
+            }
+        }
+
+        self.create_mesh(into_vertices_with_tangents(vertices), indices)
+    }
+
+    /// Loads a Wavefront OBJ file, see `MeshData::from_obj`.
+    pub fn from_obj(&mut self, path: &str) -> Result<Mesh, MeshLoadError> {
+        Ok(self.create_from_data(MeshData::from_obj(path)?))
+    }
+
+    /// Loads a mesh from a glTF file, see `MeshData::from_gltf`.
+    pub fn from_gltf(&mut self, path: &str, mesh_index: usize) -> Result<Mesh, MeshLoadError> {
+        Ok(self.create_from_data(MeshData::from_gltf(path, mesh_index)?))
+    }
+
+    /// Uploads geometry parsed ahead of time, such as by a `MeshHandle`.
+    pub fn create_from_data(&mut self, data: MeshData) -> Mesh {
+        Mesh::new(
+            self.vulkan,
+            self.vulkan_allocator,
+            self.vulkan_stager,
+            data.vertices,
+            data.indices,
+        )
+    }
+
     /// Create a sphere with the given subdvisions.
     pub fn create_sphere_uv(&mut self, slices: u32, stacks: u32) -> Mesh {
         let mut vertices = Vec::new();
@@ -243,16 +916,33 @@ impl<'a, 'b, 'c> MeshFactory<'a, 'b, 'c> {
             indices.push(vertices.len() as u32 - 2 - (i + 1) % slices);
         }
 
-        Mesh::new(
-            self.vulkan,
-            self.vulkan_allocator,
-            self.vulkan_stager,
-            into_vertices(vertices),
-            indices,
-        )
+        let mut vertices = into_vertices(vertices);
+        compute_uv_tangents(&mut vertices, &indices);
+        self.create_mesh(vertices, indices)
     }
 
     pub fn create_sphere_icosahedron(&mut self, subdivisions: u32) -> Mesh {
+        let (vertices, indices) = Self::subdivided_icosahedron(subdivisions);
+
+        // Every subdivision pushes new midpoints per triangle, so each edge midpoint is there
+        // once for both triangles sharing the edge.
+        let (mut vertices, indices) = if self.optimize {
+            optimize_vertices(&into_vertices(vertices), &indices)
+        } else {
+            (into_vertices(vertices), indices)
+        };
+        compute_uv_tangents(&mut vertices, &indices);
+        self.create_mesh(vertices, indices)
+    }
+
+    // Splits every triangle into four per subdivision, with the new corners pushed out onto the
+    // unit sphere.
+    fn subdivided_icosahedron(
+        subdivisions: u32,
+    ) -> (
+        Vec<((f32, f32, f32), (f32, f32), (f32, f32, f32))>,
+        Vec<u32>,
+    ) {
         let (vertices, indices) = Self::icosahedron();
 
         let mut vertices = vertices;
@@ -324,14 +1014,7 @@ impl<'a, 'b, 'c> MeshFactory<'a, 'b, 'c> {
             indices = new_indices;
             println!("Subdivision {} done, has {} indices", i, indices.len());
         }
-
-        Mesh::new(
-            self.vulkan,
-            self.vulkan_allocator,
-            self.vulkan_stager,
-            into_vertices(vertices),
-            indices,
-        )
+        (vertices, indices)
     }
 
     fn icosahedron() -> (
@@ -396,10 +1079,299 @@ impl<'a, 'b, 'c> MeshFactory<'a, 'b, 'c> {
     }
 }
 
+/// The world transform of the first node using the mesh, searching the default scene or the
+/// first scene if there is no default.
+fn gltf_mesh_transform(document: &gltf::Document, mesh_index: usize) -> Option<Matrix4<f32>> {
+    fn find(node: gltf::Node, parent: Matrix4<f32>, mesh_index: usize) -> Option<Matrix4<f32>> {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if node.mesh().map(|mesh| mesh.index()) == Some(mesh_index) {
+            return Some(transform);
+        }
+        node.children()
+            .find_map(|child| find(child, transform, mesh_index))
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())?;
+    scene
+        .nodes()
+        .find_map(|node| find(node, Matrix4::identity(), mesh_index))
+}
+
+type ObjVertex = (((f32, f32, f32), (f32, f32), (f32, f32, f32)), [f32; 3]);
+
+/// Parses the positions, uvs, normals and faces of an OBJ file, errors are the line number and reason.
+fn parse_obj(source: &str) -> Result<(Vec<ObjVertex>, Vec<u32>), (usize, String)> {
+    let mut positions = Vec::new();
+    // White for positions written without a color.
+    let mut colors = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    // Vertices reused by faces referencing the same position, uv and normal.
+    let mut shared_vertices: HashMap<(usize, Option<usize>, usize), u32> = HashMap::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut parts = line.split_whitespace();
+        let Some(keyword) = parts.next() else {
+            continue;
+        };
+
+        let parse_floats = |parts: std::str::SplitWhitespace, count: usize| {
+            let values = parts
+                .take(count)
+                .map(|part| part.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| (line_number, format!("Invalid number: {}", err)))?;
+            if values.len() < count {
+                return Err((line_number, format!("Expected {} numbers", count)));
+            }
+            Ok(values)
+        };
+
+        match keyword {
+            "v" => {
+                let has_color = parts.clone().count() >= 6;
+                let v = parse_floats(parts, if has_color { 6 } else { 3 })?;
+                positions.push((v[0], v[1], v[2]));
+                colors.push(if has_color {
+                    [v[3], v[4], v[5]]
+                } else {
+                    [1.0; 3]
+                });
+            }
+            "vt" => {
+                let v = parse_floats(parts, 2)?;
+                uvs.push((v[0], v[1]));
+            }
+            "vn" => {
+                let v = parse_floats(parts, 3)?;
+                normals.push((v[0], v[1], v[2]));
+            }
+            "f" => {
+                let corners = parts
+                    .map(|part| {
+                        parse_obj_corner(part, positions.len(), uvs.len(), normals.len())
+                            .map_err(|message| (line_number, message))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err((line_number, "A face needs at least 3 vertices".to_string()));
+                }
+
+                for i in 1..corners.len() - 1 {
+                    let triangle = [corners[0], corners[i], corners[i + 1]];
+
+                    // Without normals the triangle gets its own vertices with a flat normal.
+                    if triangle.iter().any(|(_, _, normal)| normal.is_none()) {
+                        let [a, b, c] = triangle.map(|(position, _, _)| positions[position]);
+                        let normal = flat_normal(a, b, c);
+                        for (position, uv, _) in triangle {
+                            indices.push(vertices.len() as u32);
+                            vertices.push((
+                                (
+                                    positions[position],
+                                    uv.map_or((0.0, 0.0), |uv| uvs[uv]),
+                                    normal,
+                                ),
+                                colors[position],
+                            ));
+                        }
+                        continue;
+                    }
+
+                    for (position, uv, normal) in triangle {
+                        let normal = normal.unwrap();
+                        let index = *shared_vertices
+                            .entry((position, uv, normal))
+                            .or_insert_with(|| {
+                                vertices.push((
+                                    (
+                                        positions[position],
+                                        uv.map_or((0.0, 0.0), |uv| uvs[uv]),
+                                        normals[normal],
+                                    ),
+                                    colors[position],
+                                ));
+                                vertices.len() as u32 - 1
+                            });
+                        indices.push(index);
+                    }
+                }
+            }
+            // Objects, groups, materials and smoothing groups don't affect the geometry.
+            _ => {}
+        }
+    }
+
+    Ok((vertices, indices))
+}
+
+/// Parses a face corner of the form `v`, `v/vt`, `v//vn` or `v/vt/vn` into zero based indices.
+fn parse_obj_corner(
+    corner: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    // OBJ indices start at 1, and negative indices count back from the last element.
+    let resolve = |index: &str, count: usize| -> Result<Option<usize>, String> {
+        if index.is_empty() {
+            return Ok(None);
+        }
+        let index = index
+            .parse::<i64>()
+            .map_err(|_| format!("Invalid face index '{}'", corner))?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(format!("Face index '{}' is out of range", corner));
+        }
+        Ok(Some(resolved as usize))
+    };
+
+    let mut parts = corner.split('/');
+    let position = resolve(parts.next().unwrap_or(""), position_count)?
+        .ok_or_else(|| format!("Face corner '{}' has no position", corner))?;
+    let uv = resolve(parts.next().unwrap_or(""), uv_count)?;
+    let normal = resolve(parts.next().unwrap_or(""), normal_count)?;
+    Ok((position, uv, normal))
+}
+
+fn flat_normal(a: (f32, f32, f32), b: (f32, f32, f32), c: (f32, f32, f32)) -> (f32, f32, f32) {
+    let ab = (b.0 - a.0, b.1 - a.1, b.2 - a.2);
+    let ac = (c.0 - a.0, c.1 - a.1, c.2 - a.2);
+    let cross = (
+        ab.1 * ac.2 - ab.2 * ac.1,
+        ab.2 * ac.0 - ab.0 * ac.2,
+        ab.0 * ac.1 - ab.1 * ac.0,
+    );
+    let length = (cross.0 * cross.0 + cross.1 * cross.1 + cross.2 * cross.2).sqrt();
+    if length == 0.0 {
+        return (0.0, 1.0, 0.0);
+    }
+    (cross.0 / length, cross.1 / length, cross.2 / length)
+}
+
+/// Finds the closest point on the triangle by checking which vertex, edge or face region the
+/// point projects into, from Real-Time Collision Detection by Christer Ericson.
+fn closest_point_on_triangle(
+    p: Vector3<f32>,
+    a: Vector3<f32>,
+    b: Vector3<f32>,
+    c: Vector3<f32>,
+) -> Vector3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    // Degenerate triangles have no face region.
+    if va + vb + vc == 0.0 {
+        return a;
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+/// The geometry of `MeshFactory::create_cube`, without uploading it.
+fn cube_geometry(size: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let half = size / 2.0;
+
+    // Each face as its normal followed by the directions of its u and v axes.
+    let faces = [
+        ((1.0, 0.0, 0.0), (0.0, 0.0, -1.0), (0.0, 1.0, 0.0)),
+        ((-1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, 1.0, 0.0)),
+        ((0.0, 1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, -1.0)),
+        ((0.0, -1.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0)),
+        ((0.0, 0.0, 1.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)),
+        ((0.0, 0.0, -1.0), (-1.0, 0.0, 0.0), (0.0, 1.0, 0.0)),
+    ];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u, v) in faces {
+        let first = vertices.len() as u32;
+        for (du, dv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let position = (
+                (normal.0 + u.0 * du + v.0 * dv) * half,
+                (normal.1 + u.1 * du + v.1 * dv) * half,
+                (normal.2 + u.2 * du + v.2 * dv) * half,
+            );
+            let uv = ((du + 1.0) / 2.0, (dv + 1.0) / 2.0);
+            vertices.push((position, uv, normal));
+        }
+        indices.extend([first, first + 1, first + 2, first + 2, first + 3, first]);
+    }
+
+    let mut vertices = into_vertices(vertices);
+    compute_uv_tangents(&mut vertices, &indices);
+
+    (vertices, indices)
+}
+
+/// Converts the vertices with a zero tangent, fill them in with `compute_uv_tangents`.
 fn into_vertices(vertices: Vec<((f32, f32, f32), (f32, f32), (f32, f32, f32))>) -> Vec<Vertex> {
+    into_vertices_with_tangents(
+        vertices
+            .into_iter()
+            .map(|(position, uv, normal)| (position, uv, normal, (0.0, 0.0, 0.0)))
+            .collect(),
+    )
+}
+
+fn into_vertices_with_tangents(
+    vertices: Vec<(
+        (f32, f32, f32),
+        (f32, f32),
+        (f32, f32, f32),
+        (f32, f32, f32),
+    )>,
+) -> Vec<Vertex> {
     vertices
         .into_iter()
-        .map(|(position, uv, normal)| Vertex {
+        .map(|(position, uv, normal, tangent)| Vertex {
             position: GlslVec3f {
                 x: position.0,
                 y: position.1,
@@ -411,6 +1383,693 @@ fn into_vertices(vertices: Vec<((f32, f32, f32), (f32, f32), (f32, f32, f32))>)
                 y: normal.1,
                 z: normal.2,
             },
+            tangent: GlslVec3f {
+                x: tangent.0,
+                y: tangent.1,
+                z: tangent.2,
+            },
+            color: GlslVec3f {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        })
+        .collect()
+}
+
+fn vertex_position(vertex: &Vertex) -> Vector3<f32> {
+    Vector3::new(vertex.position.x, vertex.position.y, vertex.position.z)
+}
+
+fn vertex_positions(vertices: &[Vertex]) -> impl Iterator<Item = Vector3<f32>> + '_ {
+    vertices.iter().map(vertex_position)
+}
+
+/// An empty mesh gets a zero sized box at the origin.
+fn compute_bounding_box(vertices: &[Vertex]) -> (Vector3<f32>, Vector3<f32>) {
+    vertex_positions(vertices)
+        .fold(None, |bounds, position| match bounds {
+            None => Some((position, position)),
+            Some((min, max)) => Some((min.inf(&position), max.sup(&position))),
+        })
+        .unwrap_or((Vector3::zeros(), Vector3::zeros()))
+}
+
+/// Centers the sphere on the middle of the bounding box, which is close to the smallest sphere
+/// for the generated meshes and cheap to find.
+fn compute_bounding_sphere(
+    vertices: &[Vertex],
+    (min, max): (Vector3<f32>, Vector3<f32>),
+) -> (Vector3<f32>, f32) {
+    let center = (min + max) * 0.5;
+    let radius = vertex_positions(vertices)
+        .map(|position| (position - center).magnitude())
+        .fold(0.0, f32::max);
+    (center, radius)
+}
+
+/// Pairs up the triangles sharing each edge, matching the ends by position alone. Edges shared
+/// by more than two triangles keep the first two.
+fn compute_edges(vertices: &[Vertex], indices: &[u32]) -> Vec<MeshEdge> {
+    let (_, position_groups) = weld_remap(vertices, SMOOTH_WELD_EPSILON, |_, _| true);
+
+    let mut edges: Vec<MeshEdge> = Vec::new();
+    let mut edge_indices: HashMap<(u32, u32), usize> = HashMap::new();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+            let (a, b) = (corners[a], corners[b]);
+            let (group_a, group_b) = (position_groups[a as usize], position_groups[b as usize]);
+            if group_a == group_b {
+                continue;
+            }
+
+            let key = (group_a.min(group_b), group_a.max(group_b));
+            match edge_indices.get(&key) {
+                Some(&index) => {
+                    let edge = &mut edges[index];
+                    if edge.triangles.1.is_none() {
+                        edge.triangles.1 = Some(triangle as u32);
+                    }
+                }
+                None => {
+                    edge_indices.insert(key, edges.len());
+                    edges.push(MeshEdge {
+                        vertices: [a, b],
+                        triangles: (triangle as u32, None),
+                    });
+                }
+            }
+        }
+    }
+
+    edges
+}
+
+/// See `Mesh::silhouette_edges`.
+fn silhouette_edges(
+    vertices: &[Vertex],
+    indices: &[u32],
+    edges: &[MeshEdge],
+    eye: Vector3<f32>,
+) -> Vec<[u32; 2]> {
+    let position = |index: u32| vertex_position(&vertices[index as usize]);
+    // Triangles are wound counter clockwise seen from the front.
+    let faces_eye = |triangle: u32| {
+        let corners = &indices[triangle as usize * 3..triangle as usize * 3 + 3];
+        let [a, b, c] = [corners[0], corners[1], corners[2]].map(position);
+        (b - a).cross(&(c - a)).dot(&(eye - a)) > 0.0
+    };
+
+    edges
+        .iter()
+        .filter(|edge| match edge.triangles {
+            (a, Some(b)) => faces_eye(a) != faces_eye(b),
+            // The rim of an open mesh outlines it from every side.
+            (_, None) => true,
         })
+        .map(|edge| edge.vertices)
         .collect()
 }
+
+/// Paints the vertices in order with `colors`, vertices past the end of the colors stay white.
+fn set_vertex_colors(vertices: &mut [Vertex], colors: impl IntoIterator<Item = [f32; 3]>) {
+    for (vertex, color) in vertices.iter_mut().zip(colors) {
+        vertex.color = GlslVec3f {
+            x: color[0],
+            y: color[1],
+            z: color[2],
+        };
+    }
+}
+
+/// Sets each tangent to the direction u increases in across the faces around the vertex,
+/// made perpendicular to the normal.
+fn compute_uv_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![(0.0, 0.0, 0.0); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| {
+            let vertex = &vertices[index as usize];
+            (
+                (vertex.position.x, vertex.position.y, vertex.position.z),
+                (vertex.uv.x, vertex.uv.y),
+            )
+        });
+        let e1 = (b.0 .0 - a.0 .0, b.0 .1 - a.0 .1, b.0 .2 - a.0 .2);
+        let e2 = (c.0 .0 - a.0 .0, c.0 .1 - a.0 .1, c.0 .2 - a.0 .2);
+        let (du1, dv1) = (b.1 .0 - a.1 .0, b.1 .1 - a.1 .1);
+        let (du2, dv2) = (c.1 .0 - a.1 .0, c.1 .1 - a.1 .1);
+
+        // Faces with degenerate uvs have no u direction to contribute.
+        let determinant = du1 * dv2 - du2 * dv1;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / determinant;
+        let face_tangent = (
+            (e1.0 * dv2 - e2.0 * dv1) * r,
+            (e1.1 * dv2 - e2.1 * dv1) * r,
+            (e1.2 * dv2 - e2.2 * dv1) * r,
+        );
+        for index in triangle {
+            let tangent: &mut (f32, f32, f32) = &mut tangents[*index as usize];
+            tangent.0 += face_tangent.0;
+            tangent.1 += face_tangent.1;
+            tangent.2 += face_tangent.2;
+        }
+    }
+
+    for (vertex, tangent) in vertices.iter_mut().zip(tangents) {
+        vertex.tangent = GlslVec3f {
+            x: tangent.0,
+            y: tangent.1,
+            z: tangent.2,
+        };
+        orthogonalize_tangent(vertex);
+    }
+}
+
+/// Removes the part of the tangent along the normal and normalizes it, a tangent parallel to
+/// the normal is replaced by any perpendicular direction.
+fn orthogonalize_tangent(vertex: &mut Vertex) {
+    let n = (vertex.normal.x, vertex.normal.y, vertex.normal.z);
+    let t = (vertex.tangent.x, vertex.tangent.y, vertex.tangent.z);
+    let d = n.0 * t.0 + n.1 * t.1 + n.2 * t.2;
+    let mut tangent = (t.0 - n.0 * d, t.1 - n.1 * d, t.2 - n.2 * d);
+
+    let mut length = (tangent.0 * tangent.0 + tangent.1 * tangent.1 + tangent.2 * tangent.2).sqrt();
+    if length <= f32::EPSILON {
+        // Cross the normal with whichever axis it is least aligned with.
+        tangent = if n.0.abs() < 0.9 {
+            (0.0, -n.2, n.1)
+        } else {
+            (n.2, 0.0, -n.0)
+        };
+        length = (tangent.0 * tangent.0 + tangent.1 * tangent.1 + tangent.2 * tangent.2).sqrt();
+    }
+
+    if length > 0.0 {
+        vertex.tangent = GlslVec3f {
+            x: tangent.0 / length,
+            y: tangent.1 / length,
+            z: tangent.2 / length,
+        };
+    }
+}
+
+// Duplicates are generated from the same math so they match exactly, this only absorbs
+// floating point error without merging neighbouring vertices.
+const OPTIMIZE_EPSILON: f32 = 1e-6;
+
+fn optimize_vertices(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let (optimized_vertices, optimized_indices) =
+        weld_vertices(vertices, indices, OPTIMIZE_EPSILON, true);
+    println!(
+        "Optimized mesh from {} to {} vertices",
+        vertices.len(),
+        optimized_vertices.len()
+    );
+    (optimized_vertices, optimized_indices)
+}
+
+fn weld_vertices(
+    vertices: &[Vertex],
+    indices: &[u32],
+    epsilon: f32,
+    match_normals: bool,
+) -> (Vec<Vertex>, Vec<u32>) {
+    // Differently painted vertices are kept apart so the color boundaries stay sharp.
+    let (welded_vertices, remap) = weld_remap(vertices, epsilon, |a, b| {
+        (a.uv.x - b.uv.x).abs() <= epsilon
+            && (a.uv.y - b.uv.y).abs() <= epsilon
+            && (a.color.x - b.color.x).abs() <= epsilon
+            && (a.color.y - b.color.y).abs() <= epsilon
+            && (a.color.z - b.color.z).abs() <= epsilon
+            && (!match_normals
+                || ((a.normal.x - b.normal.x).abs() <= epsilon
+                    && (a.normal.y - b.normal.y).abs() <= epsilon
+                    && (a.normal.z - b.normal.z).abs() <= epsilon))
+    });
+
+    let welded_indices = indices.iter().map(|index| remap[*index as usize]).collect();
+
+    (welded_vertices, welded_indices)
+}
+
+/// Merges vertices within `epsilon` in position that also pass `is_match`, returning the
+/// merged vertices and the merged index of every input vertex.
+fn weld_remap(
+    vertices: &[Vertex],
+    epsilon: f32,
+    is_match: impl Fn(&Vertex, &Vertex) -> bool,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let cell_size = epsilon.max(f32::EPSILON);
+    let cell = |position: &GlslVec3f| {
+        (
+            (position.x / cell_size).floor() as i64,
+            (position.y / cell_size).floor() as i64,
+            (position.z / cell_size).floor() as i64,
+        )
+    };
+    let is_close = |a: &Vertex, b: &Vertex| {
+        (a.position.x - b.position.x).abs() <= epsilon
+            && (a.position.y - b.position.y).abs() <= epsilon
+            && (a.position.z - b.position.z).abs() <= epsilon
+            && is_match(a, b)
+    };
+
+    // Welded vertices bucketed by position, a match can be in any neighbouring cell since
+    // epsilon is the same as the cell size.
+    let mut grid: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+    let mut welded_vertices = Vec::new();
+    let mut remap = Vec::with_capacity(vertices.len());
+
+    for vertex in vertices {
+        let (x, y, z) = cell(&vertex.position);
+
+        let mut found = None;
+        'search: for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(candidates) = grid.get(&(x + dx, y + dy, z + dz)) else {
+                        continue;
+                    };
+                    if let Some(index) = candidates
+                        .iter()
+                        .find(|index| is_close(&welded_vertices[**index as usize], vertex))
+                    {
+                        found = Some(*index);
+                        break 'search;
+                    }
+                }
+            }
+        }
+
+        let index = found.unwrap_or_else(|| {
+            let index = welded_vertices.len() as u32;
+            welded_vertices.push(*vertex);
+            grid.entry((x, y, z)).or_default().push(index);
+            index
+        });
+        remap.push(index);
+    }
+
+    (welded_vertices, remap)
+}
+
+/// Welds duplicated vertices and gives every corner the area weighted average normal of the
+/// faces around its position, vertices split by a uv seam still share the same normal.
+///
+/// Only faces within `smoothing_angle` degrees of the corner's own face are averaged in, so a
+/// vertex on a sharper edge ends up with a different normal per side and is split into one
+/// vertex per normal.
+fn smooth_vertex_normals(
+    vertices: &[Vertex],
+    indices: &[u32],
+    epsilon: f32,
+    smoothing_angle: f32,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let (mut vertices, mut indices) = weld_vertices(vertices, indices, epsilon, false);
+    let (positions, position_groups) = weld_remap(&vertices, epsilon, |_, _| true);
+
+    // Not normalized so larger faces contribute more.
+    let face_normals = indices
+        .chunks_exact(3)
+        .map(|triangle| {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|index| vertex_position(&vertices[index as usize]));
+            (b - a).cross(&(c - a))
+        })
+        .collect::<Vec<_>>();
+
+    let mut group_faces = vec![Vec::new(); positions.len()];
+    for (face, triangle) in indices.chunks_exact(3).enumerate() {
+        for index in triangle {
+            group_faces[position_groups[*index as usize] as usize].push(face);
+        }
+    }
+
+    // Past 180 degrees every face is included, which skips the angle test entirely.
+    let smooth_all = smoothing_angle >= 180.0;
+    let min_cos = smoothing_angle.clamp(0.0, 180.0).to_radians().cos();
+    let is_smoothed = |a: &Vector3<f32>, b: &Vector3<f32>| {
+        let lengths = a.magnitude() * b.magnitude();
+        // Degenerate faces have no direction, they add nothing either way.
+        smooth_all || lengths <= f32::EPSILON || a.dot(b) >= min_cos * lengths
+    };
+
+    // The split copies of every welded vertex with the normal each was given, the first one
+    // reuses the welded vertex.
+    let mut splits: Vec<Vec<(Vector3<f32>, u32)>> = vec![Vec::new(); vertices.len()];
+    for (corner, index) in indices.iter_mut().enumerate() {
+        let face_normal = face_normals[corner / 3];
+        let normal = group_faces[position_groups[*index as usize] as usize]
+            .iter()
+            .map(|face| face_normals[*face])
+            .filter(|other| is_smoothed(&face_normal, other))
+            .sum::<Vector3<f32>>();
+        let Some(normal) = normal.try_normalize(0.0) else {
+            continue;
+        };
+
+        let vertex_splits = &mut splits[*index as usize];
+        if let Some((_, split)) = vertex_splits
+            .iter()
+            .find(|(split_normal, _)| (split_normal - normal).magnitude() <= epsilon)
+        {
+            *index = *split;
+            continue;
+        }
+
+        let split = if vertex_splits.is_empty() {
+            *index
+        } else {
+            vertices.push(vertices[*index as usize]);
+            vertices.len() as u32 - 1
+        };
+        vertex_splits.push((normal, split));
+        let vertex = &mut vertices[split as usize];
+        vertex.normal = GlslVec3f {
+            x: normal.x,
+            y: normal.y,
+            z: normal.z,
+        };
+        orthogonalize_tangent(vertex);
+        *index = split;
+    }
+
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex_normal(vertex: &Vertex) -> Vector3<f32> {
+        Vector3::new(vertex.normal.x, vertex.normal.y, vertex.normal.z)
+    }
+
+    // A flat grid where every cell has its own four corners, so each inner corner is
+    // duplicated by the cells around it. Odd corners are nudged by less than the weld epsilon.
+    fn duplicated_grid(cells: u32) -> (Vec<Vertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for z in 0..cells {
+            for x in 0..cells {
+                let first = vertices.len() as u32;
+                for (dx, dz) in [(0, 0), (1, 0), (1, 1), (0, 1)] {
+                    let (u, v) = ((x + dx) as f32, (z + dz) as f32);
+                    let nudge = if (x + z) % 2 == 0 { 0.0 } else { 1e-6 };
+                    vertices.push((
+                        (u + nudge, 0.0, v - nudge),
+                        (u / cells as f32, v / cells as f32),
+                        (0.0, 1.0, 0.0),
+                    ));
+                }
+                indices.extend([first, first + 1, first + 2, first + 2, first + 3, first]);
+            }
+        }
+        (into_vertices(vertices), indices)
+    }
+
+    #[test]
+    fn weld_merges_duplicated_grid_vertices() {
+        let cells = 4;
+        let (vertices, indices) = duplicated_grid(cells);
+        assert_eq!(vertices.len(), (cells * cells * 4) as usize);
+
+        let (welded, welded_indices) = weld_vertices(&vertices, &indices, 1e-4, true);
+        assert_eq!(welded.len(), ((cells + 1) * (cells + 1)) as usize);
+        assert_eq!(welded_indices.len(), indices.len());
+        for (index, welded_index) in indices.iter().zip(&welded_indices) {
+            let original = vertex_position(&vertices[*index as usize]);
+            let position = vertex_position(&welded[*welded_index as usize]);
+            assert!((position - original).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn bounds_enclose_every_vertex() {
+        let (vertices, _) = duplicated_grid(4);
+        let (min, max) = compute_bounding_box(&vertices);
+        assert!((min - Vector3::new(0.0, 0.0, 0.0)).magnitude() < 1e-4);
+        assert!((max - Vector3::new(4.0, 0.0, 4.0)).magnitude() < 1e-4);
+
+        let (center, radius) = compute_bounding_sphere(&vertices, (min, max));
+        assert!((center - Vector3::new(2.0, 0.0, 2.0)).magnitude() < 1e-4);
+        // The grid's corners are the furthest from the middle.
+        assert!((radius - 8.0f32.sqrt()).abs() < 1e-4);
+        for position in vertex_positions(&vertices) {
+            assert!((position - center).magnitude() <= radius);
+        }
+
+        assert_eq!(
+            compute_bounding_box(&[]),
+            (Vector3::zeros(), Vector3::zeros())
+        );
+        assert_eq!(
+            compute_bounding_sphere(&[], (Vector3::zeros(), Vector3::zeros())),
+            (Vector3::zeros(), 0.0)
+        );
+    }
+
+    #[test]
+    fn cube_edges_pair_every_triangle() {
+        let (vertices, indices) = cube_geometry(2.0);
+        let edges = compute_edges(&vertices, &indices);
+        // The twelve sides of the cube and a diagonal across each face, the cube is closed so
+        // every edge has a triangle on both sides.
+        assert_eq!(edges.len(), 12 + 6);
+        for edge in &edges {
+            let (a, b) = edge.triangles;
+            assert_ne!(Some(a), b);
+            assert!(b.is_some());
+        }
+    }
+
+    #[test]
+    fn grid_edges_leave_the_rim_open() {
+        let (vertices, indices) = duplicated_grid(2);
+        let edges = compute_edges(&vertices, &indices);
+        // Six edges along each axis and a diagonal per cell, welded across the cells.
+        assert_eq!(edges.len(), 6 + 6 + 4);
+        let rim = edges.iter().filter(|edge| edge.triangles.1.is_none());
+        assert_eq!(rim.count(), 8);
+    }
+
+    #[test]
+    fn cube_silhouette_outlines_the_faces_seen() {
+        let (vertices, indices) = cube_geometry(2.0);
+        let edges = compute_edges(&vertices, &indices);
+
+        // Head on only the front face is seen, outlined by its four sides.
+        let silhouette =
+            silhouette_edges(&vertices, &indices, &edges, Vector3::new(0.0, 0.0, 10.0));
+        assert_eq!(silhouette.len(), 4);
+        for edge in &silhouette {
+            for index in edge {
+                assert!((vertex_position(&vertices[*index as usize]).z - 1.0).abs() < 1e-5);
+            }
+        }
+
+        // From a corner three faces are seen, outlined by a hexagon.
+        let silhouette =
+            silhouette_edges(&vertices, &indices, &edges, Vector3::new(10.0, 10.0, 10.0));
+        assert_eq!(silhouette.len(), 6);
+    }
+
+    #[test]
+    fn open_grid_silhouette_is_its_rim() {
+        let (vertices, indices) = duplicated_grid(2);
+        let edges = compute_edges(&vertices, &indices);
+        for eye in [Vector3::new(1.0, 5.0, 1.0), Vector3::new(1.0, -5.0, 1.0)] {
+            let silhouette = silhouette_edges(&vertices, &indices, &edges, eye);
+            assert_eq!(silhouette.len(), 8);
+        }
+    }
+
+    #[test]
+    fn panicking_load_is_an_error() {
+        let result = catch_load_panic("broken.obj", || panic!("index {} out of range", 3));
+        match result {
+            Err(MeshLoadError::Panicked(path, message)) => {
+                assert_eq!(path, "broken.obj");
+                assert_eq!(message, "index 3 out of range");
+            }
+            _ => panic!("the panic wasn't caught"),
+        }
+
+        let handle = MeshHandle::load("missing_mesh_file.obj");
+        while !handle.is_ready() {
+            std::thread::yield_now();
+        }
+        assert!(matches!(handle.take(), Some(Err(MeshLoadError::Io(..)))));
+    }
+
+    #[test]
+    fn weld_keeps_uv_seams() {
+        let (mut vertices, indices) = duplicated_grid(2);
+        // Give the first cell its own uvs, so its corners are split from the other cells.
+        for vertex in &mut vertices[..4] {
+            vertex.uv.x += 0.5;
+        }
+
+        let (welded, _) = weld_vertices(&vertices, &indices, 1e-4, true);
+        assert_eq!(welded.len(), 9 + 3);
+    }
+
+    #[test]
+    fn smoothing_angle_keeps_cube_edges_hard() {
+        let (vertices, indices) = cube_geometry(2.0);
+        let (smoothed, smoothed_indices) =
+            smooth_vertex_normals(&vertices, &indices, SMOOTH_WELD_EPSILON, 30.0);
+
+        assert_eq!(smoothed_indices.len(), indices.len());
+        for (index, smoothed_index) in indices.iter().zip(&smoothed_indices) {
+            let original = vertex_normal(&vertices[*index as usize]);
+            let normal = vertex_normal(&smoothed[*smoothed_index as usize]);
+            assert!((normal - original).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn smoothing_angle_of_180_averages_cube_corners() {
+        let (vertices, indices) = cube_geometry(2.0);
+        let (smoothed, _) = smooth_vertex_normals(
+            &vertices,
+            &indices,
+            SMOOTH_WELD_EPSILON,
+            DEFAULT_SMOOTHING_ANGLE,
+        );
+
+        for vertex in &smoothed {
+            let normal = vertex_normal(vertex);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-4);
+            // Every corner touches three faces, so the normal leans along all three axes and
+            // points away from the center like the corner does.
+            let position = vertex_position(vertex);
+            for axis in 0..3 {
+                assert!(normal[axis] * position[axis] > 0.1);
+            }
+        }
+    }
+
+    #[test]
+    fn optimize_welds_icosahedron_midpoints_without_moving_corners() {
+        let (vertices, indices) = MeshFactory::subdivided_icosahedron(3);
+        let vertices = into_vertices(vertices);
+        let (optimized, optimized_indices) = optimize_vertices(&vertices, &indices);
+
+        // Every midpoint inside a uv island is pushed once by each triangle sharing its edge.
+        assert!(
+            optimized.len() < vertices.len() * 2 / 3,
+            "{} -> {}",
+            vertices.len(),
+            optimized.len()
+        );
+
+        // The same triangles are drawn from the same corners, so the sphere looks the same.
+        assert_eq!(optimized_indices.len(), indices.len());
+        for (index, optimized_index) in indices.iter().zip(&optimized_indices) {
+            let original = &vertices[*index as usize];
+            let vertex = &optimized[*optimized_index as usize];
+            assert!((vertex_position(vertex) - vertex_position(original)).magnitude() < 1e-5);
+            assert!((vertex_normal(vertex) - vertex_normal(original)).magnitude() < 1e-5);
+            assert!((vertex.uv.x - original.uv.x).abs() < 1e-5);
+            assert!((vertex.uv.y - original.uv.y).abs() < 1e-5);
+        }
+    }
+
+    // How far a fully combed strand tilts, must match MAX_COMB_ANGLE in shell.vert.
+    const MAX_COMB_ANGLE: f32 = 1.2;
+
+    // The extrusion shell.vert gives a strand for a comb map texel, with an identity model.
+    fn combed_offset(vertex: &Vertex, texel: [f32; 2], length: f32) -> Vector3<f32> {
+        let normal = vertex_normal(vertex).normalize();
+        let comb = Vector3::new(texel[0] * 2.0 - 1.0, texel[1] * 2.0 - 1.0, 0.0);
+        let strength = comb.magnitude().min(1.0);
+        let tangent = Vector3::new(vertex.tangent.x, vertex.tangent.y, vertex.tangent.z);
+        let tangent = (tangent - normal * tangent.dot(&normal)).normalize();
+        let bitangent = normal.cross(&tangent);
+        let direction = (tangent * comb.x + bitangent * comb.y).normalize();
+        let angle = strength * MAX_COMB_ANGLE;
+        (normal * angle.cos() + direction * angle.sin()) * length
+    }
+
+    #[test]
+    fn uniform_comb_tilts_every_strand_the_same_way() {
+        let (mut vertices, indices) = duplicated_grid(4);
+        compute_uv_tangents(&mut vertices, &indices);
+
+        for texel in [[1.0, 0.5], [0.5, 0.0], [0.8, 0.9]] {
+            let first = combed_offset(&vertices[0], texel, 1.0);
+            for vertex in &vertices {
+                let offset = combed_offset(vertex, texel, 1.0);
+                assert!((offset - first).magnitude() < 1e-4, "{:?}", texel);
+            }
+        }
+
+        // Red combs along increasing u, which runs along +x on the grid.
+        let offset = combed_offset(&vertices[0], [1.0, 0.5], 1.0);
+        assert!((offset.y - MAX_COMB_ANGLE.cos()).abs() < 1e-4);
+        assert!((offset.x - MAX_COMB_ANGLE.sin()).abs() < 1e-4);
+        assert!(offset.z.abs() < 1e-4);
+    }
+
+    // The distance along the ray to the nearest triangle it hits, the depth the pre-pass leaves.
+    fn nearest_hit(
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        positions: &[Vector3<f32>],
+        indices: &[u32],
+    ) -> Option<f32> {
+        indices
+            .chunks(3)
+            .filter_map(|triangle| {
+                let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+                let (edge_ab, edge_ac) = (b - a, c - a);
+                let p = direction.cross(&edge_ac);
+                let determinant = edge_ab.dot(&p);
+                if determinant.abs() < 1e-8 {
+                    return None;
+                }
+                let to_origin = origin - a;
+                let u = to_origin.dot(&p) / determinant;
+                let q = to_origin.cross(&edge_ab);
+                let v = direction.dot(&q) / determinant;
+                let t = edge_ac.dot(&q) / determinant;
+                (u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > 0.0).then_some(t)
+            })
+            .min_by(f32::total_cmp)
+    }
+
+    #[test]
+    fn depth_prepass_keeps_fur_tips_outside_the_silhouette() {
+        let (vertices, indices) = MeshFactory::subdivided_icosahedron(3);
+        let vertices = into_vertices(vertices);
+        let positions = vertex_positions(&vertices).collect::<Vec<_>>();
+        let eye = Vector3::new(0.0, 0.0, 4.0);
+        let length = 0.3;
+
+        let mut silhouette_tips = 0;
+        for vertex in &vertices {
+            let position = vertex_position(vertex);
+            let normal = vertex_normal(vertex).normalize();
+            let facing = normal.dot(&(eye - position).normalize());
+            // An uncombed strand without wind or gravity stands straight out along the normal.
+            let tip = position + normal * length;
+            let to_tip = tip - eye;
+            let hit = nearest_hit(eye, to_tip.normalize(), &positions, &indices);
+
+            if facing.abs() < 0.1 {
+                // Nothing was drawn in the pre-pass behind a tip standing out of the silhouette.
+                assert_eq!(hit, None, "{:?}", tip);
+                silhouette_tips += 1;
+            } else if facing > 0.5 {
+                // A tip over the front of the body is nearer than the depth it is tested against.
+                let hit = hit.expect("the body is behind the tip");
+                assert!(to_tip.magnitude() < hit, "{:?}", tip);
+            }
+        }
+        assert!(silhouette_tips > 0);
+    }
+}
@@ -1,23 +1,45 @@
-use std::{any::Any, sync::Arc};
+use std::{
+    any::Any,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use ash::vk;
+use na::{Matrix4, Point3, UnitQuaternion, Vector3};
 use pyrite::{
     prelude::{AppBuilder, Assets, Input, Key, Res, ResMut, Resource, Time},
     render::render_manager::{self, RenderManager},
     vulkan::{
-        AttachmentInfo, CommandBuffer, GraphicsPipeline, GraphicsPipelineInfo, Image, ImageInfo,
-        InternalImage, RenderPass, Shader, Subpass, Vulkan, VulkanAllocator, VulkanStager,
+        AttachmentInfo, BufferInfo, CommandBuffer, GraphicsPipeline, GraphicsPipelineInfo, Image,
+        ImageInfo, InternalImage, RenderPass, Sampler, Shader, StageType, Subpass, UntypedBuffer,
+        Vulkan, VulkanAllocator, VulkanStager,
     },
 };
 
-use self::mesh::{Mesh, MeshFactory};
+use self::{
+    auto_tune::ShellAutoTune,
+    mesh::{Mesh, MeshFactory, MeshHandle, Vertex},
+    noise::{NoiseGenerator, NoiseKind, NoiseSettings, PendingNoise},
+};
 
 use super::{
+    camera::Camera,
+    debug_labels::DebugLabels,
+    device_lost::check_device_lost,
+    frame_stats::FrameStats,
+    frustum::Frustum,
+    particles::ParticleSystem,
     render::RenderPipeline,
+    sampler::SamplerSettings,
+    texture::{LoadFailure, Texture},
     watched_shaders::{self, WatchedShaders},
 };
 
-mod mesh;
+pub mod auto_tune;
+pub mod mesh;
+pub mod noise;
+
+extern crate nalgebra as na;
 
 pub fn setup_shell_renderer(app_builder: &mut AppBuilder) {
     let shell_renderer = ShellRenderer::new(
@@ -27,40 +49,486 @@ pub fn setup_shell_renderer(app_builder: &mut AppBuilder) {
         &mut *app_builder.get_resource_mut::<VulkanAllocator>(),
         &mut *app_builder.get_resource_mut::<VulkanStager>(),
         &*app_builder.get_resource::<RenderPipeline>(),
+        app_builder
+            .get_resource::<RenderManager>()
+            .frames_in_flight(),
     );
     app_builder.add_resource(shell_renderer);
+    app_builder.add_resource(ShellAutoTune::new());
     app_builder.add_system(ShellRenderer::update_system);
+    app_builder.add_system(ShellAutoTune::update_system);
 }
 
+// The color of the mesh upload region in frame captures.
+const MESH_STAGING_LABEL_COLOR: [f32; 4] = [0.8, 0.45, 0.75, 1.0];
+
 const VERTEX_FILE_PATH: &str = "shaders/shell.vert";
 const FRAGMENT_FILE_PATH: &str = "shaders/shell.frag";
 const VERTEX_NAME: &str = "shell_vert";
 const FRAGMENT_NAME: &str = "shell_frag";
+const GROUND_SHADOW_VERTEX_FILE_PATH: &str = "shaders/ground_shadow.vert";
+const GROUND_SHADOW_FRAGMENT_FILE_PATH: &str = "shaders/ground_shadow.frag";
+const GROUND_SHADOW_VERTEX_NAME: &str = "ground_shadow_vert";
+const GROUND_SHADOW_FRAGMENT_NAME: &str = "ground_shadow_frag";
+const FIN_VERTEX_FILE_PATH: &str = "shaders/fin.vert";
+const FIN_FRAGMENT_FILE_PATH: &str = "shaders/fin.frag";
+const FIN_VERTEX_NAME: &str = "fin_vert";
+const FIN_FRAGMENT_NAME: &str = "fin_frag";
+const GRID_VERTEX_FILE_PATH: &str = "shaders/grid.vert";
+const GRID_FRAGMENT_FILE_PATH: &str = "shaders/grid.frag";
+const GRID_VERTEX_NAME: &str = "grid_vert";
+const GRID_FRAGMENT_NAME: &str = "grid_frag";
+
+// Two triangles making the ground shadow quad.
+const GROUND_SHADOW_VERTEX_COUNT: u32 = 6;
+// How dark the center of the ground shadow is.
+const GROUND_SHADOW_OPACITY: f32 = 0.6;
+
+// Two triangles making the grid quad.
+const GRID_VERTEX_COUNT: u32 = 6;
+// Half the width of the grid around the camera, kept within the far plane.
+const GRID_EXTENT: f32 = 50.0;
+// Pushes the grid's depth slightly away from the camera, so the ground shadow or a ground mesh
+// at the same height wins the depth test instead of z-fighting with it.
+const GRID_DEPTH_BIAS: f32 = 1.0;
+
+// Added to the culling radius for the fur bending past its shells from the curvature droop,
+// wind and interaction.
+const FUR_CULL_MARGIN: f32 = 0.25;
+
+/// The shell count cap until `set_max_shells` is called, instancing the mesh more than this
+/// can stall the GPU for seconds.
+pub const DEFAULT_MAX_SHELLS: u32 = 512;
+
+/// The built-in meshes the shell renderer can be cycled through at runtime.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum MeshPreset {
+    Sphere,
+    Plane,
+    Cube,
+    Cylinder,
+}
+
+impl MeshPreset {
+    fn next(self) -> Self {
+        match self {
+            MeshPreset::Sphere => MeshPreset::Plane,
+            MeshPreset::Plane => MeshPreset::Cube,
+            MeshPreset::Cube => MeshPreset::Cylinder,
+            MeshPreset::Cylinder => MeshPreset::Sphere,
+        }
+    }
+
+    fn create(self, mesh_factory: &mut MeshFactory) -> Mesh {
+        match self {
+            MeshPreset::Sphere => mesh_factory.create_sphere_icosahedron(3),
+            MeshPreset::Plane => mesh_factory.create_plane(1.0, 16),
+            MeshPreset::Cube => mesh_factory.create_cube(1.0),
+            MeshPreset::Cylinder => mesh_factory.create_cylinder(0.5, 1.0, 32, true),
+        }
+    }
+}
+
+// How far from the interaction point fur is flattened while petting.
+const PETTING_RADIUS: f32 = 0.4;
+// How quickly the interaction radius eases towards its target, per second.
+const INTERACTION_EASE_SPEED: f32 = 8.0;
+// How many fur particles petting sheds.
+const SHED_PARTICLES_PER_SECOND: f32 = 60.0;
+
+// The noise density mask generated at startup and with F11.
+const DEFAULT_NOISE_SIZE: u32 = 512;
+const DEFAULT_NOISE_SEED: u32 = 1;
+const DEFAULT_NOISE_SCALE: f32 = 8.0;
+/// The largest generated noise texture, in pixels along each side.
+pub const MAX_NOISE_SIZE: u32 = 4096;
+
+/// The most fur layers that can be composited in one pass.
+pub const MAX_FUR_LAYERS: usize = 4;
+
+/// The most lights added on top of the scene light.
+pub const MAX_LIGHTS: usize = 4;
+
+/// The most meshes drawn in the shell pass, including the renderer's own mesh.
+pub const MAX_INSTANCES: usize = 8;
+
+/// Base and tip colors of green grass.
+pub const GRASS_COLORS: ([f32; 3], [f32; 3]) = ([0.3, 0.48, 0.1], [0.77, 0.97, 0.28]);
+/// Base and tip colors of brown fur.
+pub const FUR_COLORS: ([f32; 3], [f32; 3]) = ([0.22, 0.13, 0.07], [0.62, 0.45, 0.3]);
+
+/// One coat of fur, such as a short undercoat under a longer topcoat.
+#[derive(Clone, Copy, Debug)]
+pub struct FurLayer {
+    pub color: [f32; 3],
+    /// Strands per uv unit.
+    pub density: f32,
+    /// How thick the strands are at the root.
+    pub thickness: f32,
+    /// How long the strands are as a fraction of the shell height, the layer is drawn on the
+    /// shells between the previous layer's fraction and its own.
+    pub shell_fraction: f32,
+}
+
+/// How a light's `position_or_direction` is interpreted.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LightKind {
+    /// Lights everything from the direction it travels in.
+    Directional = 0,
+    /// Lights from a world space position, falling off with distance.
+    Point = 1,
+}
+
+/// A light adding a Lambert term to the fur, on top of the scene light.
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    /// The world space position of point lights, or the direction directional lights travel in.
+    pub position_or_direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub kind: LightKind,
+}
+
+/// The fur settings that can differ between instances, the rest are shared.
+#[derive(Clone, Copy, Debug)]
+pub struct FurParams {
+    pub base_color: [f32; 3],
+    pub tip_color: [f32; 3],
+    /// Strands per uv unit.
+    pub density: f32,
+    /// The height of the shells in cm.
+    pub shell_thickness: f32,
+}
+
+/// Another copy of the mesh drawn in the shell pass with its own transform and fur.
+#[derive(Clone, Copy, Debug)]
+pub struct Instance {
+    pub transform: Matrix4<f32>,
+    pub fur_params: FurParams,
+}
+
+/// Identifies an instance added with `ShellRenderer::add_instance`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InstanceId(u32);
 
 #[derive(Resource)]
 pub struct ShellRenderer {
     shader_dependency_signal: watched_shaders::DependencySignal,
     pipeline: Option<ShellPipeline>,
+    // Set when a setting baked into the pipeline changes so it is rebuilt next update.
+    pipeline_dirty: bool,
+    // The render pipeline's reverse-Z setting the pipelines were built with, their depth
+    // compare op depends on it.
+    reverse_z: bool,
+    // Whether the pipelines were built with the render pipeline's grid enabled.
+    grid: bool,
+    depth_prepass: bool,
+    // LINE draws the edges of the mesh and every shell as a line list, FILL the triangles.
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    // Only takes effect with MSAA, without it the strands keep the hard discard.
+    alpha_to_coverage: bool,
     shell_resolve_image: Image,
-    shell_resolve_depth_image: Image,
+    // Writes view space normals and the shell height to a second attachment, for screen space
+    // effects.
+    gbuffer: bool,
+    // The G-buffer setting shell.frag was last compiled for, it only declares the normal output
+    // with the G-buffer.
+    shader_gbuffer: bool,
+    // The view space normal in rgb and shell height in a, only written while the G-buffer is
+    // enabled.
+    shell_normal_image: Image,
+    // The multisampled normal attachment resolved into shell_normal_image, only exists with
+    // MSAA while the G-buffer is enabled.
+    shell_normal_msaa_image: Option<Image>,
+    // The backbuffer generation the resolve images were created for.
+    backbuffer_generation: u32,
     plane_mesh: Mesh,
+    mesh_preset: MeshPreset,
+    // A mesh file parsing in the background, replacing plane_mesh once it is ready.
+    pending_mesh: Option<MeshHandle>,
+    mesh_load_error: Option<String>,
+    // Planes per cm, shell_count follows it unless set directly.
     resolution: u32,
     shell_thickness: f32,
+    shell_count: u32,
+    // The upper bound of shell_count, every shell is a full instance of the mesh.
+    max_shells: u32,
+    specular_strength: f32,
+    shininess: f32,
+    gravity: Vector3<f32>,
+    stiffness: f32,
+    base_color: [f32; 3],
+    tip_color: [f32; 3],
+    wind_direction: [f32; 2],
+    wind_strength: f32,
+    density: f32,
+    // 0 to 1, how much neighbouring strands lean together and share heights.
+    clumping: f32,
+    ao_strength: f32,
+    ambient: f32,
+    interaction_point: Vector3<f32>,
+    // The radius set by set_interaction, the drawn radius eases towards it so the fur bends
+    // and returns smoothly.
+    target_interaction_radius: f32,
+    interaction_radius: f32,
+    // Particles owed to the particle system while petting, less than one.
+    shed_particles: f32,
+    // Sorted by shell fraction, with none the fur uniform colors and density are used.
+    layers: Vec<FurLayer>,
+    // One buffer per frame in flight. The layers are staged into the next buffer only when they
+    // change, so a frame still on the GPU keeps reading the layers it was recorded with.
+    layers_buffers: Vec<Arc<UntypedBuffer>>,
+    // The layers buffer written last, which every frame binds until the layers change again.
+    layers_buffer_index: usize,
+    layers_dirty: bool,
+    // Directional lights are normalized when set.
+    lights: Vec<Light>,
+    // Per frame in flight and staged on change, like the layers buffers.
+    lights_buffers: Vec<Arc<UntypedBuffer>>,
+    lights_buffer_index: usize,
+    lights_dirty: bool,
+    // A soft blob shadow on the ground under the mesh, drawn after the shells so it is
+    // depth tested against the fur overhanging it.
+    ground_shadow: bool,
+    ground_y: f32,
+    ground_shadow_radius: f32,
+    ground_shadow_softness: f32,
+    // Quads standing out of the silhouette of the renderer's own mesh, so the fur there isn't
+    // only the thin edges of the shells. Rebuilt when the camera moves relative to the mesh.
+    fins: bool,
+    // One buffer and its size in bytes per frame in flight, each grown to fit the most fins
+    // written to it and None until it is first written. A rebuild writes the next buffer in
+    // turn, so a frame still on the GPU keeps reading the fins it was recorded with.
+    fin_buffers: Vec<Option<(Arc<UntypedBuffer>, u64)>>,
+    // The fin buffer written by the latest rebuild.
+    fin_buffer_index: usize,
+    fin_vertex_count: u32,
+    // The eye in mesh space the fins were last built from, None when they need rebuilding.
+    fin_eye: Option<Vector3<f32>>,
+
+    // The model transform of the mesh, uploaded to the model uniform buffer each frame.
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+    // Per axis, non-uniform scales are handled by the normal matrix.
+    scale: Vector3<f32>,
+    // One buffer per frame in flight, written in turn like the fur buffers.
+    model_buffers: Vec<Arc<UntypedBuffer>>,
+    // The model buffer written by the latest update, which is the one the next frame binds.
+    model_buffer_index: usize,
+    // Every fur setting of the renderer and its instances, uploaded each frame. One buffer per
+    // frame in flight, written in turn like the camera's so a frame still on the GPU keeps
+    // reading its own settings.
+    fur_buffers: Vec<Arc<UntypedBuffer>>,
+    // The fur buffer written by the latest update, which is the one the next frame binds.
+    fur_buffer_index: usize,
+    // Drawn after the renderer's own mesh, which takes the first model uniform slot.
+    instances: Vec<(InstanceId, Instance)>,
+    next_instance_id: u32,
+
+    // Grayscale mask scaling where fur grows, white when no mask path is set.
+    density_mask: Texture,
+    density_mask_path: Option<PathBuf>,
+    density_mask_dirty: bool,
+    density_mask_failure: LoadFailure,
+    mask_threshold: f32,
+    // Fills the density mask with noise instead of loading it while noise_settings is set.
+    noise_generator: NoiseGenerator,
+    noise_settings: Option<NoiseSettings>,
+    noise_scale: f32,
+    noise_dirty: bool,
+    // The dispatch writing the density mask, exists if the mask was generated.
+    density_mask_noise: Option<PendingNoise>,
+    // Colors the strands by uv in place of the procedural colors, white is bound while none
+    // is set.
+    albedo_texture: Option<Texture>,
+    albedo_texture_path: Option<PathBuf>,
+    albedo_texture_dirty: bool,
+    albedo_texture_failure: LoadFailure,
+    // Tilts the strands in the tangent frame, they extrude along the normal while none is set.
+    comb_map: Option<Texture>,
+    comb_map_path: Option<PathBuf>,
+    comb_map_dirty: bool,
+    comb_map_failure: LoadFailure,
+    white_texture: Texture,
+    // Samples the density mask, the albedo texture and the comb map.
+    mask_sampler: Sampler,
+    // Bumped whenever mask_sampler is recreated, so descriptor sets binding it know to rewrite.
+    mask_sampler_generation: u32,
+    texture_sampler_settings: SamplerSettings,
+    texture_sampler_dirty: bool,
 }
 
 struct ShellPipeline {
     graphics_pipeline: GraphicsPipeline,
+    // The polygon mode the graphics pipeline was built with, `LINE` draws the edge index buffer.
+    polygon_mode: vk::PolygonMode,
+    // The G-buffer setting the render pass was built with, a change is drawn once shell.frag
+    // has recompiled for it.
+    gbuffer: bool,
+    // Only writes the depth of the opaque base shell, exists if the depth pre-pass is enabled.
+    depth_prepass_pipeline: Option<GraphicsPipeline>,
+    // Exists if the ground shadow is enabled.
+    ground_shadow_pipeline: Option<GraphicsPipeline>,
+    // Exists if fins are enabled.
+    fin_pipeline: Option<GraphicsPipeline>,
+    // Exists if the render pipeline's grid is enabled.
+    grid_pipeline: Option<GraphicsPipeline>,
 }
 
+// Only what changes between draws of a frame, the fur settings are in the fur uniform.
+#[repr(C)]
 struct ShellPushConstants {
     // The current time in seconds since the start of the session.
     time: f32,
-    // Planes per cm.
-    resolution: u32,
+    // The number of shells drawn per mesh, each shell is one instance.
+    shell_count: u32,
+}
+
+// Must match the push constant block in shell.vert and shell.frag.
+const _: () = assert!(std::mem::size_of::<ShellPushConstants>() == 8);
+
+// Must match MeshFur in shell.vert and shell.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct MeshFurData {
+    // The color at the root of the strands.
+    base_color: [f32; 3],
+    // Strands per uv unit, packed after base_color to fill its padding.
+    density: f32,
+    // The color at the tip of the strands.
+    tip_color: [f32; 3],
     // The height of the grass in cm.
     grass_height: f32,
 }
 
+// Must match the fur uniform in shell.vert and shell.frag, vec3s are 16 byte aligned in std140
+// so each is followed by a scalar.
+#[repr(C)]
+struct FurUniform {
+    // Planes per cm.
+    resolution: u32,
+    // The intensity of the specular highlight, 0 disables it.
+    specular_strength: f32,
+    // The specular exponent, higher values give a tighter highlight.
+    shininess: f32,
+    // 1 fades the strand edges out through the alpha for alpha-to-coverage, 0 discards them.
+    alpha_to_coverage: u32,
+    // Mask values below this are bald, only the base shell is drawn there.
+    mask_threshold: f32,
+    // How far the tips sway in the wind.
+    wind_strength: f32,
+    // The normalized direction the wind blows in on the XZ plane.
+    wind_direction: [f32; 2],
+    // The least of the scene light's Lambert term any fur receives.
+    ambient: f32,
+    // interaction_point is 16 byte aligned in GLSL.
+    _ambient_padding: [f32; 3],
+    // The world space point the fur is pushed away from.
+    interaction_point: [f32; 3],
+    // How far from the interaction point fur is pushed, 0 disables it.
+    interaction_radius: f32,
+    // The world space direction the fur droops in, its length is how strongly.
+    gravity: [f32; 3],
+    // How much the fur resists drooping, 0 droops fully and 1 keeps it straight.
+    stiffness: f32,
+    // How much darker the roots are than the tips, 0 disables the darkening.
+    ao_strength: f32,
+    // 1 colors the strands from the albedo texture, 0 uses the procedural colors.
+    use_albedo_texture: u32,
+    // How much strands gather into tufts, 0 is plain per strand noise.
+    clumping: f32,
+    // 1 tilts the strands along the comb map, 0 extrudes them along the normal.
+    use_comb_map: u32,
+    // Indexed like the model uniform.
+    meshes: [MeshFurData; MAX_INSTANCES],
+}
+
+const _: () = assert!(std::mem::size_of::<FurUniform>() == 96 + 32 * MAX_INSTANCES);
+
+#[repr(C)]
+struct GroundShadowPushConstants {
+    // The world space point on the ground under the mesh.
+    center: [f32; 3],
+    // The radius of the shadow on the ground.
+    radius: f32,
+    // The fraction of the radius the shadow fades out over.
+    softness: f32,
+    // How dark the center of the shadow is.
+    opacity: f32,
+}
+
+#[repr(C)]
+struct GridPushConstants {
+    // The world space point on the ground under the camera, the grid is centered on it.
+    center: [f32; 3],
+    // Half the width of the grid quad, the lines fade out before its edge.
+    extent: f32,
+    color: [f32; 3],
+    // The world space distance between lines.
+    spacing: f32,
+}
+
+// Must match FurLayer in shell.frag, std140 rounds the struct up to 16 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct FurLayerData {
+    color: [f32; 3],
+    density: f32,
+    thickness: f32,
+    shell_fraction: f32,
+    _padding: [f32; 2],
+}
+
+// Must match the fur layers uniform in shell.frag.
+#[repr(C)]
+struct FurLayersUniform {
+    layer_count: u32,
+    // The layers array is 16 byte aligned in std140.
+    _padding: [u32; 3],
+    layers: [FurLayerData; MAX_FUR_LAYERS],
+}
+
+const _: () = assert!(std::mem::size_of::<FurLayersUniform>() == 144);
+
+// Must match Model in shell.vert, both matrices are column major.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ModelData {
+    model: [[f32; 4]; 4],
+    // Only the upper 3x3 is used, a mat4 avoids the padded columns of a std140 mat3.
+    normal: [[f32; 4]; 4],
+}
+
+// Must match the model uniform in shell.vert, the renderer's own mesh is first and the
+// instances follow in the order they were added.
+#[repr(C)]
+struct ModelUniform {
+    models: [ModelData; MAX_INSTANCES],
+}
+
+// Must match Light in shell.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct LightData {
+    position_or_direction: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    // The LightKind, packed after color to fill its padding.
+    kind: u32,
+}
+
+// Must match the lights uniform in shell.frag.
+#[repr(C)]
+struct LightsUniform {
+    light_count: u32,
+    // The lights array is 16 byte aligned in std140.
+    _padding: [u32; 3],
+    lights: [LightData; MAX_LIGHTS],
+}
+
+const _: () = assert!(std::mem::size_of::<LightsUniform>() == 144);
+
 impl ShellRenderer {
     fn new(
         assets: &mut Assets,
@@ -69,6 +537,7 @@ impl ShellRenderer {
         vulkan_allocator: &mut VulkanAllocator,
         vulkan_stager: &mut VulkanStager,
         render_pipeline: &RenderPipeline,
+        frames_in_flight: usize,
     ) -> Self {
         // Load shaders and create dependency signal to them.
         let shader_dependency_signal = watched_shaders.create_dependency_signal();
@@ -78,259 +547,1679 @@ impl ShellRenderer {
             VERTEX_NAME,
             &shader_dependency_signal,
         );
-        watched_shaders.load_shader(
+        watched_shaders.load_shader_with_defines(
             assets,
             FRAGMENT_FILE_PATH,
             FRAGMENT_NAME,
+            fragment_defines(false),
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            GROUND_SHADOW_VERTEX_FILE_PATH,
+            GROUND_SHADOW_VERTEX_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            GROUND_SHADOW_FRAGMENT_FILE_PATH,
+            GROUND_SHADOW_FRAGMENT_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            GRID_VERTEX_FILE_PATH,
+            GRID_VERTEX_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            GRID_FRAGMENT_FILE_PATH,
+            GRID_FRAGMENT_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            FIN_VERTEX_FILE_PATH,
+            FIN_VERTEX_NAME,
+            &shader_dependency_signal,
+        );
+        watched_shaders.load_shader(
+            assets,
+            FIN_FRAGMENT_FILE_PATH,
+            FIN_FRAGMENT_NAME,
             &shader_dependency_signal,
         );
 
-        let plane_mesh = MeshFactory::factory(vulkan, vulkan_allocator, vulkan_stager)
-            .create_sphere_icosahedron(3);
+        let plane_mesh = MeshPreset::Sphere.create(
+            &mut MeshFactory::factory(vulkan, vulkan_allocator, vulkan_stager).optimize(true),
+        );
 
-        let shell_resolve_image = Image::new(
+        let shell_resolve_image = create_resolve_image(vulkan, vulkan_allocator, render_pipeline);
+        let shell_normal_image = create_normal_image(
             vulkan,
             vulkan_allocator,
-            &ImageInfo::builder()
-                .extent(render_pipeline.backbuffer_image().image_extent())
-                .format(vk::Format::R8G8B8A8_UNORM)
-                .usage(
-                    vk::ImageUsageFlags::STORAGE
-                        | vk::ImageUsageFlags::COLOR_ATTACHMENT
-                        | vk::ImageUsageFlags::TRANSFER_DST,
-                )
-                .view_subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .level_count(1)
-                        .build(),
-                )
-                .build(),
+            render_pipeline,
+            vk::SampleCountFlags::TYPE_1,
         );
 
-        let shell_resolve_depth_image = Image::new(
-            vulkan,
-            vulkan_allocator,
-            &ImageInfo::builder()
-                .extent(render_pipeline.backbuffer_image().image_extent())
-                .format(vk::Format::D32_SFLOAT)
-                .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
-                .view_subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                        .layer_count(1)
-                        .level_count(1)
+        let model_buffers = (0..frames_in_flight.max(1))
+            .map(|_| {
+                Arc::new(UntypedBuffer::new(
+                    vulkan,
+                    vulkan_allocator,
+                    &BufferInfo::builder()
+                        .size(std::mem::size_of::<ModelUniform>() as u64)
+                        .usage(
+                            vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
                         .build(),
-                )
-                .build(),
+                ))
+            })
+            .collect();
+
+        let fur_buffers = (0..frames_in_flight.max(1))
+            .map(|_| {
+                Arc::new(UntypedBuffer::new(
+                    vulkan,
+                    vulkan_allocator,
+                    &BufferInfo::builder()
+                        .size(std::mem::size_of::<FurUniform>() as u64)
+                        .usage(
+                            vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
+                        .build(),
+                ))
+            })
+            .collect();
+
+        let layers_buffers = (0..frames_in_flight.max(1))
+            .map(|_| {
+                Arc::new(UntypedBuffer::new(
+                    vulkan,
+                    vulkan_allocator,
+                    &BufferInfo::builder()
+                        .size(std::mem::size_of::<FurLayersUniform>() as u64)
+                        .usage(
+                            vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
+                        .build(),
+                ))
+            })
+            .collect();
+
+        let lights_buffers = (0..frames_in_flight.max(1))
+            .map(|_| {
+                Arc::new(UntypedBuffer::new(
+                    vulkan,
+                    vulkan_allocator,
+                    &BufferInfo::builder()
+                        .size(std::mem::size_of::<LightsUniform>() as u64)
+                        .usage(
+                            vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
+                        .build(),
+                ))
+            })
+            .collect();
+
+        let noise_generator = NoiseGenerator::new(
+            vulkan,
+            render_pipeline,
+            assets,
+            watched_shaders,
+            &shader_dependency_signal,
         );
 
+        let density_mask = Texture::white(vulkan, vulkan_allocator, vulkan_stager);
+        let white_texture = Texture::white(vulkan, vulkan_allocator, vulkan_stager);
+        let texture_sampler_settings = SamplerSettings::default();
+        let mask_sampler = texture_sampler_settings.create(vulkan);
+
         Self {
             shader_dependency_signal,
             shell_resolve_image,
-            shell_resolve_depth_image,
+            gbuffer: false,
+            shader_gbuffer: false,
+            shell_normal_image,
+            shell_normal_msaa_image: None,
+            backbuffer_generation: render_pipeline.backbuffer_generation(),
             pipeline: None,
+            pipeline_dirty: false,
+            reverse_z: render_pipeline.reverse_z(),
+            grid: render_pipeline.grid().is_some(),
+            depth_prepass: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            alpha_to_coverage: false,
             plane_mesh,
+            mesh_preset: MeshPreset::Sphere,
+            pending_mesh: None,
+            mesh_load_error: None,
             resolution: 128,
             shell_thickness: 0.35,
+            shell_count: shell_count_for(0.35, 128),
+            max_shells: DEFAULT_MAX_SHELLS,
+            specular_strength: 0.0,
+            shininess: 32.0,
+            gravity: Vector3::zeros(),
+            stiffness: 0.0,
+            base_color: GRASS_COLORS.0,
+            tip_color: GRASS_COLORS.1,
+            wind_direction: [1.0, 0.0],
+            wind_strength: 0.0,
+            density: 126.0,
+            clumping: 0.0,
+            ao_strength: 0.0,
+            // An ambient of 1 leaves the Lambert term off, so the fur looks as it did before it
+            // until the ambient is lowered.
+            ambient: 1.0,
+            interaction_point: Vector3::zeros(),
+            target_interaction_radius: 0.0,
+            interaction_radius: 0.0,
+            shed_particles: 0.0,
+            layers: Vec::new(),
+            layers_buffers,
+            layers_buffer_index: 0,
+            layers_dirty: true,
+            lights: Vec::new(),
+            lights_buffers,
+            lights_buffer_index: 0,
+            lights_dirty: true,
+            ground_shadow: false,
+            ground_y: -1.0,
+            ground_shadow_radius: 1.2,
+            ground_shadow_softness: 0.5,
+            fins: false,
+            fin_buffers: vec![None; frames_in_flight.max(1)],
+            fin_buffer_index: 0,
+            fin_vertex_count: 0,
+            fin_eye: None,
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.0),
+            model_buffers,
+            model_buffer_index: 0,
+            fur_buffers,
+            fur_buffer_index: 0,
+            instances: Vec::new(),
+            next_instance_id: 0,
+            density_mask,
+            density_mask_path: None,
+            density_mask_dirty: false,
+            density_mask_failure: LoadFailure::default(),
+            mask_threshold: 0.1,
+            noise_generator,
+            // The fur starts out grown from Worley noise, generated once the shader compiles.
+            noise_settings: Some(NoiseSettings {
+                kind: NoiseKind::Worley,
+                size: DEFAULT_NOISE_SIZE,
+                seed: DEFAULT_NOISE_SEED,
+                scale: DEFAULT_NOISE_SCALE,
+            }),
+            noise_scale: DEFAULT_NOISE_SCALE,
+            noise_dirty: true,
+            density_mask_noise: None,
+            albedo_texture: None,
+            albedo_texture_path: None,
+            albedo_texture_dirty: false,
+            albedo_texture_failure: LoadFailure::default(),
+            comb_map: None,
+            comb_map_path: None,
+            comb_map_dirty: false,
+            comb_map_failure: LoadFailure::default(),
+            white_texture,
+            mask_sampler,
+            mask_sampler_generation: 0,
+            texture_sampler_settings,
+            texture_sampler_dirty: false,
         }
     }
 
-    pub fn resolve_image(&self) -> &Image {
-        &self.shell_resolve_image
+    /// Replaces the mesh the shells are grown from.
+    ///
+    /// The old mesh buffers are part of the deps of frames already rendered with it, so they
+    /// are kept alive until those frames finish.
+    pub fn set_mesh(&mut self, mesh: Mesh) {
+        self.plane_mesh = mesh;
+        self.fin_eye = None;
     }
 
-    pub fn resolve_depth_image(&self) -> &Image {
-        &self.shell_resolve_depth_image
+    /// Loads an OBJ or glTF mesh on a worker thread, see `MeshHandle::load`.
+    ///
+    /// The current mesh keeps rendering until the new one is parsed, then it is swapped in with
+    /// `set_mesh` on the next update. If loading fails the current mesh is kept and the error
+    /// is available from `mesh_load_error`.
+    pub fn load_mesh(&mut self, path: impl Into<String>) {
+        self.pending_mesh = Some(MeshHandle::load(path));
+        self.mesh_load_error = None;
     }
 
-    pub fn is_ready(&self) -> bool {
-        self.pipeline.is_some()
+    /// Whether a mesh from `load_mesh` is still being parsed.
+    pub fn is_mesh_loading(&self) -> bool {
+        self.pending_mesh.is_some()
     }
 
-    pub fn render(
-        &self,
-        render_manager: &mut RenderManager,
-        render_pipeline: &RenderPipeline,
-        current_time: f32,
-    ) -> Vec<Arc<dyn Any + Send + Sync>> {
-        if let Some(pipeline) = &self.pipeline {
-            let backbuffer_image = render_pipeline.backbuffer_image();
+    /// Why the last `load_mesh` failed, `None` if it succeeded or is still loading.
+    pub fn mesh_load_error(&self) -> Option<&str> {
+        self.mesh_load_error.as_deref()
+    }
 
-            let render_area = vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: vk::Extent2D {
-                    width: backbuffer_image.image_extent().width,
-                    height: backbuffer_image.image_extent().height,
-                },
-            };
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
 
-            render_manager
-                .frame()
-                .command_buffer()
-                .dynamic_state_viewport(
-                    vk::Viewport::builder()
-                        .width(backbuffer_image.image_extent().width as f32)
-                        .height(backbuffer_image.image_extent().height as f32)
-                        .min_depth(0.0)
-                        .max_depth(1.0)
-                        .build(),
-                );
-            render_manager
-                .frame()
-                .command_buffer()
-                .dynamic_state_scissor(render_area);
-            render_manager
-                .frame_mut()
-                .command_buffer_mut()
-                .bind_graphics_pipeline(&pipeline.graphics_pipeline);
+    /// Sets the shell density in planes per cm, this also recomputes the shell count.
+    pub fn set_resolution(&mut self, resolution: u32) {
+        self.resolution = resolution.max(1);
+        self.set_instance_count_from_thickness();
+    }
 
-            let descriptor_sets = [render_pipeline.frame(render_manager).descriptor_set()];
-            render_manager
-                .frame_mut()
-                .command_buffer_mut()
-                .bind_descriptor_sets(
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline.graphics_pipeline.pipeline_layout(),
-                    &descriptor_sets,
-                );
+    pub fn shell_thickness(&self) -> f32 {
+        self.shell_thickness
+    }
 
-            let clear_values = &[
-                vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                },
-                vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                },
-                vk::ClearValue {
-                    depth_stencil: vk::ClearDepthStencilValue {
-                        depth: 1.0,
-                        stencil: 0,
-                    },
-                },
-            ];
+    /// Sets the height of the shells in cm, this also recomputes the shell count.
+    pub fn set_shell_thickness(&mut self, shell_thickness: f32) {
+        self.shell_thickness = shell_thickness.max(0.05);
+        self.set_instance_count_from_thickness();
+    }
 
-            render_manager.frame().command_buffer().begin_render_pass(
-                pipeline.graphics_pipeline.render_pass(),
-                render_area,
-                clear_values,
-            );
+    pub fn shell_count(&self) -> u32 {
+        self.shell_count
+    }
 
-            render_manager
-                .frame()
-                .command_buffer()
-                .write_push_constants_typed(
-                    pipeline.graphics_pipeline.pipeline_layout(),
-                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                    0,
-                    &ShellPushConstants {
-                        time: current_time,
-                        resolution: self.resolution,
-                        grass_height: self.shell_thickness,
-                    },
-                );
+    /// Overrides the shell count until the resolution or thickness is changed again.
+    pub fn set_shell_count(&mut self, shell_count: u32) {
+        self.shell_count = shell_count.clamp(1, self.max_shells);
+    }
 
-            render_manager
-                .frame_mut()
-                .command_buffer_mut()
-                .bind_vertex_buffer(0, self.plane_mesh.vertex_buffer());
-            render_manager
-                .frame_mut()
-                .command_buffer_mut()
-                .bind_index_buffer(self.plane_mesh.index_buffer(), vk::IndexType::UINT32);
-            render_manager.frame().command_buffer().draw_indexed(
-                self.plane_mesh.vertex_count() as u32,
-                // f32::floor(self.grass_height * self.resolution as f32) as u32,
-                self.resolution,
-                0,
-                0,
-                0,
-            );
+    /// Derives the shell count from the thickness and resolution as `floor(thickness *
+    /// resolution)`, so there is one shell per plane spacing, clamped to `max_shells`.
+    pub fn set_instance_count_from_thickness(&mut self) {
+        self.shell_count =
+            shell_count_for(self.shell_thickness, self.resolution).min(self.max_shells);
+    }
 
-            render_manager.frame().command_buffer().end_render_pass();
+    pub fn max_shells(&self) -> u32 {
+        self.max_shells
+    }
 
-            render_manager.frame().command_buffer().pipeline_barrier(
-                vk::PipelineStageFlags::ALL_GRAPHICS,
-                vk::PipelineStageFlags::COMPUTE_SHADER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[vk::ImageMemoryBarrier::builder()
-                    .image(render_pipeline.backbuffer_depth_image().image())
-                    .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
-                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                    .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                    .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::builder()
-                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                            .layer_count(1)
-                            .level_count(1)
-                            .build(),
-                    )
-                    .build()],
-            );
+    /// Caps the number of shell instances drawn, the current shell count is clamped to it.
+    pub fn set_max_shells(&mut self, max_shells: u32) {
+        self.max_shells = max_shells.max(1);
+        self.shell_count = self.shell_count.min(self.max_shells);
+    }
 
-            return vec![
-                self.plane_mesh.vertex_buffer().clone(),
-                self.plane_mesh.index_buffer().clone(),
-                self.shell_resolve_image.create_dep(),
-                backbuffer_image.create_dep(),
-                render_pipeline.backbuffer_depth_image().create_dep(),
-            ];
-        }
+    /// Whether the thickness and resolution ask for more shells than `max_shells`.
+    pub fn is_shell_count_capped(&self) -> bool {
+        shell_count_for(self.shell_thickness, self.resolution) > self.max_shells
+    }
 
-        vec![]
+    /// Sets the grayscale image controlling where fur grows, `None` grows fur everywhere.
+    ///
+    /// The mask is loaded on the next update and reloaded whenever the file changes.
+    pub fn set_density_mask_path(&mut self, path: Option<impl Into<PathBuf>>) {
+        self.density_mask_path = path.map(Into::into);
+        self.density_mask_dirty = true;
+        self.noise_settings = None;
+        self.noise_dirty = false;
     }
 
-    fn refresh_pipeline(
-        &mut self,
-        vulkan: &Vulkan,
-        watched_shaders: &WatchedShaders,
-        render_manager: &RenderManager,
-        render_pipeline: &RenderPipeline,
-    ) {
-        let mut subpass = Subpass::new();
-        subpass.color_attachment(
-            &render_pipeline.backbuffer_image().as_attachment(
-                AttachmentInfo::default()
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .samples(vk::SampleCountFlags::TYPE_4),
-            ),
-        );
-        subpass.resolve_attachment(
-            &self.shell_resolve_image.as_attachment(
-                AttachmentInfo::default()
-                    .samples(vk::SampleCountFlags::TYPE_1)
-                    .final_layout(vk::ImageLayout::GENERAL),
-            ),
-        );
-        subpass.depth_attachment(
-            &render_pipeline.backbuffer_depth_image().as_attachment(
-                AttachmentInfo::default()
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .samples(vk::SampleCountFlags::TYPE_4)
-                    .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                    .is_depth(true),
-            ),
-        );
+    /// Fills the density mask with tiling noise generated by a compute shader, replacing any
+    /// mask path.
+    ///
+    /// The texture is created on the next update and written the first frame it is rendered.
+    /// The same kind, size, seed and noise scale always give the same pattern.
+    pub fn generate_noise_texture(&mut self, kind: NoiseKind, size: u32, seed: u32) {
+        self.noise_settings = Some(NoiseSettings {
+            kind,
+            size: size.clamp(1, MAX_NOISE_SIZE),
+            seed,
+            scale: self.noise_scale,
+        });
+        self.noise_dirty = true;
+        self.density_mask_path = None;
+        self.density_mask_dirty = false;
+    }
 
-        let render_pass = RenderPass::new(vulkan, &[subpass]);
+    /// Sets the number of noise cells across the texture, regenerating the noise if it is used.
+    pub fn set_noise_scale(&mut self, noise_scale: f32) {
+        self.noise_scale = noise_scale.max(1.0).round();
+        if let Some(settings) = &mut self.noise_settings {
+            settings.scale = self.noise_scale;
+            self.noise_dirty = true;
+        }
+    }
 
-        let vertex_shader = Shader::new(vulkan, &watched_shaders.get_shader(VERTEX_NAME).unwrap());
-        let fragment_shader =
+    pub fn noise_scale(&self) -> f32 {
+        self.noise_scale
+    }
+
+    /// The settings of the generated density mask, `None` if the mask isn't generated.
+    pub fn noise_settings(&self) -> Option<NoiseSettings> {
+        self.noise_settings
+    }
+
+    pub fn set_mask_threshold(&mut self, mask_threshold: f32) {
+        self.mask_threshold = mask_threshold.clamp(0.0, 1.0);
+    }
+
+    pub fn mask_threshold(&self) -> f32 {
+        self.mask_threshold
+    }
+
+    /// Replaces the density mask with an already loaded texture, it is reloaded if it came
+    /// from a file that changes.
+    pub fn set_mask_texture(&mut self, texture: Texture) {
+        self.density_mask_path = texture.path().map(Path::to_path_buf);
+        self.density_mask_dirty = false;
+        self.noise_settings = None;
+        self.noise_dirty = false;
+        self.density_mask_noise = None;
+        self.density_mask = texture;
+    }
+
+    pub fn density_mask(&self) -> &Texture {
+        &self.density_mask
+    }
+
+    /// Sets the image the strand colors are looked up from by uv, `None` goes back to the
+    /// procedural colors.
+    ///
+    /// The texture is loaded on the next update and reloaded whenever the file changes, until
+    /// it loads the previous colors are kept.
+    pub fn set_albedo_texture_path(&mut self, path: Option<impl Into<PathBuf>>) {
+        self.albedo_texture_path = path.map(Into::into);
+        if self.albedo_texture_path.is_none() {
+            self.albedo_texture = None;
+        }
+        self.albedo_texture_dirty = self.albedo_texture_path.is_some();
+    }
+
+    /// Colors the strands from an already loaded texture, it is reloaded if it came from a file
+    /// that changes.
+    pub fn set_albedo_texture(&mut self, texture: Texture) {
+        self.albedo_texture_path = texture.path().map(Path::to_path_buf);
+        self.albedo_texture_dirty = false;
+        self.albedo_texture = Some(texture);
+    }
+
+    pub fn clear_albedo_texture(&mut self) {
+        self.set_albedo_texture_path(None::<PathBuf>);
+    }
+
+    /// The texture bound for the strand colors, a white pixel while none is set.
+    pub fn albedo_texture(&self) -> &Texture {
+        self.albedo_texture.as_ref().unwrap_or(&self.white_texture)
+    }
+
+    pub fn has_albedo_texture(&self) -> bool {
+        self.albedo_texture.is_some()
+    }
+
+    /// Sets the image the strands are combed along, `None` extrudes them straight along the
+    /// normal. The red and green channels are the tangent space direction, 0.5 is no tilt.
+    ///
+    /// The comb map is loaded on the next update and reloaded whenever the file changes.
+    pub fn set_comb_map_path(&mut self, path: Option<impl Into<PathBuf>>) {
+        self.comb_map_path = path.map(Into::into);
+        if self.comb_map_path.is_none() {
+            self.comb_map = None;
+        }
+        self.comb_map_dirty = self.comb_map_path.is_some();
+    }
+
+    /// Combs the strands along an already loaded texture, it is reloaded if it came from a file
+    /// that changes.
+    pub fn set_comb_map(&mut self, texture: Texture) {
+        self.comb_map_path = texture.path().map(Path::to_path_buf);
+        self.comb_map_dirty = false;
+        self.comb_map = Some(texture);
+    }
+
+    pub fn clear_comb_map(&mut self) {
+        self.set_comb_map_path(None::<PathBuf>);
+    }
+
+    /// The texture bound for the comb directions, a white pixel while none is set.
+    pub fn comb_map(&self) -> &Texture {
+        self.comb_map.as_ref().unwrap_or(&self.white_texture)
+    }
+
+    pub fn has_comb_map(&self) -> bool {
+        self.comb_map.is_some()
+    }
+
+    pub fn mask_sampler(&self) -> &Sampler {
+        &self.mask_sampler
+    }
+
+    /// Changes whenever `mask_sampler` is a different sampler.
+    pub fn mask_sampler_generation(&self) -> u32 {
+        self.mask_sampler_generation
+    }
+
+    /// Sets how the density mask and albedo texture are filtered, the sampler is recreated on
+    /// the next update.
+    pub fn set_texture_sampler(&mut self, settings: SamplerSettings) {
+        if self.texture_sampler_settings != settings {
+            self.texture_sampler_settings = settings;
+            self.texture_sampler_dirty = true;
+        }
+    }
+
+    pub fn texture_sampler(&self) -> SamplerSettings {
+        self.texture_sampler_settings
+    }
+
+    /// Renders the base mesh depth first so hidden shell fragments are rejected early.
+    ///
+    /// The shells then only test the depth without writing it, so passes after them, like the
+    /// ground shadow, see the depth of the base mesh rather than the fur.
+    pub fn set_depth_prepass(&mut self, depth_prepass: bool) {
+        if self.depth_prepass != depth_prepass {
+            self.depth_prepass = depth_prepass;
+            self.pipeline_dirty = true;
+        }
+    }
+
+    pub fn depth_prepass(&self) -> bool {
+        self.depth_prepass
+    }
+
+    pub fn polygon_mode(&self) -> vk::PolygonMode {
+        self.polygon_mode
+    }
+
+    /// Sets how the shells are drawn, `LINE` shows the mesh and every shell as wireframe. The
+    /// wireframe is drawn from the mesh edges as lines, so it works without `fillModeNonSolid`.
+    /// `POINT` isn't supported and is ignored.
+    pub fn set_polygon_mode(&mut self, polygon_mode: vk::PolygonMode) {
+        if polygon_mode != vk::PolygonMode::FILL && polygon_mode != vk::PolygonMode::LINE {
+            println!(
+                "Unsupported shell polygon mode {:?}, ignoring it.",
+                polygon_mode
+            );
+            return;
+        }
+
+        if self.polygon_mode != polygon_mode {
+            self.polygon_mode = polygon_mode;
+            self.pipeline_dirty = true;
+        }
+    }
+
+    pub fn cull_mode(&self) -> vk::CullModeFlags {
+        self.cull_mode
+    }
+
+    /// Sets which faces of the mesh and its shells are culled, `BACK` skips the far side of
+    /// closed meshes.
+    ///
+    /// Every shell is the mesh pushed out along its normals, so it keeps the winding of the
+    /// mesh and faces the same way. The far side of the outer shells can still poke out past
+    /// the silhouette at grazing angles, culling it thins the fur slightly there. Open meshes
+    /// like the plane disappear from behind when culling back faces.
+    pub fn set_cull_mode(&mut self, cull_mode: vk::CullModeFlags) {
+        if self.cull_mode != cull_mode {
+            self.cull_mode = cull_mode;
+            self.pipeline_dirty = true;
+        }
+    }
+
+    pub fn alpha_to_coverage(&self) -> bool {
+        self.alpha_to_coverage
+    }
+
+    /// Fades the strand edges out through the alpha instead of discarding them, so MSAA turns
+    /// the alpha into partial sample coverage and smooths the edges.
+    ///
+    /// This has no effect without MSAA, the strands are discarded as before.
+    pub fn set_alpha_to_coverage(&mut self, alpha_to_coverage: bool) {
+        if self.alpha_to_coverage != alpha_to_coverage {
+            self.alpha_to_coverage = alpha_to_coverage;
+            self.pipeline_dirty = true;
+        }
+    }
+
+    // Whether the strands use alpha-to-coverage this frame, it needs more than one sample.
+    fn uses_alpha_to_coverage(&self, render_pipeline: &RenderPipeline) -> bool {
+        self.alpha_to_coverage && render_pipeline.msaa_samples() != vk::SampleCountFlags::TYPE_1
+    }
+
+    /// Droops the fur along `gravity` in world space, the tips bend the most and the base shell
+    /// stays on the surface. A length of 1 bends the tips fully along it.
+    pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
+        self.gravity = gravity;
+    }
+
+    pub fn gravity(&self) -> Vector3<f32> {
+        self.gravity
+    }
+
+    /// Sets how much the fur resists gravity, clamped between 0 for full droop and 1 for none.
+    pub fn set_stiffness(&mut self, stiffness: f32) {
+        self.stiffness = stiffness.clamp(0.0, 1.0);
+    }
+
+    pub fn stiffness(&self) -> f32 {
+        self.stiffness
+    }
+
+    /// Sets the colors the strands fade between from root to tip.
+    pub fn set_colors(&mut self, base_color: [f32; 3], tip_color: [f32; 3]) {
+        self.base_color = base_color;
+        self.tip_color = tip_color;
+    }
+
+    /// The root and tip colors.
+    pub fn colors(&self) -> ([f32; 3], [f32; 3]) {
+        (self.base_color, self.tip_color)
+    }
+
+    /// Sets the wind blowing over the shells, `direction` is on the XZ plane.
+    pub fn set_wind(&mut self, direction: [f32; 2], strength: f32) {
+        let length = (direction[0] * direction[0] + direction[1] * direction[1]).sqrt();
+        if length > 0.0 {
+            self.wind_direction = [direction[0] / length, direction[1] / length];
+        }
+        self.wind_strength = strength.max(0.0);
+    }
+
+    /// The normalized wind direction and its strength.
+    pub fn wind(&self) -> ([f32; 2], f32) {
+        (self.wind_direction, self.wind_strength)
+    }
+
+    pub fn set_density(&mut self, density: f32) {
+        self.density = density.max(1.0);
+    }
+
+    pub fn density(&self) -> f32 {
+        self.density
+    }
+
+    /// Gathers strands into tufts, clamped between 0 for evenly spread strands and 1.
+    pub fn set_clumping(&mut self, clumping: f32) {
+        self.clumping = clumping.clamp(0.0, 1.0);
+    }
+
+    pub fn clumping(&self) -> f32 {
+        self.clumping
+    }
+
+    /// Darkens the strands toward their roots to fake self-shadowing, clamped between 0 and 1.
+    pub fn set_ao_strength(&mut self, ao_strength: f32) {
+        self.ao_strength = ao_strength.clamp(0.0, 1.0);
+    }
+
+    pub fn ao_strength(&self) -> f32 {
+        self.ao_strength
+    }
+
+    /// Sets the least of the scene light's Lambert term any fur receives, so fur facing away
+    /// from the light isn't black. 1, the default, turns the Lambert term off.
+    pub fn set_ambient(&mut self, ambient: f32) {
+        self.ambient = ambient.clamp(0.0, 1.0);
+    }
+
+    pub fn ambient(&self) -> f32 {
+        self.ambient
+    }
+
+    /// Flattens the fur within `radius` of a world space point, a radius of 0 lets it return.
+    pub fn set_interaction(&mut self, point: Vector3<f32>, radius: f32) {
+        // Keep the last point while returning so the fur springs back where it was pushed.
+        if radius > 0.0 {
+            self.interaction_point = point;
+        }
+        self.target_interaction_radius = radius.max(0.0);
+    }
+
+    /// Sets the coats of fur composited in the shell pass, at most `MAX_FUR_LAYERS` are kept.
+    ///
+    /// Each shell draws the layer whose range contains its height, so a short dense layer can
+    /// sit under a long sparse one. An empty slice goes back to a single coat using the colors
+    /// and density set on the renderer.
+    pub fn set_layers(&mut self, layers: &[FurLayer]) {
+        self.layers = layers
+            .iter()
+            .take(MAX_FUR_LAYERS)
+            .map(|layer| FurLayer {
+                color: layer.color,
+                density: layer.density.max(1.0),
+                thickness: layer.thickness.max(0.0),
+                shell_fraction: layer.shell_fraction.clamp(0.01, 1.0),
+            })
+            .collect();
+        self.layers
+            .sort_by(|a, b| a.shell_fraction.total_cmp(&b.shell_fraction));
+        self.layers_dirty = true;
+    }
+
+    pub fn layers(&self) -> &[FurLayer] {
+        &self.layers
+    }
+
+    /// The buffer holding the latest layers, for the frame being recorded.
+    pub fn layers_buffer(&self) -> &Arc<UntypedBuffer> {
+        &self.layers_buffers[self.layers_buffer_index]
+    }
+
+    /// Sets the lights shading the fur on top of the scene light, each adds a Lambert term.
+    ///
+    /// Only the first `MAX_LIGHTS` are used, an empty slice leaves the scene light alone.
+    pub fn set_lights(&mut self, lights: &[Light]) {
+        self.lights = lights
+            .iter()
+            .take(MAX_LIGHTS)
+            .map(|light| {
+                let mut light = *light;
+                if light.kind == LightKind::Directional {
+                    let direction = Vector3::from(light.position_or_direction);
+                    light.position_or_direction = direction
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or(-Vector3::y())
+                        .into();
+                }
+                light.intensity = light.intensity.max(0.0);
+                light
+            })
+            .collect();
+        self.lights_dirty = true;
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// The buffer holding the latest lights, for the frame being recorded.
+    pub fn lights_buffer(&self) -> &Arc<UntypedBuffer> {
+        &self.lights_buffers[self.lights_buffer_index]
+    }
+
+    /// Draws a soft circular shadow on the ground plane at `ground_y`, centered under the mesh.
+    ///
+    /// `softness` is the fraction of the radius the shadow fades out over, clamped between
+    /// 0.01 and 1.
+    pub fn set_ground_shadow(&mut self, enabled: bool, ground_y: f32, radius: f32, softness: f32) {
+        if self.ground_shadow != enabled {
+            self.ground_shadow = enabled;
+            self.pipeline_dirty = true;
+        }
+        self.ground_y = ground_y;
+        self.ground_shadow_radius = radius.max(0.0);
+        self.ground_shadow_softness = softness.clamp(0.01, 1.0);
+    }
+
+    /// Draws quads along the silhouette of the mesh as seen from the camera, textured with the
+    /// same strands as the shells, to fill out the fur at the edges where the shells are seen
+    /// edge on.
+    ///
+    /// Only the renderer's own mesh gets fins, and they stand straight out of the surface
+    /// without the wind, gravity, combing and interaction the shells bend with.
+    pub fn set_fins(&mut self, enabled: bool) {
+        if self.fins != enabled {
+            self.fins = enabled;
+            self.pipeline_dirty = true;
+        }
+    }
+
+    pub fn fins(&self) -> bool {
+        self.fins
+    }
+
+    pub fn set_translation(&mut self, translation: Vector3<f32>) {
+        self.translation = translation;
+    }
+
+    pub fn set_rotation(&mut self, rotation: UnitQuaternion<f32>) {
+        self.rotation = rotation;
+    }
+
+    /// Scales the mesh per axis, each axis is kept above 0.001 so the transform stays
+    /// invertible.
+    pub fn set_scale(&mut self, scale: Vector3<f32>) {
+        self.scale = scale.map(|axis| axis.max(0.001));
+    }
+
+    /// Places the mesh, applying the scale first, then the rotation, then the translation.
+    pub fn set_transform(
+        &mut self,
+        translation: Vector3<f32>,
+        rotation: UnitQuaternion<f32>,
+        scale: Vector3<f32>,
+    ) {
+        self.set_translation(translation);
+        self.set_rotation(rotation);
+        self.set_scale(scale);
+    }
+
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    /// The inverse transpose of the model matrix without the translation, which keeps normals
+    /// perpendicular to the surface under non-uniform scale.
+    pub fn normal_matrix(&self) -> Matrix4<f32> {
+        // The inverse transpose of a rotation is itself, so only the scale is inverted.
+        self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale.map(|axis| 1.0 / axis))
+    }
+
+    /// The center and radius of a world space sphere around the mesh and the tips of its fur.
+    pub fn bounding_sphere(&self) -> (Vector3<f32>, f32) {
+        world_bounding_sphere(
+            self.plane_mesh.bounding_sphere(),
+            &self.model_matrix(),
+            self.shell_thickness,
+        )
+    }
+
+    /// The buffer holding the transforms of the latest update, for the frame being recorded.
+    pub fn model_buffer(&self) -> &Arc<UntypedBuffer> {
+        &self.model_buffers[self.model_buffer_index]
+    }
+
+    /// The buffer holding the fur settings of the latest update, for the frame being recorded.
+    pub fn fur_buffer(&self) -> &Arc<UntypedBuffer> {
+        &self.fur_buffers[self.fur_buffer_index]
+    }
+
+    /// The fur settings of the renderer's own mesh, a starting point for new instances.
+    pub fn fur_params(&self) -> FurParams {
+        FurParams {
+            base_color: self.base_color,
+            tip_color: self.tip_color,
+            density: self.density,
+            shell_thickness: self.shell_thickness,
+        }
+    }
+
+    /// Draws another copy of the mesh in the same pass, `None` once `MAX_INSTANCES` meshes are
+    /// drawn.
+    ///
+    /// Every instance shares the mesh, shell count, lighting and pipeline state. The density
+    /// and thickness are clamped like their ShellRenderer setters.
+    pub fn add_instance(&mut self, instance: Instance) -> Option<InstanceId> {
+        if self.instances.len() + 1 >= MAX_INSTANCES {
+            return None;
+        }
+
+        let id = InstanceId(self.next_instance_id);
+        self.next_instance_id += 1;
+        let mut instance = instance;
+        instance.fur_params.density = instance.fur_params.density.max(1.0);
+        instance.fur_params.shell_thickness = instance.fur_params.shell_thickness.max(0.05);
+        self.instances.push((id, instance));
+        Some(id)
+    }
+
+    /// Stops drawing an instance, returns false if it was already removed.
+    pub fn remove_instance(&mut self, id: InstanceId) -> bool {
+        let count = self.instances.len();
+        self.instances.retain(|(instance_id, _)| *instance_id != id);
+        self.instances.len() != count
+    }
+
+    pub fn instance_mut(&mut self, id: InstanceId) -> Option<&mut Instance> {
+        self.instances
+            .iter_mut()
+            .find(|(instance_id, _)| *instance_id == id)
+            .map(|(_, instance)| instance)
+    }
+
+    pub fn set_specular_strength(&mut self, specular_strength: f32) {
+        self.specular_strength = specular_strength.max(0.0);
+    }
+
+    pub fn specular_strength(&self) -> f32 {
+        self.specular_strength
+    }
+
+    pub fn set_shininess(&mut self, shininess: f32) {
+        self.shininess = shininess.max(1.0);
+    }
+
+    pub fn shininess(&self) -> f32 {
+        self.shininess
+    }
+
+    pub fn resolve_image(&self) -> &Image {
+        &self.shell_resolve_image
+    }
+
+    /// Adds the normal output to the shell pass, the single output path is used when disabled.
+    ///
+    /// Takes effect once shell.frag is recompiled with or without its normal output.
+    pub fn set_gbuffer(&mut self, gbuffer: bool) {
+        if self.gbuffer != gbuffer {
+            self.gbuffer = gbuffer;
+            self.pipeline_dirty = true;
+        }
+    }
+
+    pub fn gbuffer(&self) -> bool {
+        self.gbuffer
+    }
+
+    /// The view space normal in rgb and the shell height in a, resolved like `resolve_image()`.
+    ///
+    /// The image always exists so it can be bound, but is only written while the G-buffer is
+    /// enabled.
+    pub fn shell_normal_image(&self) -> &Image {
+        &self.shell_normal_image
+    }
+
+    pub fn backbuffer_generation(&self) -> u32 {
+        self.backbuffer_generation
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.pipeline.is_some()
+    }
+
+    pub fn render(
+        &self,
+        vulkan: &Vulkan,
+        render_manager: &mut RenderManager,
+        render_pipeline: &RenderPipeline,
+        camera: &Camera,
+        current_time: f32,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        if let Some(pipeline) = &self.pipeline {
+            let backbuffer_image = render_pipeline.backbuffer_image();
+
+            // Copy newly loaded textures into their images before the pass samples them.
+            let mut upload_deps = self
+                .density_mask
+                .record_upload(vulkan, render_manager.frame().command_buffer());
+            upload_deps.extend(
+                self.albedo_texture()
+                    .record_upload(vulkan, render_manager.frame().command_buffer()),
+            );
+            upload_deps.extend(
+                self.comb_map()
+                    .record_upload(vulkan, render_manager.frame().command_buffer()),
+            );
+            // A generated mask is written once, before its first use.
+            if let Some(density_mask_noise) = &self.density_mask_noise {
+                upload_deps.extend(self.noise_generator.record(
+                    render_manager.frame_mut().command_buffer_mut(),
+                    &self.density_mask,
+                    density_mask_noise,
+                ));
+            }
+
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: backbuffer_image.image_extent().width,
+                    height: backbuffer_image.image_extent().height,
+                },
+            };
+
+            render_manager
+                .frame()
+                .command_buffer()
+                .dynamic_state_viewport(
+                    vk::Viewport::builder()
+                        .width(backbuffer_image.image_extent().width as f32)
+                        .height(backbuffer_image.image_extent().height as f32)
+                        .min_depth(0.0)
+                        .max_depth(1.0)
+                        .build(),
+                );
+            render_manager
+                .frame()
+                .command_buffer()
+                .dynamic_state_scissor(render_area);
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_graphics_pipeline(&pipeline.graphics_pipeline);
+
+            let descriptor_sets = [render_pipeline.frame(render_manager).descriptor_set()];
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.graphics_pipeline.pipeline_layout(),
+                    &descriptor_sets,
+                );
+
+            // Alpha is the coverage of the scene, the sky is composited where it isn't covered.
+            let color_clear_value = vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            };
+            let depth_clear_value = vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: render_pipeline.depth_clear_value(),
+                    stencil: 0,
+                },
+            };
+            let normal_clear_value = vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            };
+            // One clear value per attachment in the order create_render_pass adds them, the
+            // resolve attachments only exist with MSAA.
+            let single_sampled = render_pipeline.msaa_samples() == vk::SampleCountFlags::TYPE_1;
+            let mut clear_values = vec![color_clear_value];
+            if !single_sampled {
+                clear_values.push(color_clear_value);
+            }
+            if pipeline.gbuffer {
+                clear_values.push(normal_clear_value);
+                if !single_sampled {
+                    clear_values.push(normal_clear_value);
+                }
+            }
+            clear_values.push(depth_clear_value);
+
+            render_manager.frame().command_buffer().begin_render_pass(
+                pipeline.graphics_pipeline.render_pass(),
+                render_area,
+                &clear_values,
+            );
+
+            // Lay the grid and the ground shadow down first so the fur is depth tested against
+            // them, then switch back to the shell pipeline.
+            if let (Some(grid_pipeline), Some(grid)) =
+                (&pipeline.grid_pipeline, render_pipeline.grid())
+            {
+                let camera_position = camera.position();
+                render_manager
+                    .frame_mut()
+                    .command_buffer_mut()
+                    .bind_graphics_pipeline(grid_pipeline);
+                render_manager
+                    .frame_mut()
+                    .command_buffer_mut()
+                    .bind_descriptor_sets(
+                        vk::PipelineBindPoint::GRAPHICS,
+                        grid_pipeline.pipeline_layout(),
+                        &descriptor_sets,
+                    );
+                render_manager
+                    .frame()
+                    .command_buffer()
+                    .write_push_constants_typed(
+                        grid_pipeline.pipeline_layout(),
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        &GridPushConstants {
+                            center: [camera_position.x, grid.height, camera_position.z],
+                            extent: GRID_EXTENT.min(camera.far()),
+                            color: grid.color,
+                            spacing: grid.spacing,
+                        },
+                    );
+                render_manager
+                    .frame()
+                    .command_buffer()
+                    .draw(GRID_VERTEX_COUNT, 1, 0, 0);
+            }
+
+            // The ground shadow blends onto the grid before any fur is drawn and doesn't write
+            // depth, so the shells cover it rather than it darkening their tips.
+            if let Some(ground_shadow_pipeline) = &pipeline.ground_shadow_pipeline {
+                render_manager
+                    .frame_mut()
+                    .command_buffer_mut()
+                    .bind_graphics_pipeline(ground_shadow_pipeline);
+                render_manager
+                    .frame_mut()
+                    .command_buffer_mut()
+                    .bind_descriptor_sets(
+                        vk::PipelineBindPoint::GRAPHICS,
+                        ground_shadow_pipeline.pipeline_layout(),
+                        &descriptor_sets,
+                    );
+                render_manager
+                    .frame()
+                    .command_buffer()
+                    .write_push_constants_typed(
+                        ground_shadow_pipeline.pipeline_layout(),
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        &GroundShadowPushConstants {
+                            center: [self.translation.x, self.ground_y, self.translation.z],
+                            radius: self.ground_shadow_radius,
+                            softness: self.ground_shadow_softness,
+                            opacity: GROUND_SHADOW_OPACITY,
+                        },
+                    );
+                render_manager
+                    .frame()
+                    .command_buffer()
+                    .draw(GROUND_SHADOW_VERTEX_COUNT, 1, 0, 0);
+            }
+
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_graphics_pipeline(&pipeline.graphics_pipeline);
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.graphics_pipeline.pipeline_layout(),
+                    &descriptor_sets,
+                );
+
+            // Every mesh is drawn with its model uniform index, nearest first so the closer fur
+            // rejects what is behind it. Meshes entirely outside the view aren't drawn.
+            let frustum = Frustum::from_view_projection(&camera.view_projection());
+            let is_visible = |transform: &Matrix4<f32>, shell_thickness: f32| {
+                let (center, radius) = world_bounding_sphere(
+                    self.plane_mesh.bounding_sphere(),
+                    transform,
+                    shell_thickness,
+                );
+                frustum.intersects_sphere(center, radius + FUR_CULL_MARGIN)
+            };
+
+            let mut draws = Vec::new();
+            let mesh_visible = is_visible(&self.model_matrix(), self.shell_thickness);
+            if mesh_visible {
+                draws.push((0, self.translation));
+            }
+            for (index, (_, instance)) in self.instances.iter().enumerate() {
+                if is_visible(&instance.transform, instance.fur_params.shell_thickness) {
+                    let position = instance.transform.fixed_view::<3, 1>(0, 3).into_owned();
+                    draws.push((index as u32 + 1, position));
+                }
+            }
+            draws.sort_by(|(_, a), (_, b)| {
+                (a - camera.position())
+                    .magnitude_squared()
+                    .total_cmp(&(b - camera.position()).magnitude_squared())
+            });
+
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_vertex_buffer(0, self.plane_mesh.vertex_buffer());
+            render_manager
+                .frame_mut()
+                .command_buffer_mut()
+                .bind_index_buffer(self.plane_mesh.index_buffer(), vk::IndexType::UINT32);
+
+            // The pre-pass pipeline shares the layout, so the push constants stay bound for it.
+            render_manager
+                .frame()
+                .command_buffer()
+                .write_push_constants_typed(
+                    pipeline.graphics_pipeline.pipeline_layout(),
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    &ShellPushConstants {
+                        time: current_time,
+                        shell_count: self.shell_count,
+                    },
+                );
+
+            // The vertex shader finds the model and fur of a shell from its instance index, so
+            // each mesh starts its instances at its model index times the shell count.
+            let draw_shells =
+                |render_manager: &mut RenderManager, index_count: usize, shell_count: u32| {
+                    for (model_index, _) in &draws {
+                        render_manager.frame().command_buffer().draw_indexed(
+                            index_count as u32,
+                            shell_count,
+                            0,
+                            0,
+                            model_index * self.shell_count,
+                        );
+                    }
+                };
+
+            // Lay down the depth of every base shell, then switch back to the shell pipeline.
+            if let Some(depth_prepass_pipeline) = &pipeline.depth_prepass_pipeline {
+                render_manager
+                    .frame_mut()
+                    .command_buffer_mut()
+                    .bind_graphics_pipeline(depth_prepass_pipeline);
+                draw_shells(&mut *render_manager, self.plane_mesh.vertex_count(), 1);
+                render_manager
+                    .frame_mut()
+                    .command_buffer_mut()
+                    .bind_graphics_pipeline(&pipeline.graphics_pipeline);
+            }
+
+            // The wireframe pipeline draws lines, so it reads the edges rather than the triangles.
+            let shell_index_count = if pipeline.polygon_mode == vk::PolygonMode::LINE {
+                render_manager
+                    .frame_mut()
+                    .command_buffer_mut()
+                    .bind_index_buffer(self.plane_mesh.edge_index_buffer(), vk::IndexType::UINT32);
+                self.plane_mesh.edge_index_count()
+            } else {
+                self.plane_mesh.vertex_count()
+            };
+            draw_shells(&mut *render_manager, shell_index_count, self.shell_count);
+
+            // Stand the fins up along the silhouette, depth tested and written like the shells.
+            let fin_buffer = self.fin_buffer().filter(|_| mesh_visible);
+            if let (Some(fin_pipeline), Some(fin_buffer)) = (&pipeline.fin_pipeline, fin_buffer) {
+                if self.fin_vertex_count > 0 {
+                    render_manager
+                        .frame_mut()
+                        .command_buffer_mut()
+                        .bind_graphics_pipeline(fin_pipeline);
+                    render_manager
+                        .frame_mut()
+                        .command_buffer_mut()
+                        .bind_descriptor_sets(
+                            vk::PipelineBindPoint::GRAPHICS,
+                            fin_pipeline.pipeline_layout(),
+                            &descriptor_sets,
+                        );
+                    render_manager
+                        .frame_mut()
+                        .command_buffer_mut()
+                        .bind_vertex_buffer(0, fin_buffer);
+                    render_manager
+                        .frame()
+                        .command_buffer()
+                        .draw(self.fin_vertex_count, 1, 0, 0);
+                }
+            }
+
+            render_manager.frame().command_buffer().end_render_pass();
+
+            render_manager.frame().command_buffer().pipeline_barrier(
+                vk::PipelineStageFlags::ALL_GRAPHICS,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[vk::ImageMemoryBarrier::builder()
+                    .image(render_pipeline.backbuffer_depth_image().image())
+                    .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                            .layer_count(1)
+                            .level_count(1)
+                            .build(),
+                    )
+                    .build()],
+            );
+
+            let mut deps: Vec<Arc<dyn Any + Send + Sync>> = vec![
+                self.plane_mesh.vertex_buffer().clone(),
+                self.plane_mesh.index_buffer().clone(),
+                self.model_buffer().clone(),
+                self.fur_buffer().clone(),
+                self.density_mask.image().create_dep(),
+                self.albedo_texture().image().create_dep(),
+                self.comb_map().image().create_dep(),
+                self.shell_resolve_image.create_dep(),
+                self.shell_normal_image.create_dep(),
+                backbuffer_image.create_dep(),
+                render_pipeline.backbuffer_depth_image().create_dep(),
+            ];
+            if let Some(shell_normal_msaa_image) = &self.shell_normal_msaa_image {
+                deps.push(shell_normal_msaa_image.create_dep());
+            }
+            if let Some(fin_buffer) = self.fin_buffer() {
+                deps.push(fin_buffer.clone());
+            }
+            deps.extend(upload_deps);
+            return deps;
+        }
+
+        vec![]
+    }
+
+    fn create_render_pass(&self, vulkan: &Vulkan, render_pipeline: &RenderPipeline) -> RenderPass {
+        let msaa_samples = render_pipeline.msaa_samples();
+        let mut subpass = Subpass::new();
+        if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            // Nothing to resolve, so render straight into the output.
+            subpass.color_attachment(
+                &self.shell_resolve_image.as_attachment(
+                    AttachmentInfo::default()
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .samples(vk::SampleCountFlags::TYPE_1)
+                        .final_layout(vk::ImageLayout::GENERAL),
+                ),
+            );
+        } else {
+            subpass.color_attachment(
+                &render_pipeline.backbuffer_image().as_attachment(
+                    AttachmentInfo::default()
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .samples(msaa_samples),
+                ),
+            );
+            subpass.resolve_attachment(
+                &self.shell_resolve_image.as_attachment(
+                    AttachmentInfo::default()
+                        .samples(vk::SampleCountFlags::TYPE_1)
+                        .final_layout(vk::ImageLayout::GENERAL),
+                ),
+            );
+        }
+        if self.gbuffer {
+            match &self.shell_normal_msaa_image {
+                Some(shell_normal_msaa_image) => {
+                    subpass.color_attachment(
+                        &shell_normal_msaa_image.as_attachment(
+                            AttachmentInfo::default()
+                                .load_op(vk::AttachmentLoadOp::CLEAR)
+                                .samples(msaa_samples),
+                        ),
+                    );
+                    subpass.resolve_attachment(
+                        &self.shell_normal_image.as_attachment(
+                            AttachmentInfo::default()
+                                .samples(vk::SampleCountFlags::TYPE_1)
+                                .final_layout(vk::ImageLayout::GENERAL),
+                        ),
+                    );
+                }
+                None => {
+                    subpass.color_attachment(
+                        &self.shell_normal_image.as_attachment(
+                            AttachmentInfo::default()
+                                .load_op(vk::AttachmentLoadOp::CLEAR)
+                                .samples(vk::SampleCountFlags::TYPE_1)
+                                .final_layout(vk::ImageLayout::GENERAL),
+                        ),
+                    );
+                }
+            }
+        }
+        subpass.depth_attachment(
+            &render_pipeline.backbuffer_depth_image().as_attachment(
+                AttachmentInfo::default()
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .samples(msaa_samples)
+                    .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .is_depth(true),
+            ),
+        );
+
+        RenderPass::new(vulkan, &[subpass])
+    }
+
+    fn create_graphics_pipeline(
+        &self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        render_pipeline: &RenderPipeline,
+        color_write_mask: vk::ColorComponentFlags,
+        polygon_mode: vk::PolygonMode,
+        depth_write: bool,
+    ) -> GraphicsPipeline {
+        let render_pass = self.create_render_pass(vulkan, render_pipeline);
+
+        let vertex_shader = Shader::new(vulkan, &watched_shaders.get_shader(VERTEX_NAME).unwrap());
+        let fragment_shader =
             Shader::new(vulkan, &watched_shaders.get_shader(FRAGMENT_NAME).unwrap());
 
         let vertex_input_binding_descriptions = [Mesh::vk_vertex_input_binding_description()];
         let vertex_input_attribute_descriptions = Mesh::vk_vertex_input_attribute_descriptions();
         let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
 
-        let graphics_pipeline = GraphicsPipeline::new(
+        // The wireframe is drawn from the edge index buffer, the triangles are always filled.
+        let input_assembly = if polygon_mode == vk::PolygonMode::LINE {
+            Mesh::vk_edge_input_assembly_info()
+        } else {
+            Mesh::vk_vertex_input_assembly_info()
+        };
+
+        // The normal attachment is written with the same mask as the color.
+        let blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(color_write_mask)
+            .build();
+        let blend_attachments = if self.gbuffer {
+            vec![blend_attachment, blend_attachment]
+        } else {
+            vec![blend_attachment]
+        };
+
+        GraphicsPipeline::new(
+            vulkan,
+            GraphicsPipelineInfo::builder()
+                .vertex_shader(vertex_shader)
+                .fragment_shader(fragment_shader)
+                .vertex_input_state(
+                    vk::PipelineVertexInputStateCreateInfo::builder()
+                        .vertex_binding_descriptions(&vertex_input_binding_descriptions)
+                        .vertex_attribute_descriptions(&vertex_input_attribute_descriptions)
+                        .build(),
+                )
+                .input_assembly_state(input_assembly)
+                .rasterization_state(
+                    vk::PipelineRasterizationStateCreateInfo::builder()
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .cull_mode(self.cull_mode)
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .line_width(1.0)
+                        .build(),
+                )
+                .viewport_state(
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewports(&[])
+                        .viewport_count(1)
+                        .scissors(&[])
+                        .scissor_count(1)
+                        .build(),
+                )
+                .color_blend_state(
+                    vk::PipelineColorBlendStateCreateInfo::builder()
+                        .logic_op(vk::LogicOp::CLEAR)
+                        .attachments(&blend_attachments)
+                        .build(),
+                )
+                .depth_stencil_state(
+                    vk::PipelineDepthStencilStateCreateInfo::builder()
+                        .depth_test_enable(true)
+                        .depth_write_enable(depth_write)
+                        .depth_compare_op(render_pipeline.depth_compare_op())
+                        .build(),
+                )
+                // The coverage also masks the depth written per sample, so the pre-pass uses it
+                // too and agrees with the strands drawn over it.
+                .multisample_state(
+                    vk::PipelineMultisampleStateCreateInfo::builder()
+                        .rasterization_samples(render_pipeline.msaa_samples())
+                        .alpha_to_coverage_enable(self.uses_alpha_to_coverage(render_pipeline))
+                        .build(),
+                )
+                .dynamic_state(
+                    vk::PipelineDynamicStateCreateInfo::builder()
+                        .dynamic_states(&dynamic_states)
+                        .build(),
+                )
+                .descriptor_set_layout(render_pipeline.descriptor_set_layout())
+                .push_constant_ranges(vec![vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    offset: 0,
+                    size: std::mem::size_of::<ShellPushConstants>() as u32,
+                }])
+                .render_pass(render_pass)
+                .build(),
+        )
+    }
+
+    fn create_ground_shadow_pipeline(
+        &self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        render_pipeline: &RenderPipeline,
+    ) -> GraphicsPipeline {
+        let render_pass = self.create_render_pass(vulkan, render_pipeline);
+
+        let vertex_shader = Shader::new(
+            vulkan,
+            &watched_shaders
+                .get_shader(GROUND_SHADOW_VERTEX_NAME)
+                .unwrap(),
+        );
+        let fragment_shader = Shader::new(
+            vulkan,
+            &watched_shaders
+                .get_shader(GROUND_SHADOW_FRAGMENT_NAME)
+                .unwrap(),
+        );
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        // The shadow only darkens the color, the normals under it are left alone.
+        let mut blend_attachments = vec![vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        if self.gbuffer {
+            blend_attachments.push(
+                vk::PipelineColorBlendAttachmentState::builder()
+                    .blend_enable(false)
+                    .color_write_mask(vk::ColorComponentFlags::empty())
+                    .build(),
+            );
+        }
+
+        GraphicsPipeline::new(
+            vulkan,
+            GraphicsPipelineInfo::builder()
+                .vertex_shader(vertex_shader)
+                .fragment_shader(fragment_shader)
+                .vertex_input_state(vk::PipelineVertexInputStateCreateInfo::builder().build())
+                .input_assembly_state(
+                    vk::PipelineInputAssemblyStateCreateInfo::builder()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                        .primitive_restart_enable(false)
+                        .build(),
+                )
+                .rasterization_state(
+                    vk::PipelineRasterizationStateCreateInfo::builder()
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .line_width(1.0)
+                        .build(),
+                )
+                .viewport_state(
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewports(&[])
+                        .viewport_count(1)
+                        .scissors(&[])
+                        .scissor_count(1)
+                        .build(),
+                )
+                .color_blend_state(
+                    vk::PipelineColorBlendStateCreateInfo::builder()
+                        .logic_op(vk::LogicOp::CLEAR)
+                        .attachments(&blend_attachments)
+                        .build(),
+                )
+                // Tested against the fur but never written, so it can't hide anything.
+                .depth_stencil_state(
+                    vk::PipelineDepthStencilStateCreateInfo::builder()
+                        .depth_test_enable(true)
+                        .depth_write_enable(false)
+                        .depth_compare_op(render_pipeline.depth_compare_op())
+                        .build(),
+                )
+                .multisample_state(
+                    vk::PipelineMultisampleStateCreateInfo::builder()
+                        .rasterization_samples(render_pipeline.msaa_samples())
+                        .build(),
+                )
+                .dynamic_state(
+                    vk::PipelineDynamicStateCreateInfo::builder()
+                        .dynamic_states(&dynamic_states)
+                        .build(),
+                )
+                .descriptor_set_layout(render_pipeline.descriptor_set_layout())
+                .push_constant_ranges(vec![vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    offset: 0,
+                    size: std::mem::size_of::<GroundShadowPushConstants>() as u32,
+                }])
+                .render_pass(render_pass)
+                .build(),
+        )
+    }
+
+    fn create_grid_pipeline(
+        &self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        render_pipeline: &RenderPipeline,
+    ) -> GraphicsPipeline {
+        let render_pass = self.create_render_pass(vulkan, render_pipeline);
+
+        let vertex_shader = Shader::new(
+            vulkan,
+            &watched_shaders.get_shader(GRID_VERTEX_NAME).unwrap(),
+        );
+        let fragment_shader = Shader::new(
+            vulkan,
+            &watched_shaders.get_shader(GRID_FRAGMENT_NAME).unwrap(),
+        );
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        // The lines are alpha blended to keep their fade, and their alpha is added to the scene
+        // coverage so post processing doesn't composite the sky back over them. Alpha to
+        // coverage would leave the covered samples with the partial alpha, so it isn't used even
+        // with MSAA. The normals under the grid stay empty for SSAO to treat as background.
+        let mut blend_attachments = vec![vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        if self.gbuffer {
+            blend_attachments.push(
+                vk::PipelineColorBlendAttachmentState::builder()
+                    .blend_enable(false)
+                    .color_write_mask(vk::ColorComponentFlags::empty())
+                    .build(),
+            );
+        }
+
+        // Away from the camera is a larger depth, or a smaller one with reverse-Z.
+        let depth_bias = if render_pipeline.reverse_z() {
+            -GRID_DEPTH_BIAS
+        } else {
+            GRID_DEPTH_BIAS
+        };
+
+        GraphicsPipeline::new(
+            vulkan,
+            GraphicsPipelineInfo::builder()
+                .vertex_shader(vertex_shader)
+                .fragment_shader(fragment_shader)
+                .vertex_input_state(vk::PipelineVertexInputStateCreateInfo::builder().build())
+                .input_assembly_state(
+                    vk::PipelineInputAssemblyStateCreateInfo::builder()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                        .primitive_restart_enable(false)
+                        .build(),
+                )
+                .rasterization_state(
+                    vk::PipelineRasterizationStateCreateInfo::builder()
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .depth_bias_enable(true)
+                        .depth_bias_constant_factor(depth_bias)
+                        .depth_bias_slope_factor(depth_bias)
+                        .line_width(1.0)
+                        .build(),
+                )
+                .viewport_state(
+                    vk::PipelineViewportStateCreateInfo::builder()
+                        .viewports(&[])
+                        .viewport_count(1)
+                        .scissors(&[])
+                        .scissor_count(1)
+                        .build(),
+                )
+                .color_blend_state(
+                    vk::PipelineColorBlendStateCreateInfo::builder()
+                        .logic_op(vk::LogicOp::CLEAR)
+                        .attachments(&blend_attachments)
+                        .build(),
+                )
+                // Written so fur below the ground is hidden by it and tests against it.
+                .depth_stencil_state(
+                    vk::PipelineDepthStencilStateCreateInfo::builder()
+                        .depth_test_enable(true)
+                        .depth_write_enable(true)
+                        .depth_compare_op(render_pipeline.depth_compare_op())
+                        .build(),
+                )
+                .multisample_state(
+                    vk::PipelineMultisampleStateCreateInfo::builder()
+                        .rasterization_samples(render_pipeline.msaa_samples())
+                        .build(),
+                )
+                .dynamic_state(
+                    vk::PipelineDynamicStateCreateInfo::builder()
+                        .dynamic_states(&dynamic_states)
+                        .build(),
+                )
+                .descriptor_set_layout(render_pipeline.descriptor_set_layout())
+                .push_constant_ranges(vec![vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    offset: 0,
+                    size: std::mem::size_of::<GridPushConstants>() as u32,
+                }])
+                .render_pass(render_pass)
+                .build(),
+        )
+    }
+
+    fn create_fin_pipeline(
+        &self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        render_pipeline: &RenderPipeline,
+    ) -> GraphicsPipeline {
+        let render_pass = self.create_render_pass(vulkan, render_pipeline);
+
+        let vertex_shader = Shader::new(
+            vulkan,
+            &watched_shaders.get_shader(FIN_VERTEX_NAME).unwrap(),
+        );
+        let fragment_shader = Shader::new(
+            vulkan,
+            &watched_shaders.get_shader(FIN_FRAGMENT_NAME).unwrap(),
+        );
+
+        let vertex_input_binding_descriptions = [Mesh::vk_vertex_input_binding_description()];
+        let vertex_input_attribute_descriptions = Mesh::vk_vertex_input_attribute_descriptions();
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+        // The fins only write the color, the normals behind them are left alone.
+        let mut blend_attachments = vec![vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .build()];
+        if self.gbuffer {
+            blend_attachments.push(
+                vk::PipelineColorBlendAttachmentState::builder()
+                    .blend_enable(false)
+                    .color_write_mask(vk::ColorComponentFlags::empty())
+                    .build(),
+            );
+        }
+
+        GraphicsPipeline::new(
             vulkan,
             GraphicsPipelineInfo::builder()
                 .vertex_shader(vertex_shader)
@@ -342,6 +2231,8 @@ impl ShellRenderer {
                         .build(),
                 )
                 .input_assembly_state(Mesh::vk_vertex_input_assembly_info())
+                // Fins face whichever way the edge was wound, so both sides are drawn. They stay
+                // filled in the wireframe, a line polygon mode needs `fillModeNonSolid`.
                 .rasterization_state(
                     vk::PipelineRasterizationStateCreateInfo::builder()
                         .polygon_mode(vk::PolygonMode::FILL)
@@ -361,22 +2252,19 @@ impl ShellRenderer {
                 .color_blend_state(
                     vk::PipelineColorBlendStateCreateInfo::builder()
                         .logic_op(vk::LogicOp::CLEAR)
-                        .attachments(&[vk::PipelineColorBlendAttachmentState::builder()
-                            .blend_enable(false)
-                            .color_write_mask(vk::ColorComponentFlags::RGBA)
-                            .build()])
+                        .attachments(&blend_attachments)
                         .build(),
                 )
                 .depth_stencil_state(
                     vk::PipelineDepthStencilStateCreateInfo::builder()
                         .depth_test_enable(true)
                         .depth_write_enable(true)
-                        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+                        .depth_compare_op(render_pipeline.depth_compare_op())
                         .build(),
                 )
                 .multisample_state(
                     vk::PipelineMultisampleStateCreateInfo::builder()
-                        .rasterization_samples(vk::SampleCountFlags::TYPE_4)
+                        .rasterization_samples(render_pipeline.msaa_samples())
                         .build(),
                 )
                 .dynamic_state(
@@ -385,30 +2273,424 @@ impl ShellRenderer {
                         .build(),
                 )
                 .descriptor_set_layout(render_pipeline.descriptor_set_layout())
-                .push_constant_ranges(vec![vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                    offset: 0,
-                    size: std::mem::size_of::<ShellPushConstants>() as u32,
-                }])
+                .push_constant_ranges(vec![])
                 .render_pass(render_pass)
                 .build(),
+        )
+    }
+
+    /// The fin buffer the next frame draws, None before any fins were built.
+    fn fin_buffer(&self) -> Option<&Arc<UntypedBuffer>> {
+        self.fin_buffers[self.fin_buffer_index]
+            .as_ref()
+            .map(|(buffer, _)| buffer)
+    }
+
+    /// Rebuilds the fins along the silhouette seen from `camera_position`, in world space, if
+    /// the camera moved relative to the mesh or the mesh changed since the last rebuild.
+    fn update_fins(
+        &mut self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        stager: &mut VulkanStager,
+        camera_position: Vector3<f32>,
+    ) {
+        let Some(inverse_model) = self.model_matrix().try_inverse() else {
+            self.fin_vertex_count = 0;
+            self.fin_eye = None;
+            return;
+        };
+        let eye = inverse_model
+            .transform_point(&camera_position.into())
+            .coords;
+        if self.fin_eye == Some(eye) {
+            return;
+        }
+        self.fin_eye = Some(eye);
+
+        let edges = self.plane_mesh.silhouette_edges(eye);
+        let vertices = self.plane_mesh.fin_vertices(&edges);
+        self.fin_vertex_count = vertices.len() as u32;
+        if vertices.is_empty() {
+            return;
+        }
+
+        // The render manager has waited on the frame that last read the next buffer, as it was
+        // recorded frames in flight frames ago.
+        self.fin_buffer_index = (self.fin_buffer_index + 1) % self.fin_buffers.len();
+        let size = (vertices.len() * std::mem::size_of::<Vertex>()) as u64;
+        let fin_buffer = &mut self.fin_buffers[self.fin_buffer_index];
+        if !matches!(fin_buffer, Some((_, capacity)) if *capacity >= size) {
+            // Leave room to grow so turning the mesh doesn't reallocate every frame.
+            let capacity = size.next_power_of_two();
+            let buffer = Arc::new(UntypedBuffer::new(
+                vulkan,
+                vulkan_allocator,
+                &BufferInfo::builder()
+                    .size(capacity)
+                    .usage(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                    .build(),
+            ));
+            *fin_buffer = Some((buffer, capacity));
+        }
+        let (fin_buffer, _) = fin_buffer.as_ref().unwrap();
+
+        // Safety: the vertices are valid for size bytes until the immediate stage returns.
+        unsafe {
+            stager.schedule_stage_buffer(
+                vulkan,
+                vulkan_allocator,
+                vertices.as_ptr() as *const u8,
+                size,
+                fin_buffer,
+                StageType::Immediate,
+            );
+        }
+    }
+
+    fn refresh_pipeline(
+        &mut self,
+        vulkan: &Vulkan,
+        watched_shaders: &WatchedShaders,
+        render_manager: &RenderManager,
+        render_pipeline: &RenderPipeline,
+    ) {
+        let push_constants_size = std::mem::size_of::<ShellPushConstants>();
+        watched_shaders.check_push_constant_size(VERTEX_NAME, push_constants_size, true);
+        watched_shaders.check_push_constant_size(FRAGMENT_NAME, push_constants_size, true);
+
+        self.noise_generator
+            .refresh_pipeline(vulkan, watched_shaders);
+
+        // With the pre-pass the depth is already laid down, so the shells only test against it.
+        // The compare op passes at equal depth, which keeps the base shell redrawn over itself.
+        let graphics_pipeline = self.create_graphics_pipeline(
+            vulkan,
+            watched_shaders,
+            render_pipeline,
+            vk::ColorComponentFlags::RGBA,
+            self.polygon_mode,
+            !self.depth_prepass,
         );
 
-        self.pipeline = Some(ShellPipeline { graphics_pipeline });
+        // The pre-pass only draws the base shell, which never discards, so it writes the depth of
+        // the opaque body alone. Shell fragments behind the body are rejected before they are
+        // shaded, and fur tips standing out of its silhouette have no depth to fail against.
+        let depth_prepass_pipeline = self.depth_prepass.then(|| {
+            self.create_graphics_pipeline(
+                vulkan,
+                watched_shaders,
+                render_pipeline,
+                vk::ColorComponentFlags::empty(),
+                // The depth stays solid so only the wireframe facing the camera shows.
+                vk::PolygonMode::FILL,
+                true,
+            )
+        });
+
+        let ground_shadow_pipeline = self.ground_shadow.then(|| {
+            let push_constants_size = std::mem::size_of::<GroundShadowPushConstants>();
+            for name in [GROUND_SHADOW_VERTEX_NAME, GROUND_SHADOW_FRAGMENT_NAME] {
+                watched_shaders.check_push_constant_size(name, push_constants_size, true);
+            }
+            self.create_ground_shadow_pipeline(vulkan, watched_shaders, render_pipeline)
+        });
+
+        let fin_pipeline = self
+            .fins
+            .then(|| self.create_fin_pipeline(vulkan, watched_shaders, render_pipeline));
+
+        let grid_pipeline = render_pipeline.grid().is_some().then(|| {
+            let push_constants_size = std::mem::size_of::<GridPushConstants>();
+            for name in [GRID_VERTEX_NAME, GRID_FRAGMENT_NAME] {
+                watched_shaders.check_push_constant_size(name, push_constants_size, true);
+            }
+            self.create_grid_pipeline(vulkan, watched_shaders, render_pipeline)
+        });
+
+        self.pipeline = Some(ShellPipeline {
+            graphics_pipeline,
+            polygon_mode: self.polygon_mode,
+            gbuffer: self.gbuffer,
+            depth_prepass_pipeline,
+            ground_shadow_pipeline,
+            fin_pipeline,
+            grid_pipeline,
+        });
+        self.pipeline_dirty = false;
+        self.reverse_z = render_pipeline.reverse_z();
+        self.grid = render_pipeline.grid().is_some();
     }
 
     fn update_system(
         mut shell_renderer: ResMut<ShellRenderer>,
         vulkan: Res<Vulkan>,
-        watched_shaders: Res<WatchedShaders>,
+        mut vulkan_allocator: ResMut<VulkanAllocator>,
+        mut stager: ResMut<VulkanStager>,
+        mut watched_shaders: ResMut<WatchedShaders>,
+        mut assets: ResMut<Assets>,
         render_manager: Res<RenderManager>,
         render_pipeline: Res<RenderPipeline>,
         input: Res<Input>,
         time: Res<Time>,
+        mut camera: ResMut<Camera>,
+        mut particle_system: ResMut<ParticleSystem>,
+        debug_labels: Res<DebugLabels>,
+        frame_stats: Res<FrameStats>,
     ) {
         let shell_renderer = &mut *shell_renderer;
+        // Meshes are staged immediately on the default queue, outside the frame's commands.
+        let staging_queue = vulkan.default_queue().queue();
+
+        // Bring the mesh back into view.
+        if input.is_key_pressed(Key::F2) {
+            let (center, radius) = shell_renderer.bounding_sphere();
+            camera.frame_mesh(center, radius);
+        }
+
+        // Pet the fur at the point of the mesh closest to the camera while the key is held.
+        if input.is_key_down(Key::F) {
+            let model_matrix = shell_renderer.model_matrix();
+            let point = model_matrix.try_inverse().and_then(|inverse_model| {
+                let camera_position = inverse_model.transform_point(&camera.position().into());
+                shell_renderer
+                    .plane_mesh
+                    .closest_point(camera_position.coords)
+                    .map(|point| model_matrix.transform_point(&point.into()).coords)
+            });
+            if let Some(point) = point {
+                shell_renderer.set_interaction(point, PETTING_RADIUS);
+
+                // Shed a little fur while petting, carrying the fraction over between frames.
+                shell_renderer.shed_particles +=
+                    SHED_PARTICLES_PER_SECOND * time.delta().as_secs_f32();
+                let count = shell_renderer.shed_particles.floor();
+                shell_renderer.shed_particles -= count;
+                particle_system.spawn_particles(point, count as u32);
+            }
+        } else if shell_renderer.target_interaction_radius > 0.0 {
+            shell_renderer.set_interaction(Vector3::zeros(), 0.0);
+        }
+        let ease = 1.0 - (-INTERACTION_EASE_SPEED * time.delta().as_secs_f32()).exp();
+        shell_renderer.interaction_radius +=
+            (shell_renderer.target_interaction_radius - shell_renderer.interaction_radius) * ease;
+
+        if input.is_key_pressed(Key::F11) {
+            match shell_renderer.noise_settings.map(|settings| settings.kind) {
+                None => {
+                    shell_renderer.generate_noise_texture(
+                        NoiseKind::Worley,
+                        DEFAULT_NOISE_SIZE,
+                        DEFAULT_NOISE_SEED,
+                    );
+                }
+                Some(NoiseKind::Worley) => {
+                    shell_renderer.generate_noise_texture(
+                        NoiseKind::Perlin,
+                        DEFAULT_NOISE_SIZE,
+                        DEFAULT_NOISE_SEED,
+                    );
+                }
+                Some(NoiseKind::Perlin) => shell_renderer.set_density_mask_path(None::<PathBuf>),
+            }
+            println!(
+                "Noise density mask: {:?}",
+                shell_renderer.noise_settings.map(|settings| settings.kind)
+            );
+        }
+
+        if input.is_key_pressed(Key::F10) {
+            let fins = !shell_renderer.fins;
+            shell_renderer.set_fins(fins);
+            println!("Fins: {}", fins);
+        }
+        if shell_renderer.fins {
+            shell_renderer.update_fins(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                &mut *stager,
+                camera.position(),
+            );
+        }
+
+        // Toggle the depth pre-pass, printing the shell pass time of the mode being left so
+        // toggling back and forth compares the two.
+        if input.is_key_pressed(Key::O) {
+            let depth_prepass = !shell_renderer.depth_prepass;
+            shell_renderer.set_depth_prepass(depth_prepass);
+            println!(
+                "Depth pre-pass: {} (the shell pass took {:.2} ms on the GPU {} it)",
+                depth_prepass,
+                frame_stats.shell_ms(),
+                if depth_prepass { "without" } else { "with" },
+            );
+        }
+
+        // Toggle wireframe.
+        if input.is_key_pressed(Key::X) {
+            let polygon_mode = if shell_renderer.polygon_mode == vk::PolygonMode::FILL {
+                vk::PolygonMode::LINE
+            } else {
+                vk::PolygonMode::FILL
+            };
+            shell_renderer.set_polygon_mode(polygon_mode);
+            println!("Shell polygon mode: {:?}", shell_renderer.polygon_mode);
+        }
+
+        // Toggle back-face culling.
+        if input.is_key_pressed(Key::Z) {
+            let cull_mode = if shell_renderer.cull_mode == vk::CullModeFlags::NONE {
+                vk::CullModeFlags::BACK
+            } else {
+                vk::CullModeFlags::NONE
+            };
+            shell_renderer.set_cull_mode(cull_mode);
+            println!("Shell cull mode: {:?}", shell_renderer.cull_mode);
+        }
+
+        // Cycle through the built-in meshes.
+        if input.is_key_pressed(Key::B) {
+            let mesh_preset = shell_renderer.mesh_preset.next();
+            debug_labels.begin_queue_region(
+                staging_queue,
+                "Mesh staging",
+                MESH_STAGING_LABEL_COLOR,
+            );
+            let mesh = mesh_preset.create(
+                &mut MeshFactory::factory(&*vulkan, &mut *vulkan_allocator, &mut *stager)
+                    .optimize(true),
+            );
+            debug_labels.end_queue_region(staging_queue);
+            shell_renderer.set_mesh(mesh);
+            shell_renderer.mesh_preset = mesh_preset;
+            // A mesh still loading would replace the one picked here.
+            shell_renderer.pending_mesh = None;
+            println!("Mesh: {:?}", mesh_preset);
+        }
+
+        // Swap in the mesh loading in the background once it's parsed.
+        if shell_renderer
+            .pending_mesh
+            .as_ref()
+            .is_some_and(MeshHandle::is_ready)
+        {
+            let pending_mesh = shell_renderer.pending_mesh.take().unwrap();
+            match pending_mesh.take().unwrap() {
+                Ok(data) => {
+                    debug_labels.begin_queue_region(
+                        staging_queue,
+                        "Mesh staging",
+                        MESH_STAGING_LABEL_COLOR,
+                    );
+                    let mesh = MeshFactory::factory(&*vulkan, &mut *vulkan_allocator, &mut *stager)
+                        .create_from_data(data);
+                    debug_labels.end_queue_region(staging_queue);
+                    shell_renderer.set_mesh(mesh);
+                    println!("Loaded mesh {}", pending_mesh.path());
+                }
+                Err(err) => {
+                    // Keep rendering the current mesh.
+                    println!("{}", err);
+                    shell_renderer.mesh_load_error = Some(err.to_string());
+                }
+            }
+        }
+
+        // Switch between the grass and fur color presets.
+        if input.is_key_pressed(Key::C) {
+            let (base_color, tip_color) = if shell_renderer.tip_color == GRASS_COLORS.1 {
+                FUR_COLORS
+            } else {
+                GRASS_COLORS
+            };
+            shell_renderer.set_colors(base_color, tip_color);
+        }
+
+        // Edit wind strength.
+        let mut wind_strength = shell_renderer.wind_strength;
+        if input.is_key_repeat(Key::N) || input.is_key_pressed(Key::N) {
+            wind_strength -= 0.01;
+        }
+        if input.is_key_repeat(Key::M) || input.is_key_pressed(Key::M) {
+            wind_strength += 0.01;
+        }
+        if wind_strength != shell_renderer.wind_strength {
+            let wind_direction = shell_renderer.wind_direction;
+            shell_renderer.set_wind(wind_direction, wind_strength);
+            println!("Wind strength: {:.2}", shell_renderer.wind_strength);
+        }
+
+        // Edit strand density.
+        let mut density = shell_renderer.density;
+        if input.is_key_repeat(Key::U) || input.is_key_pressed(Key::U) {
+            density -= 2.0;
+        }
+        if input.is_key_repeat(Key::I) || input.is_key_pressed(Key::I) {
+            density += 2.0;
+        }
+        if density != shell_renderer.density {
+            shell_renderer.set_density(density);
+            println!("Density: {}", shell_renderer.density);
+        }
+
+        // Follow the backbuffer size, the render pass is built from the images so rebuild it too.
+        if shell_renderer.backbuffer_generation != render_pipeline.backbuffer_generation() {
+            shell_renderer.shell_resolve_image =
+                create_resolve_image(&*vulkan, &mut *vulkan_allocator, &*render_pipeline);
+            shell_renderer.shell_normal_image = create_normal_image(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                &*render_pipeline,
+                vk::SampleCountFlags::TYPE_1,
+            );
+            // Recreated at the new size below.
+            shell_renderer.shell_normal_msaa_image = None;
+            shell_renderer.backbuffer_generation = render_pipeline.backbuffer_generation();
+            shell_renderer.pipeline_dirty = true;
+        }
+
+        // The multisampled normal attachment only takes up memory while the G-buffer is used,
+        // which includes the old pipeline still drawing until shell.frag recompiles.
+        let msaa_samples = render_pipeline.msaa_samples();
+        let pipeline_gbuffer = shell_renderer
+            .pipeline
+            .as_ref()
+            .map_or(false, |pipeline| pipeline.gbuffer);
+        let needs_normal_msaa_image = (shell_renderer.gbuffer || pipeline_gbuffer)
+            && msaa_samples != vk::SampleCountFlags::TYPE_1;
+        if needs_normal_msaa_image != shell_renderer.shell_normal_msaa_image.is_some() {
+            shell_renderer.shell_normal_msaa_image = needs_normal_msaa_image.then(|| {
+                create_normal_image(
+                    &*vulkan,
+                    &mut *vulkan_allocator,
+                    &*render_pipeline,
+                    msaa_samples,
+                )
+            });
+        }
+
+        if shell_renderer.reverse_z != render_pipeline.reverse_z()
+            || shell_renderer.grid != render_pipeline.grid().is_some()
+        {
+            shell_renderer.pipeline_dirty = true;
+        }
+
+        // The normal output only exists in shell.frag with the G-buffer, the pipelines are
+        // rebuilt once it recompiles.
+        if shell_renderer.shader_gbuffer != shell_renderer.gbuffer {
+            shell_renderer.shader_gbuffer = shell_renderer.gbuffer;
+            watched_shaders.set_defines(
+                &mut *assets,
+                FRAGMENT_NAME,
+                fragment_defines(shell_renderer.gbuffer),
+            );
+        }
 
-        if watched_shaders.is_dependency_signaled(&shell_renderer.shader_dependency_signal) {
+        let pipeline_dirty = shell_renderer.pipeline_dirty && shell_renderer.pipeline.is_some();
+        if (watched_shaders.is_dependency_signaled(&shell_renderer.shader_dependency_signal)
+            || pipeline_dirty)
+            && !watched_shaders.is_reloading(&shell_renderer.shader_dependency_signal)
+        {
             shell_renderer.refresh_pipeline(
                 &*vulkan,
                 &*watched_shaders,
@@ -417,33 +2699,401 @@ impl ShellRenderer {
             );
         }
 
+        if shell_renderer.texture_sampler_dirty {
+            shell_renderer.texture_sampler_dirty = false;
+            // Safety: Only waits, the old sampler may still be read by frames in flight.
+            if let Err(err) = unsafe { vulkan.device().device_wait_idle() } {
+                check_device_lost(err, "waiting to replace the texture sampler");
+                println!("Failed to wait to replace the texture sampler: {}", err);
+            }
+            shell_renderer.mask_sampler = shell_renderer.texture_sampler_settings.create(&*vulkan);
+            shell_renderer.mask_sampler_generation += 1;
+        }
+
+        // Load the density mask if its path was changed or the file was modified, which also
+        // retries a mask that failed to load.
+        let density_mask_retry = shell_renderer
+            .density_mask_failure
+            .should_retry(shell_renderer.density_mask_path.as_deref());
+        if shell_renderer.density_mask_dirty
+            || shell_renderer.density_mask.is_modified()
+            || density_mask_retry
+        {
+            shell_renderer.density_mask_dirty = false;
+            let density_mask = match &shell_renderer.density_mask_path {
+                Some(path) => Texture::load(&*vulkan, &mut *vulkan_allocator, &mut *stager, path),
+                None => Ok(Texture::white(
+                    &*vulkan,
+                    &mut *vulkan_allocator,
+                    &mut *stager,
+                )),
+            };
+            match density_mask {
+                Ok(density_mask) => {
+                    shell_renderer.density_mask = density_mask;
+                    shell_renderer.density_mask_noise = None;
+                    shell_renderer.density_mask_failure.clear();
+                }
+                Err(err) => {
+                    // Keep the last good mask and wait for the file to change again.
+                    println!("{}", err);
+                    shell_renderer.density_mask.update_modified_time();
+                    if let Some(path) = &shell_renderer.density_mask_path {
+                        shell_renderer.density_mask_failure.record(path);
+                    }
+                }
+            }
+        }
+
+        // Generate the density mask once the noise shader has compiled.
+        if shell_renderer.noise_dirty && shell_renderer.noise_generator.is_ready() {
+            shell_renderer.noise_dirty = false;
+            if let Some(settings) = shell_renderer.noise_settings {
+                let (density_mask, density_mask_noise) = shell_renderer
+                    .noise_generator
+                    .create_texture(&*vulkan, &mut *vulkan_allocator, settings);
+                shell_renderer.density_mask = density_mask;
+                shell_renderer.density_mask_noise = Some(density_mask_noise);
+            }
+        }
+
+        // Load the albedo texture if its path was changed or the file was modified, which also
+        // retries a texture that failed to load.
+        let albedo_modified = shell_renderer
+            .albedo_texture
+            .as_ref()
+            .is_some_and(Texture::is_modified);
+        let albedo_retry = shell_renderer
+            .albedo_texture_failure
+            .should_retry(shell_renderer.albedo_texture_path.as_deref());
+        if shell_renderer.albedo_texture_dirty || albedo_modified || albedo_retry {
+            shell_renderer.albedo_texture_dirty = false;
+            if let Some(path) = &shell_renderer.albedo_texture_path {
+                match Texture::load(&*vulkan, &mut *vulkan_allocator, &mut *stager, path) {
+                    Ok(albedo_texture) => {
+                        shell_renderer.albedo_texture = Some(albedo_texture);
+                        shell_renderer.albedo_texture_failure.clear();
+                    }
+                    Err(err) => {
+                        // Keep the last good texture and wait for the file to change again.
+                        println!("{}", err);
+                        if let Some(albedo_texture) = &mut shell_renderer.albedo_texture {
+                            albedo_texture.update_modified_time();
+                        }
+                        shell_renderer.albedo_texture_failure.record(path);
+                    }
+                }
+            }
+        }
+
+        // Load the comb map if its path was changed or the file was modified, which also
+        // retries a comb map that failed to load.
+        let comb_map_modified = shell_renderer
+            .comb_map
+            .as_ref()
+            .is_some_and(Texture::is_modified);
+        let comb_map_retry = shell_renderer
+            .comb_map_failure
+            .should_retry(shell_renderer.comb_map_path.as_deref());
+        if shell_renderer.comb_map_dirty || comb_map_modified || comb_map_retry {
+            shell_renderer.comb_map_dirty = false;
+            if let Some(path) = &shell_renderer.comb_map_path {
+                match Texture::load(&*vulkan, &mut *vulkan_allocator, &mut *stager, path) {
+                    Ok(comb_map) => {
+                        shell_renderer.comb_map = Some(comb_map);
+                        shell_renderer.comb_map_failure.clear();
+                    }
+                    Err(err) => {
+                        // Keep the last good comb map and wait for the file to change again.
+                        println!("{}", err);
+                        if let Some(comb_map) = &mut shell_renderer.comb_map {
+                            comb_map.update_modified_time();
+                        }
+                        shell_renderer.comb_map_failure.record(path);
+                    }
+                }
+            }
+        }
+
+        // Upload the model and normal matrices of the mesh and every instance.
+        let identity = Matrix4::<f32>::identity().into();
+        let mut model_uniform = ModelUniform {
+            models: [ModelData {
+                model: identity,
+                normal: identity,
+            }; MAX_INSTANCES],
+        };
+        model_uniform.models[0] = ModelData {
+            model: shell_renderer.model_matrix().into(),
+            normal: shell_renderer.normal_matrix().into(),
+        };
+        for (data, (_, instance)) in model_uniform.models[1..]
+            .iter_mut()
+            .zip(&shell_renderer.instances)
+        {
+            let normal = instance
+                .transform
+                .try_inverse()
+                .map_or_else(Matrix4::identity, |inverse| inverse.transpose());
+            *data = ModelData {
+                model: instance.transform.into(),
+                normal: normal.into(),
+            };
+        }
+        // The render manager has waited on the frame that last read the next buffer, as it was
+        // recorded frames in flight frames ago.
+        shell_renderer.model_buffer_index =
+            (shell_renderer.model_buffer_index + 1) % shell_renderer.model_buffers.len();
+        unsafe {
+            stager.schedule_stage_buffer(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                &model_uniform as *const ModelUniform as *const u8,
+                std::mem::size_of::<ModelUniform>() as u64,
+                &shell_renderer.model_buffers[shell_renderer.model_buffer_index],
+                StageType::Immediate,
+            );
+        }
+
+        // Upload the fur layers if they changed.
+        if shell_renderer.layers_dirty {
+            let mut layers_uniform = FurLayersUniform {
+                layer_count: shell_renderer.layers.len() as u32,
+                _padding: [0; 3],
+                layers: [FurLayerData::default(); MAX_FUR_LAYERS],
+            };
+            for (data, layer) in layers_uniform.layers.iter_mut().zip(&shell_renderer.layers) {
+                *data = FurLayerData {
+                    color: layer.color,
+                    density: layer.density,
+                    thickness: layer.thickness,
+                    shell_fraction: layer.shell_fraction,
+                    _padding: [0.0; 2],
+                };
+            }
+            // The next buffer was last bound before the current one was written, at least
+            // frames in flight frames ago, so the render manager has waited on it.
+            shell_renderer.layers_buffer_index =
+                (shell_renderer.layers_buffer_index + 1) % shell_renderer.layers_buffers.len();
+            unsafe {
+                stager.schedule_stage_buffer(
+                    &*vulkan,
+                    &mut *vulkan_allocator,
+                    &layers_uniform as *const FurLayersUniform as *const u8,
+                    std::mem::size_of::<FurLayersUniform>() as u64,
+                    &shell_renderer.layers_buffers[shell_renderer.layers_buffer_index],
+                    StageType::Immediate,
+                );
+            }
+            shell_renderer.layers_dirty = false;
+        }
+
+        // Upload the lights if they changed.
+        if shell_renderer.lights_dirty {
+            let mut lights_uniform = LightsUniform {
+                light_count: shell_renderer.lights.len() as u32,
+                _padding: [0; 3],
+                lights: [LightData::default(); MAX_LIGHTS],
+            };
+            for (data, light) in lights_uniform.lights.iter_mut().zip(&shell_renderer.lights) {
+                *data = LightData {
+                    position_or_direction: light.position_or_direction,
+                    intensity: light.intensity,
+                    color: light.color,
+                    kind: light.kind as u32,
+                };
+            }
+            // Safe to overwrite for the same reason as the next layers buffer.
+            shell_renderer.lights_buffer_index =
+                (shell_renderer.lights_buffer_index + 1) % shell_renderer.lights_buffers.len();
+            unsafe {
+                stager.schedule_stage_buffer(
+                    &*vulkan,
+                    &mut *vulkan_allocator,
+                    &lights_uniform as *const LightsUniform as *const u8,
+                    std::mem::size_of::<LightsUniform>() as u64,
+                    &shell_renderer.lights_buffers[shell_renderer.lights_buffer_index],
+                    StageType::Immediate,
+                );
+            }
+            shell_renderer.lights_dirty = false;
+        }
+
         // Edit resolution.
         let mut modified = false;
         if input.is_key_repeat(Key::H) || input.is_key_pressed(Key::H) {
-            shell_renderer.resolution = (shell_renderer.resolution as i32 - 1).max(1) as u32;
+            let resolution = shell_renderer.resolution.saturating_sub(1);
+            shell_renderer.set_resolution(resolution);
             modified = true;
         }
         if input.is_key_repeat(Key::L) || input.is_key_pressed(Key::L) {
-            shell_renderer.resolution += 1;
+            // Stop increasing once the shell count is capped so H takes effect immediately.
+            if !shell_renderer.is_shell_count_capped() {
+                let resolution = shell_renderer.resolution + 1;
+                shell_renderer.set_resolution(resolution);
+            }
             modified = true;
         }
         if input.is_key_repeat(Key::J) || input.is_key_pressed(Key::J) {
-            shell_renderer.shell_thickness = (shell_renderer.shell_thickness - 0.1).max(0.05);
+            let shell_thickness = shell_renderer.shell_thickness - 0.1;
+            shell_renderer.set_shell_thickness(shell_thickness);
             modified = true;
         }
         if input.is_key_repeat(Key::K) || input.is_key_pressed(Key::K) {
-            shell_renderer.shell_thickness += 0.02;
+            if !shell_renderer.is_shell_count_capped() {
+                let shell_thickness = shell_renderer.shell_thickness + 0.02;
+                shell_renderer.set_shell_thickness(shell_thickness);
+            }
             modified = true;
         }
 
         if modified {
             println!("Resolution: {}", shell_renderer.resolution);
             println!("Grass height: {}", shell_renderer.shell_thickness);
-            println!(
-                "Plane count: {}",
-                f32::floor(shell_renderer.shell_thickness * shell_renderer.resolution as f32)
-                    as u32
+            if shell_renderer.is_shell_count_capped() {
+                println!(
+                    "Plane count: {} (capped at the max of {})",
+                    shell_renderer.shell_count, shell_renderer.max_shells
+                );
+            } else {
+                println!("Plane count: {}", shell_renderer.shell_count);
+            }
+        }
+
+        // Upload the fur settings last so this frame's edits are drawn.
+        let mut fur_uniform = FurUniform {
+            resolution: shell_renderer.resolution,
+            specular_strength: shell_renderer.specular_strength,
+            shininess: shell_renderer.shininess,
+            alpha_to_coverage: shell_renderer.uses_alpha_to_coverage(&*render_pipeline) as u32,
+            mask_threshold: shell_renderer.mask_threshold,
+            wind_strength: shell_renderer.wind_strength,
+            wind_direction: shell_renderer.wind_direction,
+            ambient: shell_renderer.ambient,
+            _ambient_padding: [0.0; 3],
+            interaction_point: shell_renderer.interaction_point.into(),
+            interaction_radius: shell_renderer.interaction_radius,
+            gravity: shell_renderer.gravity.into(),
+            stiffness: shell_renderer.stiffness,
+            ao_strength: shell_renderer.ao_strength,
+            use_albedo_texture: shell_renderer.has_albedo_texture() as u32,
+            clumping: shell_renderer.clumping,
+            use_comb_map: shell_renderer.has_comb_map() as u32,
+            meshes: [MeshFurData::default(); MAX_INSTANCES],
+        };
+        let fur_params = std::iter::once(shell_renderer.fur_params()).chain(
+            shell_renderer
+                .instances
+                .iter()
+                .map(|(_, instance)| instance.fur_params),
+        );
+        for (data, fur_params) in fur_uniform.meshes.iter_mut().zip(fur_params) {
+            *data = MeshFurData {
+                base_color: fur_params.base_color,
+                density: fur_params.density,
+                tip_color: fur_params.tip_color,
+                grass_height: fur_params.shell_thickness,
+            };
+        }
+        // The render manager has waited on the frame that last read the next buffer, as it was
+        // recorded frames in flight frames ago.
+        shell_renderer.fur_buffer_index =
+            (shell_renderer.fur_buffer_index + 1) % shell_renderer.fur_buffers.len();
+        unsafe {
+            stager.schedule_stage_buffer(
+                &*vulkan,
+                &mut *vulkan_allocator,
+                &fur_uniform as *const FurUniform as *const u8,
+                std::mem::size_of::<FurUniform>() as u64,
+                &shell_renderer.fur_buffers[shell_renderer.fur_buffer_index],
+                StageType::Immediate,
             );
         }
     }
 }
+
+/// Moves a mesh space bounding sphere into world space and grows it by the fur length.
+fn world_bounding_sphere(
+    (center, radius): (Vector3<f32>, f32),
+    transform: &Matrix4<f32>,
+    shell_thickness: f32,
+) -> (Vector3<f32>, f32) {
+    let center = transform.transform_point(&Point3::from(center)).coords;
+    // The largest axis scale bounds how far the sphere and the fur are stretched.
+    let scale = (0..3)
+        .map(|axis| transform.fixed_view::<3, 1>(0, axis).magnitude())
+        .fold(0.0, f32::max);
+    (center, (radius + shell_thickness) * scale)
+}
+
+fn shell_count_for(shell_thickness: f32, resolution: u32) -> u32 {
+    (f32::floor(shell_thickness * resolution as f32) as u32).max(1)
+}
+
+fn create_resolve_image(
+    vulkan: &Vulkan,
+    vulkan_allocator: &mut VulkanAllocator,
+    render_pipeline: &RenderPipeline,
+) -> Image {
+    Image::new(
+        vulkan,
+        vulkan_allocator,
+        &ImageInfo::builder()
+            .extent(render_pipeline.backbuffer_image().image_extent())
+            .format(render_pipeline.color_format())
+            .usage(
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .view_subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build(),
+            )
+            .build(),
+    )
+}
+
+// A pipeline without the normal attachment would warn about the normal output it doesn't write.
+fn fragment_defines(gbuffer: bool) -> Vec<String> {
+    if gbuffer {
+        vec!["GBUFFER".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+fn create_normal_image(
+    vulkan: &Vulkan,
+    vulkan_allocator: &mut VulkanAllocator,
+    render_pipeline: &RenderPipeline,
+    samples: vk::SampleCountFlags,
+) -> Image {
+    // The resolved image is read by post processing, the multisampled one is only rendered to.
+    let usage = if samples == vk::SampleCountFlags::TYPE_1 {
+        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::COLOR_ATTACHMENT
+    } else {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT
+    };
+
+    Image::new(
+        vulkan,
+        vulkan_allocator,
+        &ImageInfo::builder()
+            .extent(render_pipeline.backbuffer_image().image_extent())
+            .format(vk::Format::R16G16B16A16_SFLOAT)
+            .usage(usage)
+            .samples(samples)
+            .view_subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .level_count(1)
+                    .build(),
+            )
+            .build(),
+    )
+}
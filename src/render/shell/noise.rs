@@ -0,0 +1,249 @@
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use ash::vk;
+use pyrite::{
+    prelude::Assets,
+    vulkan::{
+        CommandBuffer, ComputePipeline, ComputePipelineInfo, DescriptorSet, DescriptorSetLayout,
+        Shader, Vulkan, VulkanAllocator,
+    },
+};
+
+use crate::render::{
+    device_lost::check_device_lost,
+    render::RenderPipeline,
+    texture::Texture,
+    watched_shaders::{DependencySignal, WatchedShaders},
+};
+
+const NOISE_FILE_PATH: &str = "shaders/noise.comp";
+const NOISE_NAME: &str = "noise_comp";
+
+// Must match the local size in noise.comp.
+const NOISE_GROUP_SIZE: u32 = 8;
+
+/// The pattern a generated noise texture is filled with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseKind {
+    /// Cellular noise, bright at scattered points and fading towards the cell edges.
+    Worley = 0,
+    /// Smooth gradient noise summed over a few octaves.
+    Perlin = 1,
+}
+
+/// Everything that decides a generated noise texture, the same settings always produce the
+/// same pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NoiseSettings {
+    pub kind: NoiseKind,
+    // The width and height of the texture in pixels.
+    pub size: u32,
+    pub seed: u32,
+    // The number of cells or lattice points across the texture, rounded to a whole number so
+    // the pattern tiles.
+    pub scale: f32,
+}
+
+// Must match the push constants in noise.comp.
+#[repr(C)]
+struct NoisePushConstants {
+    kind: u32,
+    size: u32,
+    seed: u32,
+    scale: f32,
+}
+
+/// A texture waiting for its noise to be dispatched.
+pub struct PendingNoise {
+    settings: NoiseSettings,
+    dispatch_pending: AtomicBool,
+}
+
+/// Fills textures with tiling noise on the GPU, so large patterns don't need to be built and
+/// uploaded on the CPU.
+pub struct NoiseGenerator {
+    descriptor_set_layout: DescriptorSetLayout,
+    // Binds the latest created texture, rewritten by every `create_texture` since sets aren't
+    // returned to the pool.
+    descriptor_set: DescriptorSet,
+    pipeline: Option<ComputePipeline>,
+}
+
+impl NoiseGenerator {
+    pub fn new(
+        vulkan: &Vulkan,
+        render_pipeline: &RenderPipeline,
+        assets: &mut Assets,
+        watched_shaders: &mut WatchedShaders,
+        shader_dependency_signal: &DependencySignal,
+    ) -> Self {
+        watched_shaders.load_shader(
+            assets,
+            NOISE_FILE_PATH,
+            NOISE_NAME,
+            shader_dependency_signal,
+        );
+
+        let descriptor_set_layout = DescriptorSetLayout::new(
+            vulkan,
+            &[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()],
+        );
+
+        let descriptor_set = render_pipeline
+            .descriptor_pool()
+            .allocate_descriptor_sets(&descriptor_set_layout, 1)
+            .pop()
+            .unwrap();
+
+        Self {
+            descriptor_set_layout,
+            descriptor_set,
+            pipeline: None,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.pipeline.is_some()
+    }
+
+    pub fn refresh_pipeline(&mut self, vulkan: &Vulkan, watched_shaders: &WatchedShaders) {
+        watched_shaders.check_push_constant_size(
+            NOISE_NAME,
+            std::mem::size_of::<NoisePushConstants>(),
+            true,
+        );
+
+        self.pipeline = Some(ComputePipeline::new(
+            vulkan,
+            ComputePipelineInfo::builder()
+                .shader(Shader::new(
+                    vulkan,
+                    &watched_shaders.get_shader(NOISE_NAME).unwrap(),
+                ))
+                .descriptor_set_layouts(vec![&self.descriptor_set_layout])
+                .push_constant_ranges(vec![vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .size(std::mem::size_of::<NoisePushConstants>() as u32)
+                    .build()])
+                .build(),
+        ));
+    }
+
+    /// Creates the texture the noise is written into, it has no pixels until `record` runs.
+    ///
+    /// Only the latest texture can be dispatched, an earlier pending texture is left without
+    /// pixels.
+    pub fn create_texture(
+        &self,
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        settings: NoiseSettings,
+    ) -> (Texture, PendingNoise) {
+        let size = settings.size.max(1);
+        let texture = Texture::storage(vulkan, vulkan_allocator, size, size);
+
+        // Safety: Only waits, a frame in flight may still be dispatching into the previous
+        // texture through the set rewritten below.
+        if let Err(err) = unsafe { vulkan.device().device_wait_idle() } {
+            check_device_lost(err, "waiting to create the noise texture");
+            println!("Failed to wait to create the noise texture: {}", err);
+        }
+        self.descriptor_set
+            .write()
+            .set_storage_image(0, texture.image().create_dep())
+            .submit_writes();
+
+        let pending = PendingNoise {
+            settings: NoiseSettings { size, ..settings },
+            dispatch_pending: AtomicBool::new(true),
+        };
+        (texture, pending)
+    }
+
+    /// Records the dispatch filling `texture` if it hasn't happened yet, which leaves the image
+    /// in `SHADER_READ_ONLY_OPTIMAL` like an uploaded texture.
+    pub fn record(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        texture: &Texture,
+        pending: &PendingNoise,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        let Some(pipeline) = &self.pipeline else {
+            return vec![];
+        };
+        if !pending.dispatch_pending.swap(false, Ordering::AcqRel) {
+            return vec![];
+        }
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[texture.image().image_memory_barrier(
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::SHADER_WRITE,
+            )],
+        );
+
+        command_buffer.bind_compute_pipeline(pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline.pipeline_layout(),
+            &[&self.descriptor_set],
+        );
+        let settings = pending.settings;
+        command_buffer.write_push_constants_typed(
+            pipeline.pipeline_layout(),
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &NoisePushConstants {
+                kind: settings.kind as u32,
+                size: settings.size,
+                seed: settings.seed,
+                scale: settings.scale,
+            },
+        );
+        let group_count = (settings.size + NOISE_GROUP_SIZE - 1) / NOISE_GROUP_SIZE;
+        command_buffer.dispatch_compute(group_count, group_count, 1);
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::VERTEX_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[texture.image().image_memory_barrier(
+                vk::ImageLayout::GENERAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::SHADER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+            )],
+        );
+
+        vec![
+            texture.image().create_dep(),
+            self.descriptor_set.create_dep(),
+        ]
+    }
+}
+
+impl PendingNoise {
+    pub fn settings(&self) -> NoiseSettings {
+        self.settings
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    any::Any,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use ash::vk;
+use pyrite::vulkan::{
+    BufferInfo, CommandBuffer, Image, ImageInfo, StageType, UntypedBuffer, Vulkan, VulkanAllocator,
+    VulkanStager,
+};
+
+/// An RGBA8 texture sampled by the shaders, optionally loaded from a file on disk.
+///
+/// The pixels are staged into a buffer on creation and copied into the image the next time
+/// `record_upload` is called, which leaves the image in `SHADER_READ_ONLY_OPTIMAL`.
+pub struct Texture {
+    image: Image,
+    // `None` for storage textures, which are written on the GPU instead of uploaded.
+    staging_buffer: Option<Arc<UntypedBuffer>>,
+    upload_pending: AtomicBool,
+    // The file this texture was loaded from and its modification time when it was loaded.
+    source: Option<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Texture {
+    pub fn from_rgba8(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Self {
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+
+        let image = Image::new(
+            vulkan,
+            vulkan_allocator,
+            &ImageInfo::builder()
+                .extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+                .view_subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1)
+                        .level_count(1)
+                        .build(),
+                )
+                .build(),
+        );
+
+        let staging_buffer = Arc::new(UntypedBuffer::new(
+            vulkan,
+            vulkan_allocator,
+            &BufferInfo::builder()
+                .size(pixels.len() as u64)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+                .build(),
+        ));
+
+        // Safety: pixels is a valid pointer to pixels.len() bytes.
+        unsafe {
+            vulkan_stager.schedule_stage_buffer(
+                vulkan,
+                vulkan_allocator,
+                pixels.as_ptr(),
+                pixels.len() as u64,
+                &staging_buffer,
+                StageType::Immediate,
+            )
+        };
+
+        Self {
+            image,
+            staging_buffer: Some(staging_buffer),
+            upload_pending: AtomicBool::new(true),
+            source: None,
+        }
+    }
+
+    /// An uninitialized texture that can also be written as a storage image, for textures
+    /// generated by compute shaders.
+    ///
+    /// Nothing is uploaded, whatever writes it is responsible for leaving it in
+    /// `SHADER_READ_ONLY_OPTIMAL` before it is sampled.
+    pub fn storage(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let image = Image::new(
+            vulkan,
+            vulkan_allocator,
+            &ImageInfo::builder()
+                .extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
+                .view_subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1)
+                        .level_count(1)
+                        .build(),
+                )
+                .build(),
+        );
+
+        Self {
+            image,
+            staging_buffer: None,
+            upload_pending: AtomicBool::new(false),
+            source: None,
+        }
+    }
+
+    /// A single white pixel, used when no texture is set so the binding is always valid.
+    pub fn white(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+    ) -> Self {
+        Self::from_rgba8(
+            vulkan,
+            vulkan_allocator,
+            vulkan_stager,
+            1,
+            1,
+            &[255, 255, 255, 255],
+        )
+    }
+
+    pub fn load(
+        vulkan: &Vulkan,
+        vulkan_allocator: &mut VulkanAllocator,
+        vulkan_stager: &mut VulkanStager,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let modified = modified_time(path);
+        let pixels = image::open(path)
+            .map_err(|err| anyhow::anyhow!("Failed to load texture {}: {}", path.display(), err))?
+            .to_rgba8();
+
+        let mut texture = Self::from_rgba8(
+            vulkan,
+            vulkan_allocator,
+            vulkan_stager,
+            pixels.width(),
+            pixels.height(),
+            pixels.as_raw(),
+        );
+        texture.source = Some((path.to_path_buf(), modified));
+        Ok(texture)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.source.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    /// Whether the file this texture was loaded from has changed since it was loaded.
+    pub fn is_modified(&self) -> bool {
+        match &self.source {
+            Some((path, modified)) => modified_time(path) != *modified,
+            None => false,
+        }
+    }
+
+    /// Records the current modification time, so a failed reload isn't retried until the file
+    /// changes again.
+    pub fn update_modified_time(&mut self) {
+        if let Some((path, modified)) = &mut self.source {
+            *modified = modified_time(path);
+        }
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Records the copy from the staging buffer into the image if it hasn't happened yet.
+    pub fn record_upload(
+        &self,
+        vulkan: &Vulkan,
+        command_buffer: &CommandBuffer,
+    ) -> Vec<Arc<dyn Any + Send + Sync>> {
+        let Some(staging_buffer) = &self.staging_buffer else {
+            return vec![];
+        };
+        if !self.upload_pending.swap(false, Ordering::AcqRel) {
+            return vec![];
+        }
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[self.image.image_memory_barrier(
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+            )],
+        );
+
+        let extent = self.image.image_extent();
+        unsafe {
+            vulkan.device().cmd_copy_buffer_to_image(
+                command_buffer.command_buffer(),
+                staging_buffer.buffer(),
+                self.image.image(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::BufferImageCopy::builder()
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .image_extent(extent)
+                    .build()],
+            );
+        }
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[self.image.image_memory_barrier(
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+            )],
+        );
+
+        vec![staging_buffer.clone(), self.image.create_dep()]
+    }
+}
+
+/// Remembers a texture file that failed to load, so it is retried once the file changes again
+/// instead of every frame or never.
+#[derive(Default)]
+pub struct LoadFailure {
+    // The file and its modification time when it failed.
+    failed: Option<(PathBuf, Option<SystemTime>)>,
+}
+
+impl LoadFailure {
+    pub fn record(&mut self, path: &Path) {
+        self.failed = Some((path.to_path_buf(), modified_time(path)));
+    }
+
+    pub fn clear(&mut self) {
+        self.failed = None;
+    }
+
+    /// Whether `path` is the file that failed and it has changed since.
+    pub fn should_retry(&self, path: Option<&Path>) -> bool {
+        match (&self.failed, path) {
+            (Some((failed_path, modified)), Some(path)) => {
+                failed_path == path && modified_time(path) != *modified
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
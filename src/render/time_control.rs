@@ -0,0 +1,114 @@
+use pyrite::prelude::{AppBuilder, Input, Key, Res, ResMut, Resource, Time};
+
+pub fn setup_time_control(app_builder: &mut AppBuilder) {
+    app_builder.add_resource(TimeControl::new());
+    app_builder.add_system(TimeControl::update_system);
+}
+
+// Seconds of animation time moved per real second while a scrub key is held.
+const SCRUB_SPEED: f32 = 1.0;
+// Seconds of animation time moved per step while paused, one frame at 60 fps.
+const STEP_SIZE: f32 = 1.0 / 60.0;
+// The time scales the function keys switch between, for slow motion and fast forward.
+const TIME_SCALE_KEYS: [(Key, f32); 3] = [(Key::F5, 0.25), (Key::F6, 1.0), (Key::F7, 4.0)];
+
+/// Owns the animation clock fed to the shaders, which can be scrubbed independently of real time.
+#[derive(Resource)]
+pub struct TimeControl {
+    // Accumulated in f64 so the clock doesn't lose precision over long sessions.
+    animation_time: f64,
+    // While paused the clock only moves when scrubbed or stepped.
+    paused: bool,
+    // How many seconds of animation time pass per real second.
+    time_scale: f32,
+}
+
+impl TimeControl {
+    pub fn new() -> Self {
+        Self {
+            animation_time: 0.0,
+            paused: false,
+            time_scale: 1.0,
+        }
+    }
+
+    /// The current animation time in seconds.
+    pub fn animation_time(&self) -> f32 {
+        self.animation_time as f32
+    }
+
+    /// Seeks the animation clock to an absolute time in seconds.
+    pub fn set_animation_time(&mut self, animation_time: f32) {
+        self.animation_time = animation_time.max(0.0) as f64;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Freezes the animation clock, unpausing carries on from the frozen time rather than
+    /// jumping to where real time would have taken it.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Scales how fast the animation clock follows real time, 1 is real time.
+    ///
+    /// Only the frame delta is scaled, so changing it never makes the clock jump.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    /// Moves the animation clock by a number of 60 fps frames, negative steps go backward.
+    pub fn step(&mut self, steps: i32) {
+        self.animation_time = (self.animation_time + (steps as f32 * STEP_SIZE) as f64).max(0.0);
+    }
+
+    fn update_system(mut time_control: ResMut<TimeControl>, input: Res<Input>, time: Res<Time>) {
+        let time_control = &mut *time_control;
+        let delta = time.delta().as_secs_f64();
+
+        if input.is_key_pressed(Key::T) {
+            time_control.paused = !time_control.paused;
+            println!("Animation paused: {}", time_control.paused);
+        }
+        let mut steps = 0;
+        if input.is_key_pressed(Key::LBracket) {
+            steps -= 1;
+        }
+        if input.is_key_pressed(Key::RBracket) {
+            steps += 1;
+        }
+        for (key, time_scale) in TIME_SCALE_KEYS {
+            if input.is_key_pressed(key) {
+                time_control.set_time_scale(time_scale);
+                println!("Time scale: {}x", time_scale);
+            }
+        }
+        if steps != 0 {
+            time_control.step(steps);
+            println!("Animation time: {:.3}", time_control.animation_time);
+        }
+
+        // Scrubbing takes over the clock, otherwise it follows real time.
+        let mut scrub = 0.0;
+        if input.is_key_down(Key::Comma) {
+            scrub -= 1.0;
+        }
+        if input.is_key_down(Key::Period) {
+            scrub += 1.0;
+        }
+
+        if scrub != 0.0 {
+            time_control.animation_time =
+                (time_control.animation_time + scrub * SCRUB_SPEED as f64 * delta).max(0.0);
+            println!("Animation time: {:.3}", time_control.animation_time);
+        } else if !time_control.paused {
+            time_control.animation_time += delta * time_control.time_scale as f64;
+        }
+    }
+}
@@ -1,12 +1,18 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 use pyrite::{
     asset::WatchedHandle,
     desktop::PRE_UPDATE_STAGE,
-    prelude::{AppBuilder, Assets, ResMut, Resource},
+    prelude::{AppBuilder, Assets, Input, Key, Res, ResMut, Resource},
 };
 use uuid::Uuid;
 
+use crate::asset::{set_shader_defines, shader_includes};
+
 pub fn setup_watched_shaders(app_builder: &mut AppBuilder) {
     app_builder.add_resource(WatchedShaders::new());
     app_builder.add_system_to_stage(WatchedShaders::update_system, PRE_UPDATE_STAGE);
@@ -17,6 +23,15 @@ pub struct WatchedShaders {
     // The shaders with the key being the name, and the value being the handle to the shader.
     shaders: HashMap<String, WatchedHandle<Vec<u32>>>,
     shaders_loaded: HashSet<String>,
+    shader_paths: HashMap<String, String>,
+    // The files each shader includes with their last modified time, the asset watcher only
+    // sees the shader file itself.
+    shader_includes: HashMap<String, Vec<(PathBuf, Option<SystemTime>)>>,
+    // The compile error of each shader whose latest load failed.
+    errors: HashMap<String, String>,
+    // Forced reloads still compiling, the current handle stays in use until they finish so a
+    // pipeline rebuilt in the meantime still has a shader.
+    pending_reloads: HashMap<String, WatchedHandle<Vec<u32>>>,
 
     // The key is the dependency signal, the value is the list of shaders that it depends on.
     dependency_signals: HashMap<DependencySignal, Vec<String>>,
@@ -31,6 +46,10 @@ impl WatchedShaders {
         Self {
             shaders: HashMap::new(),
             shaders_loaded: HashSet::new(),
+            shader_paths: HashMap::new(),
+            shader_includes: HashMap::new(),
+            errors: HashMap::new(),
+            pending_reloads: HashMap::new(),
             dependency_signals: HashMap::new(),
             dirty_dependency_signals: HashSet::new(),
         }
@@ -50,14 +69,79 @@ impl WatchedShaders {
         name: impl ToString,
         dependency_signal: &DependencySignal,
     ) {
-        let watched_handle = assets.load::<Vec<u32>>(file_path).into_watched();
+        let file_path = file_path.to_string();
+        let watched_handle = assets.load::<Vec<u32>>(file_path.clone()).into_watched();
         self.shaders.insert(name.to_string(), watched_handle);
+        self.shader_includes
+            .insert(name.to_string(), Self::read_includes(&file_path));
+        self.shader_paths.insert(name.to_string(), file_path);
         self.dependency_signals
             .get_mut(dependency_signal)
             .unwrap()
             .push(name.to_string());
     }
 
+    /// Loads a shader compiled with `defines` as macros, see `set_defines` to change them.
+    pub fn load_shader_with_defines(
+        &mut self,
+        assets: &mut Assets,
+        file_path: impl ToString,
+        name: impl ToString,
+        defines: Vec<String>,
+        dependency_signal: &DependencySignal,
+    ) {
+        let file_path = file_path.to_string();
+        set_shader_defines(&file_path, defines);
+        self.load_shader(assets, file_path, name, dependency_signal);
+    }
+
+    /// Recompiles a shader with different macros defined.
+    ///
+    /// Like a forced reload the current version stays in use until the new one compiles, then
+    /// its dependencies are signalled.
+    pub fn set_defines(&mut self, assets: &mut Assets, name: impl ToString, defines: Vec<String>) {
+        let name = name.to_string();
+        let file_path = self.shader_paths[&name].clone();
+        set_shader_defines(&file_path, defines);
+        self.pending_reloads
+            .insert(name, assets.load::<Vec<u32>>(file_path).into_watched());
+    }
+
+    /// Whether a shader the signal depends on is still recompiling, pipelines built now would
+    /// mix its old version with the new.
+    pub fn is_reloading(&self, dependency_signal: &DependencySignal) -> bool {
+        self.dependency_signals[dependency_signal]
+            .iter()
+            .any(|name| self.pending_reloads.contains_key(name))
+    }
+
+    /// Recompiles every shader from disk whether or not its file looks modified, for when the
+    /// watcher misses an edit.
+    ///
+    /// Each shader signals its dependencies once it compiles, failed shaders are retried too.
+    /// A shader that fails again keeps its last good version.
+    pub fn force_reload_all(&mut self, assets: &mut Assets) {
+        for (name, file_path) in &self.shader_paths {
+            self.pending_reloads.insert(
+                name.clone(),
+                assets.load::<Vec<u32>>(file_path.clone()).into_watched(),
+            );
+            self.shader_includes
+                .insert(name.clone(), Self::read_includes(file_path));
+        }
+        println!("Reloading {} shaders", self.pending_reloads.len());
+    }
+
+    /// The number of registered shaders.
+    pub fn shader_count(&self) -> usize {
+        self.shaders.len()
+    }
+
+    /// The number of registered shaders which have finished their first load.
+    pub fn loaded_shader_count(&self) -> usize {
+        self.shaders_loaded.len()
+    }
+
     pub fn is_dependency_signaled(&self, dependency_signal: &DependencySignal) -> bool {
         self.dirty_dependency_signals.contains(dependency_signal)
     }
@@ -68,10 +152,116 @@ impl WatchedShaders {
             .map(|watched_handle| watched_handle.get().unwrap().clone())
     }
 
-    pub fn update_system(mut watched_shaders: ResMut<WatchedShaders>, mut assets: ResMut<Assets>) {
+    /// The error from the shader's latest load, `None` once it compiles successfully again.
+    pub fn last_error(&self, name: impl ToString) -> Option<&String> {
+        self.errors.get(&name.to_string())
+    }
+
+    /// Every shader whose latest load failed, keyed by name.
+    pub fn current_errors(&self) -> &HashMap<String, String> {
+        &self.errors
+    }
+
+    /// Checks in debug builds that the push constant block of the loaded shader fits in the
+    /// push constant range declared for its pipeline, panicking with both sizes if not.
+    ///
+    /// With `exact` the block must be the same size, for shaders that mirror the whole Rust
+    /// struct rather than reading the first few members.
+    pub fn check_push_constant_size(&self, name: impl ToString, range_size: usize, exact: bool) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        let name = name.to_string();
+        let Some(shader) = self.get_shader(&name) else {
+            return;
+        };
+        let Some(block_size) = push_constant_block_size(&shader) else {
+            return;
+        };
+
+        let matches = if exact {
+            block_size == range_size
+        } else {
+            block_size <= range_size
+        };
+        assert!(
+            matches,
+            "Shader {} has a {} byte push constant block but the pipeline declares {} bytes",
+            name, block_size, range_size
+        );
+    }
+
+    fn read_includes(file_path: &str) -> Vec<(PathBuf, Option<SystemTime>)> {
+        shader_includes(file_path)
+            .into_iter()
+            .map(|path| {
+                let modified = Self::modified_time(&path);
+                (path, modified)
+            })
+            .collect()
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    pub fn update_system(
+        mut watched_shaders: ResMut<WatchedShaders>,
+        mut assets: ResMut<Assets>,
+        input: Res<Input>,
+    ) {
         let watched_shaders = &mut *watched_shaders;
         watched_shaders.dirty_dependency_signals.clear();
+
+        if input.is_key_pressed(Key::Grave) {
+            watched_shaders.force_reload_all(&mut *assets);
+        }
+
+        // Swap in forced reloads as they finish, signalling like a modified file would.
+        let finished_reloads = watched_shaders
+            .pending_reloads
+            .iter()
+            .filter(|(_, handle)| handle.is_loaded() || handle.is_error())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in finished_reloads {
+            let handle = watched_shaders.pending_reloads.remove(&name).unwrap();
+            if handle.is_error() {
+                let error = handle.get_error().unwrap().to_string();
+                println!("Shader {} failed to reload. Error: {}", name, error);
+                watched_shaders.errors.insert(name, error);
+                continue;
+            }
+
+            watched_shaders.errors.remove(&name);
+            watched_shaders.shaders_loaded.insert(name.clone());
+            watched_shaders.dirty_dependency_signals.extend(
+                watched_shaders
+                    .dependency_signals
+                    .iter()
+                    .filter(|(_, names)| names.contains(&name))
+                    .map(|(dependency_signal, _)| dependency_signal.clone()),
+            );
+            watched_shaders.shaders.insert(name, handle);
+        }
         for (name, shader_handle) in &mut watched_shaders.shaders {
+            // Reload the shader when one of its includes was edited, it signals again once the
+            // reload finishes like it does on the first load.
+            let file_path = &watched_shaders.shader_paths[name];
+            let include_modified = watched_shaders.shader_includes[name]
+                .iter()
+                .any(|(path, modified)| Self::modified_time(path) != *modified);
+            if include_modified {
+                *shader_handle = assets.load::<Vec<u32>>(file_path.clone()).into_watched();
+                watched_shaders.shaders_loaded.remove(name);
+                watched_shaders
+                    .shader_includes
+                    .insert(name.clone(), Self::read_includes(file_path));
+            }
+
             let new_loaded =
                 shader_handle.is_loaded() && !watched_shaders.shaders_loaded.contains(name);
             if new_loaded {
@@ -80,7 +270,14 @@ impl WatchedShaders {
 
             // Signal if the shader has been updated (file was modified) or just loaded.
             if shader_handle.update(&mut *assets) || new_loaded {
+                // The includes may have changed with the edit.
+                watched_shaders
+                    .shader_includes
+                    .insert(name.clone(), Self::read_includes(file_path));
+
                 if !shader_handle.is_error() {
+                    watched_shaders.errors.remove(name);
+
                     // Looks at what dependency signals this shader is a part of, and adds them to the
                     // dirty dependency signals list.
                     watched_shaders.dirty_dependency_signals.extend(
@@ -91,13 +288,150 @@ impl WatchedShaders {
                             .map(|(dependency_signal, _)| dependency_signal.clone()),
                     );
                 } else {
-                    println!(
-                        "Shader {} failed to load. Error: {}",
-                        name,
-                        shader_handle.get_error().unwrap()
-                    );
+                    let error = shader_handle.get_error().unwrap().to_string();
+                    println!("Shader {} failed to load. Error: {}", name, error);
+                    watched_shaders.errors.insert(name.clone(), error);
+                }
+            }
+        }
+    }
+}
+
+/// Reflects the size in bytes of the push constant block in SPIR-V, `None` if the shader has
+/// no push constants or the module couldn't be read.
+fn push_constant_block_size(spirv: &[u32]) -> Option<usize> {
+    const OP_TYPE_BOOL: u32 = 20;
+    const OP_TYPE_INT: u32 = 21;
+    const OP_TYPE_FLOAT: u32 = 22;
+    const OP_TYPE_VECTOR: u32 = 23;
+    const OP_TYPE_MATRIX: u32 = 24;
+    const OP_TYPE_ARRAY: u32 = 28;
+    const OP_TYPE_STRUCT: u32 = 30;
+    const OP_TYPE_POINTER: u32 = 32;
+    const OP_CONSTANT: u32 = 43;
+    const OP_VARIABLE: u32 = 59;
+    const OP_DECORATE: u32 = 71;
+    const OP_MEMBER_DECORATE: u32 = 72;
+    const DECORATION_ARRAY_STRIDE: u32 = 6;
+    const DECORATION_MATRIX_STRIDE: u32 = 7;
+    const DECORATION_OFFSET: u32 = 35;
+    const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+
+    // Every instruction by result id, plus the decorations layout depends on.
+    let mut types: HashMap<u32, (u32, &[u32])> = HashMap::new();
+    let mut constants: HashMap<u32, u32> = HashMap::new();
+    let mut array_strides: HashMap<u32, u32> = HashMap::new();
+    let mut member_offsets: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut matrix_strides: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut push_constant_pointer_type = None;
+
+    // Skip the 5 word header.
+    let mut words = spirv.get(5..)?;
+    while let Some(&first) = words.first() {
+        let word_count = (first >> 16) as usize;
+        let opcode = first & 0xffff;
+        if word_count == 0 || word_count > words.len() {
+            return None;
+        }
+        let operands = &words[1..word_count];
+        words = &words[word_count..];
+
+        match opcode {
+            OP_TYPE_BOOL | OP_TYPE_INT | OP_TYPE_FLOAT | OP_TYPE_VECTOR | OP_TYPE_MATRIX
+            | OP_TYPE_ARRAY | OP_TYPE_STRUCT | OP_TYPE_POINTER => {
+                types.insert(*operands.first()?, (opcode, &operands[1..]));
+            }
+            OP_CONSTANT if operands.len() >= 3 => {
+                constants.insert(operands[1], operands[2]);
+            }
+            OP_VARIABLE if operands.len() >= 3 => {
+                if operands[2] == STORAGE_CLASS_PUSH_CONSTANT {
+                    push_constant_pointer_type = Some(operands[0]);
                 }
             }
+            OP_DECORATE if operands.len() >= 3 && operands[1] == DECORATION_ARRAY_STRIDE => {
+                array_strides.insert(operands[0], operands[2]);
+            }
+            OP_MEMBER_DECORATE if operands.len() >= 4 => match operands[2] {
+                DECORATION_OFFSET => {
+                    member_offsets.insert((operands[0], operands[1]), operands[3]);
+                }
+                DECORATION_MATRIX_STRIDE => {
+                    matrix_strides.insert((operands[0], operands[1]), operands[3]);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    // The size of a type, matrix_stride is set when the type is a struct member with one.
+    fn type_size(
+        id: u32,
+        matrix_stride: Option<u32>,
+        types: &HashMap<u32, (u32, &[u32])>,
+        constants: &HashMap<u32, u32>,
+        array_strides: &HashMap<u32, u32>,
+        member_offsets: &HashMap<(u32, u32), u32>,
+        matrix_strides: &HashMap<(u32, u32), u32>,
+    ) -> Option<usize> {
+        let size = |id: u32, matrix_stride: Option<u32>| {
+            type_size(
+                id,
+                matrix_stride,
+                types,
+                constants,
+                array_strides,
+                member_offsets,
+                matrix_strides,
+            )
+        };
+
+        let (opcode, operands) = types.get(&id)?;
+        match *opcode {
+            OP_TYPE_BOOL => Some(4),
+            OP_TYPE_INT | OP_TYPE_FLOAT => Some(*operands.first()? as usize / 8),
+            OP_TYPE_VECTOR => Some(size(operands[0], None)? * operands[1] as usize),
+            OP_TYPE_MATRIX => {
+                let column_size = match matrix_stride {
+                    Some(matrix_stride) => matrix_stride as usize,
+                    None => size(operands[0], None)?,
+                };
+                Some(column_size * operands[1] as usize)
+            }
+            OP_TYPE_ARRAY => {
+                let length = *constants.get(&operands[1])? as usize;
+                let stride = match array_strides.get(&id) {
+                    Some(stride) => *stride as usize,
+                    None => size(operands[0], None)?,
+                };
+                Some(stride * length)
+            }
+            OP_TYPE_STRUCT => operands
+                .iter()
+                .enumerate()
+                .map(|(member, member_type)| {
+                    let member = member as u32;
+                    let offset = *member_offsets.get(&(id, member)).unwrap_or(&0) as usize;
+                    let matrix_stride = matrix_strides.get(&(id, member)).copied();
+                    Some(offset + size(*member_type, matrix_stride)?)
+                })
+                .try_fold(0, |block_size, end: Option<usize>| {
+                    Some(block_size.max(end?))
+                }),
+            _ => None,
         }
     }
+
+    // The variable's type is a pointer to the block struct.
+    let (_, pointer_operands) = types.get(&push_constant_pointer_type?)?;
+    type_size(
+        *pointer_operands.get(1)?,
+        None,
+        &types,
+        &constants,
+        &array_strides,
+        &member_offsets,
+        &matrix_strides,
+    )
 }
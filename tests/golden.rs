@@ -0,0 +1,39 @@
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+};
+
+/// Runs the app with `FURRY_GOLDEN` and returns what it printed for the golden check.
+///
+/// The app keeps running after the check, so it is killed once the result is read.
+fn run_golden(name: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_furry"))
+        .env("FURRY_GOLDEN", name)
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start the app");
+
+    let prefix = format!("Golden {}: ", name);
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let result = stdout
+        .lines()
+        .map_while(Result::ok)
+        .find_map(|line| line.strip_prefix(&prefix).map(str::to_string));
+
+    let _ = child.kill();
+    let _ = child.wait();
+    result.expect("the app exited before checking the golden")
+}
+
+// Needs a GPU and a display, run with `cargo test -- --ignored`. Set `FURRY_UPDATE_GOLDENS=1`
+// to write the golden from the current output instead.
+#[test]
+#[ignore]
+fn sphere_matches_golden() {
+    let result = run_golden("sphere");
+    assert!(
+        result.starts_with("match") || result.starts_with("updated"),
+        "{}",
+        result
+    );
+}